@@ -1,4 +1,4 @@
-use crate::transfer_events::{TransferEvent, TransferProgressEvent, TransferCompletedEvent, TransferFailedEvent};
+use crate::transfer_events::{CancelReason, DequeueReason, TransferEvent, TransferProgressEvent, TransferCompletedEvent, TransferFailedEvent};
 use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
 use std::sync::Arc;
@@ -37,9 +37,47 @@ pub struct PerformanceMetrics {
     pub total_connections: u64,
     pub successful_transfers: u64,
     pub failed_transfers: u64,
+    #[serde(default)]
+    pub canceled_transfers: CancellationCounts,
     pub avg_latency_ms: f64,
 }
 
+/// Canceled transfers, counted by why they were canceled
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CancellationCounts {
+    pub user_requested: u64,
+    pub shutdown: u64,
+    pub disk_full: u64,
+    #[serde(default)]
+    pub superseded: u64,
+    pub metered: u64,
+    #[serde(default)]
+    pub paused: u64,
+}
+
+impl CancellationCounts {
+    pub fn record(&mut self, reason: CancelReason) {
+        match reason {
+            CancelReason::UserRequested => self.user_requested += 1,
+            CancelReason::Shutdown => self.shutdown += 1,
+            CancelReason::DiskFull => self.disk_full += 1,
+            CancelReason::Superseded => self.superseded += 1,
+            CancelReason::Metered => self.metered += 1,
+            CancelReason::Paused => self.paused += 1,
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.user_requested
+            + self.shutdown
+            + self.disk_full
+            + self.superseded
+            + self.metered
+            + self.paused
+    }
+}
+
 /// Network activity statistics
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -126,6 +164,7 @@ impl AnalyticsService {
                 total_connections: 0,
                 successful_transfers: 0,
                 failed_transfers: 0,
+                canceled_transfers: CancellationCounts::default(),
                 avg_latency_ms: 0.0,
             })),
             network_activity: Arc::new(Mutex::new(NetworkActivity {
@@ -440,6 +479,7 @@ impl AnalyticsService {
             total_connections: 0,
             successful_transfers: 0,
             failed_transfers: 0,
+            canceled_transfers: CancellationCounts::default(),
             avg_latency_ms: 0.0,
         };
 
@@ -485,11 +525,16 @@ impl AnalyticsService {
                 activity.active_downloads += 1;
                 debug!("Transfer resumed, active downloads: {}", activity.active_downloads);
             }
-            TransferEvent::Canceled(_) => {
+            TransferEvent::Canceled(canceled) => {
+                self.performance.lock().await.canceled_transfers.record(canceled.reason);
+
                 // Decrement active downloads when canceled
                 let mut activity = self.network_activity.lock().await;
                 activity.active_downloads = activity.active_downloads.saturating_sub(1);
-                debug!("Transfer canceled, active downloads: {}", activity.active_downloads);
+                debug!(
+                    "Transfer canceled ({:?}), active downloads: {}",
+                    canceled.reason, activity.active_downloads
+                );
             }
             TransferEvent::Queued(_) => {
                 // Track queued downloads
@@ -497,6 +542,12 @@ impl AnalyticsService {
                 activity.queued_downloads += 1;
                 debug!("Transfer queued, queued downloads: {}", activity.queued_downloads);
             }
+            TransferEvent::Dequeued(dequeued) if dequeued.reason != DequeueReason::Started => {
+                // Started transfers leave the queued count via their Started event
+                let mut activity = self.network_activity.lock().await;
+                activity.queued_downloads = activity.queued_downloads.saturating_sub(1);
+                debug!("Queued transfer left, queued downloads: {}", activity.queued_downloads);
+            }
             TransferEvent::SourceConnected(source) => {
                 // Track peer connections
                 self.record_peer_connected(source.source_id.clone()).await;
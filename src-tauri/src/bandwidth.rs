@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime};
 use tokio::sync::Mutex;
@@ -6,7 +7,7 @@ use serde::{Deserialize, Serialize};
 use tracing::debug;
 use tauri::{AppHandle, Emitter};
 
-use crate::transfer_events::TransferEventBus;
+use crate::transfer_events::{TransferEventBus, TransferPriority};
 
 // ============================================================================
 // Bandwidth Events
@@ -102,6 +103,8 @@ struct Inner {
     upload_bytes_used: u64,
     download_bytes_used: u64,
     stats_last_reset: Instant,
+    // Schedulers splitting the download limit between downloads, kept at the same limit
+    download_schedulers: Vec<Arc<FairShareScheduler>>,
 }
 
 impl BandwidthController {
@@ -114,6 +117,7 @@ impl BandwidthController {
                 upload_bytes_used: 0,
                 download_bytes_used: 0,
                 stats_last_reset: Instant::now(),
+                download_schedulers: Vec::new(),
             }),
             event_bus: None,
             app_handle: Mutex::new(None),
@@ -129,6 +133,7 @@ impl BandwidthController {
                 upload_bytes_used: 0,
                 download_bytes_used: 0,
                 stats_last_reset: Instant::now(),
+                download_schedulers: Vec::new(),
             }),
             event_bus: Some(event_bus),
             app_handle: Mutex::new(None),
@@ -155,6 +160,9 @@ impl BandwidthController {
         
         inner.upload.set_limit(upload_kbps);
         inner.download.set_limit(download_kbps);
+        for scheduler in &inner.download_schedulers {
+            scheduler.set_limit(download_kbps * 1024);
+        }
         
         // Emit limits changed event
         if let Some(bus) = &self.event_bus {
@@ -174,6 +182,14 @@ impl BandwidthController {
         );
     }
     
+    /// Have `scheduler` split this controller's download limit between its downloads,
+    /// following later changes to the limit
+    pub async fn share_download_limit(&self, scheduler: Arc<FairShareScheduler>) {
+        let mut inner = self.inner.lock().await;
+        scheduler.set_limit(inner.download.limit_kbps() * 1024);
+        inner.download_schedulers.push(scheduler);
+    }
+
     /// Get current bandwidth limits
     pub async fn get_limits(&self) -> (u64, u64) {
         let inner = self.inner.lock().await;
@@ -431,6 +447,437 @@ impl TokenBucket {
     }
 }
 
+// ============================================================================
+// Fair Share Scheduler
+// ============================================================================
+
+/// Seconds of its share a download may bank while it isn't using it
+const FAIR_SHARE_BURST_SECS: f64 = 1.0;
+/// Bounds on how long a download waits before checking its share again
+const FAIR_SHARE_MIN_WAIT: Duration = Duration::from_millis(5);
+const FAIR_SHARE_MAX_WAIT: Duration = Duration::from_millis(250);
+/// Fraction of its share a download starts at, and never backs off below, under slow start
+const SLOW_START_INITIAL_FRACTION: f64 = 0.1;
+/// How long the limit counts as saturated after a download last had to wait for it
+const FAIR_SHARE_SATURATION_WINDOW: Duration = Duration::from_secs(2);
+
+/// Relative share of the download limit a transfer gets for its priority
+fn priority_weight(priority: TransferPriority) -> u32 {
+    match priority {
+        TransferPriority::Low => 1,
+        TransferPriority::Normal => 2,
+        TransferPriority::High => 4,
+    }
+}
+
+/// Splits a global download rate across concurrent downloads in proportion to their priority.
+///
+/// Each registered download has a bucket refilled at its weighted share of the limit.
+/// Once a bucket is full, further refills overflow into a surplus pool any download may
+/// draw from, so the share of a download whose sources can't keep up is not left idle.
+/// Downloads that were never registered are not limited. The scheduler is opt-in: with a
+/// limit of 0 and preemption off, `acquire` never waits.
+///
+/// A download can also be capped on its own with `set_cap`, with or without a global limit.
+/// It then gets the lower of its share and its cap, and never draws on the surplus.
+///
+/// With slow start enabled, a newly registered download gets only a tenth of its share at
+/// first and ramps up to all of it over the slow start period instead of hitting the cap at
+/// once. `back_off` halves a download's ramp after sustained errors; it then climbs back at
+/// the same pace. A download still ramping up doesn't draw on the surplus.
+///
+/// With preemption enabled, low-priority downloads give way entirely while a high-priority
+/// one runs and the budget is used up, either because downloads asking for all the limit
+/// allows still had to wait within the last couple of seconds or because every download
+/// slot is taken: they get no share and `acquire` holds them, so they issue no new
+/// chunks and leave their sources to the others. They pick up where they left off once the
+/// high-priority download finishes or is lowered.
+pub struct FairShareScheduler {
+    state: std::sync::Mutex<FairShareState>,
+    /// Woken whenever a change may lift a preemption
+    resumed: tokio::sync::Notify,
+}
+
+struct FairShareState {
+    /// Global download limit in bytes per second (0 = unlimited)
+    limit_bps: f64,
+    shares: HashMap<String, DownloadShare>,
+    surplus: f64,
+    last_refill: Instant,
+    /// Last time a download with the whole limit open to it had to wait for tokens
+    saturated_at: Option<Instant>,
+    /// Time a download takes to ramp up to its full share (zero = no slow start)
+    slow_start: Duration,
+    /// Low-priority downloads yield to high-priority ones when the budget is used up
+    preemption: bool,
+    /// Downloads allowed to run at once (0 = unlimited); all of them running counts as
+    /// the budget being used up
+    max_downloads: usize,
+}
+
+struct DownloadShare {
+    priority: TransferPriority,
+    /// May go negative: a request larger than the banked tokens borrows against refills
+    tokens: f64,
+    /// Fraction of the weighted share currently granted, up to 1.0
+    ramp: f64,
+    /// This download's own limit in bytes per second, if it has one
+    cap_bps: Option<f64>,
+}
+
+impl DownloadShare {
+    /// Weight in the split; none for a low-priority download while it is preempted
+    fn weight(&self, contended: bool) -> u32 {
+        if contended && self.priority == TransferPriority::Low {
+            0
+        } else {
+            priority_weight(self.priority)
+        }
+    }
+}
+
+impl FairShareScheduler {
+    /// Create a scheduler for `max_download_bps` bytes per second (0 = unlimited)
+    pub fn new(max_download_bps: u64) -> Self {
+        Self {
+            state: std::sync::Mutex::new(FairShareState {
+                limit_bps: max_download_bps as f64,
+                shares: HashMap::new(),
+                surplus: 0.0,
+                last_refill: Instant::now(),
+                saturated_at: None,
+                slow_start: Duration::ZERO,
+                preemption: false,
+                max_downloads: 0,
+            }),
+            resumed: tokio::sync::Notify::new(),
+        }
+    }
+
+    /// Ramp new downloads up to their share over `slow_start` (zero = no slow start)
+    pub fn set_slow_start(&self, slow_start: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.refill(Instant::now());
+        state.slow_start = slow_start;
+        if slow_start.is_zero() {
+            for share in state.shares.values_mut() {
+                share.ramp = 1.0;
+            }
+        }
+    }
+
+    /// Let high-priority downloads preempt low-priority ones when the budget is used up
+    pub fn set_preemption(&self, enabled: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.refill(Instant::now());
+        state.preemption = enabled;
+        drop(state);
+        self.resumed.notify_waiters();
+    }
+
+    /// Downloads allowed to run at once (0 = unlimited), for deciding when to preempt
+    pub fn set_max_downloads(&self, max_downloads: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.refill(Instant::now());
+        state.max_downloads = max_downloads;
+        drop(state);
+        self.resumed.notify_waiters();
+    }
+
+    /// Change the global limit; shares are recomputed from the next refill
+    pub fn set_limit(&self, max_download_bps: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.refill(Instant::now());
+        state.limit_bps = max_download_bps as f64;
+        state.surplus = state.surplus.min(state.limit_bps * FAIR_SHARE_BURST_SECS);
+        drop(state);
+        self.resumed.notify_waiters();
+    }
+
+    /// Start scheduling a download; keeps its priority if it is already registered
+    pub fn register(&self, transfer_id: &str, priority: TransferPriority) {
+        let mut state = self.state.lock().unwrap();
+        state.refill(Instant::now());
+        let ramp = state.initial_ramp();
+        state
+            .shares
+            .entry(transfer_id.to_string())
+            .or_insert(DownloadShare {
+                priority,
+                tokens: 0.0,
+                ramp,
+                cap_bps: None,
+            });
+    }
+
+    /// Change a download's priority, registering it if needed
+    pub fn set_priority(&self, transfer_id: &str, priority: TransferPriority) {
+        let mut state = self.state.lock().unwrap();
+        state.refill(Instant::now());
+        let ramp = state.initial_ramp();
+        state
+            .shares
+            .entry(transfer_id.to_string())
+            .or_insert(DownloadShare {
+                priority,
+                tokens: 0.0,
+                ramp,
+                cap_bps: None,
+            })
+            .priority = priority;
+        drop(state);
+        self.resumed.notify_waiters();
+    }
+
+    /// Limit a registered download to `max_bps` bytes per second (0 = no cap of its own)
+    ///
+    /// Under slow start a newly capped download ramps up to the cap as it would to its
+    /// share. Returns false if the download isn't registered.
+    pub fn set_cap(&self, transfer_id: &str, max_bps: u64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.refill(Instant::now());
+        let initial_ramp = state.initial_ramp();
+        let Some(share) = state.shares.get_mut(transfer_id) else {
+            return false;
+        };
+        let cap_bps = (max_bps > 0).then_some(max_bps as f64);
+        if cap_bps.is_some() && share.cap_bps.is_none() {
+            share.ramp = share.ramp.min(initial_ramp);
+        }
+        share.cap_bps = cap_bps;
+        share.tokens = share.tokens.min(cap_bps.unwrap_or(f64::MAX) * FAIR_SHARE_BURST_SECS);
+        true
+    }
+
+    /// A download's own cap in bytes per second, if it has one
+    pub fn cap(&self, transfer_id: &str) -> Option<u64> {
+        let state = self.state.lock().unwrap();
+        state
+            .shares
+            .get(transfer_id)
+            .and_then(|share| share.cap_bps)
+            .map(|cap| cap as u64)
+    }
+
+    /// Halve a download's share after sustained errors; it ramps back up as under slow start
+    ///
+    /// Does nothing while slow start is off.
+    pub fn back_off(&self, transfer_id: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.refill(Instant::now());
+        if state.slow_start.is_zero() {
+            return;
+        }
+        if let Some(share) = state.shares.get_mut(transfer_id) {
+            share.ramp = (share.ramp / 2.0).max(SLOW_START_INITIAL_FRACTION);
+        }
+    }
+
+    /// Stop scheduling a download; its share goes to the remaining downloads
+    pub fn unregister(&self, transfer_id: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.refill(Instant::now());
+        state.shares.remove(transfer_id);
+        drop(state);
+        self.resumed.notify_waiters();
+    }
+
+    /// Bytes per second a download is currently entitled to, before any surplus
+    pub fn share_bps(&self, transfer_id: &str) -> Option<f64> {
+        let state = self.state.lock().unwrap();
+        state.share_rate(transfer_id)
+    }
+
+    /// Whether a download is held back for a higher-priority one
+    pub fn is_preempted(&self, transfer_id: &str) -> bool {
+        let state = self.state.lock().unwrap();
+        state.is_preempted(transfer_id)
+    }
+
+    /// The priority a download is scheduled with, if it is registered
+    pub fn priority(&self, transfer_id: &str) -> Option<TransferPriority> {
+        let state = self.state.lock().unwrap();
+        state.shares.get(transfer_id).map(|share| share.priority)
+    }
+
+    /// Wait until `transfer_id` may download `bytes` more
+    ///
+    /// Returns false without taking anything if the download is preempted, before or
+    /// while it waits, so the caller can let go of what it holds for the chunk and
+    /// `wait_while_preempted` before asking again.
+    pub async fn acquire(&self, transfer_id: &str, bytes: usize) -> bool {
+        loop {
+            let wait = {
+                let mut state = self.state.lock().unwrap();
+                if state.is_preempted(transfer_id) {
+                    return false;
+                }
+                if bytes == 0 {
+                    return true;
+                }
+                state.take(transfer_id, bytes as f64, Instant::now())
+            };
+            match wait {
+                Ok(()) => return true,
+                Err(delay) => sleep(delay).await,
+            }
+        }
+    }
+
+    /// Wait until `transfer_id` is no longer preempted
+    pub async fn wait_while_preempted(&self, transfer_id: &str) {
+        loop {
+            let resumed = self.resumed.notified();
+            tokio::pin!(resumed);
+            resumed.as_mut().enable();
+            if !self.is_preempted(transfer_id) {
+                return;
+            }
+            resumed.await;
+        }
+    }
+}
+
+impl FairShareState {
+    fn initial_ramp(&self) -> f64 {
+        if self.slow_start.is_zero() {
+            1.0
+        } else {
+            SLOW_START_INITIAL_FRACTION
+        }
+    }
+
+    /// Whether the downloads have recently wanted more than the global limit allows
+    fn saturated(&self) -> bool {
+        self.limit_bps > 0.0
+            && self.saturated_at.is_some_and(|at| {
+                self.last_refill.saturating_duration_since(at) < FAIR_SHARE_SATURATION_WINDOW
+            })
+    }
+
+    /// Whether low-priority downloads are preempted right now
+    fn contended(&self) -> bool {
+        let budget_used_up = self.saturated()
+            || (self.max_downloads > 0 && self.shares.len() >= self.max_downloads);
+        self.preemption
+            && budget_used_up
+            && self
+                .shares
+                .values()
+                .any(|share| share.priority == TransferPriority::High)
+    }
+
+    fn is_preempted(&self, transfer_id: &str) -> bool {
+        let contended = self.contended();
+        self.shares
+            .get(transfer_id)
+            .is_some_and(|share| share.weight(contended) == 0)
+    }
+
+    /// A download's weighted share of the global limit, ignoring its cap and ramp; `None`
+    /// when there is no global limit
+    fn weighted_share(&self, share: &DownloadShare, contended: bool) -> Option<f64> {
+        let total_weight: u32 = self.shares.values().map(|s| s.weight(contended)).sum();
+        if self.limit_bps <= 0.0 || total_weight == 0 {
+            return None;
+        }
+        Some(self.limit_bps * share.weight(contended) as f64 / total_weight as f64)
+    }
+
+    fn share_rate(&self, transfer_id: &str) -> Option<f64> {
+        let share = self.shares.get(transfer_id)?;
+        let full_rate = lower_rate(self.weighted_share(share, self.contended()), share.cap_bps)?;
+        Some(full_rate * share.ramp)
+    }
+
+    /// Credit each share for the time since the last refill; what overflows full
+    /// buckets, or is held back from downloads still ramping up or capped below their
+    /// share, becomes surplus. Time with no downloads registered earns nothing.
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+        let ramp_step = if self.slow_start.is_zero() {
+            1.0
+        } else {
+            elapsed / self.slow_start.as_secs_f64()
+        };
+        let contended = self.contended();
+        let weighted_shares: Vec<Option<f64>> = self
+            .shares
+            .values()
+            .map(|share| self.weighted_share(share, contended))
+            .collect();
+
+        let mut overflow = 0.0;
+        for (share, weighted_share) in self.shares.values_mut().zip(weighted_shares) {
+            if share.weight(contended) == 0 {
+                continue;
+            }
+            if let Some(full_rate) = lower_rate(weighted_share, share.cap_bps) {
+                let rate = full_rate * share.ramp;
+                let capacity = rate * FAIR_SHARE_BURST_SECS;
+                share.tokens += elapsed * rate;
+                // Only a global limit has a surplus to give what goes unused to
+                if let Some(weighted_share) = weighted_share {
+                    overflow += elapsed * (weighted_share - rate);
+                    overflow += (share.tokens - capacity).max(0.0);
+                }
+                share.tokens = share.tokens.min(capacity);
+            }
+            share.ramp = (share.ramp + ramp_step).min(1.0);
+        }
+        self.surplus = (self.surplus + overflow).min(self.limit_bps * FAIR_SHARE_BURST_SECS);
+    }
+
+    /// Take `bytes` for a download, or say how long to wait before trying again
+    fn take(&mut self, transfer_id: &str, bytes: f64, now: Instant) -> Result<(), Duration> {
+        self.refill(now);
+        let Some(rate) = self.share_rate(transfer_id) else {
+            return Ok(());
+        };
+        let Some(share) = self.shares.get_mut(transfer_id) else {
+            return Ok(());
+        };
+
+        // Surplus would let a download skip the rest of its ramp, or go past its cap
+        let limited_by_itself = share.ramp < 1.0 || share.cap_bps.is_some();
+        let surplus = if limited_by_itself { 0.0 } else { self.surplus };
+        let available = share.tokens + surplus;
+        if available > 0.0 {
+            // Own tokens first, then surplus; anything beyond is borrowed from future refills
+            let from_surplus = (bytes - share.tokens.max(0.0)).clamp(0.0, surplus);
+            self.surplus -= from_surplus;
+            share.tokens -= bytes - from_surplus;
+            return Ok(());
+        }
+
+        // A preempted download earns nothing, so only a change of state lets it through
+        if rate <= 0.0 {
+            return Err(FAIR_SHARE_MAX_WAIT);
+        }
+        // Held back by its ramp or cap, a download says nothing about the limit
+        if !limited_by_itself {
+            self.saturated_at = Some(now);
+        }
+        let wait = Duration::from_secs_f64(-available / rate);
+        Err(wait.clamp(FAIR_SHARE_MIN_WAIT, FAIR_SHARE_MAX_WAIT))
+    }
+}
+
+/// The lower of two rates, either of which may be unlimited (`None`)
+fn lower_rate(a: Option<f64>, b: Option<f64>) -> Option<f64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+impl Default for FairShareScheduler {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
 // ============================================================================
 // Event Emission Helper
 // ============================================================================
@@ -526,6 +973,231 @@ mod tests {
         assert!(result.is_none());
     }
 
+    /// Forget whatever the shares earned while the test was registering them
+    fn drain(state: &mut FairShareState) -> Instant {
+        for share in state.shares.values_mut() {
+            share.tokens = 0.0;
+        }
+        state.surplus = 0.0;
+        state.last_refill
+    }
+
+    #[test]
+    fn test_fair_share_splits_by_priority() {
+        let scheduler = FairShareScheduler::new(5000);
+        scheduler.register("high", TransferPriority::High);
+        scheduler.register("low", TransferPriority::Low);
+        // Registering again keeps the existing priority
+        scheduler.register("high", TransferPriority::Low);
+        assert_eq!(scheduler.share_bps("high"), Some(4000.0));
+        assert_eq!(scheduler.share_bps("low"), Some(1000.0));
+
+        let mut state = scheduler.state.lock().unwrap();
+        let start = drain(&mut state);
+        let later = start + Duration::from_millis(500);
+        assert!(state.take("high", 2000.0, later).is_ok());
+        assert!(state.take("low", 500.0, later).is_ok());
+        // Both have spent what they earned; the next request must wait
+        assert!(state.take("high", 1.0, later).is_err());
+        assert!(state.take("low", 1.0, later).is_err());
+        // Unregistered downloads are not limited
+        assert!(state.take("other", 1_000_000.0, later).is_ok());
+    }
+
+    #[test]
+    fn test_fair_share_lends_unused_share() {
+        let scheduler = FairShareScheduler::new(4000);
+        scheduler.register("fast", TransferPriority::Normal);
+        scheduler.register("slow", TransferPriority::Normal);
+
+        let mut state = scheduler.state.lock().unwrap();
+        let start = drain(&mut state);
+        // "slow" banks one second of its share; the rest of its share overflows to surplus
+        let later = start + Duration::from_secs(3);
+        assert!(state.take("fast", 2000.0, later).is_ok());
+        assert!(state.take("fast", 2000.0, later).is_ok());
+        assert!(state.take("fast", 2000.0, later).is_ok());
+        assert!(state.take("fast", 1.0, later).is_err());
+        assert!(state.take("slow", 2000.0, later).is_ok());
+        assert!(state.take("slow", 1.0, later).is_err());
+    }
+
+    #[test]
+    fn test_fair_share_borrows_for_large_requests() {
+        let scheduler = FairShareScheduler::new(1000);
+        scheduler.register("a", TransferPriority::Normal);
+
+        let mut state = scheduler.state.lock().unwrap();
+        let start = drain(&mut state);
+        // A chunk bigger than the burst is granted once tokens are available...
+        assert!(state.take("a", 10_000.0, start + Duration::from_millis(100)).is_ok());
+        // ...and paid back before anything else goes through
+        let wait = state
+            .take("a", 1.0, start + Duration::from_millis(200))
+            .unwrap_err();
+        assert_eq!(wait, FAIR_SHARE_MAX_WAIT);
+        assert!(state.take("a", 1.0, start + Duration::from_secs(11)).is_ok());
+    }
+
+    #[test]
+    fn test_fair_share_slow_start_ramps_up_and_backs_off() {
+        let scheduler = FairShareScheduler::new(1000);
+        scheduler.set_slow_start(Duration::from_secs(10));
+        scheduler.register("a", TransferPriority::Normal);
+
+        let mut state = scheduler.state.lock().unwrap();
+        let start = drain(&mut state);
+        // The first second earns a tenth of the share; the rest goes to surplus
+        assert!(state.take("a", 150.0, start + Duration::from_secs(1)).is_ok());
+        // ...which a download still ramping up can't draw on
+        assert!(state.take("a", 1.0, start + Duration::from_secs(1)).is_err());
+        // The next half second earns at a fifth of the share
+        assert!(state.take("a", 1.0, start + Duration::from_millis(1500)).is_ok());
+        state.refill(start + Duration::from_secs(20));
+        assert_eq!(state.share_rate("a"), Some(1000.0));
+        drop(state);
+
+        scheduler.back_off("a");
+        assert_eq!(scheduler.share_bps("a"), Some(500.0));
+        scheduler.set_slow_start(Duration::ZERO);
+        assert_eq!(scheduler.share_bps("a"), Some(1000.0));
+    }
+
+    #[test]
+    fn test_fair_share_preemption_holds_low_priority_downloads() {
+        let scheduler = FairShareScheduler::new(0);
+        scheduler.set_preemption(true);
+        scheduler.set_max_downloads(2);
+        scheduler.register("low", TransferPriority::Low);
+        scheduler.register("normal", TransferPriority::Normal);
+        // Slots are full, but nothing of high priority is running
+        assert!(!scheduler.is_preempted("low"));
+
+        scheduler.set_priority("normal", TransferPriority::High);
+        assert!(scheduler.is_preempted("low"));
+        assert!(!scheduler.is_preempted("normal"));
+        {
+            // Without a rate limit there is no budget to take from; preemption alone holds it
+            let mut state = scheduler.state.lock().unwrap();
+            let now = Instant::now();
+            assert!(state.take("low", 1.0, now).is_ok());
+            assert!(state.take("normal", 1.0, now).is_ok());
+        }
+
+        // A free slot means there is room for both
+        scheduler.set_max_downloads(3);
+        assert!(!scheduler.is_preempted("low"));
+
+        // A rate limit only counts as used up once downloads have to wait for it...
+        scheduler.set_max_downloads(0);
+        scheduler.set_limit(3000);
+        scheduler.register("other", TransferPriority::Normal);
+        assert!(!scheduler.is_preempted("low"));
+        {
+            let mut state = scheduler.state.lock().unwrap();
+            let start = drain(&mut state);
+            assert!(state.take("other", 1.0, start).is_err());
+        }
+        // ...and then the preempted download's share goes to the others
+        assert!(scheduler.is_preempted("low"));
+        assert_eq!(scheduler.share_bps("low"), Some(0.0));
+        assert_eq!(scheduler.share_bps("normal"), Some(2000.0));
+        assert_eq!(scheduler.share_bps("other"), Some(1000.0));
+        {
+            // Until the downloads stop asking for more than the limit
+            let mut state = scheduler.state.lock().unwrap();
+            let start = state.last_refill;
+            state.refill(start + FAIR_SHARE_SATURATION_WINDOW);
+            assert!(!state.is_preempted("low"));
+            state.saturated_at = Some(start + FAIR_SHARE_SATURATION_WINDOW);
+        }
+        assert!(scheduler.is_preempted("low"));
+
+        scheduler.unregister("normal");
+        assert!(!scheduler.is_preempted("low"));
+        scheduler.set_priority("other", TransferPriority::High);
+        scheduler.set_preemption(false);
+        assert!(!scheduler.is_preempted("low"));
+    }
+
+    #[test]
+    fn test_fair_share_caps_single_downloads() {
+        let scheduler = FairShareScheduler::new(0);
+        assert!(!scheduler.set_cap("capped", 1000));
+        scheduler.register("capped", TransferPriority::Normal);
+        scheduler.register("free", TransferPriority::Normal);
+        assert!(scheduler.set_cap("capped", 1000));
+        assert_eq!(scheduler.cap("capped"), Some(1000));
+        // Without a global limit only the capped download is held back
+        assert_eq!(scheduler.share_bps("capped"), Some(1000.0));
+        assert_eq!(scheduler.share_bps("free"), None);
+        {
+            let mut state = scheduler.state.lock().unwrap();
+            let start = drain(&mut state);
+            let later = start + Duration::from_secs(1);
+            assert!(state.take("capped", 1000.0, later).is_ok());
+            assert!(state.take("capped", 1.0, later).is_err());
+            assert!(state.take("free", 1_000_000.0, later).is_ok());
+        }
+
+        // Under a global limit the cap applies when it is lower than the share, and what
+        // the capped download leaves unused goes to the others
+        scheduler.set_limit(4000);
+        assert_eq!(scheduler.share_bps("capped"), Some(1000.0));
+        assert_eq!(scheduler.share_bps("free"), Some(2000.0));
+        {
+            let mut state = scheduler.state.lock().unwrap();
+            let start = drain(&mut state);
+            let later = start + Duration::from_secs(1);
+            assert!(state.take("capped", 1000.0, later).is_ok());
+            // The surplus isn't for the capped download
+            assert!(state.take("capped", 1.0, later).is_err());
+            assert!(state.take("free", 3000.0, later).is_ok());
+            assert!(state.take("free", 1.0, later).is_err());
+        }
+
+        // A new cap ramps up under slow start; removing it restores the plain share
+        scheduler.set_slow_start(Duration::from_secs(10));
+        scheduler.register("late", TransferPriority::Normal);
+        scheduler.set_cap("late", 500);
+        assert_eq!(scheduler.share_bps("late"), Some(50.0));
+        scheduler.set_cap("late", 0);
+        assert_eq!(scheduler.cap("late"), None);
+        assert!(scheduler.share_bps("late").unwrap() < 4000.0 / 3.0);
+    }
+
+    #[tokio::test]
+    async fn test_fair_share_unlimited_never_waits() {
+        let scheduler = FairShareScheduler::default();
+        scheduler.register("a", TransferPriority::Low);
+        assert!(scheduler.acquire("a", 10_000_000).await);
+        assert_eq!(scheduler.share_bps("a"), None);
+    }
+
+    #[tokio::test]
+    async fn test_fair_share_holds_preempted_downloads_until_resumed() {
+        let scheduler = Arc::new(FairShareScheduler::new(0));
+        scheduler.set_preemption(true);
+        scheduler.set_max_downloads(2);
+        scheduler.register("low", TransferPriority::Low);
+        scheduler.register("high", TransferPriority::High);
+        assert!(!scheduler.acquire("low", 1000).await);
+        assert!(scheduler.acquire("high", 1000).await);
+
+        let waiter = {
+            let scheduler = scheduler.clone();
+            tokio::spawn(async move { scheduler.wait_while_preempted("low").await })
+        };
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+        scheduler.unregister("high");
+        tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .expect("preemption should lift")
+            .unwrap();
+        assert!(scheduler.acquire("low", 1000).await);
+    }
+
     #[test]
     fn test_direction_as_str() {
         assert_eq!(Direction::Upload.as_str(), "upload");
@@ -555,6 +1227,19 @@ mod tests {
         assert!(download_limited);
     }
 
+    #[tokio::test]
+    async fn test_bandwidth_controller_drives_shared_schedulers() {
+        let controller = BandwidthController::new();
+        controller.set_limits(0, 100).await;
+        let scheduler = Arc::new(FairShareScheduler::default());
+        scheduler.register("a", TransferPriority::Normal);
+        controller.share_download_limit(scheduler.clone()).await;
+        assert_eq!(scheduler.share_bps("a"), Some(100.0 * 1024.0));
+
+        controller.set_limits(0, 0).await;
+        assert_eq!(scheduler.share_bps("a"), None);
+    }
+
     #[tokio::test]
     async fn test_bandwidth_controller_usage_tracking() {
         let controller = BandwidthController::new();
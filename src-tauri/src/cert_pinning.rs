@@ -0,0 +1,399 @@
+// cert_pinning.rs
+// TLS certificate pinning for HTTPS and FTPS sources
+//
+// A pin ties a host to the SHA-256 of its leaf certificate or of that certificate's public
+// key (SubjectPublicKeyInfo). Public-key pins survive a certificate being renewed with the
+// same key, so they are the default. A host can have several pins, e.g. the current key and
+// a backup; a connection is accepted when any of them matches. Hosts without pins are left
+// to the normal CA validation.
+//
+// Pins are checked during the TLS handshake, so a mismatching server never sees a request.
+// HTTPS clients get that from `client_builder`, FTPS from `PinnedTlsConnector`.
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use tracing::warn;
+use suppaftp::{FtpError, FtpResult, NativeTlsConnector, NativeTlsStream, TlsConnector, TlsStream};
+
+/// What a pin's hash is taken over
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PinKind {
+    /// The DER encoding of the leaf certificate
+    Certificate,
+    /// The DER encoding of the leaf certificate's SubjectPublicKeyInfo
+    #[default]
+    PublicKey,
+}
+
+/// A pinned certificate or public key for one host
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CertificatePin {
+    pub host: String,
+    #[serde(default)]
+    pub kind: PinKind,
+    /// Hex-encoded SHA-256
+    pub sha256: String,
+}
+
+/// A pinned host presented a certificate that matches none of its pins
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[error(
+    "Certificate pin mismatch for {host}: presented certificate {certificate_sha256} \
+     (public key {public_key_sha256}) matches none of its pins"
+)]
+pub struct CertificatePinMismatch {
+    pub host: String,
+    /// SHA-256 of the presented leaf certificate, empty when none was presented
+    pub certificate_sha256: String,
+    /// SHA-256 of the presented public key, empty when it couldn't be parsed
+    pub public_key_sha256: String,
+}
+
+/// Pins grouped by host
+#[derive(Debug, Clone, Default)]
+pub struct PinSet {
+    pins: HashMap<String, Vec<CertificatePin>>,
+}
+
+impl PinSet {
+    /// Build a pin set, rejecting pins that aren't a hex SHA-256 or have no host
+    pub fn new(pins: Vec<CertificatePin>) -> Result<Self, String> {
+        let mut by_host: HashMap<String, Vec<CertificatePin>> = HashMap::new();
+        for mut pin in pins {
+            pin.host = normalize_host(&pin.host);
+            pin.sha256 = pin.sha256.trim().replace(':', "").to_ascii_lowercase();
+            if pin.host.is_empty() {
+                return Err("Certificate pin has no host".to_string());
+            }
+            if pin.sha256.len() != 64 || hex::decode(&pin.sha256).is_err() {
+                return Err(format!(
+                    "Certificate pin for {} is not a hex SHA-256: {}",
+                    pin.host, pin.sha256
+                ));
+            }
+            by_host.entry(pin.host.clone()).or_default().push(pin);
+        }
+        Ok(Self { pins: by_host })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pins.is_empty()
+    }
+
+    pub fn is_pinned(&self, host: &str) -> bool {
+        self.pins.contains_key(&normalize_host(host))
+    }
+
+    pub fn to_vec(&self) -> Vec<CertificatePin> {
+        let mut pins: Vec<_> = self.pins.values().flatten().cloned().collect();
+        pins.sort_by(|a, b| a.host.cmp(&b.host));
+        pins
+    }
+
+    /// Check the leaf certificate `host` presented, given as DER
+    ///
+    /// Unpinned hosts always pass. A pinned host that presented no certificate fails.
+    pub fn verify(
+        &self,
+        host: &str,
+        leaf_der: Option<&[u8]>,
+    ) -> Result<(), CertificatePinMismatch> {
+        let Some(pins) = self.pins.get(&normalize_host(host)) else {
+            return Ok(());
+        };
+
+        let certificate_sha256 = leaf_der.map(sha256_hex).unwrap_or_default();
+        let public_key_sha256 = leaf_der
+            .and_then(public_key_der)
+            .map(|spki| sha256_hex(&spki))
+            .unwrap_or_default();
+
+        let matched = pins.iter().any(|pin| {
+            let presented = match pin.kind {
+                PinKind::Certificate => &certificate_sha256,
+                PinKind::PublicKey => &public_key_sha256,
+            };
+            !presented.is_empty() && *presented == pin.sha256
+        });
+        if matched {
+            return Ok(());
+        }
+
+        Err(CertificatePinMismatch {
+            host: normalize_host(host),
+            certificate_sha256,
+            public_key_sha256,
+        })
+    }
+}
+
+fn normalize_host(host: &str) -> String {
+    host.trim()
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .trim_end_matches('.')
+        .to_ascii_lowercase()
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// The DER encoding of a certificate's SubjectPublicKeyInfo
+fn public_key_der(certificate_der: &[u8]) -> Option<Vec<u8>> {
+    let (_, certificate) = x509_parser::parse_x509_certificate(certificate_der).ok()?;
+    Some(certificate.public_key().raw.to_vec())
+}
+
+static PINS: Lazy<RwLock<PinSet>> = Lazy::new(|| RwLock::new(PinSet::default()));
+static PINS_PATH: Lazy<RwLock<Option<PathBuf>>> = Lazy::new(|| RwLock::new(None));
+
+/// Loads the pins saved at `path` and saves every later change there
+///
+/// A missing file means no pins. An unreadable one is ignored with a warning, leaving the
+/// current pins in place.
+pub fn set_pins_path(path: PathBuf) {
+    match std::fs::read_to_string(&path) {
+        Ok(contents) => {
+            match serde_json::from_str::<Vec<CertificatePin>>(&contents)
+                .map_err(|e| e.to_string())
+                .and_then(PinSet::new)
+            {
+                Ok(pin_set) => *PINS.write().unwrap_or_else(|e| e.into_inner()) = pin_set,
+                Err(e) => warn!("Ignoring unreadable certificate pins {:?}: {}", path, e),
+            }
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => warn!("Failed to read certificate pins {:?}: {}", path, e),
+    }
+    *PINS_PATH.write().unwrap_or_else(|e| e.into_inner()) = Some(path);
+}
+
+/// Replace the configured pins, saving them when a pins path is set
+pub fn set_pins(pins: Vec<CertificatePin>) -> Result<(), String> {
+    let pin_set = PinSet::new(pins)?;
+    let saved = pin_set.to_vec();
+    *PINS.write().unwrap_or_else(|e| e.into_inner()) = pin_set;
+
+    let path = PINS_PATH.read().unwrap_or_else(|e| e.into_inner()).clone();
+    if let Some(path) = path {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to save certificate pins: {}", e))?;
+        }
+        let json = serde_json::to_vec_pretty(&saved)
+            .map_err(|e| format!("Failed to save certificate pins: {}", e))?;
+        std::fs::write(&path, json)
+            .map_err(|e| format!("Failed to save certificate pins: {}", e))?;
+    }
+    Ok(())
+}
+
+/// The configured pins
+pub fn pins() -> Vec<CertificatePin> {
+    PINS.read().unwrap_or_else(|e| e.into_inner()).to_vec()
+}
+
+/// Check a presented leaf certificate against the configured pins
+pub fn verify(host: &str, leaf_der: Option<&[u8]>) -> Result<(), CertificatePinMismatch> {
+    PINS.read()
+        .unwrap_or_else(|e| e.into_inner())
+        .verify(host, leaf_der)
+}
+
+/// Certificate verifier that runs the normal WebPKI checks and then the configured pins
+///
+/// Every handshake goes through it, including the ones for redirect hops and reconnects,
+/// so a pinned host can't be reached with a certificate that matches none of its pins.
+#[derive(Debug)]
+pub struct PinningVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+}
+
+impl PinningVerifier {
+    pub fn new() -> Result<Self, String> {
+        let roots = rustls::RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        let inner =
+            WebPkiServerVerifier::builder_with_provider(Arc::new(roots), crypto_provider())
+                .build()
+                .map_err(|e| format!("Failed to create certificate verifier: {}", e))?;
+        Ok(Self { inner })
+    }
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+        verify(&server_name.to_str(), Some(end_entity.as_ref()))
+            .map_err(|e| rustls::Error::General(e.to_string()))?;
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+fn crypto_provider() -> Arc<rustls::crypto::CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+static TLS_CONFIG: Lazy<rustls::ClientConfig> = Lazy::new(|| {
+    let verifier = PinningVerifier::new().expect("Bundled root certificates are valid");
+    let mut config = rustls::ClientConfig::builder_with_provider(crypto_provider())
+        .with_safe_default_protocol_versions()
+        .expect("The ring provider supports the default protocol versions")
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    config
+});
+
+/// A reqwest client builder whose TLS handshakes enforce the configured pins
+///
+/// Use it for every client that talks to download sources, in place of
+/// `reqwest::Client::builder()`.
+pub fn client_builder() -> reqwest::ClientBuilder {
+    reqwest::Client::builder().use_preconfigured_tls(TLS_CONFIG.clone())
+}
+
+/// FTPS connector that checks every TLS session, control and data, against the pins
+#[derive(Debug)]
+pub struct PinnedTlsConnector {
+    inner: NativeTlsConnector,
+}
+
+impl PinnedTlsConnector {
+    pub fn new() -> Result<Self, native_tls::Error> {
+        Ok(Self {
+            inner: NativeTlsConnector::from(native_tls::TlsConnector::new()?),
+        })
+    }
+}
+
+impl TlsConnector for PinnedTlsConnector {
+    type Stream = NativeTlsStream;
+
+    fn connect(&self, domain: &str, stream: TcpStream) -> FtpResult<Self::Stream> {
+        let mut tls_stream = self.inner.connect(domain, stream)?;
+        let leaf = tls_stream
+            .get_stream_mut()
+            .peer_certificate()
+            .ok()
+            .flatten()
+            .and_then(|certificate| certificate.to_der().ok());
+        verify(domain, leaf.as_deref()).map_err(|e| FtpError::SecureError(e.to_string()))?;
+        Ok(tls_stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pin(host: &str, kind: PinKind, sha256: &str) -> CertificatePin {
+        CertificatePin {
+            host: host.to_string(),
+            kind,
+            sha256: sha256.to_string(),
+        }
+    }
+
+    #[test]
+    fn only_pinned_hosts_are_checked() {
+        let leaf = b"not really a certificate";
+        let fingerprint = sha256_hex(leaf);
+        let pins = PinSet::new(vec![
+            pin("Mirror.Example.com.", PinKind::Certificate, &"ab".repeat(32)),
+            pin("mirror.example.com", PinKind::Certificate, &fingerprint.to_uppercase()),
+        ])
+        .unwrap();
+
+        assert!(pins.verify("other.example.com", Some(b"anything")).is_ok());
+        assert!(pins.verify("MIRROR.example.com", Some(leaf)).is_ok());
+
+        let mismatch = pins.verify("mirror.example.com", Some(b"forged")).unwrap_err();
+        assert_eq!(mismatch.host, "mirror.example.com");
+        assert_eq!(mismatch.certificate_sha256, sha256_hex(b"forged"));
+        // Not a parseable certificate, so there is no public key to match
+        assert!(mismatch.public_key_sha256.is_empty());
+        assert!(pins.verify("mirror.example.com", None).is_err());
+
+        let unparseable = PinSet::new(vec![pin("mirror.example.com", PinKind::PublicKey, "")]);
+        assert!(unparseable.is_err());
+    }
+
+    #[test]
+    fn pins_are_saved_and_reloaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("certificate_pins.json");
+        let fingerprint = "cd".repeat(32);
+
+        set_pins_path(path.clone());
+        set_pins(vec![pin("Mirror.Example.com", PinKind::PublicKey, &fingerprint)]).unwrap();
+        assert!(path.exists());
+
+        // A later start picks the saved pins back up
+        *PINS.write().unwrap() = PinSet::default();
+        set_pins_path(path.clone());
+        let reloaded = pins();
+        assert_eq!(reloaded.len(), 1);
+        assert_eq!(reloaded[0].host, "mirror.example.com");
+        assert_eq!(reloaded[0].sha256, fingerprint);
+
+        set_pins(Vec::new()).unwrap();
+        *PINS_PATH.write().unwrap() = None;
+        assert!(pins().is_empty());
+    }
+
+    #[test]
+    fn pinned_client_builds() {
+        assert!(client_builder().build().is_ok());
+    }
+}
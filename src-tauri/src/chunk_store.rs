@@ -0,0 +1,809 @@
+// chunk_store.rs
+// Storage backends for downloaded chunks
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Key, Nonce,
+};
+use async_trait::async_trait;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::RwLock;
+
+/// AES-GCM nonce length; the nonce is stored in front of each encrypted chunk
+const NONCE_LEN: usize = 12;
+
+/// Key that encrypts one download's chunks at rest
+#[derive(Clone, PartialEq, Eq)]
+pub struct ChunkEncryptionKey([u8; 32]);
+
+impl ChunkEncryptionKey {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self(key)
+    }
+
+    /// Seal this key with `wrapping_key` so it can be stored next to the download it belongs
+    /// to. The result is hex: the nonce followed by the ciphertext.
+    pub fn wrap(
+        &self,
+        wrapping_key: &ChunkEncryptionKey,
+        file_hash: &str,
+    ) -> Result<String, String> {
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&wrapping_key.0));
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let aad = key_aad(file_hash);
+        let ciphertext = cipher
+            .encrypt(&nonce, Payload { msg: &self.0, aad: &aad })
+            .map_err(|e| format!("Failed to wrap the at-rest key of {}: {}", file_hash, e))?;
+        Ok(hex::encode([nonce.as_slice(), &ciphertext].concat()))
+    }
+
+    /// Open a key sealed with `wrap` for the same file
+    pub fn unwrap_from(
+        wrapped: &str,
+        wrapping_key: &ChunkEncryptionKey,
+        file_hash: &str,
+    ) -> Result<Self, String> {
+        let sealed = hex::decode(wrapped)
+            .map_err(|e| format!("Wrapped at-rest key of {} is not hex: {}", file_hash, e))?;
+        if sealed.len() < NONCE_LEN {
+            return Err(format!("Wrapped at-rest key of {} is too short", file_hash));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&wrapping_key.0));
+        let aad = key_aad(file_hash);
+        let key = cipher
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: &aad })
+            .map_err(|_| format!("Failed to unwrap the at-rest key of {}", file_hash))?;
+        let key: [u8; 32] = key
+            .try_into()
+            .map_err(|_| format!("Wrapped at-rest key of {} has the wrong length", file_hash))?;
+        Ok(Self(key))
+    }
+}
+
+/// Binds a wrapped key to its file, so it can't be moved to another download's state
+fn key_aad(file_hash: &str) -> Vec<u8> {
+    format!("{}:at-rest-key", file_hash).into_bytes()
+}
+
+impl std::fmt::Debug for ChunkEncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ChunkEncryptionKey(..)")
+    }
+}
+
+/// What one file's stored chunks take up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StoredFileUsage {
+    /// Everything stored for the file, chunk metadata included
+    pub bytes: u64,
+    pub chunk_count: usize,
+    /// Unix timestamp in seconds of the most recent write; 0 when the store doesn't track it
+    pub last_modified: u64,
+}
+
+/// Where multi-source downloads keep verified chunks between arrival and finalization.
+///
+/// Chunks are addressed by the file hash they belong to and their chunk ID, so a
+/// download can be resumed by listing what is already stored for its file.
+#[async_trait]
+pub trait ChunkStore: Send + Sync {
+    /// Persist a chunk, replacing any previous copy
+    async fn save_chunk(&self, file_hash: &str, chunk_id: u32, data: &[u8]) -> Result<(), String>;
+
+    /// Read a chunk back, failing if it is missing or its stored metadata doesn't match
+    async fn load_chunk(&self, file_hash: &str, chunk_id: u32) -> Result<Vec<u8>, String>;
+
+    /// Whether a complete copy of the chunk is stored
+    async fn exists(&self, file_hash: &str, chunk_id: u32) -> bool;
+
+    /// Remove a chunk; removing a chunk that isn't stored is not an error
+    async fn delete(&self, file_hash: &str, chunk_id: u32) -> Result<(), String>;
+
+    /// IDs of all chunks stored for a file, in ascending order
+    async fn list_for_file(&self, file_hash: &str) -> Result<Vec<u32>, String>;
+
+    /// Hashes of every file with chunks in the store
+    async fn list_files(&self) -> Result<Vec<String>, String>;
+
+    /// What the chunks stored for a file take up
+    async fn usage(&self, file_hash: &str) -> Result<StoredFileUsage, String>;
+
+    /// Remove a file's chunks that are damaged or, with `max_age` set, older than that, and
+    /// return how many went. Stores that can't tell either remove nothing.
+    async fn prune_stale(
+        &self,
+        _file_hash: &str,
+        _max_age: Option<Duration>,
+    ) -> Result<usize, String> {
+        Ok(0)
+    }
+
+    /// Remove every chunk stored for a file
+    async fn delete_file(&self, file_hash: &str) -> Result<(), String> {
+        for chunk_id in self.list_for_file(file_hash).await? {
+            self.delete(file_hash, chunk_id).await?;
+        }
+        Ok(())
+    }
+
+    /// Encrypt chunks saved for a file from now on with `key`, and decrypt them on load.
+    /// Stores that keep nothing on disk ignore this.
+    async fn set_encryption_key(&self, _file_hash: &str, _key: ChunkEncryptionKey) {}
+
+    /// Whether chunks saved for a file are encrypted at rest
+    async fn is_encrypted(&self, _file_hash: &str) -> bool {
+        false
+    }
+
+    /// The key a file's chunks are encrypted with, so it can be persisted with the download
+    async fn encryption_key(&self, _file_hash: &str) -> Option<ChunkEncryptionKey> {
+        None
+    }
+
+    /// Flush each saved chunk to stable storage before `save_chunk` returns.
+    /// Stores that keep nothing on disk ignore this.
+    fn set_durable_writes(&self, _durable: bool) {}
+}
+
+/// Default chunk directory, relative to the working directory
+pub const DEFAULT_CHUNKS_DIR: &str = "./chunks";
+
+/// Stores each chunk as `{root}/{file_hash}/chunk_{id}.dat` with a JSON `.meta` sidecar.
+///
+/// Files with an encryption key get their `.dat` files sealed with AES-256-GCM; the sidecar
+/// records `"encrypted": true` so the chunk is never read back as plaintext.
+///
+/// With durable writes on, the `.dat` file, the sidecar and their directory are fsynced in
+/// that order, so a sidecar that survives a power loss always has its full chunk behind it.
+#[derive(Debug, Clone)]
+pub struct FilesystemChunkStore {
+    root: PathBuf,
+    keys: Arc<RwLock<HashMap<String, ChunkEncryptionKey>>>,
+    durable_writes: Arc<AtomicBool>,
+}
+
+impl FilesystemChunkStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            keys: Arc::default(),
+            durable_writes: Arc::default(),
+        }
+    }
+
+    /// Whether chunk writes are fsynced
+    pub fn durable_writes(&self) -> bool {
+        self.durable_writes.load(Ordering::Relaxed)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn file_dir(&self, file_hash: &str) -> PathBuf {
+        self.root.join(file_hash)
+    }
+
+    fn chunk_paths(&self, file_hash: &str, chunk_id: u32) -> (PathBuf, PathBuf) {
+        let file_dir = self.file_dir(file_hash);
+        (
+            file_dir.join(format!("chunk_{}.dat", chunk_id)),
+            file_dir.join(format!("chunk_{}.meta", chunk_id)),
+        )
+    }
+}
+
+impl Default for FilesystemChunkStore {
+    fn default() -> Self {
+        Self::new(DEFAULT_CHUNKS_DIR)
+    }
+}
+
+/// The associated data binds a sealed chunk to its file and position, so chunk files can't
+/// be swapped around on disk without failing to decrypt
+fn chunk_aad(file_hash: &str, chunk_id: u32) -> Vec<u8> {
+    format!("{}:{}", file_hash, chunk_id).into_bytes()
+}
+
+fn seal_chunk(
+    key: &ChunkEncryptionKey,
+    file_hash: &str,
+    chunk_id: u32,
+    data: &[u8],
+) -> Result<Vec<u8>, String> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key.0));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let aad = chunk_aad(file_hash, chunk_id);
+    let ciphertext = cipher
+        .encrypt(&nonce, Payload { msg: data, aad: &aad })
+        .map_err(|e| format!("Failed to encrypt chunk {}: {}", chunk_id, e))?;
+
+    let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+fn open_chunk(
+    key: &ChunkEncryptionKey,
+    file_hash: &str,
+    chunk_id: u32,
+    sealed: &[u8],
+) -> Result<Vec<u8>, String> {
+    if sealed.len() < NONCE_LEN {
+        return Err(format!("Encrypted chunk {} is too short", chunk_id));
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key.0));
+    let aad = chunk_aad(file_hash, chunk_id);
+    cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: &aad })
+        .map_err(|_| format!("Failed to decrypt chunk {}: wrong key or corrupted data", chunk_id))
+}
+
+/// Write `contents` to `path`, fsyncing the file when `durable` is set
+async fn write_file(path: &Path, contents: &[u8], durable: bool) -> std::io::Result<()> {
+    if !durable {
+        return tokio::fs::write(path, contents).await;
+    }
+    let mut file = tokio::fs::File::create(path).await?;
+    file.write_all(contents).await?;
+    file.sync_all().await
+}
+
+/// Make the directory entries created in `dir` durable
+#[cfg(unix)]
+async fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    tokio::fs::File::open(dir).await?.sync_all().await
+}
+
+/// Windows can't open a directory as a file; NTFS journals the entries itself
+#[cfg(not(unix))]
+async fn sync_dir(_dir: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+async fn remove_if_present(path: &Path) -> Result<(), String> {
+    match tokio::fs::remove_file(path).await {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(format!("Failed to remove {}: {}", path.display(), e)),
+    }
+}
+
+#[async_trait]
+impl ChunkStore for FilesystemChunkStore {
+    async fn save_chunk(&self, file_hash: &str, chunk_id: u32, data: &[u8]) -> Result<(), String> {
+        tokio::fs::create_dir_all(self.file_dir(file_hash))
+            .await
+            .map_err(|e| format!("Failed to create chunk directory: {}", e))?;
+
+        let key = self.keys.read().await.get(file_hash).cloned();
+        let durable = self.durable_writes();
+        let file_dir = self.file_dir(file_hash);
+        let (chunk_path, metadata_path) = self.chunk_paths(file_hash, chunk_id);
+        // Drop the sidecar first so a crash mid-write never pairs it with the wrong contents
+        remove_if_present(&metadata_path).await?;
+        let contents = match &key {
+            Some(key) => Cow::Owned(seal_chunk(key, file_hash, chunk_id, data)?),
+            None => Cow::Borrowed(data),
+        };
+        write_file(&chunk_path, &contents, durable)
+            .await
+            .map_err(|e| format!("Failed to write chunk {}: {}", chunk_id, e))?;
+        if durable {
+            sync_dir(&file_dir)
+                .await
+                .map_err(|e| format!("Failed to sync chunk directory: {}", e))?;
+        }
+
+        // The sidecar is written last so its presence marks the chunk as complete.
+        // `size` is always the plaintext size.
+        let metadata = serde_json::json!({
+            "chunk_id": chunk_id,
+            "size": data.len(),
+            "encrypted": key.is_some(),
+            "stored_at": std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            "file_hash": file_hash
+        });
+        let metadata = serde_json::to_string_pretty(&metadata)
+            .map_err(|e| format!("Failed to encode chunk metadata {}: {}", chunk_id, e))?;
+        write_file(&metadata_path, metadata.as_bytes(), durable)
+            .await
+            .map_err(|e| format!("Failed to write chunk metadata {}: {}", chunk_id, e))?;
+        if durable {
+            sync_dir(&file_dir)
+                .await
+                .map_err(|e| format!("Failed to sync chunk directory: {}", e))?;
+        }
+
+        Ok(())
+    }
+
+    async fn load_chunk(&self, file_hash: &str, chunk_id: u32) -> Result<Vec<u8>, String> {
+        let (chunk_path, metadata_path) = self.chunk_paths(file_hash, chunk_id);
+
+        if !chunk_path.exists() || !metadata_path.exists() {
+            return Err(format!("Chunk {} not found on disk for file {}", chunk_id, file_hash));
+        }
+
+        // Read metadata first
+        let metadata_content = tokio::fs::read_to_string(&metadata_path)
+            .await
+            .map_err(|e| format!("Failed to read chunk metadata: {}", e))?;
+
+        let metadata: serde_json::Value = serde_json::from_str(&metadata_content)
+            .map_err(|e| format!("Failed to parse chunk metadata: {}", e))?;
+
+        let expected_file_hash = metadata["file_hash"]
+            .as_str()
+            .ok_or("Missing file_hash in metadata")?;
+        let expected_chunk_id = metadata["chunk_id"]
+            .as_u64()
+            .ok_or("Missing chunk_id in metadata")? as u32;
+        let expected_size = metadata["size"]
+            .as_u64()
+            .ok_or("Missing size in metadata")? as usize;
+        // Sidecars written before encryption existed have no flag and are plaintext
+        let encrypted = metadata["encrypted"].as_bool().unwrap_or(false);
+
+        if expected_file_hash != file_hash {
+            return Err(format!(
+                "File hash mismatch in metadata: expected {}, got {}",
+                file_hash, expected_file_hash
+            ));
+        }
+
+        if expected_chunk_id != chunk_id {
+            return Err(format!(
+                "Chunk ID mismatch in metadata: expected {}, got {}",
+                chunk_id, expected_chunk_id
+            ));
+        }
+
+        let mut chunk_data = tokio::fs::read(&chunk_path)
+            .await
+            .map_err(|e| format!("Failed to read chunk data: {}", e))?;
+
+        if encrypted {
+            let key = self.keys.read().await.get(file_hash).cloned().ok_or_else(|| {
+                format!(
+                    "Chunk {} of {} is encrypted at rest but no key is set for the file",
+                    chunk_id, file_hash
+                )
+            })?;
+            chunk_data = open_chunk(&key, file_hash, chunk_id, &chunk_data)?;
+        }
+
+        if chunk_data.len() != expected_size {
+            return Err(format!(
+                "Chunk size mismatch: expected {}, got {}",
+                expected_size,
+                chunk_data.len()
+            ));
+        }
+
+        Ok(chunk_data)
+    }
+
+    async fn exists(&self, file_hash: &str, chunk_id: u32) -> bool {
+        let (chunk_path, metadata_path) = self.chunk_paths(file_hash, chunk_id);
+        chunk_path.exists() && metadata_path.exists()
+    }
+
+    async fn delete(&self, file_hash: &str, chunk_id: u32) -> Result<(), String> {
+        let (chunk_path, metadata_path) = self.chunk_paths(file_hash, chunk_id);
+        remove_if_present(&metadata_path).await?;
+        remove_if_present(&chunk_path).await
+    }
+
+    async fn list_for_file(&self, file_hash: &str) -> Result<Vec<u32>, String> {
+        let file_dir = self.file_dir(file_hash);
+        if !file_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut chunk_ids = Vec::new();
+        let mut dir_entries = tokio::fs::read_dir(&file_dir)
+            .await
+            .map_err(|e| format!("Failed to read chunks directory: {}", e))?;
+
+        while let Some(entry) = dir_entries
+            .next_entry()
+            .await
+            .map_err(|e| format!("Failed to read directory entry: {}", e))?
+        {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+
+            // A chunk counts once both its metadata and data files are present
+            let chunk_id = file_name
+                .strip_prefix("chunk_")
+                .and_then(|s| s.strip_suffix(".meta"))
+                .and_then(|id| id.parse::<u32>().ok());
+            if let Some(chunk_id) = chunk_id {
+                if file_dir.join(format!("chunk_{}.dat", chunk_id)).exists() {
+                    chunk_ids.push(chunk_id);
+                }
+            }
+        }
+
+        chunk_ids.sort_unstable();
+        Ok(chunk_ids)
+    }
+
+    async fn list_files(&self) -> Result<Vec<String>, String> {
+        if !self.root.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut file_hashes = Vec::new();
+        let mut dir_entries = tokio::fs::read_dir(&self.root)
+            .await
+            .map_err(|e| format!("Failed to read chunks directory: {}", e))?;
+        while let Some(entry) = dir_entries
+            .next_entry()
+            .await
+            .map_err(|e| format!("Failed to read directory entry: {}", e))?
+        {
+            if entry.path().is_dir() {
+                file_hashes.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+        file_hashes.sort_unstable();
+        Ok(file_hashes)
+    }
+
+    async fn usage(&self, file_hash: &str) -> Result<StoredFileUsage, String> {
+        let file_dir = self.file_dir(file_hash);
+        let mut usage = StoredFileUsage::default();
+        if !file_dir.is_dir() {
+            return Ok(usage);
+        }
+
+        let mut entries = tokio::fs::read_dir(&file_dir)
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", file_dir.display(), e))?;
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", file_dir.display(), e))?
+        {
+            let Ok(metadata) = entry.metadata().await else {
+                continue;
+            };
+            if !metadata.is_file() {
+                continue;
+            }
+            usage.bytes += metadata.len();
+            if entry.path().extension().is_some_and(|ext| ext == "dat") {
+                usage.chunk_count += 1;
+            }
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+                .map_or(0, |since_epoch| since_epoch.as_secs());
+            usage.last_modified = usage.last_modified.max(modified);
+        }
+        Ok(usage)
+    }
+
+    async fn prune_stale(
+        &self,
+        file_hash: &str,
+        max_age: Option<Duration>,
+    ) -> Result<usize, String> {
+        let file_dir = self.file_dir(file_hash);
+        if !file_dir.is_dir() {
+            return Ok(0);
+        }
+
+        let mut removed = 0;
+        let mut dir_entries = tokio::fs::read_dir(&file_dir)
+            .await
+            .map_err(|e| format!("Failed to read file chunks directory: {}", e))?;
+        while let Some(entry) = dir_entries
+            .next_entry()
+            .await
+            .map_err(|e| format!("Failed to read file dir entry: {}", e))?
+        {
+            // Each chunk is judged by its metadata file
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let Some(stem) = file_name.strip_suffix(".meta") else {
+                continue;
+            };
+            let metadata_path = entry.path();
+            let dat_path = file_dir.join(format!("{}.dat", stem));
+
+            let stale = if !dat_path.exists() {
+                // Orphaned metadata
+                true
+            } else {
+                let expired = match tokio::fs::metadata(&metadata_path).await {
+                    Ok(metadata) => max_age.is_some_and(|max_age| {
+                        metadata
+                            .modified()
+                            .ok()
+                            .and_then(|modified| modified.elapsed().ok())
+                            .is_some_and(|age| age > max_age)
+                    }),
+                    // Unreadable metadata counts as corrupted
+                    Err(_) => true,
+                };
+                expired
+                    || tokio::fs::read_to_string(&metadata_path)
+                        .await
+                        .ok()
+                        .and_then(|content| {
+                            serde_json::from_str::<serde_json::Value>(&content).ok()
+                        })
+                        .is_none()
+            };
+            if stale {
+                remove_if_present(&metadata_path).await?;
+                remove_if_present(&dat_path).await?;
+                removed += 1;
+            }
+        }
+
+        // Drop the directory once nothing is left in it
+        if let Ok(mut remaining) = tokio::fs::read_dir(&file_dir).await {
+            if matches!(remaining.next_entry().await, Ok(None)) {
+                let _ = tokio::fs::remove_dir(&file_dir).await;
+            }
+        }
+        Ok(removed)
+    }
+
+    async fn delete_file(&self, file_hash: &str) -> Result<(), String> {
+        self.keys.write().await.remove(file_hash);
+        match tokio::fs::remove_dir_all(self.file_dir(file_hash)).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(format!("Failed to remove chunk directory for {}: {}", file_hash, e)),
+        }
+    }
+
+    async fn set_encryption_key(&self, file_hash: &str, key: ChunkEncryptionKey) {
+        self.keys.write().await.insert(file_hash.to_string(), key);
+    }
+
+    async fn is_encrypted(&self, file_hash: &str) -> bool {
+        self.keys.read().await.contains_key(file_hash)
+    }
+
+    async fn encryption_key(&self, file_hash: &str) -> Option<ChunkEncryptionKey> {
+        self.keys.read().await.get(file_hash).cloned()
+    }
+
+    fn set_durable_writes(&self, durable: bool) {
+        self.durable_writes.store(durable, Ordering::Relaxed);
+    }
+}
+
+/// Keeps chunks in memory; nothing survives a restart. Meant for tests and embedders
+/// that assemble output elsewhere.
+#[derive(Debug, Default)]
+pub struct MemoryChunkStore {
+    chunks: RwLock<HashMap<String, BTreeMap<u32, Vec<u8>>>>,
+}
+
+impl MemoryChunkStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl ChunkStore for MemoryChunkStore {
+    async fn save_chunk(&self, file_hash: &str, chunk_id: u32, data: &[u8]) -> Result<(), String> {
+        self.chunks
+            .write()
+            .await
+            .entry(file_hash.to_string())
+            .or_default()
+            .insert(chunk_id, data.to_vec());
+        Ok(())
+    }
+
+    async fn load_chunk(&self, file_hash: &str, chunk_id: u32) -> Result<Vec<u8>, String> {
+        self.chunks
+            .read()
+            .await
+            .get(file_hash)
+            .and_then(|chunks| chunks.get(&chunk_id))
+            .cloned()
+            .ok_or_else(|| format!("Chunk {} not found in memory for file {}", chunk_id, file_hash))
+    }
+
+    async fn exists(&self, file_hash: &str, chunk_id: u32) -> bool {
+        self.chunks
+            .read()
+            .await
+            .get(file_hash)
+            .is_some_and(|chunks| chunks.contains_key(&chunk_id))
+    }
+
+    async fn delete(&self, file_hash: &str, chunk_id: u32) -> Result<(), String> {
+        let mut chunks = self.chunks.write().await;
+        if let Some(file_chunks) = chunks.get_mut(file_hash) {
+            file_chunks.remove(&chunk_id);
+            if file_chunks.is_empty() {
+                chunks.remove(file_hash);
+            }
+        }
+        Ok(())
+    }
+
+    async fn list_for_file(&self, file_hash: &str) -> Result<Vec<u32>, String> {
+        Ok(self
+            .chunks
+            .read()
+            .await
+            .get(file_hash)
+            .map(|chunks| chunks.keys().copied().collect())
+            .unwrap_or_default())
+    }
+
+    async fn list_files(&self) -> Result<Vec<String>, String> {
+        let mut file_hashes: Vec<String> = self.chunks.read().await.keys().cloned().collect();
+        file_hashes.sort_unstable();
+        Ok(file_hashes)
+    }
+
+    async fn usage(&self, file_hash: &str) -> Result<StoredFileUsage, String> {
+        Ok(self
+            .chunks
+            .read()
+            .await
+            .get(file_hash)
+            .map(|chunks| StoredFileUsage {
+                bytes: chunks.values().map(|data| data.len() as u64).sum(),
+                chunk_count: chunks.len(),
+                last_modified: 0,
+            })
+            .unwrap_or_default())
+    }
+
+    async fn delete_file(&self, file_hash: &str) -> Result<(), String> {
+        self.chunks.write().await.remove(file_hash);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    async fn exercise_store(store: &dyn ChunkStore) {
+        assert!(store.list_for_file("abc").await.unwrap().is_empty());
+        assert!(!store.exists("abc", 0).await);
+
+        store.save_chunk("abc", 2, b"two").await.unwrap();
+        store.save_chunk("abc", 0, b"zero").await.unwrap();
+        store.save_chunk("other", 0, b"elsewhere").await.unwrap();
+
+        assert!(store.exists("abc", 0).await);
+        assert_eq!(store.load_chunk("abc", 2).await.unwrap(), b"two");
+        assert_eq!(store.list_for_file("abc").await.unwrap(), vec![0, 2]);
+
+        store.delete("abc", 0).await.unwrap();
+        store.delete("abc", 7).await.unwrap();
+        assert!(!store.exists("abc", 0).await);
+        assert!(store.load_chunk("abc", 0).await.is_err());
+
+        assert_eq!(store.list_files().await.unwrap(), vec!["abc", "other"]);
+        let usage = store.usage("abc").await.unwrap();
+        assert_eq!(usage.chunk_count, 1);
+        assert!(usage.bytes >= 3);
+
+        store.delete_file("abc").await.unwrap();
+        assert!(store.list_for_file("abc").await.unwrap().is_empty());
+        assert_eq!(store.list_for_file("other").await.unwrap(), vec![0]);
+        assert_eq!(store.list_files().await.unwrap(), vec!["other"]);
+        assert_eq!(store.usage("abc").await.unwrap(), StoredFileUsage::default());
+    }
+
+    #[tokio::test]
+    async fn filesystem_store_round_trips_chunks() {
+        let dir = tempdir().unwrap();
+        exercise_store(&FilesystemChunkStore::new(dir.path())).await;
+    }
+
+    #[tokio::test]
+    async fn filesystem_store_round_trips_chunks_with_durable_writes() {
+        let dir = tempdir().unwrap();
+        let store = FilesystemChunkStore::new(dir.path());
+        store.set_durable_writes(true);
+        assert!(store.durable_writes());
+        exercise_store(&store).await;
+    }
+
+    #[tokio::test]
+    async fn memory_store_round_trips_chunks() {
+        exercise_store(&MemoryChunkStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn filesystem_store_prunes_damaged_and_old_chunks() {
+        let dir = tempdir().unwrap();
+        let store = FilesystemChunkStore::new(dir.path());
+        store.save_chunk("abc", 0, b"intact").await.unwrap();
+        store.save_chunk("abc", 1, b"orphaned").await.unwrap();
+        store.save_chunk("abc", 2, b"garbled").await.unwrap();
+        std::fs::remove_file(store.chunk_paths("abc", 1).0).unwrap();
+        std::fs::write(store.chunk_paths("abc", 2).1, b"not json").unwrap();
+
+        assert_eq!(store.prune_stale("abc", None).await.unwrap(), 2);
+        assert_eq!(store.list_for_file("abc").await.unwrap(), vec![0]);
+        let usage = store.usage("abc").await.unwrap();
+        assert_eq!(usage.chunk_count, 1);
+        assert!(usage.last_modified > 0);
+
+        // Everything is older than no time at all, and the emptied directory goes too
+        assert_eq!(store.prune_stale("abc", Some(Duration::ZERO)).await.unwrap(), 1);
+        assert!(store.list_files().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn filesystem_store_rejects_size_mismatch() {
+        let dir = tempdir().unwrap();
+        let store = FilesystemChunkStore::new(dir.path());
+        store.save_chunk("abc", 1, b"original").await.unwrap();
+
+        let (chunk_path, _) = store.chunk_paths("abc", 1);
+        std::fs::write(chunk_path, b"truncated!!").unwrap();
+
+        assert!(store.load_chunk("abc", 1).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn filesystem_store_encrypts_chunks_at_rest() {
+        let dir = tempdir().unwrap();
+        let store = FilesystemChunkStore::new(dir.path());
+        store
+            .set_encryption_key("abc", ChunkEncryptionKey::new([7u8; 32]))
+            .await;
+        assert!(store.is_encrypted("abc").await);
+
+        store.save_chunk("abc", 0, b"sensitive bytes").await.unwrap();
+        let (chunk_path, _) = store.chunk_paths("abc", 0);
+        let on_disk = std::fs::read(&chunk_path).unwrap();
+        assert!(!on_disk
+            .windows(b"sensitive".len())
+            .any(|window| window == b"sensitive"));
+        assert_eq!(store.load_chunk("abc", 0).await.unwrap(), b"sensitive bytes");
+
+        // Without the key (e.g. after a restart) the chunk can't be read back
+        let reopened = FilesystemChunkStore::new(dir.path());
+        assert!(reopened.load_chunk("abc", 0).await.is_err());
+
+        reopened
+            .set_encryption_key("abc", ChunkEncryptionKey::new([8u8; 32]))
+            .await;
+        assert!(reopened.load_chunk("abc", 0).await.is_err());
+    }
+
+    #[test]
+    fn wrapped_keys_open_only_with_their_wrapping_key_and_file() {
+        let wrapping_key = ChunkEncryptionKey::new([5u8; 32]);
+        let key = ChunkEncryptionKey::new([7u8; 32]);
+        let wrapped = key.wrap(&wrapping_key, "abc").unwrap();
+        assert!(!wrapped.contains(&hex::encode([7u8; 32])));
+        assert_eq!(ChunkEncryptionKey::unwrap_from(&wrapped, &wrapping_key, "abc").unwrap(), key);
+        assert!(ChunkEncryptionKey::unwrap_from(&wrapped, &wrapping_key, "def").is_err());
+        let other = ChunkEncryptionKey::new([9u8; 32]);
+        assert!(ChunkEncryptionKey::unwrap_from(&wrapped, &other, "abc").is_err());
+    }
+}
@@ -53,6 +53,20 @@ pub const DEFAULT_RECONNECT_COOLDOWN_SECS: u64 = 5;
 // Retry Configuration
 // ============================================================================
 
+/// How a retry delay is randomized around the exponential backoff
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JitterStrategy {
+    /// Up to `jitter_factor` of the backoff either way
+    #[default]
+    Proportional,
+    /// Anywhere between zero and the backoff ("full jitter")
+    Full,
+    /// Between the initial delay and three times the previous delay, capped at the maximum
+    /// ("decorrelated jitter"); the attempt number isn't used
+    Decorrelated,
+}
+
 /// Configuration for retry behavior with exponential backoff
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RetryConfig {
@@ -66,6 +80,9 @@ pub struct RetryConfig {
     pub backoff_multiplier: f64,
     /// Jitter factor to randomize delays (0.0 to 1.0)
     pub jitter_factor: f64,
+    /// How delays are randomized, so retries of failures that happened together spread out
+    #[serde(default)]
+    pub jitter: JitterStrategy,
     /// Whether to reset retry count on success
     pub reset_on_success: bool,
 }
@@ -78,6 +95,7 @@ impl Default for RetryConfig {
             max_delay_ms: DEFAULT_MAX_DELAY_MS,
             backoff_multiplier: DEFAULT_BACKOFF_MULTIPLIER,
             jitter_factor: DEFAULT_JITTER_FACTOR,
+            jitter: JitterStrategy::Proportional,
             reset_on_success: true,
         }
     }
@@ -92,6 +110,7 @@ impl RetryConfig {
             max_delay_ms: 15_000,
             backoff_multiplier: 2.0,
             jitter_factor: 0.2,
+            jitter: JitterStrategy::Decorrelated,
             reset_on_success: true,
         }
     }
@@ -104,6 +123,7 @@ impl RetryConfig {
             max_delay_ms: 60_000,
             backoff_multiplier: 2.0,
             jitter_factor: 0.15,
+            jitter: JitterStrategy::Proportional,
             reset_on_success: true,
         }
     }
@@ -116,6 +136,22 @@ impl RetryConfig {
             max_delay_ms: 10_000,
             backoff_multiplier: 1.5,
             jitter_factor: 0.1,
+            jitter: JitterStrategy::Proportional,
+            reset_on_success: true,
+        }
+    }
+
+    /// Create a config for re-downloading failed chunks. Retries back off quickly
+    /// because another source usually has the chunk, and are fully jittered because
+    /// chunks tend to fail in batches when a source drops.
+    pub fn for_chunks() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_delay_ms: 500,
+            max_delay_ms: 30_000,
+            backoff_multiplier: 2.0,
+            jitter_factor: 0.2,
+            jitter: JitterStrategy::Full,
             reset_on_success: true,
         }
     }
@@ -128,26 +164,44 @@ impl RetryConfig {
             max_delay_ms: 5_000,
             backoff_multiplier: 1.5,
             jitter_factor: 0.1,
+            jitter: JitterStrategy::Proportional,
             reset_on_success: true,
         }
     }
 
     /// Calculate delay for a given attempt number (0-indexed)
     pub fn calculate_delay(&self, attempt: u32) -> Duration {
+        self.next_delay(attempt, None)
+    }
+
+    /// Calculate delay for a given attempt number (0-indexed), knowing the delay waited
+    /// before the previous attempt. Only decorrelated jitter uses `previous`; the initial
+    /// delay stands in for it on the first retry.
+    pub fn next_delay(&self, attempt: u32, previous: Option<Duration>) -> Duration {
         let base_delay = self.initial_delay_ms as f64
             * self.backoff_multiplier.powi(attempt as i32);
         let capped_delay = base_delay.min(self.max_delay_ms as f64);
 
-        // Apply jitter
-        let jitter_range = capped_delay * self.jitter_factor;
-        let jitter = if jitter_range > 0.0 {
-            (rand::random::<f64>() * 2.0 - 1.0) * jitter_range
-        } else {
-            0.0
+        let final_delay = match self.jitter {
+            JitterStrategy::Proportional => {
+                let jitter_range = capped_delay * self.jitter_factor;
+                let jitter = if jitter_range > 0.0 {
+                    (rand::random::<f64>() * 2.0 - 1.0) * jitter_range
+                } else {
+                    0.0
+                };
+                capped_delay + jitter
+            }
+            JitterStrategy::Full => rand::random::<f64>() * capped_delay,
+            JitterStrategy::Decorrelated => {
+                let floor = self.initial_delay_ms as f64;
+                let previous = previous.map_or(floor, |delay| delay.as_millis() as f64);
+                let ceiling = (previous * 3.0).max(floor);
+                (floor + rand::random::<f64>() * (ceiling - floor)).min(self.max_delay_ms as f64)
+            }
         };
 
-        let final_delay = (capped_delay + jitter).max(0.0) as u64;
-        Duration::from_millis(final_delay)
+        Duration::from_millis(final_delay.max(0.0) as u64)
     }
 
     /// Check if we should retry given the current attempt count
@@ -204,6 +258,8 @@ pub struct ConnectionTracker {
     pub last_error: Option<String>,
     /// When the next retry is scheduled
     pub next_retry_at: Option<Instant>,
+    /// Delay waited before the scheduled retry, for decorrelated jitter
+    pub last_delay: Option<Duration>,
     /// Retry configuration for this connection
     pub config: RetryConfig,
     /// Creation timestamp
@@ -223,6 +279,7 @@ impl ConnectionTracker {
             last_failure: None,
             last_error: None,
             next_retry_at: None,
+            last_delay: None,
             config,
             created_at: Instant::now(),
         }
@@ -235,6 +292,7 @@ impl ConnectionTracker {
         self.total_successes += 1;
         if self.config.reset_on_success {
             self.consecutive_failures = 0;
+            self.last_delay = None;
         }
         self.next_retry_at = None;
         self.last_error = None;
@@ -249,7 +307,10 @@ impl ConnectionTracker {
         self.last_error = Some(error.into());
 
         if self.config.should_retry(self.consecutive_failures) {
-            let delay = self.config.calculate_delay(self.consecutive_failures - 1);
+            let delay = self
+                .config
+                .next_delay(self.consecutive_failures - 1, self.last_delay);
+            self.last_delay = Some(delay);
             self.next_retry_at = Some(Instant::now() + delay);
             self.state = ConnectionState::BackingOff;
             debug!(
@@ -291,6 +352,7 @@ impl ConnectionTracker {
         self.state = ConnectionState::Disconnected;
         self.consecutive_failures = 0;
         self.next_retry_at = None;
+        self.last_delay = None;
         self.last_error = None;
     }
 
@@ -474,6 +536,7 @@ where
     E: std::fmt::Display,
 {
     let mut attempt = 0u32;
+    let mut previous_delay = None;
 
     loop {
         match operation().await {
@@ -498,7 +561,8 @@ where
                     return Err(e);
                 }
 
-                let delay = config.calculate_delay(attempt - 1);
+                let delay = config.next_delay(attempt - 1, previous_delay);
+                previous_delay = Some(delay);
                 warn!(
                     "{} failed (attempt {}), retrying in {:?}: {}",
                     operation_name, attempt, delay, e
@@ -698,6 +762,7 @@ mod tests {
             max_delay_ms: 10000,
             backoff_multiplier: 2.0,
             jitter_factor: 0.0, // No jitter for predictable testing
+            jitter: JitterStrategy::Proportional,
             reset_on_success: true,
         };
 
@@ -722,6 +787,34 @@ mod tests {
         assert_eq!(delay4.as_millis(), 10000);
     }
 
+    #[test]
+    fn test_full_and_decorrelated_jitter_stay_in_range() {
+        let full = RetryConfig {
+            initial_delay_ms: 1000,
+            max_delay_ms: 10000,
+            backoff_multiplier: 2.0,
+            jitter: JitterStrategy::Full,
+            ..Default::default()
+        };
+        let delays: Vec<u128> = (0..64).map(|_| full.calculate_delay(2).as_millis()).collect();
+        assert!(delays.iter().all(|&delay| delay <= 4000));
+        // Retries of failures that happened together don't all land at once
+        assert!(delays.iter().any(|&delay| delay != delays[0]));
+
+        let decorrelated = RetryConfig {
+            jitter: JitterStrategy::Decorrelated,
+            ..full
+        };
+        let mut previous = None;
+        for attempt in 0..20 {
+            let delay = decorrelated.next_delay(attempt, previous);
+            let ceiling = previous.map_or(3000, |previous: Duration| previous.as_millis() * 3);
+            assert!(delay.as_millis() >= 1000);
+            assert!(delay.as_millis() <= ceiling.min(10000));
+            previous = Some(delay);
+        }
+    }
+
     #[test]
     fn test_should_retry() {
         let config = RetryConfig {
@@ -3762,22 +3762,15 @@ async fn run_dht_node(
                         if let Some(metadata) = cache.get(&file_hash).cloned() {
                             drop(cache); // Release lock before async operations
 
-                            // Get file data from file transfer service
+                            // Read just this chunk from the file transfer service's storage
                             if let Some(ft_service) = &file_transfer_service {
-                                match ft_service.get_file_data(&file_hash).await {
-                                    Some(file_data) => {
-                                        // Calculate chunk boundaries
-                                        let start = (chunk_index as usize) * chunk_size;
-                                        let end = (start + chunk_size).min(file_data.len());
-
-                                        if start < file_data.len() {
-                                            let chunk_data = file_data[start..end].to_vec();
-
-                                            // Calculate total chunks
-                                            let total_chunks = ((file_data.len() + chunk_size - 1)
-                                                / chunk_size)
-                                                as u32;
+                                match ft_service.open_file_reader(&file_hash, chunk_size).await {
+                                    Some(mut reader) => {
+                                        let total_chunks = reader.total_chunks();
 
+                                        if let Ok(chunk_data) =
+                                            reader.read_chunk(chunk_index).await
+                                        {
                                             // Calculate checksum
                                             let checksum = {
                                                 use sha2::{Digest, Sha256};
@@ -3797,6 +3790,7 @@ async fn run_dht_node(
                                                 encrypted_key_bundle: metadata
                                                     .encrypted_key_bundle
                                                     .clone(),
+                                                merkle_proof: None,
                                             };
 
                                             // Send chunk to peer
@@ -3815,7 +3809,7 @@ async fn run_dht_node(
                                             }
                                         } else {
                                             warn!(
-                                                "Chunk index {} out of bounds for file {}",
+                                                "Chunk {} of file {} is out of bounds or unreadable",
                                                 chunk_index, file_hash
                                             );
                                         }
@@ -8289,12 +8283,49 @@ pub struct MagnetData {
     pub info_hash: String,
     pub display_name: Option<String>,
     pub trackers: Vec<String>,
+    /// Exact file size in bytes (`xl`), when the magnet carries it
+    pub exact_length: Option<u64>,
+    /// BitTorrent v2 multihash (`btmh`) of hybrid torrents, hex encoded
+    pub info_hash_v2: Option<String>,
+}
+
+impl MagnetData {
+    /// Metadata standing in for a missing DHT record. Needs the exact length, since a
+    /// chunk plan can't be built without the file size.
+    pub fn to_file_metadata(&self) -> Option<FileMetadata> {
+        let file_size = self.exact_length?;
+        Some(FileMetadata {
+            merkle_root: self.info_hash.clone(),
+            file_name: self
+                .display_name
+                .clone()
+                .unwrap_or_else(|| self.info_hash.clone()),
+            file_size,
+            info_hash: Some(self.info_hash.clone()),
+            trackers: (!self.trackers.is_empty()).then(|| self.trackers.clone()),
+            ..Default::default()
+        })
+    }
+}
+
+/// `dn` becomes the output file name, so only a bare name is kept: anything with a path
+/// separator, a `..`/`.` component, a drive prefix or a NUL byte is dropped.
+fn bare_file_name(name: &str) -> Option<String> {
+    let name = name.trim();
+    let bare = !name.is_empty()
+        && name != "."
+        && name != ".."
+        && !name.contains(['/', '\\', '\0'])
+        && !std::path::Path::new(name).is_absolute()
+        && !(name.len() >= 2 && name.as_bytes()[1] == b':');
+    bare.then(|| name.to_string())
 }
 
 /// Parses a magnet URI string into a `MagnetData` struct.
 ///
-/// This function extracts the info hash (btih), display name (dn),
-/// and tracker URLs (tr) from a standard magnet link.
+/// This function extracts the info hash (btih), display name (dn), exact length (xl),
+/// tracker URLs (tr) and the BitTorrent v2 multihash (btmh) from a standard magnet link.
+/// Magnets with only a v2 hash are rejected, since downloads need the v1 info hash.
 ///
 /// # Examples
 ///
@@ -8318,22 +8349,45 @@ pub fn parse_magnet_uri(uri: &str) -> Result<MagnetData, String> {
             acc
         });
 
-    let info_hash = params
-        .get("xt")
-        .and_then(|xts| {
-            xts.iter()
-                .find_map(|xt| xt.strip_prefix("urn:btih:").map(|hash| hash.to_lowercase()))
-        })
-        .ok_or_else(|| "Magnet URI is missing 'xt' (info hash) parameter".to_string())?;
+    let info_hash = params.get("xt").and_then(|xts| {
+        xts.iter()
+            .find_map(|xt| xt.strip_prefix("urn:btih:").map(|hash| hash.to_lowercase()))
+    });
+    let info_hash_v2 = params.get("xt").and_then(|xts| {
+        xts.iter()
+            .find_map(|xt| xt.strip_prefix("urn:btmh:").map(|hash| hash.to_lowercase()))
+    });
+    let info_hash = match (info_hash, &info_hash_v2) {
+        (Some(info_hash), _) => info_hash,
+        (None, Some(_)) => {
+            return Err(
+                "Magnet URI only has a BitTorrent v2 hash (btmh); a v1 hash (btih) is required"
+                    .to_string(),
+            )
+        }
+        (None, None) => {
+            return Err("Magnet URI is missing 'xt' (info hash) parameter".to_string())
+        }
+    };
 
-    let display_name = params.get("dn").and_then(|dns| dns.first().cloned());
+    let display_name = params
+        .get("dn")
+        .and_then(|dns| dns.first())
+        .and_then(|dn| bare_file_name(dn));
 
     let trackers = params.get("tr").cloned().unwrap_or_default();
 
+    let exact_length = params
+        .get("xl")
+        .and_then(|xls| xls.first())
+        .and_then(|xl| xl.parse::<u64>().ok());
+
     Ok(MagnetData {
         info_hash,
         display_name,
         trackers,
+        exact_length,
+        info_hash_v2,
     })
 }
 
@@ -8694,6 +8748,8 @@ mod tests {
                     "udp://tracker.openbittorrent.com:80".to_string(),
                     "udp://tracker.leechers-paradise.org:6969".to_string(),
                 ],
+                exact_length: None,
+                info_hash_v2: None,
             }
         );
     }
@@ -8708,6 +8764,8 @@ mod tests {
                 info_hash: "b263275b1e3138b29596356533f685c33103575c".to_string(),
                 display_name: None,
                 trackers: vec![],
+                exact_length: None,
+                info_hash_v2: None,
             }
         );
     }
@@ -8725,6 +8783,66 @@ mod tests {
         assert!(parse_magnet_uri("magnet:?dn=MyFile").is_err());
     }
 
+    #[test]
+    fn test_parse_magnet_uri_keeps_only_bare_display_names() {
+        let magnet = |dn: &str| {
+            format!(
+                "magnet:?xt=urn:btih:b263275b1e3138b29596356533f685c33103575c&dn={}&xl=10",
+                dn
+            )
+        };
+        for unsafe_name in [
+            "..",
+            ".",
+            "..%2F..%2Fetc%2Fpasswd",
+            "%2Fetc%2Fpasswd",
+            "dir%2Ffile.txt",
+            "..%5Cwindows%5Cfile.txt",
+            "C%3Afile.txt",
+            "bad%00name",
+            "%20%20",
+        ] {
+            let result = parse_magnet_uri(&magnet(unsafe_name)).unwrap();
+            assert_eq!(result.display_name, None, "{} was kept", unsafe_name);
+            assert_eq!(
+                result.to_file_metadata().unwrap().file_name,
+                "b263275b1e3138b29596356533f685c33103575c"
+            );
+        }
+
+        let result = parse_magnet_uri(&magnet("..hidden.tar.gz")).unwrap();
+        assert_eq!(result.display_name.as_deref(), Some("..hidden.tar.gz"));
+    }
+
+    #[test]
+    fn test_parse_magnet_uri_length_and_v2_hints() {
+        let v2 = "1220caf1e1c30e81cb361b9ee167c4aa64228a7fa4fa9f6105232b28ad099f3a302e";
+        let magnet = format!(
+            "magnet:?xt=urn:btih:b263275b1e3138b29596356533f685c33103575c&xt=urn:btmh:{}&dn=Sintel.mp4&xl=129241752",
+            v2
+        );
+        let result = parse_magnet_uri(&magnet).unwrap();
+        assert_eq!(result.exact_length, Some(129241752));
+        assert_eq!(result.info_hash_v2.as_deref(), Some(v2));
+
+        let metadata = result.to_file_metadata().unwrap();
+        assert_eq!(metadata.file_name, "Sintel.mp4");
+        assert_eq!(metadata.file_size, 129241752);
+        assert_eq!(
+            metadata.info_hash.as_deref(),
+            Some("b263275b1e3138b29596356533f685c33103575c")
+        );
+
+        // No usable length means no chunk plan
+        let without_length =
+            parse_magnet_uri("magnet:?xt=urn:btih:b263275b1e3138b29596356533f685c33103575c&xl=big")
+                .unwrap();
+        assert!(without_length.to_file_metadata().is_none());
+
+        let v2_only = format!("magnet:?xt=urn:btmh:{}", v2);
+        assert!(parse_magnet_uri(&v2_only).unwrap_err().contains("btmh"));
+    }
+
     #[test]
     fn test_torrent_piece_hash_verification() {
         // Simulate a torrent file with 3 pieces.
@@ -4,6 +4,7 @@
 // This module implements the download restart system as specified in docs/download-restart.md
 // Owner: Team Hawks (Nick)
 
+use crate::cert_pinning;
 use chrono::{DateTime, Utc};
 use futures_util::StreamExt;
 use hex;
@@ -564,7 +565,9 @@ impl DownloadRestartService {
         }
 
         let destination_path = dest_path;
-        let client = Client::new();
+        let client = cert_pinning::client_builder()
+            .build()
+            .map_err(|e| DownloadError::Source(format!("Failed to create HTTP client: {}", e)))?;
 
         self.set_state(
             &download_id,
@@ -10,7 +10,7 @@ use crate::file_transfer::FileTransferService;
 use crate::ftp_client;
 use crate::http_download::HttpDownloadClient;
 use crate::protocols::ed2k::Ed2kProtocolHandler;
-use crate::protocols::traits::{DownloadOptions, ProtocolHandler};
+use crate::protocols::traits::{DownloadOptions, OnConflict, ProtocolHandler};
 use crate::transfer_events::{
     calculate_eta, calculate_progress, current_timestamp_ms, SourceInfo, SourceType,
     TransferCompletedEvent, TransferEventBus, TransferFailedEvent, TransferProgressEvent,
@@ -423,6 +423,7 @@ impl DownloadScheduler {
                         download_speed_bps: speed,
                         upload_speed_bps: 0.0,
                         eta_seconds: eta,
+                        chunks_completed_delta: 0,
                         active_sources: 1,
                         timestamp: current_timestamp_ms(),
                     };
@@ -553,6 +554,10 @@ impl DownloadScheduler {
                 chunk_size: None,
                 encryption: false,
                 bandwidth_limit: None,
+                on_conflict: OnConflict::default(),
+                http_headers: Default::default(),
+                torrent_files: None,
+                http_auth: None,
             };
 
             // Start the download
@@ -128,6 +128,10 @@ pub struct Ed2kSourceInfo {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub timeout_secs: Option<u64>,
 
+    /// Limit in seconds for fetching one 9.28MB chunk once connected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_timeout_secs: Option<u64>,
+
     /// ED2K chunk hashes (MD4 hashes for each 9.28MB chunk)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub chunk_hashes: Option<Vec<String>>,
@@ -139,6 +143,28 @@ pub struct Ed2kSourceInfo {
 pub struct BitTorrentSourceInfo {
     /// Magnet URI for the torrent
     pub magnet_uri: String,
+
+    /// Files to fetch from a multi-file torrent; the whole torrent when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_filter: Option<TorrentFileFilter>,
+}
+
+/// Selects which files of a multi-file torrent to download
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "camelCase")]
+pub enum TorrentFileFilter {
+    /// Zero-based indices into the torrent's file list
+    Indices(Vec<usize>),
+    /// Regular expression matched against each file's path within the torrent
+    PathPattern(String),
+}
+
+impl TorrentFileFilter {
+    /// An empty pattern, which callers treat as no filter at all. An empty list of
+    /// indices selects nothing and is rejected instead.
+    pub fn selects_everything(&self) -> bool {
+        matches!(self, TorrentFileFilter::PathPattern(pattern) if pattern.is_empty())
+    }
 }
 
 
@@ -163,6 +189,17 @@ impl DownloadSource {
         }
     }
 
+    /// The `ProtocolManager` name of the protocol the source is reached over
+    pub fn protocol_name(&self) -> &'static str {
+        match self {
+            DownloadSource::P2p(_) => "webrtc",
+            DownloadSource::Http(_) => "http",
+            DownloadSource::Ftp(_) => "ftp",
+            DownloadSource::Ed2k(_) => "ed2k",
+            DownloadSource::BitTorrent(_) => "bittorrent",
+        }
+    }
+
     /// Returns a display string for logging and UI
     pub fn display_name(&self) -> String {
         match self {
@@ -258,6 +295,23 @@ impl DownloadSource {
     }
 }
 
+impl HttpSourceInfo {
+    /// Headers to send with every request: the custom headers, then `auth_header` as
+    /// `Authorization`
+    pub fn request_headers(&self) -> Vec<(&str, &str)> {
+        let mut headers: Vec<(&str, &str)> = self
+            .headers
+            .iter()
+            .flatten()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        if let Some(auth_header) = &self.auth_header {
+            headers.push(("Authorization", auth_header.as_str()));
+        }
+        headers
+    }
+}
+
 impl std::fmt::Display for DownloadSource {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.display_name())
@@ -266,6 +320,56 @@ impl std::fmt::Display for DownloadSource {
 
 // Helper functions
 
+/// Headers whose values are credentials and must never be logged
+const SENSITIVE_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "x-api-key",
+    "x-auth-token",
+];
+
+/// Whether a header's value should be hidden from logs
+pub fn is_sensitive_header(name: &str) -> bool {
+    SENSITIVE_HEADERS
+        .iter()
+        .any(|sensitive| sensitive.eq_ignore_ascii_case(name))
+}
+
+/// Format headers for logging with credential values masked
+pub fn redact_headers<'a>(headers: impl IntoIterator<Item = (&'a str, &'a str)>) -> String {
+    headers
+        .into_iter()
+        .map(|(name, value)| {
+            if is_sensitive_header(name) {
+                format!("{}: <redacted>", name)
+            } else {
+                format!("{}: {}", name, value)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Build a request header map, rejecting names or values that aren't valid HTTP.
+/// Errors name the offending header but never include its value.
+pub fn header_map<'a>(
+    headers: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> Result<reqwest::header::HeaderMap, String> {
+    use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        let header_name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| format!("Invalid HTTP header name: {}", name))?;
+        let mut header_value = HeaderValue::from_str(value)
+            .map_err(|_| format!("Invalid value for HTTP header {}", name))?;
+        header_value.set_sensitive(is_sensitive_header(name));
+        map.insert(header_name, header_value);
+    }
+    Ok(map)
+}
+
 /// Extract domain/host from URL
 fn extract_domain(url: &str) -> Option<String> {
     // Simple domain extraction (not using full URL parser to avoid dependencies)
@@ -315,6 +419,36 @@ mod tests {
         assert_eq!(source.priority_score(), 50);
     }
 
+    #[test]
+    fn test_http_request_headers_are_redacted_in_logs() {
+        let info = HttpSourceInfo {
+            url: "https://cdn.example.com/file.zip".to_string(),
+            auth_header: Some("Bearer secret-token".to_string()),
+            verify_ssl: true,
+            headers: Some(vec![
+                ("User-Agent".to_string(), "chiral/1.0".to_string()),
+                ("Referer".to_string(), "https://example.com/".to_string()),
+            ]),
+            timeout_secs: None,
+        };
+
+        let headers = info.request_headers();
+        let map = header_map(headers.iter().copied()).unwrap();
+        assert_eq!(map["user-agent"], "chiral/1.0");
+        assert_eq!(map["authorization"], "Bearer secret-token");
+        assert!(map["authorization"].is_sensitive());
+
+        let logged = redact_headers(headers.iter().copied());
+        assert!(logged.contains("User-Agent: chiral/1.0"));
+        assert!(logged.contains("Authorization: <redacted>"));
+        assert!(!logged.contains("secret-token"));
+
+        let err = header_map([("Bad Header", "x")]).unwrap_err();
+        assert!(err.contains("Bad Header"));
+        let err = header_map([("X-Api-Key", "line\nbreak")]).unwrap_err();
+        assert!(!err.contains("break"));
+    }
+
     #[test]
     fn test_ftp_source_creation() {
         let source = DownloadSource::Ftp(FtpSourceInfo {
@@ -375,6 +509,7 @@ mod tests {
             file_name: Some("ubuntu.iso".to_string()),
             sources: Some(vec!["192.168.1.1:4662".to_string()]),
             timeout_secs: Some(30),
+            chunk_timeout_secs: None,
             chunk_hashes: None,
         });
 
@@ -393,6 +528,7 @@ mod tests {
             file_name: None,
             sources: None,
             timeout_secs: Some(30),
+            chunk_timeout_secs: None,
             chunk_hashes: None,
         });
 
@@ -409,6 +545,7 @@ mod tests {
             file_name: None,
             sources: None,
             timeout_secs: None,
+            chunk_timeout_secs: None,
             chunk_hashes: None,
         });
 
@@ -439,6 +576,7 @@ mod tests {
 
         let bittorrent = DownloadSource::BitTorrent(BitTorrentSourceInfo {
             magnet_uri: "magnet:?xt=urn:btih:08ada5a7a6183aae1e09d831df6748d566095a10".to_string(),
+            file_filter: None,
         });
 
         // Verify priority order: P2P (180) > BitTorrent (90) > HTTP (50) > ED2K (30) > FTP (25)
@@ -458,6 +596,7 @@ mod tests {
     fn test_bittorrent_source_creation() {
         let source = DownloadSource::BitTorrent(BitTorrentSourceInfo {
             magnet_uri: "magnet:?xt=urn:btih:08ada5a7a6183aae1e09d831df6748d566095a10&dn=Sintel".to_string(),
+            file_filter: None,
         });
 
         assert_eq!(source.source_type(), "BitTorrent");
@@ -476,6 +615,7 @@ mod tests {
             file_name: Some("test.iso".to_string()),
             sources: Some(vec!["192.168.1.1:4662".to_string()]),
             timeout_secs: Some(60),
+            chunk_timeout_secs: Some(600),
             chunk_hashes: None,
         };
 
@@ -488,9 +628,35 @@ mod tests {
         assert!(json.contains("\"fileHash\""));
         assert!(json.contains("\"fileSize\":1024000"));
         assert!(json.contains("\"fileName\":\"test.iso\""));
+        assert!(json.contains("\"chunkTimeoutSecs\":600"));
 
         // Deserialize and verify
         let deserialized: DownloadSource = serde_json::from_str(&json).unwrap();
         assert_eq!(deserialized.source_type(), "ED2K");
     }
+
+    #[test]
+    fn test_bittorrent_file_filter_serialization() {
+        let source = DownloadSource::BitTorrent(BitTorrentSourceInfo {
+            magnet_uri: "magnet:?xt=urn:btih:08ada5a7a6183aae1e09d831df6748d566095a10".to_string(),
+            file_filter: Some(TorrentFileFilter::Indices(vec![2])),
+        });
+
+        let json = serde_json::to_string(&source).unwrap();
+        assert!(json.contains("\"fileFilter\":{\"type\":\"indices\",\"value\":[2]}"));
+
+        match serde_json::from_str(&json).unwrap() {
+            DownloadSource::BitTorrent(info) => {
+                assert_eq!(info.file_filter, Some(TorrentFileFilter::Indices(vec![2])))
+            }
+            other => panic!("unexpected source {:?}", other),
+        }
+
+        // Sources without a filter download the whole torrent
+        let whole: BitTorrentSourceInfo =
+            serde_json::from_str(r#"{"magnetUri":"magnet:?xt=urn:btih:abc"}"#).unwrap();
+        assert!(whole.file_filter.is_none());
+        assert!(TorrentFileFilter::PathPattern(String::new()).selects_everything());
+        assert!(!TorrentFileFilter::Indices(Vec::new()).selects_everything());
+    }
 }
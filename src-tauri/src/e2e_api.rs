@@ -1240,6 +1240,10 @@ async fn api_download(
                         chunk_size: None,
                         encryption: false,
                         bandwidth_limit: None,
+                        on_conflict: crate::protocols::traits::OnConflict::default(),
+                        http_headers: Default::default(),
+                        torrent_files: None,
+                        http_auth: None,
                     };
                     handler
                         .download(&ftp_url, opts)
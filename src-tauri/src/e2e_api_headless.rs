@@ -26,6 +26,7 @@ use crate::download_source::HttpSourceInfo;
 use crate::http_download::HttpDownloadClient;
 use crate::http_server;
 use crate::manager::Sha256Hasher;
+use crate::multi_source_download::MultiSourceDownloadService;
 use crate::transaction_services;
 use crate::{dht, ethereum};
 use crate::{file_transfer::FileTransferService, manager::ChunkManager};
@@ -45,6 +46,8 @@ pub struct HeadlessE2eState {
     pub chunk_manager: Option<Arc<ChunkManager>>,
     /// Embedded FTP server (used for FTP upload E2E).
     pub ftp_server: Option<Arc<ftp_server::FtpServer>>,
+    /// Multi-source download service (used for the transfer status endpoint).
+    pub multi_source: Option<Arc<MultiSourceDownloadService>>,
     /// BitTorrent handler (used for BitTorrent upload/download E2E).
     pub bittorrent_handler: Option<Arc<bittorrent_handler::BitTorrentHandler>>,
 }
@@ -327,6 +330,7 @@ pub async fn start_headless_e2e_api_server(
 fn create_router(state: HeadlessE2eState) -> Router {
     Router::new()
         .route("/api/health", get(api_health))
+        .route("/api/status", get(api_status))
         .route("/api/dht/peers", get(api_dht_peers))
         .route("/api/upload", post(api_upload_generate))
         .route("/api/search", post(api_search))
@@ -356,6 +360,20 @@ async fn api_health(State(state): State<Arc<HeadlessE2eState>>) -> impl IntoResp
     )
 }
 
+/// Transfer subsystem status: downloads, seeds, throughput, connections and storage
+async fn api_status(State(state): State<Arc<HeadlessE2eState>>) -> impl IntoResponse {
+    match &state.multi_source {
+        Some(service) => (StatusCode::OK, Json(service.system_status().await)).into_response(),
+        None => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(http_server::ErrorResponse {
+                error: "Transfer services are not running in this node.".to_string(),
+            }),
+        )
+            .into_response(),
+    }
+}
+
 async fn api_dht_peers(State(state): State<Arc<HeadlessE2eState>>) -> impl IntoResponse {
     let peers = state.dht.get_connected_peers().await;
     (StatusCode::OK, Json(PeersResponse { peers }))
@@ -1179,6 +1197,10 @@ async fn api_download(
             chunk_size: None,
             encryption: false,
             bandwidth_limit: None,
+            on_conflict: crate::protocols::traits::OnConflict::default(),
+            http_headers: Default::default(),
+            torrent_files: None,
+            http_auth: None,
         };
         if let Err(e) = handler.download(&ftp_url, opts).await {
             return (
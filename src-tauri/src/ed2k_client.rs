@@ -14,6 +14,7 @@ use std::time::Duration;
 use tokio::fs::File;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
+use tokio_socks::tcp::Socks5Stream;
 use tracing::{debug, info};
 
 /// ed2k chunk size: 9.28 MB (9,728,000 bytes)
@@ -31,8 +32,15 @@ pub struct Ed2kConfig {
     /// Connection timeout
     pub timeout: Duration,
 
+    /// Limit for a server reply once connected, including a whole 9.28MB chunk
+    pub read_timeout: Duration,
+
     /// Client ID (generated or assigned by server)
     pub client_id: Option<String>,
+
+    /// SOCKS5 proxy ("host:port") every connection goes through; a proxy that can't be
+    /// reached fails the connection instead of falling back to a direct one
+    pub proxy: Option<String>,
 }
 
 impl Default for Ed2kConfig {
@@ -40,11 +48,28 @@ impl Default for Ed2kConfig {
         Self {
             server_url: String::new(),
             timeout: Duration::from_secs(30),
+            read_timeout: Duration::from_secs(300),
             client_id: None,
+            proxy: None,
         }
     }
 }
 
+/// Open a TCP connection to `addr`, through the SOCKS5 proxy when one is given
+async fn open_stream(addr: &str, proxy: Option<&str>) -> Result<TcpStream, Ed2kError> {
+    match proxy {
+        Some(proxy) => Socks5Stream::connect(proxy, addr)
+            .await
+            .map(Socks5Stream::into_inner)
+            .map_err(|e| {
+                Ed2kError::ConnectionError(format!("via SOCKS5 proxy {}: {}", proxy, e))
+            }),
+        None => TcpStream::connect(addr)
+            .await
+            .map_err(|e| Ed2kError::ConnectionError(e.to_string())),
+    }
+}
+
 /// Information about an ed2k file
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Ed2kFileInfo {
@@ -259,6 +284,16 @@ pub enum Ed2kError {
     HexError(#[from] hex::FromHexError),
 }
 
+impl Ed2kError {
+    /// Whether the server connection can't be used after this error
+    pub fn is_connection_lost(&self) -> bool {
+        matches!(
+            self,
+            Ed2kError::ConnectionError(_) | Ed2kError::Timeout | Ed2kError::IoError(_)
+        )
+    }
+}
+
 impl Ed2kClient {
     /// Create a new ed2k client
     pub fn new(server_url: String) -> Self {
@@ -316,11 +351,10 @@ impl Ed2kClient {
         let addr = format!("{}:{}", ip, port);
         let stream = tokio::time::timeout(
             self.config.timeout,
-            TcpStream::connect(&addr)
+            open_stream(&addr, self.config.proxy.as_deref())
         )
         .await
-        .map_err(|_| Ed2kError::Timeout)?
-        .map_err(|e| Ed2kError::ConnectionError(e.to_string()))?;
+        .map_err(|_| Ed2kError::Timeout)??;
 
         self.connection = Some(stream);
 
@@ -471,7 +505,7 @@ impl Ed2kClient {
 
         // Read with timeout
         let read_result = tokio::time::timeout(
-            self.config.timeout,
+            self.config.read_timeout,
             async {
                 let mut buffer = vec![0u8; 8192]; // 8KB buffer
                 let mut total_read = 0;
@@ -777,7 +811,7 @@ impl Ed2kClient {
         Self::send_packet(conn, opcodes::OP_GETSOURCES, &hash_bytes).await?;
 
         // Receive response packet
-        let timeout_secs = self.config.timeout.as_secs();
+        let timeout_secs = self.config.read_timeout.as_secs();
         let (opcode, payload) = Self::receive_packet(conn, timeout_secs).await?;
 
         if opcode != opcodes::OP_FOUNDSOURCES {
@@ -853,17 +887,34 @@ impl Ed2kClient {
         self.connection.is_some()
     }
 
+    /// SOCKS5 proxy the client connects through, if any
+    pub fn proxy(&self) -> Option<&str> {
+        self.config.proxy.as_deref()
+    }
+
+    /// Route later connections through `proxy`. An open connection is dropped so the
+    /// next one can't reuse a socket opened without it.
+    pub fn set_proxy(&mut self, proxy: Option<String>) {
+        if self.config.proxy != proxy {
+            self.connection = None;
+        }
+        self.config.proxy = proxy;
+    }
+
     // Peer-to-peer communication methods
 
-    /// Connect to an ED2K peer for file transfer
-    pub async fn connect_to_peer(addr: &str, timeout_secs: u64) -> Result<TcpStream, Ed2kError> {
+    /// Connect to an ED2K peer for file transfer, through `proxy` when one is given
+    pub async fn connect_to_peer(
+        addr: &str,
+        timeout_secs: u64,
+        proxy: Option<&str>,
+    ) -> Result<TcpStream, Ed2kError> {
         let stream = tokio::time::timeout(
             Duration::from_secs(timeout_secs),
-            TcpStream::connect(addr)
+            open_stream(addr, proxy)
         )
         .await
-        .map_err(|_| Ed2kError::Timeout)?
-        .map_err(|e| Ed2kError::ConnectionError(e.to_string()))?;
+        .map_err(|_| Ed2kError::Timeout)??;
 
         info!("Connected to ED2K peer: {}", addr);
         Ok(stream)
@@ -1420,6 +1471,14 @@ mod tests {
         assert!(Ed2kClient::verify_md4_hash(data, expected_hash));
     }
 
+    #[test]
+    fn test_connection_lost_errors() {
+        assert!(Ed2kError::Timeout.is_connection_lost());
+        assert!(Ed2kError::ConnectionError("reset".to_string()).is_connection_lost());
+        assert!(!Ed2kError::HashMismatch.is_connection_lost());
+        assert!(!Ed2kError::ProtocolError("unexpected opcode".to_string()).is_connection_lost());
+    }
+
     #[test]
     fn test_compute_md4_hash() {
         let data = b"hello world";
@@ -37,6 +37,51 @@ pub struct FileResponse {
     pub file_size: u64,
 }
 
+/// Reads a stored file one chunk at a time
+///
+/// Seeders serve from this instead of `get_file_data`, so only the chunk being sent is held
+/// in memory and files larger than RAM can be seeded.
+pub struct StoredFileReader {
+    file: tokio::fs::File,
+    file_size: u64,
+    chunk_size: usize,
+}
+
+impl StoredFileReader {
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    pub fn total_chunks(&self) -> u32 {
+        self.file_size.div_ceil(self.chunk_size as u64) as u32
+    }
+
+    /// Read chunk `chunk_index`; the last chunk may be short
+    pub async fn read_chunk(&mut self, chunk_index: u32) -> Result<Vec<u8>, String> {
+        use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+        let start = chunk_index as u64 * self.chunk_size as u64;
+        if start >= self.file_size {
+            return Err(format!(
+                "Chunk {} is past the end of the file ({} bytes)",
+                chunk_index, self.file_size
+            ));
+        }
+        let len = (self.file_size - start).min(self.chunk_size as u64) as usize;
+
+        self.file
+            .seek(std::io::SeekFrom::Start(start))
+            .await
+            .map_err(|e| format!("Failed to seek to chunk {}: {}", chunk_index, e))?;
+        let mut data = vec![0u8; len];
+        self.file
+            .read_exact(&mut data)
+            .await
+            .map_err(|e| format!("Failed to read chunk {}: {}", chunk_index, e))?;
+        Ok(data)
+    }
+}
+
 // Simplified file transfer service without complex libp2p request-response
 // This provides basic file storage and retrieval functionality
 
@@ -972,6 +1017,21 @@ impl FileTransferService {
         }
     }
 
+    /// Open a stored file for reading in `chunk_size` pieces, without loading it whole
+    pub async fn open_file_reader(
+        &self,
+        file_hash: &str,
+        chunk_size: usize,
+    ) -> Option<StoredFileReader> {
+        let file = tokio::fs::File::open(self.storage_dir.join(file_hash)).await.ok()?;
+        let file_size = file.metadata().await.ok()?.len();
+        Some(StoredFileReader {
+            file,
+            file_size,
+            chunk_size: chunk_size.max(1),
+        })
+    }
+
     pub async fn download_metrics_snapshot(&self) -> DownloadMetricsSnapshot {
         let metrics = self.download_metrics.lock().await;
         metrics.snapshot()
@@ -1119,4 +1179,32 @@ mod tests {
             MAX_DOWNLOAD_ATTEMPTS.saturating_sub(1) as u64
         );
     }
+
+    #[tokio::test]
+    async fn stored_file_reader_serves_chunks_from_disk() {
+        let temp_dir = tempdir().expect("temp dir");
+        let keystore = Arc::new(Mutex::new(crate::keystore::Keystore::new()));
+        let service = FileTransferService::new_with_storage_dir(
+            temp_dir.path().to_path_buf(),
+            false,
+            keystore,
+            None,
+        )
+        .await
+        .unwrap();
+        let data: Vec<u8> = (0..10u8).collect();
+        service
+            .store_file_data("hash".to_string(), "file.bin".to_string(), data.clone())
+            .await;
+
+        let mut reader = service.open_file_reader("hash", 4).await.unwrap();
+        assert_eq!(reader.file_size(), 10);
+        assert_eq!(reader.total_chunks(), 3);
+        // Out of order, as chunk requests arrive
+        assert_eq!(reader.read_chunk(2).await.unwrap(), vec![8, 9]);
+        assert_eq!(reader.read_chunk(0).await.unwrap(), vec![0, 1, 2, 3]);
+        assert_eq!(reader.read_chunk(1).await.unwrap(), vec![4, 5, 6, 7]);
+        assert!(reader.read_chunk(3).await.is_err());
+        assert!(service.open_file_reader("missing", 4).await.is_none());
+    }
 }
@@ -5,6 +5,7 @@
 // It supports both regular FTP and FTP over TLS (FTPS), passive/active modes,
 // and encrypted password handling.
 
+use crate::cert_pinning::PinnedTlsConnector;
 use crate::download_source::FtpSourceInfo;
 use anyhow::{anyhow, Context, Result};
 use std::net::ToSocketAddrs;
@@ -13,7 +14,7 @@ use std::sync::atomic::{AtomicU8, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 use suppaftp::types::FileType;
-use suppaftp::{FtpStream, NativeTlsConnector, NativeTlsFtpStream};
+use suppaftp::{FtpStream, NativeTlsFtpStream};
 use tokio::task::spawn_blocking;
 use tracing::{debug, info, warn};
 use serde::{Serialize, Deserialize};
@@ -179,10 +180,8 @@ impl FtpClient {
             "Connecting to FTPS server"
         );
 
-        // Create TLS connector
-        let tls_connector = NativeTlsConnector::from(
-            native_tls::TlsConnector::new().context("Failed to create TLS connector")?,
-        );
+        // Create TLS connector; it also enforces any certificate pins for the host
+        let tls_connector = PinnedTlsConnector::new().context("Failed to create TLS connector")?;
 
         // Note: connect_secure_implicit doesn't support timeout directly,
         // so we use the deprecated method but set timeouts after connection
@@ -485,9 +484,8 @@ pub async fn list_ftp_directory(source_info: &FtpSourceInfo) -> Result<Vec<FtpFi
         // Connect to FTP server
         let ftp_stream = if source_clone.use_ftps {
             // FTPS connection
-            let tls_connector = NativeTlsConnector::from(
-                native_tls::TlsConnector::new().context("Failed to create TLS connector")?
-            );
+            let tls_connector =
+                PinnedTlsConnector::new().context("Failed to create TLS connector")?;
 
             let mut stream = NativeTlsFtpStream::connect_secure_implicit(
                 format!("{}:{}", host, port),
@@ -611,9 +609,8 @@ pub async fn delete_ftp_file(source_info: &FtpSourceInfo) -> Result<()> {
 
         if source_clone.use_ftps {
             // FTPS connection
-            let tls_connector = NativeTlsConnector::from(
-                native_tls::TlsConnector::new().context("Failed to create TLS connector")?
-            );
+            let tls_connector =
+                PinnedTlsConnector::new().context("Failed to create TLS connector")?;
 
             let mut stream = NativeTlsFtpStream::connect_secure_implicit(
                 format!("{}:{}", host, port),
@@ -694,9 +691,8 @@ pub async fn rename_ftp_file(source_info: &FtpSourceInfo, new_name: &str) -> Res
 
         if source_clone.use_ftps {
             // FTPS connection
-            let tls_connector = NativeTlsConnector::from(
-                native_tls::TlsConnector::new().context("Failed to create TLS connector")?
-            );
+            let tls_connector =
+                PinnedTlsConnector::new().context("Failed to create TLS connector")?;
 
             let mut stream = NativeTlsFtpStream::connect_secure_implicit(
                 format!("{}:{}", host, port),
@@ -759,9 +755,8 @@ pub async fn create_ftp_directory(source_info: &FtpSourceInfo) -> Result<()> {
 
         if source_clone.use_ftps {
             // FTPS connection
-            let tls_connector = NativeTlsConnector::from(
-                native_tls::TlsConnector::new().context("Failed to create TLS connector")?
-            );
+            let tls_connector =
+                PinnedTlsConnector::new().context("Failed to create TLS connector")?;
 
             let mut stream = NativeTlsFtpStream::connect_secure_implicit(
                 format!("{}:{}", host, port),
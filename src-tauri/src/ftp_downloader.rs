@@ -1,6 +1,6 @@
-// Note: Read trait is used in closure within try_download_range_blocking
-#[allow(unused_imports)]
 use std::io::Read;
+use serde::Serialize;
+use std::collections::HashMap;
 use std::time::Duration;
 use suppaftp::{FtpError, FtpStream};
 use suppaftp::types::FileType;
@@ -13,6 +13,8 @@ use url::Url;
 pub struct FtpDownloadConfig {
     /// Connection timeout in seconds
     pub timeout_secs: u64,
+    /// Read/write timeout in seconds once connected, also bounding each range download
+    pub read_timeout_secs: u64,
     /// Maximum number of retry attempts for failed operations
     pub max_retries: u32,
     /// Use passive mode (PASV) instead of active mode
@@ -25,6 +27,7 @@ impl Default for FtpDownloadConfig {
     fn default() -> Self {
         Self {
             timeout_secs: 30,
+            read_timeout_secs: 30,
             max_retries: 3,
             passive_mode: true,  // Passive mode works better behind NAT
             connection_pool_size: 5,
@@ -32,6 +35,34 @@ impl Default for FtpDownloadConfig {
     }
 }
 
+/// What an FTP server advertises in its FEAT reply
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FtpServerCapabilities {
+    /// `REST STREAM`: a transfer can start at an offset, so ranges can be fetched in parallel
+    pub supports_rest: bool,
+    /// `SIZE`: the file size can be queried before downloading
+    pub supports_size: bool,
+}
+
+impl FtpServerCapabilities {
+    /// Read capabilities from parsed FEAT lines, keyed by feature name (e.g. "REST" -> "STREAM")
+    pub fn from_features(features: &HashMap<String, Option<String>>) -> Self {
+        let mut capabilities = Self::default();
+        for (name, params) in features {
+            let name = name.trim().to_ascii_uppercase();
+            if name == "SIZE" {
+                capabilities.supports_size = true;
+            } else if name == "REST" {
+                capabilities.supports_rest = params
+                    .as_deref()
+                    .is_some_and(|params| params.to_ascii_uppercase().contains("STREAM"));
+            }
+        }
+        capabilities
+    }
+}
+
 /// FTP credentials structure
 #[derive(Debug, Clone)]
 pub struct FtpCredentials {
@@ -60,6 +91,151 @@ impl FtpCredentials {
     }
 }
 
+/// One range transfer and the state it left the control connection in
+struct RangeTransfer {
+    result: Result<Vec<u8>, String>,
+    /// The transfer was closed cleanly and the connection can take the next command
+    connection_usable: bool,
+}
+
+/// Skip `skip` bytes of a data connection, then read up to `size` bytes, stopping early at EOF
+fn read_range(reader: &mut impl Read, skip: u64, size: usize) -> std::io::Result<Vec<u8>> {
+    if skip > 0 {
+        let skipped = std::io::copy(&mut reader.by_ref().take(skip), &mut std::io::sink())?;
+        debug!("Skipped {} bytes to reach offset", skipped);
+    }
+    let mut data = Vec::with_capacity(size);
+    reader.take(size as u64).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Ranges of one file cut from a single RETR that is read front to back
+///
+/// Without REST every transfer starts at byte 0, so fetching each range with its own RETR
+/// reads the file over again for every range. Asked for in offset order, the ranges here come
+/// out of one transfer instead, each read once. The transfer runs on a blocking thread, since
+/// `suppaftp` reads the data socket synchronously.
+pub struct SequentialRanges {
+    /// Dropped once the transfer failed, which also tells the thread to stop
+    requests: Option<tokio::sync::mpsc::UnboundedSender<(u64, u64)>>,
+    replies: tokio::sync::mpsc::Receiver<Result<Vec<u8>, String>>,
+    /// Hands the connection back when the transfer was closed cleanly
+    task: Option<task::JoinHandle<Option<FtpStream>>>,
+    timeout: Duration,
+}
+
+impl SequentialRanges {
+    /// Read `size` bytes at `start`, which must not come before the end of the last range.
+    /// Returns fewer bytes if the file ends first.
+    pub async fn read(&mut self, start: u64, size: u64) -> Result<Vec<u8>, String> {
+        let sent = self
+            .requests
+            .as_ref()
+            .is_some_and(|requests| requests.send((start, size)).is_ok());
+        if !sent {
+            return Err("The FTP transfer already ended".to_string());
+        }
+
+        match tokio::time::timeout(self.timeout, self.replies.recv()).await {
+            Ok(Some(Ok(data))) => Ok(data),
+            Ok(Some(Err(e))) => {
+                self.requests = None;
+                Err(e)
+            }
+            Ok(None) => {
+                self.requests = None;
+                Err("The FTP transfer ended early".to_string())
+            }
+            Err(_) => {
+                // The thread may be stuck on the data socket; leave it and the connection
+                self.requests = None;
+                self.task = None;
+                Err(format!(
+                    "FTP range read timed out after {}s (likely passive port blocked)",
+                    self.timeout.as_secs()
+                ))
+            }
+        }
+    }
+
+    /// End the transfer and return the connection, if it can take the next command
+    pub async fn finish(mut self) -> Option<FtpStream> {
+        self.requests = None;
+        let task = self.task.take()?;
+        tokio::time::timeout(self.timeout, task).await.ok()?.ok()?
+    }
+
+    /// Serve range requests from one RETR until the requests stop or a read fails
+    fn serve(
+        mut stream: FtpStream,
+        remote_path: &str,
+        mut requests: tokio::sync::mpsc::UnboundedReceiver<(u64, u64)>,
+        replies: tokio::sync::mpsc::Sender<Result<Vec<u8>, String>>,
+    ) -> Option<FtpStream> {
+        let mut data_stream = None;
+        let mut position = 0u64;
+        let mut at_end = false;
+
+        while let Some((start, size)) = requests.blocking_recv() {
+            if data_stream.is_none() {
+                match stream.retr_as_stream(remote_path) {
+                    Ok(reader) => data_stream = Some(reader),
+                    Err(e) => {
+                        let _ = replies.blocking_send(Err(format!("RETR command failed: {}", e)));
+                        // A refused RETR leaves the connection in step; a broken socket doesn't
+                        return matches!(e, FtpError::UnexpectedResponse(_)).then_some(stream);
+                    }
+                }
+            }
+            let Some(reader) = data_stream.as_mut() else {
+                break;
+            };
+            if start < position {
+                let _ = replies.blocking_send(Err(format!(
+                    "Range at {} is behind the transfer, which is at {}",
+                    start, position
+                )));
+                break;
+            }
+
+            let data = match read_range(reader, start - position, size as usize) {
+                Ok(data) => data,
+                Err(e) => {
+                    let error = format!("FTP data connection failed: {}", e);
+                    let _ = replies.blocking_send(Err(error));
+                    return None;
+                }
+            };
+            position = start + data.len() as u64;
+            at_end = data.len() < size as usize;
+            if replies.blocking_send(Ok(data)).is_err() || at_end {
+                break;
+            }
+        }
+
+        // Closed as in `try_download_range_blocking`
+        let Some(mut data_stream) = data_stream else {
+            return Some(stream);
+        };
+        let at_end = at_end || matches!(data_stream.read(&mut [0u8; 1]), Ok(0));
+        let closed = if at_end {
+            stream.finalize_retr_stream(data_stream)
+        } else {
+            stream.abort(data_stream)
+        };
+        match closed {
+            Ok(()) => Some(stream),
+            Err(e) => {
+                warn!(
+                    "Transfer of {} didn't complete cleanly, not reusing the connection: {}",
+                    remote_path, e
+                );
+                None
+            }
+        }
+    }
+}
+
 /// FTP downloader service for byte-range downloads
 pub struct FtpDownloader {
     config: FtpDownloadConfig,
@@ -123,8 +299,9 @@ impl FtpDownloader {
         let host_clone = host.clone();
 
         let stream = task::spawn_blocking(move || -> Result<FtpStream, String> {
-            // Create timeout duration
+            // Create timeout durations
             let timeout = Duration::from_secs(config.timeout_secs);
+            let read_timeout = Duration::from_secs(config.read_timeout_secs);
 
             // Resolve address - use ToSocketAddrs to handle both hostnames and IP addresses
             use std::net::ToSocketAddrs;
@@ -142,11 +319,11 @@ impl FtpDownloader {
             // Set read/write timeouts on the underlying stream
             ftp_stream
                 .get_ref()
-                .set_read_timeout(Some(timeout))
+                .set_read_timeout(Some(read_timeout))
                 .map_err(|e| format!("Failed to set read timeout: {}", e))?;
             ftp_stream
                 .get_ref()
-                .set_write_timeout(Some(timeout))
+                .set_write_timeout(Some(read_timeout))
                 .map_err(|e| format!("Failed to set write timeout: {}", e))?;
 
             debug!("FTP connection established with timeout configured");
@@ -182,8 +359,10 @@ impl FtpDownloader {
 
     /// Download a specific byte range from FTP server
     ///
-    /// Uses REST (restart) command to set the starting offset, then RETR to download.
-    /// Reads exactly `size` bytes from the data connection.
+    /// Reads and discards the bytes before `start_byte`, which works on every server; see
+    /// `download_range_with_timeout` for REST-based ranges. Reads exactly `size` bytes.
+    /// Retries stop once the connection is out of step with the server, and a connection that
+    /// couldn't close the transfer shouldn't be reused even when the data was returned.
     ///
     /// # Arguments
     /// * `stream` - Active FTP connection
@@ -220,37 +399,33 @@ impl FtpDownloader {
         let mut last_error = String::new();
 
         for attempt in 1..=max_retries {
-            match Self::try_download_range_blocking(stream, remote_path, start_byte, size) {
+            let transfer =
+                Self::try_download_range_blocking(stream, remote_path, start_byte, size, false);
+            match transfer.result {
+                Ok(data) if data.len() == size as usize => {
+                    info!(
+                        "Successfully downloaded {} bytes from FTP (attempt {})",
+                        data.len(),
+                        attempt
+                    );
+                    return Ok(data);
+                }
                 Ok(data) => {
-                    if data.len() != size as usize {
-                        last_error = format!(
-                            "Size mismatch: expected {} bytes, got {}",
-                            size,
-                            data.len()
-                        );
-                        warn!("Attempt {}/{}: {}", attempt, max_retries, last_error);
-
-                        if attempt < max_retries {
-                            tokio::time::sleep(Duration::from_millis(1000 * attempt as u64)).await;
-                            continue;
-                        }
-                    } else {
-                        info!(
-                            "Successfully downloaded {} bytes from FTP (attempt {})",
-                            data.len(),
-                            attempt
-                        );
-                        return Ok(data);
-                    }
+                    last_error = format!(
+                        "Size mismatch: expected {} bytes, got {}",
+                        size,
+                        data.len()
+                    );
                 }
-                Err(e) => {
-                    last_error = e.clone();
-                    warn!("Attempt {}/{} failed: {}", attempt, max_retries, e);
+                Err(e) => last_error = e,
+            }
+            warn!("Attempt {}/{} failed: {}", attempt, max_retries, last_error);
 
-                    if attempt < max_retries {
-                        tokio::time::sleep(Duration::from_millis(1000 * attempt as u64)).await;
-                    }
-                }
+            if !transfer.connection_usable {
+                break;
+            }
+            if attempt < max_retries {
+                tokio::time::sleep(Duration::from_millis(1000 * attempt as u64)).await;
             }
         }
 
@@ -268,23 +443,28 @@ impl FtpDownloader {
     ///   (common when passive ports are blocked by firewall/NAT).
     /// - In async code, this leads to "forever running" downloads and 10-minute E2E timeouts.
     ///
-    /// Returns:
-    /// - Ok((stream, data)) on success
-    /// - Err((Some(stream), err)) on a non-timeout error where the stream is still returned
-    /// - Err((None, err)) on timeout (the stream is considered poisoned / lost)
+    /// With `use_rest` the transfer starts at `start_byte` via REST (see `probe_capabilities`);
+    /// otherwise the bytes before it are read and discarded.
+    ///
+    /// The stream is handed back only when the transfer was closed cleanly on the control
+    /// connection, so it can go back to a pool:
+    /// - Ok((stream, data)) on success; the stream is `None` if the transfer couldn't be closed
+    /// - Err((Some(stream), err)) on a non-timeout error where the stream is still usable
+    /// - Err((None, err)) on timeout or a broken connection (the stream is poisoned / lost)
     pub async fn download_range_with_timeout(
         &self,
         stream: FtpStream,
         remote_path: String,
         start_byte: u64,
         size: u64,
-    ) -> Result<(FtpStream, Vec<u8>), (Option<FtpStream>, String)> {
+        use_rest: bool,
+    ) -> Result<(Option<FtpStream>, Vec<u8>), (Option<FtpStream>, String)> {
         if size == 0 {
-            return Ok((stream, Vec::new()));
+            return Ok((Some(stream), Vec::new()));
         }
 
         let max_retries = self.config.max_retries.max(1);
-        let timeout = Duration::from_secs(self.config.timeout_secs.max(1));
+        let timeout = Duration::from_secs(self.config.read_timeout_secs.max(1));
         let mut last_error = String::new();
         let mut stream_opt: Option<FtpStream> = Some(stream);
 
@@ -300,39 +480,45 @@ impl FtpDownloader {
                 }
             };
             let fut = task::spawn_blocking(move || {
-                let res =
-                    Self::try_download_range_blocking(&mut stream_for_task, &path_clone, start_byte, size);
-                (stream_for_task, res)
+                let transfer = Self::try_download_range_blocking(
+                    &mut stream_for_task,
+                    &path_clone,
+                    start_byte,
+                    size,
+                    use_rest,
+                );
+                (stream_for_task, transfer)
             });
 
             match tokio::time::timeout(timeout, fut).await {
                 Ok(joined) => match joined {
-                    Ok((returned_stream, Ok(data))) => {
-                        if data.len() != size as usize {
-                            last_error = format!(
-                                "Size mismatch: expected {} bytes, got {}",
-                                size,
-                                data.len()
-                            );
-                            warn!("Attempt {}/{}: {}", attempt, max_retries, last_error);
-                            stream_opt = Some(returned_stream);
-                            if attempt < max_retries {
-                                tokio::time::sleep(Duration::from_millis(1000 * attempt as u64)).await;
-                                continue;
+                    Ok((returned_stream, transfer)) => {
+                        let returned_stream =
+                            transfer.connection_usable.then_some(returned_stream);
+                        match transfer.result {
+                            Ok(data) if data.len() == size as usize => {
+                                info!(
+                                    "Successfully downloaded {} bytes from FTP (attempt {})",
+                                    data.len(),
+                                    attempt
+                                );
+                                return Ok((returned_stream, data));
+                            }
+                            Ok(data) => {
+                                last_error = format!(
+                                    "Size mismatch: expected {} bytes, got {}",
+                                    size,
+                                    data.len()
+                                );
                             }
-                        } else {
-                            info!(
-                                "Successfully downloaded {} bytes from FTP (attempt {})",
-                                data.len(),
-                                attempt
-                            );
-                            return Ok((returned_stream, data));
+                            Err(e) => last_error = e,
                         }
-                    }
-                    Ok((returned_stream, Err(e))) => {
-                        last_error = e;
                         warn!("Attempt {}/{} failed: {}", attempt, max_retries, last_error);
-                        stream_opt = Some(returned_stream);
+                        if returned_stream.is_none() {
+                            // The connection is out of step with the server; retry on a new one
+                            return Err((None, last_error));
+                        }
+                        stream_opt = returned_stream;
                         if attempt < max_retries {
                             tokio::time::sleep(Duration::from_millis(1000 * attempt as u64)).await;
                             continue;
@@ -363,74 +549,97 @@ impl FtpDownloader {
         ))
     }
 
+    /// Read ranges of `remote_path` in offset order from one transfer on `stream`; see
+    /// `SequentialRanges`. Each read is bounded by the read timeout.
+    pub fn sequential_ranges(&self, stream: FtpStream, remote_path: String) -> SequentialRanges {
+        let (requests, request_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (reply_tx, replies) = tokio::sync::mpsc::channel(1);
+        let task = task::spawn_blocking(move || {
+            SequentialRanges::serve(stream, &remote_path, request_rx, reply_tx)
+        });
+        SequentialRanges {
+            requests: Some(requests),
+            replies,
+            task: Some(task),
+            timeout: Duration::from_secs(self.config.read_timeout_secs.max(1)),
+        }
+    }
+
     /// Blocking implementation of range download (called from spawn_blocking)
+    ///
+    /// The transfer is brought to a close on the control connection before returning: a range
+    /// that ran to the end of the file is finalized (226 Transfer complete), one that stopped
+    /// short is aborted with ABOR, since the server is still sending. A connection that couldn't
+    /// be closed cleanly may still owe the reply to this transfer, which the next command would
+    /// read instead of its own, so it is reported as unusable.
     fn try_download_range_blocking(
         stream: &mut FtpStream,
         remote_path: &str,
         start_byte: u64,
         size: u64,
-    ) -> Result<Vec<u8>, String> {
-        // Send REST command to set starting position (if supported by suppaftp 6.x)
-        // Note: suppaftp 6.x may not have rest() method, so we rely on RETR with offset
-
+        use_rest: bool,
+    ) -> RangeTransfer {
         debug!("Attempting to download {} bytes from offset {}", size, start_byte);
 
-        // Use RETR command with a closure to read data
-        // suppaftp v6 API: retr(file_name, reader_fn)
-        let buffer = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
-        let buffer_clone = buffer.clone();
-        let target_size = size as usize;
-
-        stream
-            .retr(remote_path, |reader| {
-                let mut buf = buffer_clone.lock().unwrap();
-
-                // Skip to start_byte if offset is needed
-                if start_byte > 0 {
-                    // Read and discard bytes until we reach start_byte
-                    let mut skip_buf = vec![0u8; 8192];
-                    let mut skipped = 0u64;
-
-                    while skipped < start_byte {
-                        let to_skip = std::cmp::min(8192, (start_byte - skipped) as usize);
-                        match reader.read(&mut skip_buf[..to_skip]) {
-                            Ok(0) => break,  // EOF
-                            Ok(n) => skipped += n as u64,
-                            Err(e) => return Err(FtpError::ConnectionError(std::io::Error::new(std::io::ErrorKind::Other, format!("Skip error: {}", e)))),
-                        }
-                    }
+        // REST makes the server start at the offset; without it the bytes before the offset
+        // are read and discarded
+        let mut skip_bytes = start_byte;
+        if use_rest && start_byte > 0 {
+            match stream.resume_transfer(start_byte as usize) {
+                Ok(()) => skip_bytes = 0,
+                Err(e) => warn!(
+                    "REST {} rejected, reading up to the offset instead: {}",
+                    start_byte, e
+                ),
+            }
+        }
 
-                    debug!("Skipped {} bytes to reach offset", skipped);
+        let mut data_stream = match stream.retr_as_stream(remote_path) {
+            Ok(data_stream) => data_stream,
+            Err(e) => {
+                return RangeTransfer {
+                    // A refused RETR leaves the connection in step; a broken socket doesn't
+                    connection_usable: matches!(e, FtpError::UnexpectedResponse(_)),
+                    result: Err(format!("RETR command failed: {}", e)),
                 }
+            }
+        };
 
-                // Read the actual data
-                let mut total_read = 0usize;
-                let mut read_buf = vec![0u8; 8192];
-
-                while total_read < target_size {
-                    let to_read = std::cmp::min(8192, target_size - total_read);
-                    match reader.read(&mut read_buf[..to_read]) {
-                        Ok(0) => {
-                            warn!("EOF after {} bytes (expected {})", total_read, target_size);
-                            break;
-                        }
-                        Ok(n) => {
-                            buf.extend_from_slice(&read_buf[..n]);
-                            total_read += n;
-                            debug!("Read {} bytes (total: {}/{})", n, total_read, target_size);
-                        }
-                        Err(e) => return Err(FtpError::ConnectionError(e)),
-                    }
+        let data = match read_range(&mut data_stream, skip_bytes, size as usize) {
+            Ok(data) => data,
+            Err(e) => {
+                return RangeTransfer {
+                    connection_usable: false,
+                    result: Err(format!("FTP data connection failed: {}", e)),
                 }
+            }
+        };
+        if data.len() < size as usize {
+            warn!("EOF after {} bytes (expected {})", data.len(), size);
+        }
 
-                Ok(())
-            })
-            .map_err(|e| format!("RETR command failed: {}", e))?;
-
-        let data = buffer.lock().unwrap().clone();
+        let at_end = data.len() < size as usize || matches!(data_stream.read(&mut [0u8; 1]), Ok(0));
+        let closed = if at_end {
+            stream.finalize_retr_stream(data_stream)
+        } else {
+            stream.abort(data_stream)
+        };
+        let connection_usable = match closed {
+            Ok(()) => true,
+            Err(e) => {
+                warn!(
+                    "Transfer of {} didn't complete cleanly, not reusing the connection: {}",
+                    remote_path, e
+                );
+                false
+            }
+        };
         debug!("Completed reading {} bytes from FTP", data.len());
 
-        Ok(data)
+        RangeTransfer {
+            result: Ok(data),
+            connection_usable,
+        }
     }
 
     /// Get the size of a file on the FTP server
@@ -454,16 +663,29 @@ impl FtpDownloader {
         Ok(size as u64)
     }
 
+    /// Ask the server which optional commands it supports (FEAT, RFC 2389)
+    pub async fn probe_capabilities(
+        &self,
+        stream: &mut FtpStream,
+    ) -> Result<FtpServerCapabilities, String> {
+        // Synchronous operation since we can't move &mut stream
+        let features = stream
+            .feat()
+            .map_err(|e| format!("FEAT command failed: {}", e))?;
+        let capabilities = FtpServerCapabilities::from_features(&features);
+
+        debug!("FTP server capabilities: {:?}", capabilities);
+
+        Ok(capabilities)
+    }
+
     /// Test if FTP server supports REST command (resume capability)
     ///
-    /// For suppaftp v6, we assume REST is not directly accessible,
-    /// so we return true optimistically (will be tested during actual download)
-    pub async fn supports_resume(&self, _stream: &mut FtpStream) -> Result<bool, String> {
-        debug!("REST command support assumed (will be tested during download)");
-
-        // suppaftp v6 doesn't expose REST command directly
-        // We'll rely on reading with offset in try_download_range_blocking
-        Ok(true)
+    /// Based on the FEAT reply; servers without FEAT are reported as unsupported.
+    pub async fn supports_resume(&self, stream: &mut FtpStream) -> Result<bool, String> {
+        self.probe_capabilities(stream)
+            .await
+            .map(|capabilities| capabilities.supports_rest)
     }
 
     /// Download entire file (without range)
@@ -581,6 +803,41 @@ mod tests {
         assert!(config.passive_mode);
     }
 
+    #[test]
+    fn test_capabilities_from_features() {
+        let features: HashMap<String, Option<String>> = [
+            ("SIZE".to_string(), None),
+            ("rest".to_string(), Some("stream".to_string())),
+            ("MDTM".to_string(), None),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            FtpServerCapabilities::from_features(&features),
+            FtpServerCapabilities {
+                supports_rest: true,
+                supports_size: true,
+            }
+        );
+
+        // REST without STREAM only restarts other transfer modes
+        let block_only: HashMap<String, Option<String>> =
+            [("REST".to_string(), None)].into_iter().collect();
+        assert!(!FtpServerCapabilities::from_features(&block_only).supports_rest);
+    }
+
+    #[test]
+    fn test_read_range_leaves_the_rest_of_the_transfer_unread() {
+        let mut data_stream = std::io::Cursor::new((0u8..20).collect::<Vec<u8>>());
+        assert_eq!(read_range(&mut data_stream, 5, 4).unwrap(), vec![5, 6, 7, 8]);
+        // The remainder is still pending, so the transfer has to be aborted rather than finalized
+        assert_eq!(data_stream.position(), 9);
+
+        let mut data_stream = std::io::Cursor::new(vec![1u8, 2, 3]);
+        assert_eq!(read_range(&mut data_stream, 1, 10).unwrap(), vec![2, 3]);
+        assert_eq!(data_stream.read(&mut [0u8; 1]).unwrap(), 0);
+    }
+
     #[tokio::test]
     async fn test_downloader_creation() {
         let downloader = FtpDownloader::new();
@@ -591,6 +848,7 @@ mod tests {
     async fn test_custom_config() {
         let config = FtpDownloadConfig {
             timeout_secs: 60,
+            read_timeout_secs: 120,
             max_retries: 5,
             passive_mode: false,
             connection_pool_size: 10,
@@ -621,6 +879,7 @@ mod tests {
         // Test that timeout from config is accessible
         let config = FtpDownloadConfig {
             timeout_secs: 45,
+            read_timeout_secs: 45,
             max_retries: 3,
             passive_mode: true,
             connection_pool_size: 5,
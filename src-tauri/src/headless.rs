@@ -8,8 +8,11 @@ use crate::bittorrent_handler;
 use crate::file_transfer::FileTransferService;
 use crate::http_server;
 use crate::keystore::Keystore;
+use crate::multi_source_download::MultiSourceDownloadService;
 use crate::webrtc_service::{set_webrtc_service, WebRTCService};
 use crate::{bandwidth::BandwidthController, manager::ChunkManager};
+use chiral_network::analytics::AnalyticsService;
+use chiral_network::transfer_events::TransferEventBus;
 use clap::Parser;
 use std::{sync::Arc, time::Duration};
 use tokio::signal;
@@ -300,7 +303,7 @@ pub async fn run_headless(mut args: CliArgs) -> Result<(), Box<dyn std::error::E
     let dht_service = DhtService::new(
         dht_config,
         file_transfer_service.clone(),
-        webrtc_service,
+        webrtc_service.clone(),
         chunk_manager.clone(),
     )
     .await?;
@@ -500,18 +503,38 @@ pub async fn run_headless(mut args: CliArgs) -> Result<(), Box<dyn std::error::E
                     "Headless E2E BitTorrent listen port range: {}..{} (end-exclusive)",
                     bt_port, bt_port_end
                 );
-                let bt_handler = match bittorrent_handler::BitTorrentHandler::new_with_port_range(
-                    bt_download_dir,
-                    dht_arc.clone(),
-                    Some(bt_port..bt_port_end),
-                )
-                .await
-                {
-                    Ok(h) => Some(Arc::new(h)),
-                    Err(e) => {
-                        warn!("Failed to initialize BitTorrent handler in headless mode: {}", e);
-                        None
+                let bt_handler =
+                    match bittorrent_handler::BitTorrentHandler::new_with_port_range_and_proxy(
+                        bt_download_dir,
+                        dht_arc.clone(),
+                        Some(bt_port..bt_port_end),
+                        args.socks5_proxy.clone(),
+                    )
+                    .await
+                    {
+                        Ok(h) => Some(Arc::new(h)),
+                        Err(e) => {
+                            warn!("Failed to initialize BitTorrent handler in headless mode: {}", e);
+                            None
+                        }
+                    };
+
+                // Backs the status endpoint; needs the P2P services the E2E API turns on
+                let multi_source = match (&webrtc_service, &chunk_manager) {
+                    (Some(webrtc), Some(chunk_manager)) => {
+                        let service = Arc::new(MultiSourceDownloadService::new(
+                            dht_arc.clone(),
+                            webrtc.clone(),
+                            bt_handler.clone(),
+                            Arc::new(TransferEventBus::detached()),
+                            Arc::new(AnalyticsService::new()),
+                            chunk_manager.clone(),
+                        ));
+                        let runner = service.clone();
+                        tokio::spawn(async move { runner.run().await });
+                        Some(service)
                     }
+                    _ => None,
                 };
 
                 let state = HeadlessE2eState {
@@ -535,6 +558,7 @@ pub async fn run_headless(mut args: CliArgs) -> Result<(), Box<dyn std::error::E
                             port,
                         )))
                     },
+                    multi_source: multi_source.clone(),
                     bittorrent_handler: bt_handler,
                 };
                 match start_headless_e2e_api_server(state, port).await {
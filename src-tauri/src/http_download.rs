@@ -1,5 +1,6 @@
+use crate::cert_pinning;
 use crate::transfer_events::{
-    calculate_eta, calculate_progress, current_timestamp_ms, ChunkCompletedEvent,
+    calculate_chunk_speed, calculate_eta, calculate_progress, current_timestamp_ms, ChunkCompletedEvent,
     ChunkFailedEvent, SourceConnectedEvent, SourceDisconnectedEvent, SourceInfo,
     SourceType, TransferCompletedEvent, TransferEventBus, TransferFailedEvent,
     TransferPriority, TransferProgressEvent, TransferResumedEvent, TransferStartedEvent,
@@ -143,7 +144,7 @@ pub struct HttpDownloadClient {
 impl HttpDownloadClient {
     pub fn new() -> Self {
         Self {
-            http_client: Client::builder()
+            http_client: cert_pinning::client_builder()
                 // Use longer timeout for chunk downloads (60 seconds)
                 // This accounts for large chunks and slow networks
                 .timeout(std::time::Duration::from_secs(60))
@@ -158,7 +159,7 @@ impl HttpDownloadClient {
     /// Create with downloader peer ID for provider-side metrics tracking
     pub fn new_with_peer_id(downloader_peer_id: Option<String>) -> Self {
         Self {
-            http_client: Client::builder()
+            http_client: cert_pinning::client_builder()
                 .timeout(std::time::Duration::from_secs(60))
                 .build()
                 .expect("Failed to create HTTP client"),
@@ -171,7 +172,7 @@ impl HttpDownloadClient {
     /// Create with app handle for event bus integration
     pub fn new_with_event_bus(app_handle: AppHandle) -> Self {
         Self {
-            http_client: Client::builder()
+            http_client: cert_pinning::client_builder()
                 .timeout(std::time::Duration::from_secs(60))
                 .build()
                 .expect("Failed to create HTTP client"),
@@ -187,7 +188,7 @@ impl HttpDownloadClient {
         app_handle: AppHandle,
     ) -> Self {
         Self {
-            http_client: Client::builder()
+            http_client: cert_pinning::client_builder()
                 .timeout(std::time::Duration::from_secs(60))
                 .build()
                 .expect("Failed to create HTTP client"),
@@ -200,7 +201,7 @@ impl HttpDownloadClient {
     /// Create with custom timeout
     pub fn with_timeout(timeout_secs: u64) -> Self {
         Self {
-            http_client: Client::builder()
+            http_client: cert_pinning::client_builder()
                 .timeout(std::time::Duration::from_secs(timeout_secs))
                 .build()
                 .expect("Failed to create HTTP client"),
@@ -772,6 +773,7 @@ impl HttpDownloadClient {
                                             source_type: SourceType::Http,
                                             completed_at: current_timestamp_ms(),
                                             download_duration_ms: chunk_duration_ms,
+                                            chunk_speed_bps: calculate_chunk_speed(data.len(), chunk_duration_ms),
                                             verified: true,
                                         });
 
@@ -796,6 +798,7 @@ impl HttpDownloadClient {
                                                 download_speed_bps: speed,
                                                 upload_speed_bps: 0.0,
                                                 eta_seconds: eta,
+                                                chunks_completed_delta: 0,
                                                 active_sources: 1,
                                                 timestamp: current_timestamp_ms(),
                                             });
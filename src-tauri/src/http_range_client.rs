@@ -9,6 +9,7 @@
 // - §5.3: Expected HTTP server behaviour (HEAD, Range requests, ETag handling)
 // - §7: Client algorithm (PreparingHead, Streaming, Resume path)
 
+use crate::cert_pinning;
 use async_trait::async_trait;
 use bytes::Bytes;
 use chrono::{DateTime, Utc};
@@ -89,7 +90,7 @@ impl HttpRangeClient {
     /// 
     /// Per §5.3: Redirects are disabled - we treat 3xx as errors.
     pub fn new() -> Result<Self, SourceError> {
-        let client = Client::builder()
+        let client = cert_pinning::client_builder()
             .timeout(std::time::Duration::from_secs(30))
             .redirect(reqwest::redirect::Policy::none()) // Per §5.3: treat redirects as errors
             .build()
@@ -102,7 +103,7 @@ impl HttpRangeClient {
     /// 
     /// Per §5.3: Redirects are disabled - we treat 3xx as errors.
     pub fn with_timeout(timeout_secs: u64) -> Result<Self, SourceError> {
-        let client = Client::builder()
+        let client = cert_pinning::client_builder()
             .timeout(std::time::Duration::from_secs(timeout_secs))
             .redirect(reqwest::redirect::Policy::none()) // Per §5.3: treat redirects as errors
             .build()
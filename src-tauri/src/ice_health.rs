@@ -0,0 +1,617 @@
+//! Health checks for the STUN/TURN servers WebRTC uses for NAT traversal.
+//!
+//! Every configured ICE server URL is probed periodically: STUN URLs with a binding request,
+//! TURN URLs with an unauthenticated Allocate (a 401 challenge proves the server is up). URLs
+//! that stop answering a few probes in a row are left out of new peer connections and put
+//! back once they recover. Probes reach the servers directly, so none are sent while a proxy
+//! is configured.
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::timeout;
+use tracing::{debug, info, warn};
+use webrtc::ice_transport::ice_credential_type::RTCIceCredentialType;
+use webrtc::ice_transport::ice_server::RTCIceServer;
+
+pub const DEFAULT_HEALTH_CHECK_INTERVAL_SECS: u64 = 60;
+const PROBE_TIMEOUT_SECS: u64 = 5;
+// Failed probes in a row before a URL is left out, so one lost datagram doesn't drop it
+const UNHEALTHY_AFTER_FAILURES: u32 = 3;
+
+const STUN_HEADER_LEN: usize = 20;
+const STUN_MAGIC_COOKIE: u32 = 0x2112_A442;
+const STUN_BINDING: u16 = 0x0001;
+const TURN_ALLOCATE: u16 = 0x0003;
+const STUN_CLASS_MASK: u16 = 0x0110;
+const STUN_SUCCESS_RESPONSE: u16 = 0x0100;
+const STUN_ERROR_RESPONSE: u16 = 0x0110;
+const ATTR_REQUESTED_TRANSPORT: u16 = 0x0019;
+const TRANSPORT_PROTOCOL_UDP: u8 = 17;
+
+static ICE_SERVER_HEALTH: Lazy<RwLock<IceServerHealth>> =
+    Lazy::new(|| RwLock::new(IceServerHealth::new(default_ice_servers())));
+static HEALTH_CHECKS_RUNNING: AtomicBool = AtomicBool::new(false);
+static PROXY_CONFIGURED: AtomicBool = AtomicBool::new(false);
+
+// A panic while holding the lock leaves whole probe results behind, so the data stays usable
+fn read_health() -> RwLockReadGuard<'static, IceServerHealth> {
+    ICE_SERVER_HEALTH.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn write_health() -> RwLockWriteGuard<'static, IceServerHealth> {
+    ICE_SERVER_HEALTH.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// One STUN/TURN server entry, as configured by the user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IceServerConfig {
+    pub urls: Vec<String>,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub credential: String,
+}
+
+impl IceServerConfig {
+    fn to_rtc(&self, urls: Vec<String>) -> RTCIceServer {
+        if self.username.is_empty() {
+            RTCIceServer {
+                urls,
+                ..Default::default()
+            }
+        } else {
+            RTCIceServer {
+                urls,
+                username: self.username.clone(),
+                credential: self.credential.clone(),
+                credential_type: RTCIceCredentialType::Password,
+            }
+        }
+    }
+}
+
+/// Servers used until the user configures their own
+pub fn default_ice_servers() -> Vec<IceServerConfig> {
+    vec![
+        // Google STUN servers (reliable, no auth needed)
+        IceServerConfig {
+            urls: vec![
+                "stun:stun.l.google.com:19302".to_string(),
+                "stun:stun1.l.google.com:19302".to_string(),
+                "stun:stun2.l.google.com:19302".to_string(),
+                "stun:stun3.l.google.com:19302".to_string(),
+            ],
+            username: String::new(),
+            credential: String::new(),
+        },
+        // Evan Brass experimental TURN server (free, public)
+        IceServerConfig {
+            urls: vec![
+                "turn:stun.evan-brass.net".to_string(),
+                "turn:stun.evan-brass.net?transport=tcp".to_string(),
+                "stun:stun.evan-brass.net".to_string(),
+            ],
+            username: "guest".to_string(),
+            credential: "password".to_string(),
+        },
+    ]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IceScheme {
+    Stun,
+    Turn,
+    /// TURN over TLS; only the TCP connect is checked
+    Turns,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IceUrl {
+    pub scheme: IceScheme,
+    pub host: String,
+    pub port: u16,
+    pub tcp: bool,
+}
+
+/// Parse a `stun:`, `turn:` or `turns:` URL (RFC 7064/7065)
+pub fn parse_ice_url(url: &str) -> Result<IceUrl, String> {
+    let (scheme, rest) = url
+        .split_once(':')
+        .ok_or_else(|| format!("Missing scheme in ICE server URL: {}", url))?;
+    let scheme = match scheme.to_ascii_lowercase().as_str() {
+        "stun" => IceScheme::Stun,
+        "turn" => IceScheme::Turn,
+        "turns" => IceScheme::Turns,
+        other => return Err(format!("Unsupported ICE server scheme: {}", other)),
+    };
+
+    let (address, query) = match rest.split_once('?') {
+        Some((address, query)) => (address, Some(query)),
+        None => (rest, None),
+    };
+    let tcp = match query.and_then(|query| query.strip_prefix("transport=")) {
+        Some(transport) if transport.eq_ignore_ascii_case("tcp") => true,
+        Some(transport) if transport.eq_ignore_ascii_case("udp") => false,
+        Some(transport) => return Err(format!("Unsupported ICE transport: {}", transport)),
+        None => scheme == IceScheme::Turns,
+    };
+
+    let (host, port) = if let Some(bracketed) = address.strip_prefix('[') {
+        // IPv6 literal, optionally followed by :port
+        let (host, after) = bracketed
+            .split_once(']')
+            .ok_or_else(|| format!("Unterminated IPv6 address in ICE server URL: {}", url))?;
+        (host, after.strip_prefix(':'))
+    } else {
+        match address.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (address, None),
+        }
+    };
+    let port = match port {
+        Some(port) => port.parse().map_err(|_| format!("Invalid port in ICE server URL: {}", url))?,
+        None if scheme == IceScheme::Turns => 5349,
+        None => 3478,
+    };
+    if host.is_empty() {
+        return Err(format!("Missing host in ICE server URL: {}", url));
+    }
+
+    Ok(IceUrl {
+        scheme,
+        host: host.to_string(),
+        port,
+        tcp,
+    })
+}
+
+/// Health of one ICE server URL
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IceServerStatus {
+    pub url: String,
+    /// Unchecked URLs count as healthy, as do URLs with fewer than
+    /// `UNHEALTHY_AFTER_FAILURES` failed probes in a row
+    pub healthy: bool,
+    pub last_checked_ms: Option<u64>,
+    pub rtt_ms: Option<u64>,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+}
+
+impl IceServerStatus {
+    fn unchecked(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            healthy: true,
+            last_checked_ms: None,
+            rtt_ms: None,
+            consecutive_failures: 0,
+            last_error: None,
+        }
+    }
+}
+
+/// Configured ICE servers plus the latest probe result for each of their URLs
+#[derive(Debug)]
+pub struct IceServerHealth {
+    servers: Vec<IceServerConfig>,
+    status: HashMap<String, IceServerStatus>,
+}
+
+impl IceServerHealth {
+    pub fn new(servers: Vec<IceServerConfig>) -> Self {
+        let mut health = Self {
+            servers: Vec::new(),
+            status: HashMap::new(),
+        };
+        health.set_servers(servers);
+        health
+    }
+
+    /// Replace the configured servers, keeping results for URLs that are still listed
+    pub fn set_servers(&mut self, servers: Vec<IceServerConfig>) {
+        let mut status = HashMap::new();
+        for url in servers.iter().flat_map(|server| server.urls.iter()) {
+            if status.contains_key(url) {
+                continue;
+            }
+            let entry = self
+                .status
+                .remove(url)
+                .unwrap_or_else(|| IceServerStatus::unchecked(url));
+            status.insert(url.clone(), entry);
+        }
+        self.servers = servers;
+        self.status = status;
+    }
+
+    pub fn servers(&self) -> &[IceServerConfig] {
+        &self.servers
+    }
+
+    pub fn urls(&self) -> Vec<String> {
+        let mut seen = HashSet::new();
+        self.servers
+            .iter()
+            .flat_map(|server| server.urls.iter())
+            .filter(|url| seen.insert(url.as_str()))
+            .cloned()
+            .collect()
+    }
+
+    /// Store a probe result. Returns the new health when it changed.
+    pub fn record(
+        &mut self,
+        url: &str,
+        result: Result<Duration, String>,
+        now_ms: u64,
+    ) -> Option<bool> {
+        let status = self.status.get_mut(url)?;
+        let was_healthy = status.healthy;
+        status.last_checked_ms = Some(now_ms);
+        match result {
+            Ok(rtt) => {
+                status.healthy = true;
+                status.rtt_ms = Some(rtt.as_millis() as u64);
+                status.consecutive_failures = 0;
+                status.last_error = None;
+            }
+            Err(e) => {
+                status.rtt_ms = None;
+                status.consecutive_failures += 1;
+                status.last_error = Some(e);
+                if status.consecutive_failures >= UNHEALTHY_AFTER_FAILURES {
+                    status.healthy = false;
+                }
+            }
+        }
+        (status.healthy != was_healthy).then_some(status.healthy)
+    }
+
+    /// Servers to hand to new peer connections, without the URLs that failed their last probe.
+    /// When every URL is down the full list is returned, since a stale server beats none.
+    pub fn healthy_servers(&self) -> Vec<RTCIceServer> {
+        let healthy: Vec<RTCIceServer> = self
+            .servers
+            .iter()
+            .filter_map(|server| {
+                let urls: Vec<String> = server
+                    .urls
+                    .iter()
+                    .filter(|url| self.status.get(*url).is_none_or(|status| status.healthy))
+                    .cloned()
+                    .collect();
+                (!urls.is_empty()).then(|| server.to_rtc(urls))
+            })
+            .collect();
+
+        if healthy.is_empty() {
+            self.servers
+                .iter()
+                .map(|server| server.to_rtc(server.urls.clone()))
+                .collect()
+        } else {
+            healthy
+        }
+    }
+
+    pub fn statuses(&self) -> Vec<IceServerStatus> {
+        self.urls()
+            .iter()
+            .filter_map(|url| self.status.get(url).cloned())
+            .collect()
+    }
+}
+
+/// Replace the configured ICE servers. Takes effect for the next peer connection.
+pub fn set_ice_servers(servers: Vec<IceServerConfig>) -> Result<(), String> {
+    if servers.iter().all(|server| server.urls.is_empty()) {
+        return Err("At least one ICE server URL is required".to_string());
+    }
+    for url in servers.iter().flat_map(|server| server.urls.iter()) {
+        parse_ice_url(url)?;
+    }
+    write_health().set_servers(servers);
+    Ok(())
+}
+
+pub fn ice_servers() -> Vec<IceServerConfig> {
+    read_health().servers().to_vec()
+}
+
+/// The ICE servers that answered their recent health checks
+pub fn healthy_ice_servers() -> Vec<RTCIceServer> {
+    read_health().healthy_servers()
+}
+
+pub fn ice_server_statuses() -> Vec<IceServerStatus> {
+    read_health().statuses()
+}
+
+/// Stop or restart health checks as a proxy is configured or cleared. The probes would
+/// reach the servers directly and give away our address.
+pub fn set_proxy_configured(configured: bool) {
+    PROXY_CONFIGURED.store(configured, Ordering::SeqCst);
+}
+
+/// Probe every configured URL once and update the healthy set. Without probing while a
+/// proxy is configured, the latest statuses are returned as they are.
+pub async fn check_ice_servers() -> Vec<IceServerStatus> {
+    if PROXY_CONFIGURED.load(Ordering::SeqCst) {
+        debug!("Proxy configured, skipping ICE server health checks");
+        return ice_server_statuses();
+    }
+
+    let urls = read_health().urls();
+    let results = futures::future::join_all(urls.iter().map(|url| async move {
+        let result = match parse_ice_url(url) {
+            Ok(parsed) => probe_ice_url(&parsed).await,
+            Err(e) => Err(e),
+        };
+        (url, result)
+    }))
+    .await;
+
+    let now = now_ms();
+    let mut health = write_health();
+    for (url, result) in results {
+        if let Err(e) = &result {
+            debug!("ICE server {} failed its health check: {}", url, e);
+        }
+        match health.record(url, result, now) {
+            Some(true) => info!("ICE server {} recovered, using it again", url),
+            Some(false) => warn!("ICE server {} is unreachable, leaving it out", url),
+            None => {}
+        }
+    }
+    health.statuses()
+}
+
+/// Start the periodic health check loop. Later calls are no-ops.
+pub fn spawn_health_checks(interval: Duration) {
+    if HEALTH_CHECKS_RUNNING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            check_ice_servers().await;
+        }
+    });
+}
+
+/// Send one STUN binding request (or TURN Allocate) and wait for any matching response
+pub async fn probe_ice_url(url: &IceUrl) -> Result<Duration, String> {
+    let addr = tokio::net::lookup_host((url.host.as_str(), url.port))
+        .await
+        .map_err(|e| format!("Failed to resolve {}: {}", url.host, e))?
+        .next()
+        .ok_or_else(|| format!("No address found for {}", url.host))?;
+
+    let started = Instant::now();
+    let probe = async {
+        if url.scheme == IceScheme::Turns {
+            // Checking the TLS layer would need a TLS client; a listening port is close enough
+            TcpStream::connect(addr)
+                .await
+                .map(|_| ())
+                .map_err(|e| format!("Failed to connect to {}: {}", addr, e))
+        } else {
+            let method = if url.scheme == IceScheme::Turn {
+                TURN_ALLOCATE
+            } else {
+                STUN_BINDING
+            };
+            let transaction_id: [u8; 12] = rand::random();
+            let request = encode_stun_request(method, &transaction_id);
+            if url.tcp {
+                probe_tcp(addr, &request, method, &transaction_id).await
+            } else {
+                probe_udp(addr, &request, method, &transaction_id).await
+            }
+        }
+    };
+
+    timeout(Duration::from_secs(PROBE_TIMEOUT_SECS), probe)
+        .await
+        .map_err(|_| format!("No answer from {} within {}s", addr, PROBE_TIMEOUT_SECS))??;
+    Ok(started.elapsed())
+}
+
+async fn probe_udp(
+    addr: SocketAddr,
+    request: &[u8],
+    method: u16,
+    transaction_id: &[u8; 12],
+) -> Result<(), String> {
+    let bind_addr = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+    let socket = UdpSocket::bind(bind_addr)
+        .await
+        .map_err(|e| format!("Failed to bind UDP socket: {}", e))?;
+    socket
+        .send_to(request, addr)
+        .await
+        .map_err(|e| format!("Failed to send to {}: {}", addr, e))?;
+
+    let mut buf = [0u8; 1500];
+    loop {
+        let (len, from) = socket
+            .recv_from(&mut buf)
+            .await
+            .map_err(|e| format!("Failed to read from {}: {}", addr, e))?;
+        // Stray datagrams are ignored; the timeout in the caller bounds the wait
+        if from == addr && is_stun_response(&buf[..len], method, transaction_id) {
+            return Ok(());
+        }
+    }
+}
+
+async fn probe_tcp(
+    addr: SocketAddr,
+    request: &[u8],
+    method: u16,
+    transaction_id: &[u8; 12],
+) -> Result<(), String> {
+    let mut stream = TcpStream::connect(addr)
+        .await
+        .map_err(|e| format!("Failed to connect to {}: {}", addr, e))?;
+    stream
+        .write_all(request)
+        .await
+        .map_err(|e| format!("Failed to send to {}: {}", addr, e))?;
+
+    let mut header = [0u8; STUN_HEADER_LEN];
+    stream
+        .read_exact(&mut header)
+        .await
+        .map_err(|e| format!("Failed to read from {}: {}", addr, e))?;
+    if is_stun_response(&header, method, transaction_id) {
+        Ok(())
+    } else {
+        Err(format!("{} did not answer with a STUN response", addr))
+    }
+}
+
+/// Build a STUN request with no attributes, or with REQUESTED-TRANSPORT for a TURN Allocate
+pub fn encode_stun_request(method: u16, transaction_id: &[u8; 12]) -> Vec<u8> {
+    let attributes: Vec<u8> = if method == TURN_ALLOCATE {
+        let mut attribute = Vec::with_capacity(8);
+        attribute.extend_from_slice(&ATTR_REQUESTED_TRANSPORT.to_be_bytes());
+        attribute.extend_from_slice(&4u16.to_be_bytes());
+        attribute.extend_from_slice(&[TRANSPORT_PROTOCOL_UDP, 0, 0, 0]);
+        attribute
+    } else {
+        Vec::new()
+    };
+
+    let mut message = Vec::with_capacity(STUN_HEADER_LEN + attributes.len());
+    message.extend_from_slice(&method.to_be_bytes());
+    message.extend_from_slice(&(attributes.len() as u16).to_be_bytes());
+    message.extend_from_slice(&STUN_MAGIC_COOKIE.to_be_bytes());
+    message.extend_from_slice(transaction_id);
+    message.extend_from_slice(&attributes);
+    message
+}
+
+/// Whether `data` starts with a success or error response to our request. An error response
+/// still proves the server is alive (TURN answers an unauthenticated Allocate with 401).
+pub fn is_stun_response(data: &[u8], method: u16, transaction_id: &[u8; 12]) -> bool {
+    if data.len() < STUN_HEADER_LEN {
+        return false;
+    }
+    let message_type = u16::from_be_bytes([data[0], data[1]]);
+    let class = message_type & STUN_CLASS_MASK;
+    (class == STUN_SUCCESS_RESPONSE || class == STUN_ERROR_RESPONSE)
+        && message_type & !STUN_CLASS_MASK == method
+        && data[4..8] == STUN_MAGIC_COOKIE.to_be_bytes()
+        && data[8..20] == transaction_id[..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(urls: &[&str]) -> IceServerConfig {
+        IceServerConfig {
+            urls: urls.iter().map(|url| url.to_string()).collect(),
+            username: String::new(),
+            credential: String::new(),
+        }
+    }
+
+    #[test]
+    fn parses_ice_urls() {
+        let url = parse_ice_url("stun:stun.l.google.com:19302").unwrap();
+        assert_eq!(url.scheme, IceScheme::Stun);
+        assert_eq!(url.host, "stun.l.google.com");
+        assert_eq!(url.port, 19302);
+        assert!(!url.tcp);
+
+        let url = parse_ice_url("turn:stun.evan-brass.net?transport=tcp").unwrap();
+        assert_eq!(url.scheme, IceScheme::Turn);
+        assert_eq!(url.port, 3478);
+        assert!(url.tcp);
+
+        let url = parse_ice_url("turns:[2001:db8::1]").unwrap();
+        assert_eq!(url.host, "2001:db8::1");
+        assert_eq!(url.port, 5349);
+        assert!(url.tcp);
+
+        assert!(parse_ice_url("http://example.com").is_err());
+        assert!(parse_ice_url("stun:host:notaport").is_err());
+    }
+
+    #[test]
+    fn recognises_matching_stun_responses() {
+        let transaction_id = [7u8; 12];
+        let request = encode_stun_request(TURN_ALLOCATE, &transaction_id);
+        assert_eq!(request.len(), STUN_HEADER_LEN + 8);
+        assert_eq!(&request[2..4], &8u16.to_be_bytes());
+
+        let mut response = request.clone();
+        // 401 challenge to the Allocate
+        response[..2].copy_from_slice(&(TURN_ALLOCATE | STUN_ERROR_RESPONSE).to_be_bytes());
+        assert!(is_stun_response(&response, TURN_ALLOCATE, &transaction_id));
+        assert!(!is_stun_response(&response, STUN_BINDING, &transaction_id));
+        assert!(!is_stun_response(&response, TURN_ALLOCATE, &[8u8; 12]));
+        // Our own request is not a response
+        assert!(!is_stun_response(&request, TURN_ALLOCATE, &transaction_id));
+    }
+
+    #[test]
+    fn unhealthy_urls_rotate_out_and_back_in() {
+        let mut health = IceServerHealth::new(vec![
+            server(&["stun:a.example:3478", "stun:b.example:3478"]),
+            server(&["turn:c.example"]),
+        ]);
+        assert_eq!(health.healthy_servers().len(), 2);
+
+        // One lost probe is not enough to leave a URL out
+        assert_eq!(health.record("turn:c.example", Err("timeout".into()), 1), None);
+        assert_eq!(health.healthy_servers().len(), 2);
+        assert_eq!(health.record("turn:c.example", Err("timeout".into()), 2), None);
+        assert_eq!(health.record("turn:c.example", Err("timeout".into()), 3), Some(false));
+        for now in 1..UNHEALTHY_AFTER_FAILURES as u64 {
+            health.record("stun:a.example:3478", Err("timeout".into()), now);
+        }
+        assert_eq!(health.record("stun:a.example:3478", Err("timeout".into()), 3), Some(false));
+        let servers = health.healthy_servers();
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0].urls, vec!["stun:b.example:3478".to_string()]);
+
+        // With everything down the full list is used rather than none
+        for now in 1..=UNHEALTHY_AFTER_FAILURES as u64 {
+            health.record("stun:b.example:3478", Err("timeout".into()), now);
+        }
+        assert_eq!(health.healthy_servers().len(), 2);
+
+        assert_eq!(health.record("turn:c.example", Ok(Duration::from_millis(40)), 4), Some(true));
+        let servers = health.healthy_servers();
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0].urls, vec!["turn:c.example".to_string()]);
+
+        // Reconfiguring keeps results for URLs that stay
+        health.set_servers(vec![server(&["turn:c.example", "stun:d.example"])]);
+        let statuses = health.statuses();
+        assert_eq!(statuses.len(), 2);
+        assert_eq!(statuses[0].rtt_ms, Some(40));
+        assert!(statuses[1].last_checked_ms.is_none());
+    }
+}
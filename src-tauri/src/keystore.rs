@@ -2,10 +2,12 @@ use aes::cipher::{KeyIvInit, StreamCipher};
 use aes::Aes256;
 use ctr::Ctr128BE;
 use directories::ProjectDirs;
+use hkdf::Hkdf;
 use hmac::Hmac;
 use pbkdf2::pbkdf2;
 use rand::{thread_rng, RngCore};
 use serde::{Deserialize, Serialize};
+use sha2::Sha256;
 use sha3::Sha3_256;
 use std::fs;
 use std::path::PathBuf;
@@ -359,6 +361,32 @@ impl Keystore {
     }
 }
 
+/// Derive the key that encrypts a download's chunks at rest from the account's private key.
+/// Every file gets its own key, and the same account re-derives it to resume the download.
+pub fn derive_chunk_storage_key(private_key: &str, file_hash: &str) -> Result<[u8; 32], String> {
+    let private_key_bytes = hex::decode(private_key.trim_start_matches("0x"))
+        .map_err(|e| format!("Invalid private key: {}", e))?;
+
+    let hk = Hkdf::<Sha256>::new(Some(file_hash.as_bytes()), &private_key_bytes);
+    let mut key = [0u8; 32];
+    hk.expand(b"chiral-network-chunk-at-rest", &mut key)
+        .map_err(|e| format!("HKDF expansion failed: {}", e))?;
+    Ok(key)
+}
+
+/// Derive the key that seals at-rest keys in persisted download states from the account's
+/// private key, so nothing on disk alone can open them.
+pub fn derive_at_rest_wrapping_key(private_key: &str) -> Result<[u8; 32], String> {
+    let private_key_bytes = hex::decode(private_key.trim_start_matches("0x"))
+        .map_err(|e| format!("Invalid private key: {}", e))?;
+
+    let hk = Hkdf::<Sha256>::new(None, &private_key_bytes);
+    let mut key = [0u8; 32];
+    hk.expand(b"chiral-network-at-rest-wrapping", &mut key)
+        .map_err(|e| format!("HKDF expansion failed: {}", e))?;
+    Ok(key)
+}
+
 fn derive_key(password: &str, salt: &[u8]) -> Result<[u8; 32], String> {
     let mut key = [0u8; 32];
     // Increased iterations from 4096 to 100000 for better security
@@ -5,8 +5,10 @@ pub mod bandwidth;
 pub mod config; 
 pub mod control_plane;
 pub mod multi_source_download;
+pub mod chunk_store;
 pub mod download_restart;
 pub mod transfer_events;
+pub mod transfer_history;
 
 // Connection retry and resilience framework
 pub mod connection_retry;
@@ -22,6 +24,7 @@ pub mod http_download;
 pub mod bittorrent_handler;
 pub mod chiral_bittorrent_extension;
 pub mod download_paths;
+pub mod cert_pinning;
 
 // Required modules for multi_source_download
 pub mod dht;
@@ -30,6 +33,7 @@ pub mod ftp_downloader;
 pub mod ftp_server;
 pub mod peer_selection;
 pub mod peer_cache;
+pub mod ice_health;
 pub mod webrtc_service;
 
 // Required modules for encryption and keystore functionality
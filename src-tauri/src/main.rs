@@ -36,9 +36,10 @@ pub mod webhook_manager;
 
 // Re-export modules from the lib crate
 use chiral_network::{
-    analytics, bandwidth, bittorrent_handler, dht, download_restart, download_source, ed2k_client,
-    encryption, file_transfer, ftp_bookmarks, ftp_client, http_download, keystore, logger, manager,
-    multi_source_download, peer_selection, protocols, reputation, stream_auth, webrtc_service,
+    analytics, bandwidth, bittorrent_handler, cert_pinning, chunk_store, dht, download_restart,
+    download_source, ed2k_client, encryption, file_transfer, ftp_bookmarks, ftp_client,
+    http_download, ice_health, keystore, logger, manager, multi_source_download, peer_selection,
+    protocols, reputation, stream_auth, transfer_history, webrtc_service,
 };
 use headless::create_dht_config_from_args;
 
@@ -62,8 +63,12 @@ use bandwidth::BandwidthController;
 use chiral_network::download_paths;
 use chiral_network::payment_checkpoint::PaymentCheckpointService;
 use chiral_network::transfer_events::{
-    current_timestamp_ms, ErrorCategory, SourceInfo, SourceType, TransferCompletedEvent,
-    TransferEventBus, TransferFailedEvent, TransferStartedEvent,
+    current_timestamp_ms, event_coalescing_config, set_event_coalescing_config, CancelReason,
+    ErrorCategory,
+    EventCoalescingConfig, SeedVerificationFailedEvent, SeedingStartedEvent, SourceInfo,
+    SourceSummary, SourceType,
+    TransferCompletedEvent, TransferEventBus, TransferFailedEvent, TransferPriority,
+    TransferStartedEvent,
 };
 use dht::{models::DhtMetricsSnapshot, models::FileMetadata, DhtConfig, DhtEvent, DhtService};
 use directories::ProjectDirs;
@@ -101,7 +106,12 @@ use fs2::available_space;
 use geth_downloader::GethDownloader;
 use keystore::Keystore;
 use lazy_static::lazy_static;
-use multi_source_download::{MultiSourceDownloadService, MultiSourceEvent, MultiSourceProgress};
+use multi_source_download::{
+    ChunkDirectoryInfo, DownloadRequest, DownloadTarget, ImportResult, MeteredMode,
+    MultiSourceConfig, MultiSourceDownloadService, MultiSourceEvent, MultiSourceProgress,
+    RepairResult, SourceErrorEntry, SourceTypeWeights, StartDownloadError, SwarmHealth,
+    SystemStatus,
+};
 use serde::{Deserialize, Serialize};
 use sha2::Digest;
 use std::collections::{HashMap, VecDeque};
@@ -131,7 +141,9 @@ use tokio::{
 };
 use totp_rs::{Algorithm, Secret, TOTP};
 use tracing::{error, info, warn};
-use webrtc_service::{set_webrtc_service, WebRTCFileRequest, WebRTCService};
+use webrtc_service::{
+    set_webrtc_service, NatTestResult, PeerLimits, WebRTCFileRequest, WebRTCService,
+};
 
 use manager::ChunkManager; // Import the ChunkManager
                            // For key encoding
@@ -354,6 +366,8 @@ struct AppState {
     file_transfer_pump: Mutex<Option<JoinHandle<()>>>,
     multi_source_pump: Mutex<Option<JoinHandle<()>>>,
     socks5_proxy_cli: Mutex<Option<String>>,
+    // Proxy the DHT was started with; downloads must not bypass it
+    socks5_proxy: Mutex<Option<String>>,
     analytics: Arc<analytics::AnalyticsService>,
     bandwidth: Arc<BandwidthController>,
     payment_checkpoint: Arc<PaymentCheckpointService>,
@@ -396,6 +410,8 @@ struct AppState {
 
     // Protocol manager for handling different download/upload protocols
     protocol_manager: Arc<ProtocolManager>,
+    // ED2K handler registered with the protocol manager, kept to update its proxy
+    ed2k_protocol_handler: Arc<protocols::ed2k::Ed2kProtocolHandler>,
 
     // AutoRelay timeline persistence across DHT restarts
     autorelay_last_enabled: Arc<Mutex<Option<SystemTime>>>,
@@ -432,6 +448,7 @@ async fn create_chiral_account(state: State<'_, AppState>) -> Result<EthAccount,
         let mut active_key = state.active_account_private_key.lock().await;
         *active_key = Some(account.private_key.clone());
     }
+    share_at_rest_wrapping_key(&state).await;
 
     Ok(account)
 }
@@ -454,6 +471,7 @@ async fn import_chiral_account(
         let mut active_key = state.active_account_private_key.lock().await;
         *active_key = Some(account.private_key.clone());
     }
+    share_at_rest_wrapping_key(&state).await;
 
     Ok(account)
 }
@@ -485,6 +503,25 @@ async fn download(identifier: String, state: State<'_, AppState>) -> Result<(),
     state.protocol_manager.download_simple(&identifier).await
 }
 
+#[tauri::command]
+fn get_protocol_states(state: State<'_, AppState>) -> Vec<protocols::ProtocolState> {
+    state.protocol_manager.protocol_states()
+}
+
+/// Turn a protocol off (or back on) without unregistering it, e.g. BitTorrent on a
+/// corporate network. The choice is remembered across restarts.
+#[tauri::command]
+fn set_protocol_enabled(
+    state: State<'_, AppState>,
+    protocol: String,
+    enabled: bool,
+) -> Result<(), String> {
+    state
+        .protocol_manager
+        .set_protocol_enabled(&protocol, enabled)
+        .map_err(|e| e.to_string())
+}
+
 /// Tauri command to download a torrent from raw .torrent file bytes.
 #[tauri::command]
 async fn download_torrent_from_bytes(
@@ -588,6 +625,43 @@ async fn seed(file_path: String, state: State<'_, AppState>) -> Result<String, S
     state.protocol_manager.seed_simple(&file_path).await
 }
 
+/// Re-hash a seeded file against the hash it is seeded under; a file that no longer matches
+/// stops being seeded.
+#[tauri::command]
+async fn verify_seed(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    file_hash: String,
+) -> Result<protocols::seeding::VerifyResult, String> {
+    let result = state
+        .protocol_manager
+        .verify_seed(&file_hash)
+        .await
+        .map_err(|e| e.to_string())?;
+    report_seed_verification(&app, &result);
+    Ok(result)
+}
+
+/// Tell the frontend when a seed was stopped for failing verification
+fn report_seed_verification(app: &tauri::AppHandle, result: &protocols::seeding::VerifyResult) {
+    if result.intact {
+        return;
+    }
+    TransferEventBus::new(app.clone()).emit_seed_verification_failed(
+        SeedVerificationFailedEvent {
+            transfer_id: result.file_hash.clone(),
+            file_hash: result.file_hash.clone(),
+            file_path: result.file_path.to_string_lossy().to_string(),
+            actual_hash: result.actual_hash.clone(),
+            error: result
+                .error
+                .clone()
+                .unwrap_or_else(|| "File no longer matches its hash".to_string()),
+            failed_at: current_timestamp_ms(),
+        },
+    );
+}
+
 /// Helper function to create and seed a BitTorrent file.
 /// It takes a local file path and handler, creates a torrent, starts seeding, and returns a magnet link.
 async fn create_and_seed_torrent_internal(
@@ -667,6 +741,7 @@ async fn load_account_from_keystore(
             .set_active_private_key(Some(private_key.clone()))
             .await;
     }
+    share_at_rest_wrapping_key(&state).await;
 
     // Derive account details from private key
     get_account_from_private_key(&private_key)
@@ -988,6 +1063,30 @@ async fn set_bandwidth_limits(
     Ok(())
 }
 
+#[tauri::command]
+fn get_transfer_event_coalescing() -> EventCoalescingConfig {
+    event_coalescing_config()
+}
+
+#[tauri::command]
+fn set_transfer_event_coalescing(config: EventCoalescingConfig) -> Result<(), String> {
+    if !config.max_progress_per_second.is_finite() || config.max_progress_per_second < 0.0 {
+        return Err("maxProgressPerSecond must be a non-negative number".to_string());
+    }
+    set_event_coalescing_config(config);
+    Ok(())
+}
+
+#[tauri::command]
+fn get_certificate_pins() -> Vec<cert_pinning::CertificatePin> {
+    cert_pinning::pins()
+}
+
+#[tauri::command]
+fn set_certificate_pins(pins: Vec<cert_pinning::CertificatePin>) -> Result<(), String> {
+    cert_pinning::set_pins(pins)
+}
+
 #[tauri::command]
 async fn establish_webrtc_connection(
     state: State<'_, AppState>,
@@ -1027,6 +1126,8 @@ async fn send_webrtc_file_request(
                 }
             },
             recipient_public_key: None, // No encryption for basic downloads
+            merkle_root: None,
+            binary_chunk_frames: true,
         };
         webrtc.send_file_request(peer_id, request).await
     } else {
@@ -1047,6 +1148,47 @@ async fn get_webrtc_connection_status(
     }
 }
 
+#[tauri::command]
+async fn run_webrtc_connectivity_test() -> Result<NatTestResult, String> {
+    WebRTCService::run_connectivity_test().await
+}
+
+#[tauri::command]
+async fn get_webrtc_peer_limits() -> Result<PeerLimits, String> {
+    Ok(webrtc_service::peer_limits().await)
+}
+
+#[tauri::command]
+async fn set_webrtc_peer_limits(limits: PeerLimits) -> Result<(), String> {
+    webrtc_service::set_peer_limits(limits).await;
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_ice_servers() -> Result<Vec<ice_health::IceServerConfig>, String> {
+    Ok(ice_health::ice_servers())
+}
+
+#[tauri::command]
+async fn set_ice_servers(
+    servers: Vec<ice_health::IceServerConfig>,
+) -> Result<Vec<ice_health::IceServerStatus>, String> {
+    ice_health::set_ice_servers(servers)?;
+    Ok(ice_health::check_ice_servers().await)
+}
+
+/// Latest health check result for each STUN/TURN URL; `refresh` probes them all first
+#[tauri::command]
+async fn get_ice_server_health(
+    refresh: Option<bool>,
+) -> Result<Vec<ice_health::IceServerStatus>, String> {
+    if refresh.unwrap_or(false) {
+        Ok(ice_health::check_ice_servers().await)
+    } else {
+        Ok(ice_health::ice_server_statuses())
+    }
+}
+
 #[tauri::command]
 async fn disconnect_from_peer(state: State<'_, AppState>, peer_id: String) -> Result<(), String> {
     let webrtc = { state.webrtc.lock().await.as_ref().cloned() };
@@ -1608,6 +1750,18 @@ async fn start_dht_node(
     let cli_proxy = state.socks5_proxy_cli.lock().await.clone();
     // Prioritize the command-line argument. Fall back to the one from the UI.
     let final_proxy_address = cli_proxy.or(proxy_address.clone()).unwrap_or_default();
+    let socks5_proxy = Some(final_proxy_address.clone()).filter(|proxy| !proxy.is_empty());
+    *state.socks5_proxy.lock().await = socks5_proxy.clone();
+    // Services created before the DHT must not keep connecting without the proxy
+    state.ed2k_protocol_handler.set_proxy(socks5_proxy.clone()).await;
+    state.bittorrent_handler.set_required_proxy(socks5_proxy.clone()).await;
+    ice_health::set_proxy_configured(socks5_proxy.is_some());
+    let multi_source_service = state.multi_source_download.lock().await.clone();
+    if let Some(service) = multi_source_service {
+        let mut config = service.config().await;
+        config.socks5_proxy = socks5_proxy;
+        service.set_config(config).await;
+    }
 
     // Get the file transfer service for DHT integration
     let file_transfer_service = {
@@ -3640,12 +3794,32 @@ async fn start_file_transfer_service(
         let multi_source_service = MultiSourceDownloadService::new(
             dht_service,
             webrtc_arc.clone(),
-            state.bittorrent_handler.clone(),
+            Some(state.bittorrent_handler.clone()),
             transfer_event_bus,
             state.analytics.clone(),
             chunk_manager,
-        );
+        )
+        .with_config(MultiSourceConfig {
+            socks5_proxy: state.socks5_proxy.lock().await.clone(),
+            ..MultiSourceConfig::default()
+        })
+        .with_protocol_switches(state.protocol_manager.protocol_switches());
+        multi_source_service
+            .share_bandwidth_limit(&state.bandwidth)
+            .await;
+        // Downloads paused for a metered connection before the last exit wait for it again
+        if let Err(e) = multi_source_service.restore_metered_paused().await {
+            warn!("Failed to restore downloads paused for a metered connection: {}", e);
+        }
+        // Nothing is running yet, so this only restores the mode, and starts the downloads
+        // restored above if the connection is no longer metered
+        multi_source_service
+            .set_metered(load_metered_mode(app.app_handle()).await)
+            .await?;
         let multi_source_arc = Arc::new(multi_source_service);
+        state
+            .protocol_manager
+            .set_multi_source_service(multi_source_arc.clone());
 
         // Update WebRTCService with MultiSourceDownloadService for hash verification
         // Since WebRTCService is already created and may have active connections,
@@ -3673,6 +3847,7 @@ async fn start_file_transfer_service(
             let mut multi_source_guard = state.multi_source_download.lock().await;
             *multi_source_guard = Some(multi_source_arc.clone());
         }
+        share_at_rest_wrapping_key(&state).await;
 
         // Start multi-source download service
         {
@@ -3899,9 +4074,12 @@ async fn upload_file_to_network(
 
                 // Create ED2K protocol handler with an active server URL
                 // ED2K now works in decentralized P2P mode with DHT support
-                let ed2k_handler = protocols::ed2k::Ed2kProtocolHandler::new(
-                    "ed2k://|server|45.82.80.155|5687|/".to_string(),
-                );
+                let ed2k_handler =
+                    protocols::ed2k::Ed2kProtocolHandler::with_config(ed2k_client::Ed2kConfig {
+                        server_url: "ed2k://|server|45.82.80.155|5687|/".to_string(),
+                        proxy: state.socks5_proxy.lock().await.clone(),
+                        ..Default::default()
+                    });
 
                 // Seed the file using the protocol handler
                 let seed_options = protocols::traits::SeedOptions {
@@ -4720,13 +4898,11 @@ async fn test_ftp_connection(
 
     // Connect based on FTPS setting
     if use_ftps {
-        use suppaftp::{NativeTlsConnector, NativeTlsFtpStream};
+        use suppaftp::NativeTlsFtpStream;
 
-        // Create TLS connector
-        let tls_connector = NativeTlsConnector::from(
-            native_tls::TlsConnector::new()
-                .map_err(|e| format!("Failed to create TLS connector: {}", e))?,
-        );
+        // Create TLS connector; it also enforces any certificate pins for the host
+        let tls_connector = cert_pinning::PinnedTlsConnector::new()
+            .map_err(|e| format!("Failed to create TLS connector: {}", e))?;
 
         // Connect to FTPS server
         let mut ftp_stream = NativeTlsFtpStream::connect_secure_implicit(
@@ -4834,13 +5010,11 @@ async fn upload_to_external_ftp(
 
     // Upload based on FTPS setting
     if use_ftps {
-        use suppaftp::{NativeTlsConnector, NativeTlsFtpStream};
+        use suppaftp::NativeTlsFtpStream;
 
-        // Create TLS connector
-        let tls_connector = NativeTlsConnector::from(
-            native_tls::TlsConnector::new()
-                .map_err(|e| format!("Failed to create TLS connector: {}", e))?,
-        );
+        // Create TLS connector; it also enforces any certificate pins for the host
+        let tls_connector = cert_pinning::PinnedTlsConnector::new()
+            .map_err(|e| format!("Failed to create TLS connector: {}", e))?;
 
         // Connect to FTPS server
         let mut ftp_stream = NativeTlsFtpStream::connect_secure_implicit(
@@ -5527,6 +5701,8 @@ async fn download_file_from_network(
                                     file_size: metadata.file_size,
                                     requester_peer_id: dht_service.get_peer_id().await,
                                     recipient_public_key: None,
+                                    merkle_root: None,
+                                    binary_chunk_frames: true,
                                 };
 
                                 match webrtc_service
@@ -5608,6 +5784,8 @@ async fn download_file_from_network(
                                                                         .get_peer_id()
                                                                         .await,
                                                                     recipient_public_key: None,
+                                                                    merkle_root: None,
+                                                                    binary_chunk_frames: true,
                                                                 };
 
                                                             match webrtc_service
@@ -6396,10 +6574,12 @@ async fn pump_multi_source_events(app: tauri::AppHandle, ms: Arc<MultiSourceDown
                     }
                 }
                 MultiSourceEvent::DownloadCompleted {
-                    file_hash: _,
-                    output_path: _,
+                    file_hash,
+                    output_path,
                     duration_secs: _,
                     average_speed_bps: _,
+                    labels: _,
+                    seed_after_download,
                 } => {
                     if let Err(err) = app.emit("multi_source_download_completed", &event) {
                         warn!(
@@ -6407,6 +6587,14 @@ async fn pump_multi_source_events(app: tauri::AppHandle, ms: Arc<MultiSourceDown
                             err
                         );
                     }
+                    if !seed_after_download.is_empty() && !output_path.is_empty() {
+                        tokio::spawn(seed_completed_download(
+                            app.clone(),
+                            file_hash.clone(),
+                            output_path.clone(),
+                            seed_after_download.clone(),
+                        ));
+                    }
                 }
                 _ => {
                     if let Err(err) = app.emit("multi_source_event", &event) {
@@ -6418,6 +6606,46 @@ async fn pump_multi_source_events(app: tauri::AppHandle, ms: Arc<MultiSourceDown
     }
 }
 
+/// Seed a finished multi-source download on the protocols it was started with
+async fn seed_completed_download(
+    app: tauri::AppHandle,
+    file_hash: String,
+    output_path: String,
+    protocols: Vec<String>,
+) {
+    let protocol_manager = app.state::<AppState>().protocol_manager.clone();
+    let seeding = match protocol_manager
+        .seed_file_multi_protocol(
+            PathBuf::from(&output_path),
+            protocols.clone(),
+            protocols::traits::SeedOptions::default(),
+        )
+        .await
+    {
+        Ok(seeding) if !seeding.is_empty() => seeding,
+        Ok(_) => {
+            warn!("None of {:?} could seed {}", protocols, output_path);
+            return;
+        }
+        Err(e) => {
+            warn!("Failed to seed {} after download: {}", output_path, e);
+            return;
+        }
+    };
+
+    info!("Seeding {} on {:?}", output_path, seeding.keys().collect::<Vec<_>>());
+    TransferEventBus::new(app.clone()).emit_seeding_started(SeedingStartedEvent {
+        transfer_id: file_hash.clone(),
+        file_hash,
+        file_path: output_path,
+        identifiers: seeding
+            .into_iter()
+            .map(|(protocol, info)| (protocol, info.identifier))
+            .collect(),
+        started_at: current_timestamp_ms(),
+    });
+}
+
 #[tauri::command]
 async fn start_multi_source_download(
     state: State<'_, AppState>,
@@ -6425,18 +6653,89 @@ async fn start_multi_source_download(
     output_path: String,
     max_peers: Option<usize>,
     chunk_size: Option<usize>,
-) -> Result<String, String> {
+    metadata: Option<FileMetadata>,
+    use_http_sources: Option<bool>,
+    labels: Option<Vec<String>>,
+    seed_after_download: Option<Vec<String>>,
+    encrypt_at_rest: Option<bool>,
+    expected_sha256: Option<String>,
+    min_sources: Option<usize>,
+    min_sources_wait_secs: Option<u64>,
+    priority: Option<TransferPriority>,
+    output_permissions: Option<u32>,
+    preallocate: Option<bool>,
+    source_weights: Option<SourceTypeWeights>,
+    max_download_bps: Option<u64>,
+) -> Result<String, StartDownloadError> {
     let ms = {
         let ms_guard = state.multi_source_download.lock().await;
         ms_guard.as_ref().cloned()
     };
 
+    let at_rest_key = if encrypt_at_rest.unwrap_or(false) {
+        let private_key = state
+            .active_account_private_key
+            .lock()
+            .await
+            .clone()
+            .ok_or_else(|| {
+                "Encrypting chunks at rest needs an unlocked account. Please log in again."
+                    .to_string()
+            })?;
+        let key = keystore::derive_chunk_storage_key(&private_key, &file_hash)?;
+        Some(chunk_store::ChunkEncryptionKey::new(key))
+    } else {
+        None
+    };
+
     if let Some(multi_source_service) = ms {
         multi_source_service
-            .start_download(file_hash.clone(), output_path, max_peers, chunk_size)
+            .start_download_request(DownloadRequest {
+                max_peers,
+                chunk_size,
+                metadata,
+                use_http_sources: use_http_sources.unwrap_or(false),
+                labels: labels.unwrap_or_default(),
+                seed_after_download: seed_after_download.unwrap_or_default(),
+                at_rest_key,
+                expected_sha256,
+                min_sources,
+                min_sources_wait_secs,
+                priority,
+                output_permissions,
+                preallocate,
+                source_weights,
+                max_download_bps,
+                ..DownloadRequest::new(
+                    file_hash.clone(),
+                    DownloadTarget::File(PathBuf::from(output_path)),
+                )
+            })
             .await?;
 
         Ok(format!("Multi-source download started for: {}", file_hash))
+    } else {
+        Err("Multi-source download service not available".to_string().into())
+    }
+}
+
+#[tauri::command]
+async fn start_multi_source_magnet_download(
+    state: State<'_, AppState>,
+    magnet: String,
+    output_path: String,
+    max_peers: Option<usize>,
+    chunk_size: Option<usize>,
+) -> Result<String, String> {
+    let ms = {
+        let ms_guard = state.multi_source_download.lock().await;
+        ms_guard.as_ref().cloned()
+    };
+
+    if let Some(multi_source_service) = ms {
+        multi_source_service
+            .start_magnet_download(&magnet, output_path, max_peers, chunk_size)
+            .await
     } else {
         Err("Multi-source download service not available".to_string())
     }
@@ -6446,6 +6745,8 @@ async fn start_multi_source_download(
 async fn cancel_multi_source_download(
     state: State<'_, AppState>,
     file_hash: String,
+    purge: Option<bool>,
+    reason: Option<CancelReason>,
 ) -> Result<(), String> {
     let ms = {
         let ms_guard = state.multi_source_download.lock().await;
@@ -6453,7 +6754,169 @@ async fn cancel_multi_source_download(
     };
 
     if let Some(multi_source_service) = ms {
-        multi_source_service.cancel_download(file_hash).await
+        let reason = reason.unwrap_or_default();
+        if purge.unwrap_or(false) {
+            multi_source_service.cancel_and_purge(file_hash, reason).await
+        } else {
+            multi_source_service.cancel_download(file_hash, reason).await
+        }
+    } else {
+        Err("Multi-source download service not available".to_string())
+    }
+}
+
+/// Where the metered-connection mode is kept between runs
+fn metered_mode_path(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    Ok(app
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to get app data dir: {}", e))?
+        .join("metered_mode.json"))
+}
+
+/// The saved metered-connection mode; off when none was saved or it can't be read
+async fn load_metered_mode(app: &tauri::AppHandle) -> MeteredMode {
+    let Ok(path) = metered_mode_path(app) else {
+        return MeteredMode::default();
+    };
+    match tokio::fs::read(&path).await {
+        Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|e| {
+            warn!("Ignoring unreadable {}: {}", path.display(), e);
+            MeteredMode::default()
+        }),
+        Err(_) => MeteredMode::default(),
+    }
+}
+
+/// Turn metered-connection mode on or off and save it for the next run. While on, downloads
+/// larger than `small_download_bytes` are paused or held in the queue.
+#[tauri::command]
+async fn set_metered_connection(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    metered: bool,
+    small_download_bytes: Option<u64>,
+) -> Result<usize, String> {
+    let mode = MeteredMode {
+        enabled: metered,
+        small_download_bytes: small_download_bytes.unwrap_or(0),
+    };
+    let path = metered_mode_path(&app)?;
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    let contents = serde_json::to_vec_pretty(&mode).map_err(|e| e.to_string())?;
+    tokio::fs::write(&path, contents)
+        .await
+        .map_err(|e| format!("Failed to save metered mode: {}", e))?;
+
+    let ms = {
+        let ms_guard = state.multi_source_download.lock().await;
+        ms_guard.as_ref().cloned()
+    };
+
+    // Without the service the saved mode is applied when it starts
+    match ms {
+        Some(multi_source_service) => multi_source_service.set_metered(mode).await,
+        None => Ok(0),
+    }
+}
+
+#[tauri::command]
+async fn get_metered_connection(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<MeteredMode, String> {
+    let ms = {
+        let ms_guard = state.multi_source_download.lock().await;
+        ms_guard.as_ref().cloned()
+    };
+
+    match ms {
+        Some(multi_source_service) => Ok(multi_source_service.metered_mode().await),
+        None => Ok(load_metered_mode(&app).await),
+    }
+}
+
+#[tauri::command]
+async fn export_download_manifest(
+    state: State<'_, AppState>,
+    file_hash: String,
+) -> Result<String, String> {
+    let ms = {
+        let ms_guard = state.multi_source_download.lock().await;
+        ms_guard.as_ref().cloned()
+    };
+
+    if let Some(multi_source_service) = ms {
+        multi_source_service.export_download_manifest(&file_hash).await
+    } else {
+        Err("Multi-source download service not available".to_string())
+    }
+}
+
+#[tauri::command]
+async fn import_download_manifest(
+    state: State<'_, AppState>,
+    manifest: String,
+    output_path: String,
+) -> Result<String, String> {
+    let ms = {
+        let ms_guard = state.multi_source_download.lock().await;
+        ms_guard.as_ref().cloned()
+    };
+
+    if let Some(multi_source_service) = ms {
+        multi_source_service
+            .import_download_manifest(&manifest, DownloadTarget::File(PathBuf::from(output_path)))
+            .await
+    } else {
+        Err("Multi-source download service not available".to_string())
+    }
+}
+
+#[tauri::command]
+async fn pause_all_multi_source_downloads(state: State<'_, AppState>) -> Result<usize, String> {
+    let ms = {
+        let ms_guard = state.multi_source_download.lock().await;
+        ms_guard.as_ref().cloned()
+    };
+
+    if let Some(multi_source_service) = ms {
+        multi_source_service.pause_all().await
+    } else {
+        Err("Multi-source download service not available".to_string())
+    }
+}
+
+#[tauri::command]
+async fn resume_all_multi_source_downloads(state: State<'_, AppState>) -> Result<usize, String> {
+    let ms = {
+        let ms_guard = state.multi_source_download.lock().await;
+        ms_guard.as_ref().cloned()
+    };
+
+    if let Some(multi_source_service) = ms {
+        multi_source_service.resume_all().await
+    } else {
+        Err("Multi-source download service not available".to_string())
+    }
+}
+
+#[tauri::command]
+async fn cancel_all_multi_source_downloads(
+    state: State<'_, AppState>,
+    purge: Option<bool>,
+) -> Result<usize, String> {
+    let ms = {
+        let ms_guard = state.multi_source_download.lock().await;
+        ms_guard.as_ref().cloned()
+    };
+
+    if let Some(multi_source_service) = ms {
+        multi_source_service.cancel_all(purge.unwrap_or(false)).await
     } else {
         Err("Multi-source download service not available".to_string())
     }
@@ -6476,6 +6939,290 @@ async fn get_multi_source_progress(
     }
 }
 
+#[tauri::command]
+async fn get_multi_source_source_stats(
+    state: State<'_, AppState>,
+    file_hash: String,
+) -> Result<Vec<SourceSummary>, String> {
+    let ms = {
+        let ms_guard = state.multi_source_download.lock().await;
+        ms_guard.as_ref().cloned()
+    };
+
+    if let Some(multi_source_service) = ms {
+        Ok(multi_source_service.get_source_stats(&file_hash).await)
+    } else {
+        Err("Multi-source download service not available".to_string())
+    }
+}
+
+#[tauri::command]
+async fn get_swarm_health(
+    state: State<'_, AppState>,
+    file_hash: String,
+) -> Result<SwarmHealth, String> {
+    let ms = {
+        let ms_guard = state.multi_source_download.lock().await;
+        ms_guard.as_ref().cloned()
+    };
+
+    if let Some(multi_source_service) = ms {
+        multi_source_service.swarm_health(&file_hash).await
+    } else {
+        Err("Multi-source download service not available".to_string())
+    }
+}
+
+#[tauri::command]
+async fn get_source_error_log(
+    state: State<'_, AppState>,
+    file_hash: String,
+    source_id: String,
+) -> Result<Vec<SourceErrorEntry>, String> {
+    let ms = {
+        let ms_guard = state.multi_source_download.lock().await;
+        ms_guard.as_ref().cloned()
+    };
+
+    if let Some(multi_source_service) = ms {
+        multi_source_service
+            .get_source_error_log(&file_hash, &source_id)
+            .await
+    } else {
+        Err("Multi-source download service not available".to_string())
+    }
+}
+
+#[tauri::command]
+async fn query_transfer_history(
+    filter: Option<transfer_history::HistoryFilter>,
+) -> Result<transfer_history::HistoryPage, String> {
+    transfer_history::query_history(filter.unwrap_or_default()).await
+}
+
+#[tauri::command]
+async fn clear_transfer_history() -> Result<(), String> {
+    transfer_history::clear_history().await
+}
+
+#[tauri::command]
+async fn resume_multi_source_download_to(
+    state: State<'_, AppState>,
+    file_hash: String,
+    output_path: String,
+) -> Result<(), String> {
+    let ms = {
+        let ms_guard = state.multi_source_download.lock().await;
+        ms_guard.as_ref().cloned()
+    };
+
+    if let Some(multi_source_service) = ms {
+        multi_source_service
+            .resume_download_to(&file_hash, output_path)
+            .await
+    } else {
+        Err("Multi-source download service not available".to_string())
+    }
+}
+
+#[tauri::command]
+async fn get_transfer_system_status(state: State<'_, AppState>) -> Result<SystemStatus, String> {
+    let ms = {
+        let ms_guard = state.multi_source_download.lock().await;
+        ms_guard.as_ref().cloned()
+    };
+
+    if let Some(multi_source_service) = ms {
+        Ok(multi_source_service.system_status().await)
+    } else {
+        Err("Multi-source download service not available".to_string())
+    }
+}
+
+#[tauri::command]
+async fn list_multi_source_downloads_by_label(
+    state: State<'_, AppState>,
+    label: String,
+) -> Result<Vec<MultiSourceProgress>, String> {
+    let ms = {
+        let ms_guard = state.multi_source_download.lock().await;
+        ms_guard.as_ref().cloned()
+    };
+
+    if let Some(multi_source_service) = ms {
+        Ok(multi_source_service.list_downloads_by_label(&label).await)
+    } else {
+        Err("Multi-source download service not available".to_string())
+    }
+}
+
+#[tauri::command]
+async fn import_local_file(
+    state: State<'_, AppState>,
+    file_path: String,
+    file_hash: String,
+) -> Result<ImportResult, String> {
+    let ms = {
+        let ms_guard = state.multi_source_download.lock().await;
+        ms_guard.as_ref().cloned()
+    };
+
+    if let Some(multi_source_service) = ms {
+        multi_source_service
+            .import_local_file(&file_path, &file_hash)
+            .await
+    } else {
+        Err("Multi-source download service not available".to_string())
+    }
+}
+
+#[tauri::command]
+async fn add_multi_source_download_source(
+    state: State<'_, AppState>,
+    file_hash: String,
+    source: download_source::DownloadSource,
+) -> Result<(), String> {
+    let ms = {
+        let ms_guard = state.multi_source_download.lock().await;
+        ms_guard.as_ref().cloned()
+    };
+
+    if let Some(multi_source_service) = ms {
+        multi_source_service.add_source(&file_hash, source).await
+    } else {
+        Err("Multi-source download service not available".to_string())
+    }
+}
+
+#[tauri::command]
+async fn list_chunk_directories(
+    state: State<'_, AppState>,
+) -> Result<Vec<ChunkDirectoryInfo>, String> {
+    let ms = {
+        let ms_guard = state.multi_source_download.lock().await;
+        ms_guard.as_ref().cloned()
+    };
+
+    if let Some(multi_source_service) = ms {
+        multi_source_service.list_chunk_directories().await
+    } else {
+        Err("Multi-source download service not available".to_string())
+    }
+}
+
+#[tauri::command]
+async fn prune_chunk_directory(
+    state: State<'_, AppState>,
+    file_hash: String,
+) -> Result<u64, String> {
+    let ms = {
+        let ms_guard = state.multi_source_download.lock().await;
+        ms_guard.as_ref().cloned()
+    };
+
+    if let Some(multi_source_service) = ms {
+        multi_source_service.prune_chunk_directory(&file_hash).await
+    } else {
+        Err("Multi-source download service not available".to_string())
+    }
+}
+
+#[tauri::command]
+async fn check_download_source(
+    state: State<'_, AppState>,
+    file_hash: String,
+    source: download_source::DownloadSource,
+) -> Result<bool, String> {
+    let ms = {
+        let ms_guard = state.multi_source_download.lock().await;
+        ms_guard.as_ref().cloned()
+    };
+
+    if let Some(multi_source_service) = ms {
+        multi_source_service.source_has_file(&source, &file_hash).await
+    } else {
+        Err("Multi-source download service not available".to_string())
+    }
+}
+
+#[tauri::command]
+async fn repair_file(
+    state: State<'_, AppState>,
+    file_hash: String,
+    existing_file_path: String,
+) -> Result<RepairResult, String> {
+    let ms = {
+        let ms_guard = state.multi_source_download.lock().await;
+        ms_guard.as_ref().cloned()
+    };
+
+    if let Some(multi_source_service) = ms {
+        multi_source_service
+            .repair_file(&file_hash, &existing_file_path)
+            .await
+    } else {
+        Err("Multi-source download service not available".to_string())
+    }
+}
+
+#[tauri::command]
+async fn set_multi_source_download_speed_limit(
+    state: State<'_, AppState>,
+    file_hash: String,
+    max_download_bps: u64,
+) -> Result<(), String> {
+    let ms = {
+        let ms_guard = state.multi_source_download.lock().await;
+        ms_guard.as_ref().cloned()
+    };
+
+    if let Some(multi_source_service) = ms {
+        multi_source_service
+            .set_download_speed_limit(&file_hash, max_download_bps)
+            .await
+    } else {
+        Err("Multi-source download service not available".to_string())
+    }
+}
+
+#[tauri::command]
+async fn set_multi_source_download_priority(
+    state: State<'_, AppState>,
+    file_hash: String,
+    priority: TransferPriority,
+) -> Result<(), String> {
+    let ms = {
+        let ms_guard = state.multi_source_download.lock().await;
+        ms_guard.as_ref().cloned()
+    };
+
+    if let Some(multi_source_service) = ms {
+        multi_source_service
+            .set_download_priority(&file_hash, priority)
+            .await
+    } else {
+        Err("Multi-source download service not available".to_string())
+    }
+}
+
+#[tauri::command]
+async fn set_multi_source_download_max_peers(
+    state: State<'_, AppState>,
+    file_hash: String,
+    max_peers: usize,
+) -> Result<(), String> {
+    let ms = {
+        let ms_guard = state.multi_source_download.lock().await;
+        ms_guard.as_ref().cloned()
+    };
+
+    if let Some(multi_source_service) = ms {
+        multi_source_service.set_max_peers(&file_hash, max_peers).await
+    } else {
+        Err("Multi-source download service not available".to_string())
+    }
+}
+
 #[tauri::command]
 async fn update_proxy_latency(
     state: State<'_, AppState>,
@@ -7258,9 +8005,31 @@ async fn logout(state: State<'_, AppState>) -> Result<(), ()> {
         webrtc_service.set_active_private_key(None).await;
     }
 
+    // Encrypted downloads can't be saved or resumed until an account is unlocked again
+    drop(active_key);
+    share_at_rest_wrapping_key(&state).await;
+
     Ok(())
 }
 
+/// Give the download service the key, derived from the unlocked account, that seals the
+/// at-rest keys of encrypted downloads in their saved state
+async fn share_at_rest_wrapping_key(state: &AppState) {
+    let private_key = state.active_account_private_key.lock().await.clone();
+    let key = match private_key.map(|key| keystore::derive_at_rest_wrapping_key(&key)) {
+        Some(Ok(key)) => Some(chunk_store::ChunkEncryptionKey::new(key)),
+        Some(Err(e)) => {
+            warn!("Failed to derive the at-rest wrapping key: {}", e);
+            None
+        }
+        None => None,
+    };
+    let multi_source_service = state.multi_source_download.lock().await.clone();
+    if let Some(service) = multi_source_service {
+        service.set_at_rest_wrapping_key(key).await;
+    }
+}
+
 async fn get_active_account(state: &State<'_, AppState>) -> Result<String, String> {
     state
         .active_account
@@ -8000,6 +8769,14 @@ async fn shutdown_application(app_handle: tauri::AppHandle) {
         }
         let _ = app_handle.emit("proxy_reset", ());
 
+        // Record running downloads as stopped by the shutdown; their chunks are kept
+        let multi_source = state.multi_source_download.lock().await.clone();
+        if let Some(multi_source) = multi_source {
+            if timeout(Duration::from_secs(5), multi_source.cleanup()).await.is_err() {
+                tracing::warn!("Timed out stopping multi-source downloads");
+            }
+        }
+
         {
             *state.webrtc.lock().await = None;
             *state.file_transfer.lock().await = None;
@@ -8524,6 +9301,7 @@ async fn download_ftp(url: String, app_handle: tauri::AppHandle) -> Result<(), S
                         download_speed_bps: speed,
                         upload_speed_bps: 0.0,
                         eta_seconds: eta,
+                        chunks_completed_delta: 0,
                         active_sources: 1,
                         timestamp: current_timestamp_ms(),
                     };
@@ -8979,6 +9757,8 @@ fn main() {
     // Parse command line arguments
     use clap::Parser;
     let args = headless::CliArgs::parse();
+    // Set before any WebRTC service starts probing ICE servers
+    ice_health::set_proxy_configured(args.socks5_proxy.is_some());
 
     // Handle --download-geth flag
     if args.download_geth {
@@ -9152,6 +9932,15 @@ fn main() {
 
     // Store DHT service and related data for later use in setup()
     let dht_service_for_bt = dht_service_arc.clone();
+    let bt_socks5_proxy = args.socks5_proxy.clone();
+    let ed2k_protocol_handler_arc = Arc::new(
+        protocols::ed2k::Ed2kProtocolHandler::with_config(ed2k_client::Ed2kConfig {
+            server_url: "ed2k://|server|45.82.80.155|5687|/".to_string(),
+            proxy: args.socks5_proxy.clone(),
+            ..Default::default()
+        }),
+    );
+    let ed2k_handler = ed2k_protocol_handler_arc.clone();
 
     let (bittorrent_handler_arc, ftp_server_arc, protocol_manager_arc, ftp_event_bus_holder) =
         runtime.block_on(async move {
@@ -9184,6 +9973,7 @@ fn main() {
                 download_dir.clone(),
                 dht_service_for_bt,
                 port_range,
+                bt_socks5_proxy,
             )
             .await;
             let bittorrent_handler_arc = Arc::new(bittorrent_handler);
@@ -9196,7 +9986,34 @@ fn main() {
                 2121, // FTP port
             ));
 
+            cert_pinning::set_pins_path(
+                directories::ProjectDirs::from("com", "chiral-network", "chiral-network")
+                    .map(|dirs| dirs.data_dir().join("certificate_pins.json"))
+                    .unwrap_or_else(|| {
+                        std::env::current_dir().unwrap().join("certificate_pins.json")
+                    }),
+            );
+
             let mut manager = ProtocolManager::new();
+            manager.set_protocol_states_path(
+                directories::ProjectDirs::from("com", "chiral-network", "chiral-network")
+                    .map(|dirs| dirs.data_dir().join("protocol_states.json"))
+                    .unwrap_or_else(|| {
+                        std::env::current_dir().unwrap().join("protocol_states.json")
+                    }),
+            );
+            let seeding_stats_path =
+                directories::ProjectDirs::from("com", "chiral-network", "chiral-network")
+                    .map(|dirs| Ok(dirs.data_dir().join("seeding_stats.json")))
+                    .unwrap_or_else(|| {
+                        std::env::current_dir().map(|dir| dir.join("seeding_stats.json"))
+                    });
+            match seeding_stats_path {
+                Ok(path) => manager.set_seeding_registry(
+                    protocols::seeding::SeedingRegistry::with_stats_path(path),
+                ),
+                Err(e) => warn!("Upload statistics won't be saved: no data directory ({})", e),
+            }
 
             // Wrap the simple handler in the enhanced protocol handler
             let bittorrent_protocol_handler =
@@ -9204,10 +10021,7 @@ fn main() {
             manager.register(Arc::new(bittorrent_protocol_handler));
 
             // Register ED2K and FTP handlers
-            let ed2k_handler = protocols::ed2k::Ed2kProtocolHandler::new(
-                "ed2k://|server|45.82.80.155|5687|/".to_string(),
-            );
-            manager.register(Arc::new(ed2k_handler));
+            manager.register(ed2k_handler);
 
             let (ftp_handler, ftp_event_bus_holder) =
                 protocols::ftp::FtpProtocolHandler::with_ftp_server(ftp_server.clone());
@@ -9221,6 +10035,10 @@ fn main() {
             )
         });
 
+    runtime.block_on(webrtc_service::set_seeding_registry(
+        protocol_manager_arc.seeding_registry(),
+    ));
+
     // Reputation system Tauri commands
     #[tauri::command]
     async fn publish_reputation_verdict(
@@ -9310,6 +10128,7 @@ fn main() {
             file_transfer_pump: Mutex::new(None),
             multi_source_pump: Mutex::new(None),
             socks5_proxy_cli: Mutex::new(args.socks5_proxy),
+            socks5_proxy: Mutex::new(None),
             analytics: Arc::new(analytics::AnalyticsService::new()),
             bandwidth: Arc::new(BandwidthController::new()),
             payment_checkpoint: Arc::new(PaymentCheckpointService::new()),
@@ -9358,6 +10177,7 @@ fn main() {
 
             // Protocol Manager with BitTorrent support
             protocol_manager: protocol_manager_arc,
+            ed2k_protocol_handler: ed2k_protocol_handler_arc,
 
             // AutoRelay timeline persistence across DHT restarts
             autorelay_last_enabled: Arc::new(Mutex::new(None)),
@@ -9501,8 +10321,33 @@ fn main() {
             start_ftp_download,
             download_blocks_from_network,
             start_multi_source_download,
+            start_multi_source_magnet_download,
+            export_download_manifest,
+            import_download_manifest,
             cancel_multi_source_download,
+            pause_all_multi_source_downloads,
+            resume_all_multi_source_downloads,
+            set_metered_connection,
+            get_metered_connection,
+            cancel_all_multi_source_downloads,
             get_multi_source_progress,
+            get_multi_source_source_stats,
+            get_swarm_health,
+            get_source_error_log,
+            query_transfer_history,
+            clear_transfer_history,
+            resume_multi_source_download_to,
+            get_transfer_system_status,
+            list_multi_source_downloads_by_label,
+            import_local_file,
+            repair_file,
+            add_multi_source_download_source,
+            check_download_source,
+            list_chunk_directories,
+            prune_chunk_directory,
+            set_multi_source_download_priority,
+            set_multi_source_download_speed_limit,
+            set_multi_source_download_max_peers,
             update_proxy_latency,
             get_proxy_optimization_status,
             download_file_multi_source,
@@ -9546,9 +10391,22 @@ fn main() {
             cleanup_inactive_peers,
             test_backend_connection,
             set_bandwidth_limits,
+            get_transfer_event_coalescing,
+            set_transfer_event_coalescing,
+            get_certificate_pins,
+            get_protocol_states,
+            verify_seed,
+            set_protocol_enabled,
+            set_certificate_pins,
             establish_webrtc_connection,
             send_webrtc_file_request,
             get_webrtc_connection_status,
+            run_webrtc_connectivity_test,
+            get_webrtc_peer_limits,
+            set_webrtc_peer_limits,
+            get_ice_servers,
+            set_ice_servers,
+            get_ice_server_health,
             disconnect_from_peer,
             create_temp_file_for_streaming,
             append_chunk_to_temp_file,
@@ -9672,6 +10530,11 @@ fn main() {
                 app.handle().clone(),
             );
 
+            // Record finished transfers to disk as their events go by
+            if let Ok(app_data_dir) = app.path().app_data_dir() {
+                transfer_history::init(app_data_dir.join(transfer_history::HISTORY_FILE_NAME));
+            }
+
             // Load settings from disk
             // We only need log-related settings during setup; parse them from settings.json
             // without depending on a local `load_settings_from_file` helper.
@@ -9903,6 +10766,28 @@ fn main() {
             // NOTE: You must add `start_proof_of_storage_watcher` to the invoke_handler call in the
             // real code where you register other commands. For brevity the snippet above shows where to add it.
 
+            // Re-check long-lived seeds against their hashes, e.g. after disk trouble
+            {
+                let app_handle = app.handle().clone();
+
+                tauri::async_runtime::spawn(async move {
+                    let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+                    loop {
+                        interval.tick().await;
+                        let Some(state) = app_handle.try_state::<AppState>() else {
+                            continue;
+                        };
+                        let protocol_manager = state.protocol_manager.clone();
+                        let results = protocol_manager
+                            .verify_stale_seeds(protocols::seeding::SEED_VERIFY_INTERVAL)
+                            .await;
+                        for result in &results {
+                            report_seed_verification(&app_handle, result);
+                        }
+                    }
+                });
+            }
+
             // Auto-start HTTP server
             // Spawn directly in setup() - no need to wait for window events
             {
@@ -10153,6 +11038,7 @@ fn main() {
                                             state.active_account_private_key.lock().await;
                                         *active_key = Some(account.private_key.clone());
                                     }
+                                    share_at_rest_wrapping_key(&state).await;
                                     tracing::info!("E2E: imported account from CHIRAL_PRIVATE_KEY");
                                 }
                                 Err(e) => {
@@ -10234,12 +11120,14 @@ async fn create_bt_handler_with_fallback(
     download_dir: PathBuf,
     dht_service: Arc<DhtService>,
     port_range: Range<u16>,
+    socks5_proxy: Option<String>,
 ) -> bittorrent_handler::BitTorrentHandler {
     // Try the requested range first
-    if let Ok(h) = bittorrent_handler::BitTorrentHandler::new_with_port_range(
+    if let Ok(h) = bittorrent_handler::BitTorrentHandler::new_with_port_range_and_proxy(
         download_dir.clone(),
         dht_service.clone(),
         Some(port_range.clone()),
+        socks5_proxy.clone(),
     )
     .await
     {
@@ -10258,10 +11146,11 @@ async fn create_bt_handler_with_fallback(
         let start = rng.gen_range(30000..60000);
         let fallback = start..(start + 10);
 
-        match bittorrent_handler::BitTorrentHandler::new_with_port_range(
+        match bittorrent_handler::BitTorrentHandler::new_with_port_range_and_proxy(
             download_dir.clone(),
             dht_service.clone(),
             Some(fallback.clone()),
+            socks5_proxy.clone(),
         )
         .await
         {
@@ -17,6 +17,8 @@ use std::collections::HashMap;
 
 // Simple thread-safe LRU cache implementation
 const L1_CACHE_CAPACITY: usize = 128;
+// Number of locks chunk writes are spread over
+const CHUNK_WRITE_LOCK_SHARDS: usize = 64;
 
 struct LruCache {
     map: HashMap<String, Vec<u8>>,
@@ -63,6 +65,18 @@ impl LruCache {
 
 lazy_static! {
     static ref L1_CACHE: Mutex<LruCache> = Mutex::new(LruCache::new(L1_CACHE_CAPACITY));
+    // Saves of the same chunk always take the same shard, so they serialize
+    static ref CHUNK_WRITE_LOCKS: Vec<Mutex<()>> =
+        (0..CHUNK_WRITE_LOCK_SHARDS).map(|_| Mutex::new(())).collect();
+}
+
+/// Lock guarding writes to `chunk_path`, shared by every ChunkManager
+fn chunk_write_lock(chunk_path: &Path) -> &'static Mutex<()> {
+    use std::hash::{Hash, Hasher as _};
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    chunk_path.hash(&mut hasher);
+    &CHUNK_WRITE_LOCKS[hasher.finish() as usize % CHUNK_WRITE_LOCK_SHARDS]
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
@@ -105,6 +119,14 @@ pub struct ChunkManager {
     storage_path: PathBuf,
 }
 
+/// How much the chunk directory holds
+#[derive(serde::Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ChunkStorageStats {
+    pub chunk_count: usize,
+    pub total_bytes: u64,
+}
+
 /// The result of a canonical, one-time encryption of a file.
 pub struct CanonicalEncryptionResult {
     pub manifest: FileManifest,
@@ -218,9 +240,16 @@ impl ChunkManager {
     }
 
     // This function now saves the combined [nonce][ciphertext] blob
+    /// Store a chunk under its content hash.
+    ///
+    /// Concurrent saves of the same hash serialize on a shared lock; whichever runs second
+    /// finds the chunk already written and leaves it alone.
     pub fn save_chunk(&self, hash: &str, data_with_nonce: &[u8]) -> Result<(), Error> {
         fs::create_dir_all(&self.storage_path)?;
         let chunk_path = self.storage_path.join(hash);
+        let _write_guard = chunk_write_lock(&chunk_path)
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
         // --- Deduplication: Only write if the chunk does not already exist ---
         if chunk_path.exists() {
             // Already present, skip writing
@@ -230,7 +259,11 @@ impl ChunkManager {
             }
             return Ok(());
         }
-        fs::write(&chunk_path, data_with_nonce)?;
+        // Write beside the chunk and rename it into place, so the chunk path only ever
+        // holds a complete chunk
+        let tmp_path = chunk_path.with_extension("part");
+        fs::write(&tmp_path, data_with_nonce)?;
+        fs::rename(&tmp_path, &chunk_path)?;
         // Prime the L1 cache
         {
             if let Ok(mut cache) = L1_CACHE.lock() {
@@ -240,6 +273,26 @@ impl ChunkManager {
         Ok(())
     }
 
+    /// Count the stored chunks and their size on disk; half-written `.part` files are skipped
+    pub fn storage_stats(&self) -> Result<ChunkStorageStats, Error> {
+        let mut stats = ChunkStorageStats::default();
+        let entries = match fs::read_dir(&self.storage_path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(stats),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() || entry.path().extension().is_some_and(|ext| ext == "part") {
+                continue;
+            }
+            stats.chunk_count += 1;
+            stats.total_bytes += metadata.len();
+        }
+        Ok(stats)
+    }
+
     pub fn read_chunk(&self, hash: &str) -> Result<Vec<u8>, Error> {
         // Check L1 cache first
         {
@@ -465,6 +518,93 @@ pub fn verify_chunk_with_proof(
     ))
 }
 
+/// Inclusion proof for a single chunk in a file's Merkle tree.
+/// Together with the trusted root, this is enough to verify the chunk without the full hash list.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ChunkMerkleProof {
+    /// Sibling hashes on the path from the chunk's leaf to the root, hex encoded.
+    pub proof_hashes: Vec<String>,
+    /// Number of chunks (leaves) in the tree.
+    pub total_leaves: usize,
+}
+
+/// A Merkle tree over a file's chunks, kept by a seeder to hand out per-chunk proofs.
+pub struct ChunkMerkleTree {
+    tree: MerkleTree<Sha256Hasher>,
+    total_leaves: usize,
+}
+
+impl ChunkMerkleTree {
+    /// Builds the tree from the original (unencrypted) chunk data, in chunk order.
+    pub fn from_chunks<'a>(chunks: impl IntoIterator<Item = &'a [u8]>) -> Self {
+        Self::from_leaf_hashes(chunks.into_iter().map(Sha256Hasher::hash).collect())
+    }
+
+    /// Builds the tree from the SHA-256 of each chunk, in chunk order, for callers that
+    /// hash the chunks as they stream them rather than holding the whole file.
+    pub fn from_leaf_hashes(leaves: Vec<[u8; 32]>) -> Self {
+        ChunkMerkleTree {
+            tree: MerkleTree::<Sha256Hasher>::from_leaves(&leaves),
+            total_leaves: leaves.len(),
+        }
+    }
+
+    /// The hex-encoded root, or `None` for a file with no chunks.
+    pub fn root_hex(&self) -> Option<String> {
+        self.tree.root().map(hex::encode)
+    }
+
+    /// The inclusion proof for `chunk_index`, or `None` if the index is out of range.
+    pub fn proof(&self, chunk_index: usize) -> Option<ChunkMerkleProof> {
+        if chunk_index >= self.total_leaves {
+            return None;
+        }
+        let proof = self.tree.proof(&[chunk_index]);
+        Some(ChunkMerkleProof {
+            proof_hashes: proof.proof_hashes().iter().map(hex::encode).collect(),
+            total_leaves: self.total_leaves,
+        })
+    }
+}
+
+/// Verifies a chunk against the file's Merkle root using only its index and inclusion proof.
+/// Unlike `verify_chunk_with_proof`, no expected per-chunk hash is needed.
+pub fn verify_chunk_merkle(
+    chunk_index: usize,
+    data: &[u8],
+    proof: &ChunkMerkleProof,
+    merkle_root_hex: &str,
+) -> Result<bool, String> {
+    if chunk_index >= proof.total_leaves {
+        return Ok(false);
+    }
+
+    let merkle_root: [u8; 32] = hex::decode(merkle_root_hex)
+        .map_err(|e| e.to_string())?
+        .try_into()
+        .map_err(|_| "Invalid Merkle root length".to_string())?;
+
+    let proof_hashes: Vec<[u8; 32]> = proof
+        .proof_hashes
+        .iter()
+        .map(|h| {
+            hex::decode(h)
+                .map_err(|e| e.to_string())?
+                .try_into()
+                .map_err(|_| "Invalid proof hash length".to_string())
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let leaf = Sha256Hasher::hash(data);
+    Ok(rs_merkle::MerkleProof::<Sha256Hasher>::new(proof_hashes).verify(
+        merkle_root,
+        &[chunk_index],
+        &[leaf],
+        proof.total_leaves,
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -510,6 +650,61 @@ mod tests {
         // 5. Cleanup is handled by tempdir dropping
     }
 
+    #[test]
+    fn test_concurrent_saves_of_one_chunk_leave_one_intact_file() {
+        let dir = tempdir().unwrap();
+        let manager = ChunkManager::new(dir.path().to_path_buf());
+        let data: Vec<u8> = (0..1_000_000u32).map(|i| (i % 251) as u8).collect();
+        let hash = hex::encode(sha2::Sha256::digest(&data));
+
+        std::thread::scope(|scope| {
+            for _ in 0..16 {
+                scope.spawn(|| manager.save_chunk(&hash, &data).unwrap());
+            }
+        });
+
+        assert_eq!(fs::read(dir.path().join(&hash)).unwrap(), data);
+        let entries: Vec<_> = fs::read_dir(dir.path()).unwrap().collect();
+        assert_eq!(entries.len(), 1);
+    }
+
+    #[test]
+    fn test_storage_stats_skip_partial_chunks() {
+        let dir = tempdir().unwrap();
+        let manager = ChunkManager::new(dir.path().join("chunks"));
+        assert_eq!(manager.storage_stats().unwrap(), ChunkStorageStats::default());
+
+        manager.save_chunk("aa", &[1; 10]).unwrap();
+        manager.save_chunk("bb", &[2; 30]).unwrap();
+        fs::write(dir.path().join("chunks").join("cc.part"), [3; 50]).unwrap();
+
+        let stats = manager.storage_stats().unwrap();
+        assert_eq!(stats.chunk_count, 2);
+        assert_eq!(stats.total_bytes, 40);
+    }
+
+    #[test]
+    fn test_verify_chunk_merkle() {
+        let chunks: Vec<Vec<u8>> = (0..5u8).map(|i| vec![i; 100]).collect();
+        let tree = ChunkMerkleTree::from_chunks(chunks.iter().map(|c| c.as_slice()));
+        let root = tree.root_hex().expect("tree has a root");
+
+        for (index, chunk) in chunks.iter().enumerate() {
+            let proof = tree.proof(index).expect("index in range");
+            assert_eq!(proof.total_leaves, 5);
+            assert!(verify_chunk_merkle(index, chunk, &proof, &root).unwrap());
+        }
+
+        // Tampered data, a proof used at the wrong index, and a foreign root are all rejected
+        let proof = tree.proof(2).unwrap();
+        assert!(!verify_chunk_merkle(2, b"tampered", &proof, &root).unwrap());
+        assert!(!verify_chunk_merkle(3, &chunks[2], &proof, &root).unwrap());
+        assert!(!verify_chunk_merkle(2, &chunks[2], &proof, &hex::encode([0u8; 32])).unwrap());
+        assert!(!verify_chunk_merkle(7, &chunks[2], &proof, &root).unwrap());
+        assert!(verify_chunk_merkle(2, &chunks[2], &proof, "not-hex").is_err());
+        assert!(tree.proof(5).is_none());
+    }
+
     #[test]
     fn test_merkle_tree_proof_and_verification() {
         // 1. Create some mock chunk data and their hashes (leaves)
@@ -6,7 +6,7 @@
 use super::options::{
     DetectionPreferences, FileTransferOptions, TransferProgress, TransferResult, TransferStatus,
 };
-use super::traits::{DownloadOptions, ProtocolError, SeedOptions};
+use super::traits::{DownloadOptions, OnConflict, ProtocolError, SeedOptions};
 use super::ProtocolManager;
 use std::collections::HashMap;
 use std::path::PathBuf;
@@ -496,15 +496,28 @@ impl ProtocolManager {
             .ok_or_else(|| {
                 ProtocolError::InvalidIdentifier(format!("Protocol not found: {}", protocol))
             })?;
+        if !self.is_protocol_enabled(protocol) {
+            return Err(ProtocolError::InvalidIdentifier(format!(
+                "Protocol {} is disabled",
+                protocol
+            )));
+        }
 
         // Convert FileTransferOptions to DownloadOptions
-        let download_opts = DownloadOptions {
+        let mut download_opts = DownloadOptions {
             output_path: options.output_path.unwrap_or_default(),
             max_peers: options.max_connections,
             chunk_size: options.chunk_size,
             encryption: options.encryption,
             bandwidth_limit: options.bandwidth_limit,
+            on_conflict: OnConflict::default(),
+            http_headers: Default::default(),
+            torrent_files: None,
+            http_auth: None,
         };
+        download_opts.output_path = download_opts
+            .on_conflict
+            .resolve(&download_opts.output_path)?;
 
         // Start the download
         let handle = handler.download(identifier, download_opts).await?;
@@ -13,7 +13,7 @@ use crate::bittorrent_handler::BitTorrentHandler;
 use crate::transfer_events::{
     current_timestamp_ms, DisconnectReason, ErrorCategory, PauseReason,
     SourceConnectedEvent, SourceDisconnectedEvent, SourceInfo, SourceSummary,
-    SourceType, TransferCanceledEvent, TransferCompletedEvent, TransferEventBus,
+    SourceType, CancelReason, TransferCanceledEvent, TransferCompletedEvent, TransferEventBus,
     TransferFailedEvent, TransferPausedEvent, TransferProgressEvent,
     TransferResumedEvent, TransferStartedEvent,
 };
@@ -185,7 +185,11 @@ impl ProtocolHandler for BitTorrentProtocolHandler {
         }
 
         // Start the download using the underlying handler
-        let _handle = match self.handler.start_download(identifier).await {
+        let _handle = match self
+            .handler
+            .start_download_with_filter(identifier, options.torrent_files.as_ref())
+            .await
+        {
             Ok(h) => h,
             Err(e) => {
                 // Emit failed event
@@ -428,6 +432,7 @@ impl ProtocolHandler for BitTorrentProtocolHandler {
                 downloaded_bytes,
                 total_bytes,
                 keep_partial: false,
+                reason: CancelReason::UserRequested,
             });
         }
 
@@ -548,6 +553,7 @@ impl ProtocolHandler for BitTorrentProtocolHandler {
                             download_speed_bps: progress.download_speed,
                             upload_speed_bps: 0.0,
                             eta_seconds: progress.eta_seconds.map(|e| e as u32),
+                            chunks_completed_delta: 0,
                             active_sources: 1, // Swarm
                             timestamp: now_ms,
                         });
@@ -568,7 +574,8 @@ impl ProtocolHandler for BitTorrentProtocolHandler {
                     downloaded_bytes: progress.downloaded_bytes,
                     total_bytes: progress.total_bytes,
                     download_speed: progress.download_speed,
-                    eta_seconds: progress.eta_seconds,
+                    eta_seconds: progress.eta_seconds.map(|e| e as u32),
+                    remaining_bytes: progress.total_bytes.saturating_sub(progress.downloaded_bytes),
                     active_peers: 0, // librqbit doesn't expose peer count easily
                     status,
                 })
@@ -582,9 +589,11 @@ impl ProtocolHandler for BitTorrentProtocolHandler {
                         total_bytes: state.total_bytes,
                         download_speed: 0.0,
                         eta_seconds: None,
+                        remaining_bytes: 0,
                         active_peers: 0,
                         status: state.status.clone(),
-                    })
+                    }
+                    .with_estimates())
                 } else {
                     Err(ProtocolError::DownloadNotFound(identifier.to_string()))
                 }
@@ -604,6 +613,7 @@ impl ProtocolHandler for BitTorrentProtocolHandler {
             supports_multi_source: true,
             supports_encryption: true,
             supports_dht: true,
+            stable_seed_identifiers: true, // The info hash is derived from the content
         }
     }
 }
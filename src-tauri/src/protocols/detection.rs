@@ -287,6 +287,7 @@ mod tests {
                 supports_encryption: true,
                 supports_multi_source: false,
                 supports_dht: false,
+                stable_seed_identifiers: false,
             },
         };
 
@@ -299,6 +300,7 @@ mod tests {
                 supports_encryption: true,
                 supports_multi_source: true,
                 supports_dht: true,
+                stable_seed_identifiers: true,
             },
         };
 
@@ -311,6 +313,7 @@ mod tests {
                 supports_encryption: false,
                 supports_multi_source: true,
                 supports_dht: false,
+                stable_seed_identifiers: true,
             },
         };
 
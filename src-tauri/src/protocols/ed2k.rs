@@ -84,6 +84,11 @@ impl Ed2kProtocolHandler {
         }
     }
 
+    /// Route the handler's connections through a SOCKS5 proxy, or directly with `None`
+    pub async fn set_proxy(&self, proxy: Option<String>) {
+        self.client.lock().await.set_proxy(proxy);
+    }
+
     /// Generate FileManifest for a file (public helper for upload workflows)
     /// 
     /// This method is called from main.rs during ED2K file upload
@@ -402,6 +407,7 @@ impl ProtocolHandler for Ed2kProtocolHandler {
                 total_bytes: file_info.file_size,
                 download_speed: 0.0,
                 eta_seconds: None,
+                remaining_bytes: 0,
                 active_peers: 0,
                 status: DownloadStatus::FetchingMetadata,
             });
@@ -443,6 +449,15 @@ impl ProtocolHandler for Ed2kProtocolHandler {
                         info!("ED2K: Connected to server for enhanced peer discovery");
                         true
                     },
+                    // Going on would reach peers without the proxy and leak our address
+                    Err(e) if c.proxy().is_some() => {
+                        error!("ED2K: Server connection through the SOCKS5 proxy failed: {}", e);
+                        let mut prog = progress.lock().await;
+                        if let Some(p) = prog.get_mut(&id) {
+                            p.status = DownloadStatus::Failed;
+                        }
+                        return;
+                    }
                     Err(e) => {
                         warn!("ED2K: Server connection failed, operating in direct P2P mode: {}", e);
                         false
@@ -525,18 +540,12 @@ impl ProtocolHandler for Ed2kProtocolHandler {
                 let downloaded = all_data.len() as u64;
                 let elapsed = start_time.elapsed().as_secs_f64();
                 let speed = if elapsed > 0.0 { downloaded as f64 / elapsed } else { 0.0 };
-                let eta = if speed > 0.0 && file_size > downloaded {
-                    Some(((file_size - downloaded) as f64 / speed) as u64)
-                } else {
-                    None
-                };
 
                 {
                     let mut prog = progress.lock().await;
                     if let Some(p) = prog.get_mut(&id) {
                         p.downloaded_bytes = downloaded;
                         p.download_speed = speed;
-                        p.eta_seconds = eta;
                     }
                 }
             }
@@ -839,18 +848,12 @@ impl ProtocolHandler for Ed2kProtocolHandler {
                 let downloaded = all_data.len() as u64;
                 let elapsed = start_time.elapsed().as_secs_f64();
                 let speed = if elapsed > 0.0 { downloaded as f64 / elapsed } else { 0.0 };
-                let eta = if speed > 0.0 && file_size > downloaded {
-                    Some(((file_size - downloaded) as f64 / speed) as u64)
-                } else {
-                    None
-                };
 
                 {
                     let mut prog = progress.lock().await;
                     if let Some(p) = prog.get_mut(&id) {
                         p.downloaded_bytes = downloaded;
                         p.download_speed = speed;
-                        p.eta_seconds = eta;
                     }
                 }
             }
@@ -903,6 +906,7 @@ impl ProtocolHandler for Ed2kProtocolHandler {
         progress
             .get(identifier)
             .cloned()
+            .map(DownloadProgress::with_estimates)
             .ok_or_else(|| ProtocolError::DownloadNotFound(identifier.to_string()))
     }
 
@@ -918,6 +922,7 @@ impl ProtocolHandler for Ed2kProtocolHandler {
             supports_multi_source: true,
             supports_encryption: false, // ED2K doesn't have built-in encryption
             supports_dht: true,         // Can use DHT for peer discovery
+            stable_seed_identifiers: true, // The link is built from the content's MD4 hash
         }
     }
 }
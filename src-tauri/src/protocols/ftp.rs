@@ -10,9 +10,9 @@ use super::traits::{
 use crate::ftp_downloader::{FtpDownloader, FtpCredentials, FtpDownloadConfig};
 use crate::ftp_client::{download_from_ftp_with_progress_controlled, TransferControl};
 use crate::transfer_events::{
-    current_timestamp_ms, ChunkCompletedEvent, DisconnectReason, ErrorCategory,
+    calculate_chunk_speed, current_timestamp_ms, ChunkCompletedEvent, DisconnectReason, ErrorCategory,
     SourceConnectedEvent, SourceDisconnectedEvent, SourceInfo, SourceSummary,
-    SourceType, TransferCanceledEvent, TransferCompletedEvent, TransferEventBus,
+    SourceType, CancelReason, TransferCanceledEvent, TransferCompletedEvent, TransferEventBus,
     TransferFailedEvent, TransferPausedEvent, TransferResumedEvent, TransferStartedEvent, PauseReason,
 };
 use async_trait::async_trait;
@@ -247,6 +247,7 @@ impl FtpProtocolHandler {
                                     download_speed_bps: download_speed,
                                     upload_speed_bps: 0.0,
                                     eta_seconds,
+                                    chunks_completed_delta: 0,
                                     active_sources: 1,
                                     timestamp: current_timestamp_ms(),
                                 });
@@ -342,6 +343,7 @@ impl ProtocolHandler for FtpProtocolHandler {
                 total_bytes: 0,
                 download_speed: 0.0,
                 eta_seconds: None,
+                remaining_bytes: 0,
                 active_peers: 1, // FTP has "1 peer" (the server)
                 status: DownloadStatus::FetchingMetadata,
             });
@@ -479,6 +481,7 @@ impl ProtocolHandler for FtpProtocolHandler {
                             download_speed_bps: speed,
                             upload_speed_bps: 0.0,
                             eta_seconds,
+                            chunks_completed_delta: 0,
                             active_sources: 1,
                             timestamp: current_timestamp_ms(),
                         });
@@ -507,6 +510,7 @@ impl ProtocolHandler for FtpProtocolHandler {
                     // Emit completion events
                     if let Some(ref bus) = event_bus {
                         // Emit chunk completed (single chunk for FTP)
+                        let download_duration_ms = start_time.elapsed().as_millis() as u64;
                         bus.emit_chunk_completed(ChunkCompletedEvent {
                             transfer_id: id.clone(),
                             chunk_id: 0,
@@ -514,7 +518,11 @@ impl ProtocolHandler for FtpProtocolHandler {
                             source_id: task_source_id.clone(),
                             source_type: SourceType::Ftp,
                             completed_at: current_timestamp_ms(),
-                            download_duration_ms: start_time.elapsed().as_millis() as u64,
+                            download_duration_ms,
+                            chunk_speed_bps: calculate_chunk_speed(
+                                downloaded_bytes as usize,
+                                download_duration_ms,
+                            ),
                             verified: true,
                         });
 
@@ -610,6 +618,7 @@ impl ProtocolHandler for FtpProtocolHandler {
                                 downloaded_bytes,
                                 total_bytes: 0,
                                 keep_partial: true,
+                                reason: CancelReason::UserRequested,
                             });
                         }
                         tracing::info!("FTP download canceled ({} bytes)", downloaded_bytes);
@@ -858,6 +867,7 @@ impl ProtocolHandler for FtpProtocolHandler {
                             download_speed_bps: speed,
                             upload_speed_bps: 0.0,
                             eta_seconds,
+                            chunks_completed_delta: 0,
                             active_sources: 1,
                             timestamp: current_timestamp_ms(),
                         });
@@ -956,6 +966,7 @@ impl ProtocolHandler for FtpProtocolHandler {
                     downloaded_bytes,
                     total_bytes: state.file_size,
                     keep_partial: true,
+                    reason: CancelReason::UserRequested,
                 });
             }
 
@@ -973,6 +984,7 @@ impl ProtocolHandler for FtpProtocolHandler {
         progress
             .get(identifier)
             .cloned()
+            .map(DownloadProgress::with_estimates)
             .ok_or_else(|| ProtocolError::DownloadNotFound(identifier.to_string()))
     }
 
@@ -988,6 +1000,7 @@ impl ProtocolHandler for FtpProtocolHandler {
             supports_multi_source: false,
             supports_encryption: true,  // FTPS
             supports_dht: false,
+            stable_seed_identifiers: false,
         }
     }
 }
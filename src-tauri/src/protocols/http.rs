@@ -4,18 +4,24 @@
 //! Supports TransferEventBus integration for UI progress tracking.
 
 use super::traits::{
-    DownloadHandle, DownloadOptions, DownloadProgress, DownloadStatus,
+    DownloadHandle, DownloadOptions, DownloadProgress, DownloadStatus, HttpAuth,
     ProtocolCapabilities, ProtocolError, ProtocolHandler, SeedOptions, SeedingInfo,
 };
 use crate::transfer_events::{
-    current_timestamp_ms, DisconnectReason, ErrorCategory,
+    calculate_eta, current_timestamp_ms, DisconnectReason, ErrorCategory,
     SourceConnectedEvent, SourceDisconnectedEvent, SourceInfo, SourceSummary,
-    SourceType, TransferCanceledEvent, TransferCompletedEvent, TransferEventBus,
+    SourceType, CancelReason, TransferCanceledEvent, TransferCompletedEvent, TransferEventBus,
     TransferFailedEvent, TransferProgressEvent, TransferStartedEvent,
 };
+use crate::cert_pinning;
+use crate::download_source::{header_map, redact_headers};
 use async_trait::async_trait;
+use base64::{engine::general_purpose, Engine as _};
 use futures::stream::StreamExt;
-use reqwest::Client;
+use md5::Md5;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, RANGE, WWW_AUTHENTICATE};
+use reqwest::{Client, Method, Response, StatusCode, Url};
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -53,12 +59,291 @@ struct HttpDownloadState {
     is_paused: bool,
     /// Bytes downloaded so far (for resume support)
     downloaded_bytes: u64,
+    /// Custom request headers, reapplied on resume
+    headers: HeaderMap,
+    /// Credentials, reapplied on resume. Held in memory only.
+    auth: Option<HttpAuth>,
+}
+
+/// Value for the `Authorization` header of a Basic or Bearer credential
+fn auth_header(auth: &HttpAuth) -> Option<HeaderValue> {
+    let value = match auth {
+        HttpAuth::Basic { user, pass } => format!(
+            "Basic {}",
+            general_purpose::STANDARD.encode(format!("{}:{}", user, pass))
+        ),
+        HttpAuth::Bearer(token) => format!("Bearer {}", token),
+    };
+    let mut value = HeaderValue::from_str(&value).ok()?;
+    value.set_sensitive(true);
+    Some(value)
+}
+
+/// Value for a header that is sent before the server asks for credentials.
+/// Digest needs a challenge first, and a Basic password would travel in the
+/// clear over plain HTTP, so Basic is only sent up front over HTTPS.
+fn preemptive_auth_header(auth: &HttpAuth, url: &Url) -> Option<HeaderValue> {
+    match auth {
+        HttpAuth::Basic { .. } if url.scheme() != "https" => None,
+        _ => auth_header(auth),
+    }
+}
+
+/// Whether two URLs share scheme, host and port
+fn same_origin(a: &Url, b: &Url) -> bool {
+    a.scheme() == b.scheme()
+        && a.host_str() == b.host_str()
+        && a.port_or_known_default() == b.port_or_known_default()
+}
+
+/// Send a request with the download's credentials attached. A `401` is answered
+/// once with the Basic user and password, as a Digest response when the server
+/// offers one or as Basic if it wasn't sent up front. Challenges from another
+/// origin, reached through a redirect, are not answered.
+async fn send_authenticated(
+    client: &Client,
+    method: Method,
+    url: &str,
+    headers: &HeaderMap,
+    auth: Option<&HttpAuth>,
+) -> Result<Response, reqwest::Error> {
+    let mut headers = headers.clone();
+    let original_url = Url::parse(url).ok();
+    let preemptive = match (auth, &original_url) {
+        (Some(auth), Some(original_url)) => preemptive_auth_header(auth, original_url),
+        _ => None,
+    };
+    let sent_basic = preemptive.is_some();
+    if let Some(value) = preemptive {
+        headers.insert(AUTHORIZATION, value);
+    }
+
+    let response = client
+        .request(method.clone(), url)
+        .headers(headers.clone())
+        .send()
+        .await?;
+    if response.status() != StatusCode::UNAUTHORIZED {
+        return Ok(response);
+    }
+    let Some(HttpAuth::Basic { user, pass }) = auth else {
+        return Ok(response);
+    };
+    // Answer against the URL that issued the challenge, which may be a same-origin
+    // redirect target; credentials never go to a host the download wasn't pointed at
+    let challenged_url = response.url().clone();
+    if !original_url.is_some_and(|original| same_origin(&original, &challenged_url)) {
+        return Ok(response);
+    }
+    let challenges: Vec<&str> = response
+        .headers()
+        .get_all(WWW_AUTHENTICATE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect();
+
+    let digest = challenges.iter().find_map(|c| DigestChallenge::parse(c));
+    let value = if let Some(challenge) = digest {
+        let uri = match challenged_url.query() {
+            Some(query) => format!("{}?{}", challenged_url.path(), query),
+            None => challenged_url.path().to_string(),
+        };
+        let cnonce = hex::encode(rand::random::<[u8; 8]>());
+        let authorization = challenge.authorization(user, pass, method.as_str(), &uri, &cnonce);
+        let Ok(mut value) = HeaderValue::from_str(&authorization) else {
+            return Ok(response);
+        };
+        value.set_sensitive(true);
+        value
+    } else if !sent_basic && challenges.iter().any(|c| is_basic_challenge(c)) {
+        let Some(value) = auth.and_then(auth_header) else {
+            return Ok(response);
+        };
+        value
+    } else {
+        return Ok(response);
+    };
+    headers.insert(AUTHORIZATION, value);
+
+    client
+        .request(method, challenged_url)
+        .headers(headers)
+        .send()
+        .await
+}
+
+/// Whether a `WWW-Authenticate` value offers the Basic scheme
+fn is_basic_challenge(header: &str) -> bool {
+    header
+        .split_whitespace()
+        .next()
+        .is_some_and(|scheme| scheme.trim_end_matches(',').eq_ignore_ascii_case("basic"))
+}
+
+/// A `WWW-Authenticate: Digest` challenge (RFC 7616)
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DigestChallenge {
+    realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    /// Algorithm token as sent by the server, echoed back in the response
+    algorithm: Option<String>,
+    /// Whether the server offered `qop=auth`
+    qop_auth: bool,
+    sha256: bool,
+    session: bool,
+}
+
+impl DigestChallenge {
+    /// Parse a Digest challenge; other schemes, unsupported algorithms and
+    /// `auth-int`-only challenges yield `None`
+    fn parse(header: &str) -> Option<Self> {
+        let (scheme, params) = header.trim().split_once(char::is_whitespace)?;
+        if !scheme.eq_ignore_ascii_case("digest") {
+            return None;
+        }
+        let params = parse_auth_params(params);
+
+        let algorithm = params.get("algorithm").cloned();
+        let (sha256, session) = match algorithm.as_deref().map(str::to_ascii_uppercase).as_deref() {
+            None | Some("MD5") => (false, false),
+            Some("MD5-SESS") => (false, true),
+            Some("SHA-256") => (true, false),
+            Some("SHA-256-SESS") => (true, true),
+            Some(_) => return None,
+        };
+        let qop_auth = match params.get("qop") {
+            None => false,
+            Some(qop) => {
+                if !qop
+                    .split(',')
+                    .any(|option| option.trim().eq_ignore_ascii_case("auth"))
+                {
+                    return None;
+                }
+                true
+            }
+        };
+
+        Some(Self {
+            realm: params.get("realm")?.clone(),
+            nonce: params.get("nonce")?.clone(),
+            opaque: params.get("opaque").cloned(),
+            algorithm,
+            qop_auth,
+            sha256,
+            session,
+        })
+    }
+
+    fn hash(&self, data: &str) -> String {
+        if self.sha256 {
+            hex::encode(Sha256::digest(data.as_bytes()))
+        } else {
+            hex::encode(Md5::digest(data.as_bytes()))
+        }
+    }
+
+    /// Build the `Authorization` header value answering this challenge
+    fn authorization(
+        &self,
+        user: &str,
+        pass: &str,
+        method: &str,
+        uri: &str,
+        cnonce: &str,
+    ) -> String {
+        // Each answer follows a fresh challenge, so the nonce count never advances
+        const NONCE_COUNT: &str = "00000001";
+
+        let mut ha1 = self.hash(&format!("{}:{}:{}", user, self.realm, pass));
+        if self.session {
+            ha1 = self.hash(&format!("{}:{}:{}", ha1, self.nonce, cnonce));
+        }
+        let ha2 = self.hash(&format!("{}:{}", method, uri));
+        let response = if self.qop_auth {
+            self.hash(&format!(
+                "{}:{}:{}:{}:auth:{}",
+                ha1, self.nonce, NONCE_COUNT, cnonce, ha2
+            ))
+        } else {
+            self.hash(&format!("{}:{}:{}", ha1, self.nonce, ha2))
+        };
+
+        let mut header = format!(
+            "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", response=\"{}\"",
+            quote_param(user),
+            quote_param(&self.realm),
+            quote_param(&self.nonce),
+            quote_param(uri),
+            response
+        );
+        if let Some(algorithm) = &self.algorithm {
+            header.push_str(&format!(", algorithm={}", algorithm));
+        }
+        if self.qop_auth {
+            header.push_str(&format!(
+                ", qop=auth, nc={}, cnonce=\"{}\"",
+                NONCE_COUNT, cnonce
+            ));
+        }
+        if let Some(opaque) = &self.opaque {
+            header.push_str(&format!(", opaque=\"{}\"", quote_param(opaque)));
+        }
+        header
+    }
+}
+
+/// Split `name=value, name="quoted, value"` auth parameters; names are lowercased
+fn parse_auth_params(input: &str) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    let mut rest = input;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+        let Some((name, after)) = rest.split_once('=') else {
+            break;
+        };
+        let after = after.trim_start();
+        let (value, remaining) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let mut value = String::new();
+                let mut end = quoted.len();
+                let mut chars = quoted.char_indices();
+                while let Some((i, c)) = chars.next() {
+                    match c {
+                        '\\' => {
+                            if let Some((_, escaped)) = chars.next() {
+                                value.push(escaped);
+                            }
+                        }
+                        '"' => {
+                            end = i + 1;
+                            break;
+                        }
+                        _ => value.push(c),
+                    }
+                }
+                (value, &quoted[end..])
+            }
+            None => {
+                let end = after.find(',').unwrap_or(after.len());
+                (after[..end].trim().to_string(), &after[end..])
+            }
+        };
+        params.insert(name.trim().to_ascii_lowercase(), value);
+        rest = remaining;
+    }
+    params
+}
+
+fn quote_param(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
 }
 
 impl HttpProtocolHandler {
     /// Creates a new HTTP protocol handler (no event bus)
     pub fn new() -> Result<Self, ProtocolError> {
-        let client = Client::builder()
+        let client = cert_pinning::client_builder()
             .user_agent("Chiral-Network/1.0")
             .timeout(Duration::from_secs(300))
             .build()
@@ -74,7 +359,7 @@ impl HttpProtocolHandler {
 
     /// Creates a handler with custom timeout (no event bus)
     pub fn with_timeout(timeout_secs: u64) -> Result<Self, ProtocolError> {
-        let client = Client::builder()
+        let client = cert_pinning::client_builder()
             .user_agent("Chiral-Network/1.0")
             .timeout(Duration::from_secs(timeout_secs))
             .build()
@@ -90,7 +375,7 @@ impl HttpProtocolHandler {
 
     /// Creates a handler with event bus for UI integration
     pub fn with_event_bus(app_handle: AppHandle) -> Result<Self, ProtocolError> {
-        let client = Client::builder()
+        let client = cert_pinning::client_builder()
             .user_agent("Chiral-Network/1.0")
             .timeout(Duration::from_secs(300))
             .build()
@@ -106,7 +391,7 @@ impl HttpProtocolHandler {
 
     /// Creates a handler with custom timeout and event bus
     pub fn with_timeout_and_event_bus(timeout_secs: u64, app_handle: AppHandle) -> Result<Self, ProtocolError> {
-        let client = Client::builder()
+        let client = cert_pinning::client_builder()
             .user_agent("Chiral-Network/1.0")
             .timeout(Duration::from_secs(timeout_secs))
             .build()
@@ -155,6 +440,8 @@ impl HttpProtocolHandler {
     async fn download_with_progress(
         client: Client,
         url: String,
+        headers: HeaderMap,
+        auth: Option<HttpAuth>,
         output_path: PathBuf,
         progress: Arc<Mutex<HashMap<String, DownloadProgress>>>,
         active_downloads: Arc<Mutex<HashMap<String, HttpDownloadState>>>,
@@ -167,7 +454,9 @@ impl HttpProtocolHandler {
         let source_id = format!("http-{}", url.split('/').nth(2).unwrap_or("unknown"));
 
         // Initial HEAD request to get content length
-        let head_response = match client.head(&url).send().await {
+        let head_response =
+            send_authenticated(&client, Method::HEAD, &url, &headers, auth.as_ref()).await;
+        let head_response = match head_response {
             Ok(r) => r,
             Err(e) => {
                 if let Some(ref bus) = event_bus {
@@ -246,7 +535,9 @@ impl HttpProtocolHandler {
         }
 
         // Start download
-        let response = match client.get(&url).send().await {
+        let response =
+            send_authenticated(&client, Method::GET, &url, &headers, auth.as_ref()).await;
+        let response = match response {
             Ok(r) => r,
             Err(e) => {
                 if let Some(ref bus) = event_bus {
@@ -325,6 +616,7 @@ impl HttpProtocolHandler {
                                 downloaded_bytes,
                                 total_bytes,
                                 keep_partial: false,
+                                reason: CancelReason::UserRequested,
                             });
                         }
                         return Err(ProtocolError::Internal("Download cancelled".to_string()));
@@ -360,8 +652,8 @@ impl HttpProtocolHandler {
                                 0.0
                             };
 
-                            let eta = if speed > 0.0 && total_bytes > downloaded_bytes {
-                                Some(((total_bytes - downloaded_bytes) as f64 / speed) as u64)
+                            let eta = if total_bytes > 0 {
+                                calculate_eta(total_bytes.saturating_sub(downloaded_bytes), speed)
                             } else {
                                 None
                             };
@@ -372,7 +664,6 @@ impl HttpProtocolHandler {
                             if let Some(p) = prog.get_mut(&download_id) {
                                 p.downloaded_bytes = downloaded_bytes;
                                 p.download_speed = speed;
-                                p.eta_seconds = eta;
                             }
                             drop(prog);
 
@@ -402,7 +693,8 @@ impl HttpProtocolHandler {
                                         progress_percentage: progress_pct,
                                         download_speed_bps: speed,
                                         upload_speed_bps: 0.0,
-                                        eta_seconds: eta.map(|e| e as u32),
+                                        eta_seconds: eta,
+                                        chunks_completed_delta: 0,
                                         active_sources: 1,
                                         timestamp: now_ms,
                                     });
@@ -497,6 +789,8 @@ impl HttpProtocolHandler {
     async fn download_with_range(
         client: Client,
         url: &str,
+        mut headers: HeaderMap,
+        auth: Option<HttpAuth>,
         output_path: PathBuf,
         resume_from: u64,
         progress: Arc<Mutex<HashMap<String, DownloadProgress>>>,
@@ -514,13 +808,12 @@ impl HttpProtocolHandler {
             .map_err(|e| ProtocolError::Internal(format!("Failed to open file for resume: {}", e)))?;
 
         // Create range header for resume
-        let range_header = format!("bytes={}-", resume_from);
+        let range_header = HeaderValue::from_str(&format!("bytes={}-", resume_from))
+            .map_err(|e| ProtocolError::Internal(e.to_string()))?;
+        headers.insert(RANGE, range_header);
 
         // Make request with Range header
-        let response = client
-            .get(url)
-            .header("Range", range_header)
-            .send()
+        let response = send_authenticated(&client, Method::GET, url, &headers, auth.as_ref())
             .await
             .map_err(|e| ProtocolError::NetworkError(e.to_string()))?;
 
@@ -638,6 +931,34 @@ impl ProtocolHandler for HttpProtocolHandler {
             }
         }
 
+        let headers = header_map(
+            options
+                .http_headers
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+        )
+        .map_err(ProtocolError::ProtocolSpecific)?;
+        if !headers.is_empty() {
+            info!(
+                "HTTP: using custom headers for {}: {}",
+                identifier,
+                redact_headers(
+                    options
+                        .http_headers
+                        .iter()
+                        .map(|(name, value)| (name.as_str(), value.as_str()))
+                )
+            );
+        }
+        let auth = options.http_auth;
+        match &auth {
+            Some(HttpAuth::Basic { .. }) => {
+                info!("HTTP: using basic credentials for {}", identifier)
+            }
+            Some(HttpAuth::Bearer(_)) => info!("HTTP: using bearer token for {}", identifier),
+            None => {}
+        }
+
         let started_at = Self::now();
 
         // Create cancellation channel
@@ -651,6 +972,7 @@ impl ProtocolHandler for HttpProtocolHandler {
                 total_bytes: 0,
                 download_speed: 0.0,
                 eta_seconds: None,
+                remaining_bytes: 0,
                 active_peers: 1, // HTTP has "1 peer" (the server)
                 status: DownloadStatus::FetchingMetadata,
             });
@@ -669,6 +991,8 @@ impl ProtocolHandler for HttpProtocolHandler {
                 total_bytes: 0,
                 is_paused: false,
                 downloaded_bytes: 0,
+                headers: headers.clone(),
+                auth: auth.clone(),
             });
         }
 
@@ -685,6 +1009,8 @@ impl ProtocolHandler for HttpProtocolHandler {
             if let Err(e) = Self::download_with_progress(
                 client,
                 url,
+                headers,
+                auth,
                 output_path,
                 progress,
                 active_downloads,
@@ -793,6 +1119,8 @@ impl ProtocolHandler for HttpProtocolHandler {
                 // Spawn resumed download task
                 let client = self.client.clone();
                 let url = state.url.clone();
+                let headers = state.headers.clone();
+                let auth = state.auth.clone();
                 let output_path = state.output_path.clone();
                 let progress = self.download_progress.clone();
                 let active_downloads = self.active_downloads.clone();
@@ -806,6 +1134,8 @@ impl ProtocolHandler for HttpProtocolHandler {
                     match Self::download_with_range(
                         client,
                         &url,
+                        headers,
+                        auth,
                         output_path.clone(),
                         resume_from,
                         progress,
@@ -857,6 +1187,7 @@ impl ProtocolHandler for HttpProtocolHandler {
                     downloaded_bytes: 0, // Will be updated by download task
                     total_bytes: state.total_bytes,
                     keep_partial: false,
+                    reason: CancelReason::UserRequested,
                 });
             }
 
@@ -874,6 +1205,7 @@ impl ProtocolHandler for HttpProtocolHandler {
         progress
             .get(identifier)
             .cloned()
+            .map(DownloadProgress::with_estimates)
             .ok_or_else(|| ProtocolError::DownloadNotFound(identifier.to_string()))
     }
 
@@ -889,6 +1221,7 @@ impl ProtocolHandler for HttpProtocolHandler {
             supports_multi_source: true,  // Can download same file from multiple URLs
             supports_encryption: true,    // HTTPS
             supports_dht: false,
+            stable_seed_identifiers: false,
         }
     }
 }
@@ -916,4 +1249,88 @@ mod tests {
         assert_ne!(id1, id3);
         assert!(id1.starts_with("http-"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_preemptive_auth_header() {
+        let https = Url::parse("https://example.com/file.zip").unwrap();
+        let http = Url::parse("http://example.com/file.zip").unwrap();
+        let credentials = HttpAuth::Basic {
+            user: "Aladdin".to_string(),
+            pass: "open sesame".to_string(),
+        };
+        let basic = preemptive_auth_header(&credentials, &https).unwrap();
+        assert_eq!(basic, "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==");
+        assert!(basic.is_sensitive());
+        // Over plain HTTP the password waits for the server to ask
+        assert!(preemptive_auth_header(&credentials, &http).is_none());
+
+        let token = HttpAuth::Bearer("abc123".to_string());
+        let bearer = preemptive_auth_header(&token, &https).unwrap();
+        assert_eq!(bearer, "Bearer abc123");
+        assert!(bearer.is_sensitive());
+    }
+
+    #[test]
+    fn test_challenges_are_answered_only_by_the_original_origin() {
+        let original = Url::parse("https://files.example.com/a.zip").unwrap();
+        let same = Url::parse("https://files.example.com:443/mirror/a.zip").unwrap();
+        assert!(same_origin(&original, &same));
+        for other in [
+            "https://evil.example.net/a.zip",
+            "http://files.example.com/a.zip",
+            "https://files.example.com:8443/a.zip",
+        ] {
+            assert!(!same_origin(&original, &Url::parse(other).unwrap()), "{}", other);
+        }
+
+        assert!(is_basic_challenge(r#"Basic realm="files""#));
+        assert!(is_basic_challenge("basic"));
+        assert!(!is_basic_challenge(r#"Bearer realm="files""#));
+        assert!(!is_basic_challenge(r#"Digest realm="r", nonce="n""#));
+    }
+
+    #[test]
+    fn test_digest_challenge_response() {
+        // Example from RFC 2617 section 3.5
+        let challenge = DigestChallenge::parse(
+            r#"Digest realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#,
+        )
+        .unwrap();
+        assert_eq!(challenge.realm, "testrealm@host.com");
+        assert!(challenge.qop_auth);
+
+        let authorization = challenge.authorization(
+            "Mufasa",
+            "Circle Of Life",
+            "GET",
+            "/dir/index.html",
+            "0a4f113b",
+        );
+        assert!(authorization.starts_with("Digest username=\"Mufasa\""));
+        assert!(authorization.contains("response=\"6629fae49393a05397450978507c4ef1\""));
+        assert!(authorization.contains("qop=auth, nc=00000001, cnonce=\"0a4f113b\""));
+        assert!(authorization.contains("opaque=\"5ccc069c403ebaf9f0171e9517f40e41\""));
+        assert!(!authorization.contains("Circle Of Life"));
+    }
+
+    #[test]
+    fn test_digest_challenge_parsing() {
+        assert!(DigestChallenge::parse(r#"Basic realm="files""#).is_none());
+        assert!(
+            DigestChallenge::parse(r#"Digest realm="r", nonce="n", algorithm=SHA-512"#).is_none()
+        );
+        assert!(DigestChallenge::parse(r#"Digest realm="r", nonce="n", qop="auth-int""#).is_none());
+
+        let challenge = DigestChallenge::parse(
+            r#"digest realm="a \"quoted\", realm",nonce=xyz, algorithm=SHA-256"#,
+        )
+        .unwrap();
+        assert_eq!(challenge.realm, "a \"quoted\", realm");
+        assert_eq!(challenge.nonce, "xyz");
+        assert!(challenge.sha256);
+        assert!(!challenge.qop_auth);
+        assert!(challenge
+            .authorization("u", "p", "HEAD", "/f", "c")
+            .contains("algorithm=SHA-256"));
+    }
+}
@@ -45,12 +45,14 @@ pub mod api;
 pub use traits::{
     // ProtocolManager
     ProtocolHandler,
+    SeedReader,
     ProtocolCapabilities,
     ProtocolError,
     DownloadHandle,
     DownloadOptions,
     DownloadProgress,
     DownloadStatus,
+    OnConflict,
     SeedOptions,
     SeedingInfo,
     // Legacy exports for backward compatibility
@@ -72,12 +74,16 @@ pub use api::ActiveTransfer;
 // Re-export multi-source types
 pub use multi_source::{MultiSourceCoordinator, SourceInfo, ChunkAssignment};
 
-use crate::protocols::seeding::{SeedingEntry, SeedingRegistry};
+use crate::multi_source_download::{HashAlgorithm, MultiSourceDownloadService};
+use crate::protocols::seeding::{SeedingEntry, SeedingRegistry, VerifyResult};
 use detection::ProtocolDetector;
-use sha2::{Digest, Sha256};
-use std::collections::HashMap;
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
 use tokio::sync::RwLock;
 use tracing::{debug, info, warn};
 
@@ -104,6 +110,36 @@ pub struct ProtocolManager {
     /// Active file transfers (downloads and uploads)
     /// Maps transfer_id -> ActiveTransfer
     pub(crate) active_transfers: Arc<RwLock<HashMap<String, ActiveTransfer>>>,
+    /// Where content seeded from a reader is written for handlers that need a path
+    seed_staging_dir: PathBuf,
+    /// Protocols switched off at runtime; their handlers stay registered
+    disabled_protocols: Arc<std::sync::RwLock<HashSet<String>>>,
+    /// Where the enabled flags are saved, when they outlive the process
+    protocol_states_path: Option<PathBuf>,
+    /// Service behind in-memory downloads, once the file transfer service has started
+    multi_source_service: std::sync::RwLock<Option<Arc<MultiSourceDownloadService>>>,
+}
+
+/// Whether a registered protocol is currently used
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProtocolState {
+    pub name: String,
+    pub enabled: bool,
+}
+
+/// A live view of which protocols a `ProtocolManager` has switched off
+///
+/// Services that pick their own sources or seed outside the manager check it, so turning a
+/// protocol off reaches them too. The default view has everything enabled.
+#[derive(Debug, Clone, Default)]
+pub struct ProtocolSwitches(Arc<std::sync::RwLock<HashSet<String>>>);
+
+impl ProtocolSwitches {
+    /// Whether `name` is used; protocols nobody disabled, registered or not, are
+    pub fn is_enabled(&self, name: &str) -> bool {
+        !self.0.read().unwrap_or_else(|e| e.into_inner()).contains(name)
+    }
 }
 
 impl ProtocolManager {
@@ -116,7 +152,147 @@ impl ProtocolManager {
             detector: ProtocolDetector::new(),
             multi_source: MultiSourceCoordinator::new(HashMap::new()),
             active_transfers: Arc::new(RwLock::new(HashMap::new())),
+            seed_staging_dir: default_seed_staging_dir(),
+            disabled_protocols: Arc::new(std::sync::RwLock::new(HashSet::new())),
+            protocol_states_path: None,
+            multi_source_service: std::sync::RwLock::new(None),
+        }
+    }
+
+    /// Loads the enabled flags saved at `path` and saves every later change there
+    ///
+    /// Protocols the file doesn't mention are enabled. A missing or unreadable file leaves
+    /// every protocol enabled.
+    pub fn set_protocol_states_path(&mut self, path: PathBuf) {
+        let saved: HashMap<String, bool> = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable protocol states {:?}: {}", path, e);
+                HashMap::new()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                warn!("Failed to read protocol states {:?}: {}", path, e);
+                HashMap::new()
+            }
+        };
+        *self.disabled_protocols.write().unwrap_or_else(|e| e.into_inner()) = saved
+            .into_iter()
+            .filter(|(_, enabled)| !enabled)
+            .map(|(name, _)| name)
+            .collect();
+        self.protocol_states_path = Some(path);
+    }
+
+    /// Turns a registered protocol off or back on without unregistering its handler
+    ///
+    /// A disabled protocol is skipped when finding handlers, discovering sources and
+    /// detecting protocols. Transfers it already started keep running.
+    pub fn set_protocol_enabled(&self, name: &str, enabled: bool) -> Result<(), ProtocolError> {
+        if !self.handlers.iter().any(|h| h.name() == name) {
+            return Err(ProtocolError::InvalidIdentifier(format!("Unknown protocol: {}", name)));
+        }
+        {
+            let mut disabled = self.disabled_protocols.write().unwrap_or_else(|e| e.into_inner());
+            if enabled {
+                disabled.remove(name);
+            } else {
+                disabled.insert(name.to_string());
+            }
+        }
+        info!("Protocol {} {}", name, if enabled { "enabled" } else { "disabled" });
+
+        // The runtime switch stands even if it can't be saved
+        if let Err(e) = self.save_protocol_states() {
+            warn!("Failed to save protocol states: {}", e);
+        }
+        Ok(())
+    }
+
+    /// Whether `name` is used; protocols nobody disabled, registered or not, are
+    pub fn is_protocol_enabled(&self, name: &str) -> bool {
+        self.protocol_switches().is_enabled(name)
+    }
+
+    /// The enabled flags as they change, for services outside the manager
+    pub fn protocol_switches(&self) -> ProtocolSwitches {
+        ProtocolSwitches(self.disabled_protocols.clone())
+    }
+
+    /// Hands in-memory downloads to `service`, replacing any service set before
+    pub fn set_multi_source_service(&self, service: Arc<MultiSourceDownloadService>) {
+        *self.multi_source_service.write().unwrap_or_else(|e| e.into_inner()) = Some(service);
+    }
+
+    /// Downloads the file with `file_hash` straight into memory instead of to disk
+    ///
+    /// Only `max_peers` and `chunk_size` of `options` apply. Files over the service's
+    /// `max_memory_download_bytes` are refused.
+    pub async fn download_to_memory(
+        &self,
+        file_hash: &str,
+        options: &DownloadOptions,
+    ) -> Result<Vec<u8>, ProtocolError> {
+        let service = self
+            .multi_source_service
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+            .ok_or_else(|| {
+                ProtocolError::Internal("The file transfer service isn't running".to_string())
+            })?;
+        service
+            .download_to_memory(file_hash.to_string(), options.max_peers, options.chunk_size, None)
+            .await
+            .map_err(ProtocolError::ProtocolSpecific)
+    }
+
+    /// Every registered protocol and whether it is enabled, in registration order
+    pub fn protocol_states(&self) -> Vec<ProtocolState> {
+        self.handlers
+            .iter()
+            .map(|h| ProtocolState {
+                name: h.name().to_string(),
+                enabled: self.is_protocol_enabled(h.name()),
+            })
+            .collect()
+    }
+
+    fn save_protocol_states(&self) -> std::io::Result<()> {
+        let Some(path) = &self.protocol_states_path else {
+            return Ok(());
+        };
+        let disabled = self.disabled_protocols.read().unwrap_or_else(|e| e.into_inner()).clone();
+        // Disabled protocols that aren't registered right now keep their saved flag
+        let states: HashMap<&str, bool> = self
+            .handlers
+            .iter()
+            .map(|h| (h.name(), !disabled.contains(h.name())))
+            .chain(disabled.iter().map(|name| (name.as_str(), false)))
+            .collect();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
         }
+        std::fs::write(path, serde_json::to_vec_pretty(&states)?)
+    }
+
+    /// Registered handlers whose protocol is enabled
+    fn enabled_handlers(&self) -> impl Iterator<Item = &Arc<dyn ProtocolHandler>> + '_ {
+        let disabled = self.disabled_protocols.read().unwrap_or_else(|e| e.into_inner()).clone();
+        self.handlers
+            .iter()
+            .filter(move |h| !disabled.contains(h.name()))
+    }
+
+    /// Enabled handlers keyed by protocol name, as the detector wants them
+    fn enabled_handler_map(&self) -> HashMap<String, &dyn ProtocolHandler> {
+        self.enabled_handlers()
+            .map(|h| (h.name().to_string(), h.as_ref()))
+            .collect()
+    }
+
+    /// Writes content seeded from a reader under `dir` instead of `default_seed_staging_dir`
+    pub fn set_seed_staging_dir(&mut self, dir: PathBuf) {
+        self.seed_staging_dir = dir;
     }
 
     /// Registers an enhanced protocol handler
@@ -140,19 +316,20 @@ impl ProtocolManager {
         self.multi_source = MultiSourceCoordinator::new(handlers_map);
     }
 
-    /// Finds a handler that supports the given identifier
+    /// Finds an enabled handler that supports the given identifier
     pub fn find_handler(&self, identifier: &str) -> Option<&dyn ProtocolHandler> {
-        self.handlers
-            .iter()
+        self.enabled_handlers()
             .find(|h| h.supports(identifier))
             .map(|h| h.as_ref())
     }
 
     /// Finds a simple handler that supports the given identifier
+    ///
+    /// Disabling a protocol covers its legacy handler too, so it can't be used as a fallback.
     fn find_simple_handler(&self, identifier: &str) -> Option<&dyn SimpleProtocolHandler> {
         self.simple_handlers
             .iter()
-            .find(|h| h.supports(identifier))
+            .find(|h| self.is_protocol_enabled(h.name()) && h.supports(identifier))
             .map(|h| h.as_ref())
     }
 
@@ -163,10 +340,20 @@ impl ProtocolManager {
     pub async fn download(
         &self,
         identifier: &str,
-        options: DownloadOptions,
+        mut options: DownloadOptions,
     ) -> Result<DownloadHandle, ProtocolError> {
         info!("Starting download for identifier: {}", identifier);
 
+        // Avoid clobbering an existing file unless the caller asked for it
+        let resolved_path = options.on_conflict.resolve(&options.output_path)?;
+        if resolved_path != options.output_path {
+            info!(
+                "Output path {:?} exists, downloading to {:?} instead",
+                options.output_path, resolved_path
+            );
+            options.output_path = resolved_path;
+        }
+
         // Discover all available sources for this identifier
         let sources = self.discover_sources(identifier).await?;
         info!("Found {} source(s) for download", sources.len());
@@ -213,8 +400,8 @@ impl ProtocolManager {
     ) -> Result<Vec<SourceInfo>, ProtocolError> {
         let mut sources = Vec::new();
 
-        // Check each protocol to see if it supports this identifier
-        for handler in &self.handlers {
+        // Check each enabled protocol to see if it supports this identifier
+        for handler in self.enabled_handlers() {
             if handler.supports(identifier) {
                 let source = SourceInfo {
                     protocol: handler.name().to_string(),
@@ -279,6 +466,30 @@ impl ProtocolManager {
             .map(|h| h.capabilities())
     }
 
+    /// Lists the registered protocols whose capabilities satisfy `pred`
+    ///
+    /// Protocols are returned in registration order.
+    pub fn handlers_matching(
+        &self,
+        pred: impl Fn(&ProtocolCapabilities) -> bool,
+    ) -> Vec<&'static str> {
+        self.handlers
+            .iter()
+            .filter(|h| pred(&h.capabilities()))
+            .map(|h| h.name())
+            .collect()
+    }
+
+    /// Lists the registered protocols that can seed files
+    pub fn seeding_capable_protocols(&self) -> Vec<&'static str> {
+        self.handlers_matching(|caps| caps.supports_seeding)
+    }
+
+    /// Lists the registered protocols that support encrypted transfers
+    pub fn encryption_capable_protocols(&self) -> Vec<&'static str> {
+        self.handlers_matching(|caps| caps.supports_encryption)
+    }
+
     /// Get best protocol handler for an identifier
     ///
     /// Uses priority ordering to select the best handler that supports
@@ -291,7 +502,7 @@ impl ProtocolManager {
         let priority = ["bittorrent", "ed2k", "http", "ftp"];
 
         for protocol in &priority {
-            if let Some(handler) = self.handlers.iter().find(|h| h.name() == *protocol) {
+            if let Some(handler) = self.enabled_handlers().find(|h| h.name() == *protocol) {
                 if handler.supports(identifier) {
                     return Ok(handler.clone());
                 }
@@ -419,19 +630,117 @@ impl ProtocolManager {
             ));
         }
 
-        let mut results = HashMap::new();
         // Use SHA-256 as the unique file identifier
         let file_hash = self.calculate_file_hash(&file_path).await?;
+        self.seed_path_on_protocols(file_hash, file_path, protocols, options).await
+    }
+
+    /// Seed `size` bytes read from `reader` on multiple protocols and register them under
+    /// `file_name`.
+    ///
+    /// The content is hashed as it is read and written once, to `output_path` when given and
+    /// otherwise to the staging directory, and every protocol seeds from that file. Staged
+    /// content is removed again once it stops being seeded; a file at `output_path` is kept.
+    /// Returns the content's SHA-256 hash with the per-protocol results.
+    pub async fn seed_reader_multi_protocol(
+        &self,
+        mut reader: SeedReader,
+        size: u64,
+        file_name: &str,
+        output_path: Option<PathBuf>,
+        protocols: Vec<String>,
+        options: SeedOptions,
+    ) -> Result<(String, HashMap<String, SeedingInfo>), ProtocolError> {
+        info!("Seeding {} ({} bytes) from a reader on protocols: {:?}", file_name, size, protocols);
+
+        let (file_hash, file_path, staged) = match output_path {
+            Some(output_path) => {
+                let file_hash = write_seed_file(&mut reader, size, &output_path).await?;
+                (file_hash, output_path, None)
+            }
+            None => {
+                let staged =
+                    stage_seed_content(&mut reader, size, file_name, &self.seed_staging_dir)
+                        .await?;
+                (staged.file_hash.clone(), staged.file_path.clone(), Some(staged))
+            }
+        };
+        match self
+            .seed_path_on_protocols(file_hash.clone(), file_path, protocols, options)
+            .await
+        {
+            Ok(results) => Ok((file_hash, results)),
+            Err(e) => {
+                if let Some(staged) = staged {
+                    staged.discard().await;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    async fn seed_path_on_protocols(
+        &self,
+        file_hash: String,
+        file_path: PathBuf,
+        protocols: Vec<String>,
+        options: SeedOptions,
+    ) -> Result<HashMap<String, SeedingInfo>, ProtocolError> {
+        let mut results = HashMap::new();
+        let seeding_capable = self.seeding_capable_protocols();
+        // Only seeds of this very path are reused; a copy elsewhere is seeded in its own right
+        let already_seeding = self
+            .seeding_registry
+            .entries
+            .read()
+            .await
+            .get(&file_hash)
+            .filter(|entry| entry.file_path == file_path)
+            .map(|entry| entry.protocols.clone())
+            .unwrap_or_default();
 
         for protocol_name in protocols {
+            if !self.is_protocol_enabled(&protocol_name) {
+                warn!("Protocol {} is disabled; not seeding {} on it", protocol_name, file_hash);
+                continue;
+            }
             if let Some(handler) = self.handlers.iter().find(|h| h.name() == protocol_name) {
-                if !handler.capabilities().supports_seeding {
+                if !seeding_capable.iter().any(|&name| name == protocol_name) {
                     warn!("Protocol {} does not support seeding.", protocol_name);
                     continue;
                 }
-                
+
+                // Seeding the same content again keeps the identifier it already has
+                if let Some(seeding_info) = already_seeding.get(&protocol_name) {
+                    info!(
+                        "{} is already seeding {} as {}",
+                        protocol_name, file_hash, seeding_info.identifier
+                    );
+                    results.insert(protocol_name, seeding_info.clone());
+                    continue;
+                }
+
                 match handler.seed(file_path.clone(), options.clone()).await {
                     Ok(seeding_info) => {
+                        // The handler only answers to the identifier it seeded under, so that
+                        // is the one advertised and later stopped, even if links handed out
+                        // for the same content before named another
+                        if handler.capabilities().stable_seed_identifiers {
+                            let previous = self
+                                .seeding_registry
+                                .persisted_identifier(&file_hash, &protocol_name)
+                                .await;
+                            if let Some(previous) =
+                                previous.filter(|previous| *previous != seeding_info.identifier)
+                            {
+                                warn!(
+                                    "{} seeded {} as {} instead of its earlier identifier {}; \
+                                     links to the earlier one won't reach this seed",
+                                    protocol_name, file_hash, seeding_info.identifier, previous
+                                );
+                            }
+                        }
+
                         // Add to registry
                         self.seeding_registry
                             .add_seeding(
@@ -496,43 +805,187 @@ impl ProtocolManager {
         // Remove from registry
         self.seeding_registry.remove_seeding(file_hash).await;
 
+        // Content seeded from a reader was only staged to be seeded
+        if entry.file_path.starts_with(&self.seed_staging_dir) {
+            remove_staged_file(&entry.file_path).await;
+        }
+
         Ok(())
     }
 
-    /// List all files currently being seeded.
+    /// Re-hash a seeded file and check it still matches the hash it is seeded under.
+    ///
+    /// A file that changed or can no longer be read stops being seeded on every protocol,
+    /// so peers aren't served corrupted data.
+    pub async fn verify_seed(&self, file_hash: &str) -> Result<VerifyResult, ProtocolError> {
+        let file_path = self
+            .seeding_registry
+            .entries
+            .read()
+            .await
+            .get(file_hash)
+            .map(|entry| entry.file_path.clone())
+            .ok_or_else(|| {
+                ProtocolError::DownloadNotFound("File not found in seeding registry".to_string())
+            })?;
+
+        let (actual_hash, error) = match self.calculate_file_hash(&file_path).await {
+            Ok(hash) => (Some(hash), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        let intact = actual_hash
+            .as_deref()
+            .is_some_and(|hash| hash.eq_ignore_ascii_case(file_hash));
+        let verified_at = seeding::unix_now();
+
+        if intact {
+            self.seeding_registry.mark_verified(file_hash, verified_at).await;
+        } else {
+            warn!(
+                "Seeded file {:?} no longer matches {} ({}); stopping seeding",
+                file_path,
+                file_hash,
+                error.as_deref().or(actual_hash.as_deref()).unwrap_or_default()
+            );
+            self.stop_seeding_all(file_hash).await?;
+        }
+
+        Ok(VerifyResult {
+            file_hash: file_hash.to_string(),
+            file_path,
+            intact,
+            actual_hash,
+            error,
+            verified_at,
+        })
+    }
+
+    /// Verify every seed that hasn't been checked for `max_age`, counting from when it
+    /// started seeding. Files are hashed one at a time to keep the disk load down.
+    pub async fn verify_stale_seeds(&self, max_age: Duration) -> Vec<VerifyResult> {
+        let now = seeding::unix_now();
+        let due: Vec<String> = self
+            .seeding_registry
+            .entries
+            .read()
+            .await
+            .values()
+            .filter(|entry| {
+                let checked_at = entry.last_verified_at.unwrap_or(entry.started_at);
+                now.saturating_sub(checked_at) >= max_age.as_secs()
+            })
+            .map(|entry| entry.file_hash.clone())
+            .collect();
+
+        let mut results = Vec::new();
+        for file_hash in due {
+            match self.verify_seed(&file_hash).await {
+                Ok(result) => results.push(result),
+                // Stopped seeding while the others were checked
+                Err(e) => debug!("Skipped verifying {}: {}", file_hash, e),
+            }
+        }
+        results
+    }
+
+    /// List all files currently being seeded, with up-to-date upload statistics.
     pub async fn list_seeding_files(&self) -> Vec<SeedingEntry> {
+        self.refresh_seeding_stats().await;
         self.seeding_registry.list_all().await
     }
 
+    /// Files being seeded that are tagged with `label`.
+    pub async fn list_seeding_files_by_label(&self, label: &str) -> Vec<SeedingEntry> {
+        self.refresh_seeding_stats().await;
+        self.seeding_registry.list_by_label(label).await
+    }
+
+    /// Replaces the labels used to group a seeded file.
+    pub async fn set_seeding_labels(&self, file_hash: &str, labels: Vec<String>) {
+        self.seeding_registry.set_labels(file_hash, labels).await;
+    }
+
+    /// Replaces the seeding registry, e.g. with one that persists upload statistics.
+    pub fn set_seeding_registry(&mut self, registry: SeedingRegistry) {
+        self.seeding_registry = registry;
+    }
+
+    /// Shared handle to the seeding registry, for services that upload outside the
+    /// protocol handlers (such as WebRTC) to record their uploads.
+    pub fn seeding_registry(&self) -> SeedingRegistry {
+        self.seeding_registry.clone()
+    }
+
+    /// Pulls the upload counters each seeding handler reports into the registry
+    async fn refresh_seeding_stats(&self) {
+        // (protocol, identifier) -> file_hash for everything currently registered
+        let seeded: HashMap<(String, String), String> = {
+            let entries = self.seeding_registry.entries.read().await;
+            entries
+                .values()
+                .flat_map(|entry| {
+                    entry.protocols.iter().map(|(protocol, info)| {
+                        (
+                            (protocol.clone(), info.identifier.clone()),
+                            entry.file_hash.clone(),
+                        )
+                    })
+                })
+                .collect()
+        };
+        if seeded.is_empty() {
+            return;
+        }
+
+        for handler in &self.handlers {
+            if !handler.capabilities().supports_seeding {
+                continue;
+            }
+            let protocol = handler.name().to_string();
+            match handler.list_seeding().await {
+                Ok(infos) => {
+                    for info in infos {
+                        let key = (protocol.clone(), info.identifier.clone());
+                        if let Some(file_hash) = seeded.get(&key) {
+                            self.seeding_registry
+                                .sync_protocol_stats(file_hash, &protocol, info)
+                                .await;
+                        }
+                    }
+                }
+                Err(e) => debug!("Failed to list seeding for {}: {}", protocol, e),
+            }
+        }
+    }
+
     /// Calculate file hash (SHA-256)
     pub async fn calculate_file_hash(&self, file_path: &PathBuf) -> Result<String, ProtocolError> {
-        let data = tokio::fs::read(file_path)
-            .await
-            .map_err(|e| ProtocolError::FileNotFound(e.to_string()))?;
+        self.calculate_file_hash_with(file_path, HashAlgorithm::Sha256).await
+    }
 
-        let mut hasher = Sha256::new();
-        hasher.update(&data);
-        Ok(hex::encode(hasher.finalize()))
+    /// Calculate file hash with the given algorithm
+    pub async fn calculate_file_hash_with(
+        &self,
+        file_path: &PathBuf,
+        algorithm: HashAlgorithm,
+    ) -> Result<String, ProtocolError> {
+        // Stream the file in fixed-size buffers so memory stays flat for large seeds
+        let file_path = file_path.clone();
+        tokio::task::spawn_blocking(move || hash_file_blocking(&file_path, algorithm))
+            .await
+            .map_err(|e| ProtocolError::Internal(format!("Hashing task failed: {}", e)))?
     }
 
-    /// Returns all protocols that can serve the file
+    /// Returns all enabled protocols that can serve the file
     pub async fn detect_protocols(&self, file_identifier: String) -> Vec<String> {
-        let mut map: HashMap<String, &dyn ProtocolHandler> = HashMap::new();
-        for handler in &self.handlers {
-            map.insert(handler.name().to_string(), handler.as_ref());
-        }
-    
+        let map = self.enabled_handler_map();
         self.detector.detect_all(&file_identifier, &map).await
     }
     
 
     /// Returns the best protocol for downloading the file
     pub async fn detect_best_protocol(&self, file_identifier: String) -> Option<String> {
-        let mut map: HashMap<String, &dyn ProtocolHandler> = HashMap::new();
-        for handler in &self.handlers {
-            map.insert(handler.name().to_string(), handler.as_ref());
-        }
-
+        let map = self.enabled_handler_map();
         self.detector.detect_best(&file_identifier, &map).await
     }
 
@@ -566,11 +1019,7 @@ impl ProtocolManager {
         // Update detector preferences
         self.detector.set_priority(preferences);
 
-        let mut map: HashMap<String, &dyn ProtocolHandler> = HashMap::new();
-        for handler in &self.handlers {
-            map.insert(handler.name().to_string(), handler.as_ref());
-        }
-
+        let map = self.enabled_handler_map();
         self.detector.detect_best(&file_identifier, &map).await
     }
 }
@@ -580,4 +1029,203 @@ impl Default for ProtocolManager {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+
+/// Size of each read when hashing files
+const HASH_READ_BUFFER_SIZE: usize = 4 * 1024 * 1024;
+
+/// Hash a file incrementally on the current (blocking) thread
+fn hash_file_blocking(file_path: &Path, algorithm: HashAlgorithm) -> Result<String, ProtocolError> {
+    let mut file = std::fs::File::open(file_path)
+        .map_err(|e| ProtocolError::FileNotFound(e.to_string()))?;
+
+    let mut hasher = algorithm.hasher();
+    let mut buffer = vec![0u8; HASH_READ_BUFFER_SIZE];
+    loop {
+        let read = match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(ProtocolError::Internal(format!("Failed to read file: {}", e))),
+        };
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hasher.finalize_hex())
+}
+
+/// Directory under the app data directory that holds content seeded from readers
+pub const DEFAULT_SEED_STAGING_DIR: &str = "chiral-seeds";
+
+/// Where content seeded from a reader is kept by default
+///
+/// The app data directory rather than the system temp directory, which may be cleaned
+/// while the content is still seeded, or be a small in-memory filesystem.
+pub fn default_seed_staging_dir() -> PathBuf {
+    directories::ProjectDirs::from("com", "chiral-network", "chiral-network")
+        .map(|dirs| dirs.data_dir().join(DEFAULT_SEED_STAGING_DIR))
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_SEED_STAGING_DIR))
+}
+
+/// Content written to the staging directory by `stage_seed_content`
+#[derive(Debug, Clone)]
+pub struct StagedSeed {
+    pub file_hash: String,
+    pub file_path: PathBuf,
+    /// False when the same content was already staged under this name
+    pub newly_staged: bool,
+}
+
+impl StagedSeed {
+    /// Remove the content again after seeding it failed. Content staged by an earlier call
+    /// is left for whatever seeds it.
+    pub async fn discard(&self) {
+        if self.newly_staged {
+            remove_staged_file(&self.file_path).await;
+        }
+    }
+}
+
+/// Remove a staged file and the directory of its hash once nothing else is staged there
+async fn remove_staged_file(file_path: &Path) {
+    if let Err(e) = tokio::fs::remove_file(file_path).await {
+        warn!("Failed to remove staged seed {}: {}", file_path.display(), e);
+    }
+    if let Some(hash_dir) = file_path.parent() {
+        // Fails, as it should, while other names for the same content are staged
+        let _ = tokio::fs::remove_dir(hash_dir).await;
+    }
+}
+
+fn staging_error(action: &str, e: impl std::fmt::Display) -> ProtocolError {
+    ProtocolError::Internal(format!("Failed to {}: {}", action, e))
+}
+
+/// Write `size` bytes from `reader` to `{staging_dir}/{sha256}/{file_name}`, hashing them
+/// on the way
+///
+/// The content is written to a file in `staging_dir` and renamed into place, so it never
+/// crosses filesystems. Fails without leaving a file behind if the reader yields more or
+/// fewer than `size` bytes, or the file can't be moved into place.
+pub async fn stage_seed_content(
+    reader: &mut (dyn AsyncRead + Send + Unpin),
+    size: u64,
+    file_name: &str,
+    staging_dir: &Path,
+) -> Result<StagedSeed, ProtocolError> {
+    // Only the final component, so a name can't escape the staging directory
+    let file_name = Path::new(file_name)
+        .file_name()
+        .ok_or_else(|| {
+            ProtocolError::InvalidIdentifier(format!("Invalid file name: {}", file_name))
+        })?
+        .to_owned();
+
+    tokio::fs::create_dir_all(staging_dir)
+        .await
+        .map_err(|e| staging_error("create staging directory", e))?;
+    let incoming_path = staging_dir.join(format!(".incoming-{}", uuid::Uuid::new_v4()));
+
+    let file_hash = match write_and_hash(reader, size, &incoming_path).await {
+        Ok(file_hash) => file_hash,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&incoming_path).await;
+            return Err(e);
+        }
+    };
+
+    let hash_dir = staging_dir.join(&file_hash);
+    let file_path = hash_dir.join(&file_name);
+    let already_staged = tokio::fs::try_exists(&file_path).await.unwrap_or(false);
+    let moved = match tokio::fs::create_dir_all(&hash_dir).await {
+        Ok(()) => tokio::fs::rename(&incoming_path, &file_path)
+            .await
+            .map_err(|e| staging_error("move staging file", e)),
+        Err(e) => Err(staging_error("create staging directory", e)),
+    };
+    if let Err(e) = moved {
+        let _ = tokio::fs::remove_file(&incoming_path).await;
+        return Err(e);
+    }
+    debug!("Staged {} for seeding at {}", file_hash, file_path.display());
+    Ok(StagedSeed {
+        file_hash,
+        file_path,
+        newly_staged: !already_staged,
+    })
+}
+
+/// Write `size` bytes from `reader` to `path`, returning their SHA-256
+///
+/// The content goes to a file beside `path` first and is renamed into place once complete,
+/// so `path` never holds partial content and is left alone if the reader falls short.
+async fn write_seed_file(
+    reader: &mut (dyn AsyncRead + Send + Unpin),
+    size: u64,
+    path: &Path,
+) -> Result<String, ProtocolError> {
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    tokio::fs::create_dir_all(dir)
+        .await
+        .map_err(|e| staging_error("create output directory", e))?;
+    let incoming_path = dir.join(format!(".incoming-{}", uuid::Uuid::new_v4()));
+
+    let moved = match write_and_hash(reader, size, &incoming_path).await {
+        Ok(file_hash) => tokio::fs::rename(&incoming_path, path)
+            .await
+            .map(|()| file_hash)
+            .map_err(|e| staging_error("move file into place", e)),
+        Err(e) => Err(e),
+    };
+    if moved.is_err() {
+        let _ = tokio::fs::remove_file(&incoming_path).await;
+    }
+    moved
+}
+
+/// Copy exactly `size` bytes from `reader` to a new file at `path`, returning their SHA-256
+async fn write_and_hash(
+    reader: &mut (dyn AsyncRead + Send + Unpin),
+    size: u64,
+    path: &Path,
+) -> Result<String, ProtocolError> {
+    let mut file = tokio::fs::File::create(path)
+        .await
+        .map_err(|e| staging_error("create staging file", e))?;
+    let mut hasher = HashAlgorithm::Sha256.hasher();
+    let mut buffer = vec![0u8; HASH_READ_BUFFER_SIZE.min(size.max(1) as usize)];
+    let mut written = 0u64;
+    loop {
+        let read = reader
+            .read(&mut buffer)
+            .await
+            .map_err(|e| staging_error("read content", e))?;
+        if read == 0 {
+            break;
+        }
+        written += read as u64;
+        if written > size {
+            return Err(ProtocolError::Internal(format!(
+                "Content is longer than the declared {} bytes",
+                size
+            )));
+        }
+        hasher.update(&buffer[..read]);
+        file.write_all(&buffer[..read])
+            .await
+            .map_err(|e| staging_error("write staging file", e))?;
+    }
+    if written != size {
+        return Err(ProtocolError::Internal(format!(
+            "Content ended after {} of the declared {} bytes",
+            written, size
+        )));
+    }
+    file.flush()
+        .await
+        .map_err(|e| staging_error("write staging file", e))?;
+    Ok(hasher.finalize_hex())
+}
@@ -419,6 +419,11 @@ impl MultiSourceCoordinator {
                     chunk_size: None,
                     encryption: false,
                     bandwidth_limit: None,
+                    // Per-source temp files are ours to replace
+                    on_conflict: OnConflict::Overwrite,
+                    http_headers: Default::default(),
+                    torrent_files: None,
+                    http_auth: None,
                 },
             )
             .await?;
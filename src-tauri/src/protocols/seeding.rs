@@ -1,13 +1,29 @@
 // src-tauri/src/protocols/seeding.rs
 
 use super::traits::SeedingInfo;
+use crate::multi_source_download::normalize_labels;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 use tracing::{info, warn};
 
+/// How often upload statistics are written to disk while uploads are in progress
+const STATS_PERSIST_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long a seeded file goes before it is re-checked against its hash
+pub const SEED_VERIFY_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Most peers remembered per file for telling repeat peers from new ones; beyond it the
+/// least recently served are forgotten
+const MAX_SERVED_PEERS: usize = 10_000;
+
+/// How long a peer is remembered after it was last served; one that comes back later is
+/// counted again
+const SERVED_PEER_TTL_SECS: u64 = 30 * 24 * 60 * 60;
+
 /// Represents a single file being seeded, potentially across multiple protocols.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SeedingEntry {
@@ -22,13 +38,116 @@ pub struct SeedingEntry {
     /// Unix timestamp when seeding first started
     pub started_at: u64,
     /// Total bytes uploaded across all protocols for this file
-    pub total_uploaded: u64,
+    pub bytes_uploaded: u64,
+    /// Number of distinct peers this file has been uploaded to. Peers are told apart by ID
+    /// where the protocol reports one; otherwise the most it served at once is counted.
+    pub peer_count: u32,
+    /// Unix timestamp of the most recent upload, if any
+    pub last_upload_at: Option<u64>,
+    /// User-chosen labels for grouping transfers
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// Unix timestamp of the last check that the file still matches its hash
+    #[serde(default)]
+    pub last_verified_at: Option<u64>,
+}
+
+/// Outcome of re-checking a seeded file against the hash it is seeded under
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyResult {
+    pub file_hash: String,
+    pub file_path: PathBuf,
+    /// Whether the file still hashes to `file_hash`. When it doesn't, seeding was stopped.
+    pub intact: bool,
+    /// What the file hashes to now; unset when it couldn't be read
+    pub actual_hash: Option<String>,
+    /// Why the file couldn't be read
+    pub error: Option<String>,
+    pub verified_at: u64,
+}
+
+/// Upload counters for one file hash, as persisted between runs
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct UploadRecord {
+    bytes_uploaded: u64,
+    /// Distinct identified peers served, including those since forgotten
+    peer_count: u32,
+    last_upload_at: Option<u64>,
+    /// Peers already counted in `peer_count`, with when each was last served
+    #[serde(default)]
+    served_peers: HashMap<String, u64>,
+    /// Most peers each protocol that doesn't identify them reported at once
+    #[serde(default)]
+    peak_peers: HashMap<String, u32>,
+    /// Kept with the statistics so a file's labels survive re-seeding and restarts
+    #[serde(default)]
+    labels: Vec<String>,
+    /// The identifier (magnet link, ed2k link...) each protocol last seeded the file under
+    #[serde(default)]
+    identifiers: HashMap<String, String>,
+}
+
+impl UploadRecord {
+    /// Distinct peers served, counting the protocols that don't identify them by their peak
+    fn total_peers(&self) -> u32 {
+        self.peak_peers
+            .values()
+            .fold(self.peer_count, |total, peak| total.saturating_add(*peak))
+    }
+
+    /// Note an upload to `peer_id` at `now`, counting it unless it was served recently
+    fn serve_peer(&mut self, peer_id: &str, now: u64) {
+        let fresh = |served_at: u64| now.saturating_sub(served_at) < SERVED_PEER_TTL_SECS;
+        if self
+            .served_peers
+            .insert(peer_id.to_string(), now)
+            .is_some_and(fresh)
+        {
+            return;
+        }
+        self.peer_count = self.peer_count.saturating_add(1);
+
+        if self.served_peers.len() <= MAX_SERVED_PEERS {
+            return;
+        }
+        self.served_peers.retain(|_, served_at| fresh(*served_at));
+        if self.served_peers.len() > MAX_SERVED_PEERS {
+            let mut served_at: Vec<u64> = self.served_peers.values().copied().collect();
+            let excess = self.served_peers.len() - MAX_SERVED_PEERS;
+            let (_, cutoff, _) = served_at.select_nth_unstable(excess - 1);
+            let cutoff = *cutoff;
+            let mut to_forget = excess;
+            self.served_peers.retain(|_, served_at| {
+                if to_forget > 0 && *served_at <= cutoff {
+                    to_forget -= 1;
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+    }
+}
+
+pub(crate) fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
 }
 
 /// Manages all active seeding entries in a thread-safe way.
+///
+/// Upload statistics are tracked per file hash separately from the entries, so they
+/// outlive a file being unseeded and re-seeded and, with a stats path, restarts.
+#[derive(Clone)]
 pub struct SeedingRegistry {
     /// Map of file_hash -> SeedingEntry
     pub entries: Arc<RwLock<HashMap<String, SeedingEntry>>>,
+    upload_stats: Arc<RwLock<HashMap<String, UploadRecord>>>,
+    stats_path: Option<PathBuf>,
+    last_persisted: Arc<std::sync::Mutex<Option<Instant>>>,
 }
 
 impl SeedingRegistry {
@@ -36,6 +155,31 @@ impl SeedingRegistry {
     pub fn new() -> Self {
         Self {
             entries: Arc::new(RwLock::new(HashMap::new())),
+            upload_stats: Arc::new(RwLock::new(HashMap::new())),
+            stats_path: None,
+            last_persisted: Arc::new(std::sync::Mutex::new(None)),
+        }
+    }
+
+    /// Creates a registry whose upload statistics are loaded from and saved to `path`.
+    /// A missing or unreadable file starts the statistics from zero.
+    pub fn with_stats_path(path: PathBuf) -> Self {
+        let upload_stats = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable seeding stats {:?}: {}", path, e);
+                HashMap::new()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                warn!("Failed to read seeding stats {:?}: {}", path, e);
+                HashMap::new()
+            }
+        };
+
+        Self {
+            upload_stats: Arc::new(RwLock::new(upload_stats)),
+            stats_path: Some(path),
+            ..Self::new()
         }
     }
 
@@ -68,39 +212,381 @@ impl SeedingRegistry {
                 file_hash: file_hash.clone(),
                 file_size,
                 protocols: HashMap::new(),
-                started_at: std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap_or_default()
-                    .as_secs(),
-                total_uploaded: 0,
+                started_at: unix_now(),
+                bytes_uploaded: 0,
+                peer_count: 0,
+                last_upload_at: None,
+                labels: Vec::new(),
+                last_verified_at: None,
             }
         });
 
+        // The latest seed serves from `file_path`
+        entry.file_path = file_path;
+
         // Add or update the protocol-specific info
-        entry.protocols.insert(protocol, seeding_info);
+        let identifier = seeding_info.identifier.clone();
+        entry.protocols.insert(protocol.clone(), seeding_info);
+        drop(entries);
+
+        // Remember the identifier so later seeds of the content can keep handing it out
+        let changed = {
+            let mut upload_stats = self.upload_stats.write().await;
+            let record = upload_stats.entry(file_hash).or_default();
+            record.identifiers.insert(protocol, identifier.clone()).as_ref() != Some(&identifier)
+        };
+        if changed {
+            if let Err(e) = self.flush_stats().await {
+                warn!("Failed to save seeding stats: {}", e);
+            }
+        }
         Ok(())
     }
 
+    /// The identifier `protocol` last seeded `file_hash` under, in this run or a previous one
+    pub async fn persisted_identifier(&self, file_hash: &str, protocol: &str) -> Option<String> {
+        self.upload_stats
+            .read()
+            .await
+            .get(file_hash)
+            .and_then(|record| record.identifiers.get(protocol).cloned())
+    }
+
+    /// Records that `file_hash` was found to still match its content at `verified_at`
+    pub async fn mark_verified(&self, file_hash: &str, verified_at: u64) {
+        if let Some(entry) = self.entries.write().await.get_mut(file_hash) {
+            entry.last_verified_at = Some(verified_at);
+        }
+    }
+
     /// Removes a file from the seeding registry entirely (stops seeding on all protocols).
     pub async fn remove_seeding(&self, file_hash: &str) {
         let mut entries = self.entries.write().await;
         if entries.remove(file_hash).is_some() {
             info!("Removed seeding entry for file hash: {}", file_hash);
         }
+        drop(entries);
+
+        if let Err(e) = self.flush_stats().await {
+            warn!("Failed to save seeding stats: {}", e);
+        }
     }
 
-    /// Returns a list of all currently seeding files.
+    /// Returns a list of all currently seeding files, with their upload statistics.
     pub async fn list_all(&self) -> Vec<SeedingEntry> {
         let entries = self.entries.read().await;
-        entries.values().cloned().collect()
+        let upload_stats = self.upload_stats.read().await;
+        entries
+            .values()
+            .cloned()
+            .map(|mut entry| {
+                if let Some(record) = upload_stats.get(&entry.file_hash) {
+                    entry.bytes_uploaded = record.bytes_uploaded;
+                    entry.peer_count = record.total_peers();
+                    entry.last_upload_at = record.last_upload_at;
+                    entry.labels = record.labels.clone();
+                }
+                entry
+            })
+            .collect()
+    }
+
+    /// Seeded files tagged with `label`, with their upload statistics.
+    pub async fn list_by_label(&self, label: &str) -> Vec<SeedingEntry> {
+        let label = label.trim();
+        self.list_all()
+            .await
+            .into_iter()
+            .filter(|entry| entry.labels.iter().any(|l| l == label))
+            .collect()
+    }
+
+    /// Replaces the labels of a file, seeded now or later, and saves them right away.
+    pub async fn set_labels(&self, file_hash: &str, labels: Vec<String>) {
+        {
+            let mut upload_stats = self.upload_stats.write().await;
+            let record = upload_stats.entry(file_hash.to_string()).or_default();
+            record.labels = normalize_labels(labels);
+        }
+
+        if let Err(e) = self.flush_stats().await {
+            warn!("Failed to save seeding stats: {}", e);
+        }
     }
 
     /// Updates the total uploaded bytes for a specific file.
     /// This would be called periodically by a monitoring task.
     pub async fn update_stats(&self, file_hash: &str, bytes_uploaded_delta: u64) {
-        let mut entries = self.entries.write().await;
-        if let Some(entry) = entries.get_mut(file_hash) {
-            entry.total_uploaded += bytes_uploaded_delta;
+        self.record_upload(file_hash, None, bytes_uploaded_delta).await;
+    }
+
+    /// Records `bytes` uploaded for a file. A peer seen for the first time is added to
+    /// the file's peer count.
+    pub async fn record_upload(&self, file_hash: &str, peer_id: Option<&str>, bytes: u64) {
+        {
+            let mut upload_stats = self.upload_stats.write().await;
+            let record = upload_stats.entry(file_hash.to_string()).or_default();
+            record.bytes_uploaded += bytes;
+            if let Some(peer_id) = peer_id {
+                record.serve_peer(peer_id, unix_now());
+            }
+            if bytes > 0 {
+                record.last_upload_at = Some(unix_now());
+            }
+        }
+
+        self.persist_if_due().await;
+    }
+
+    /// Folds the latest counters a protocol handler reports for a seeded file into its
+    /// statistics. Handlers report running totals, so only the growth since the last
+    /// report is counted; a total that went backwards means the handler restarted.
+    ///
+    /// Handlers report how many peers are connected but not which, so a peer that leaves
+    /// and comes back can't be told from a new one; only the most connected at once counts.
+    pub async fn sync_protocol_stats(&self, file_hash: &str, protocol: &str, info: SeedingInfo) {
+        let (bytes, active_peers) = {
+            let mut entries = self.entries.write().await;
+            let Some(entry) = entries.get_mut(file_hash) else {
+                return;
+            };
+            let previous_bytes = entry
+                .protocols
+                .get(protocol)
+                .map(|previous| previous.bytes_uploaded)
+                .unwrap_or_default();
+            let bytes = if info.bytes_uploaded >= previous_bytes {
+                info.bytes_uploaded - previous_bytes
+            } else {
+                info.bytes_uploaded
+            };
+            let active_peers = u32::try_from(info.active_peers).unwrap_or(u32::MAX);
+            entry.protocols.insert(protocol.to_string(), info);
+            (bytes, active_peers)
+        };
+
+        {
+            let mut upload_stats = self.upload_stats.write().await;
+            let record = upload_stats.entry(file_hash.to_string()).or_default();
+            let peak = record.peak_peers.entry(protocol.to_string()).or_default();
+            if bytes == 0 && active_peers <= *peak {
+                return;
+            }
+            *peak = (*peak).max(active_peers);
+            record.bytes_uploaded += bytes;
+            if bytes > 0 {
+                record.last_upload_at = Some(unix_now());
+            }
+        }
+
+        self.persist_if_due().await;
+    }
+
+    /// Writes the upload statistics to the stats path, if one is configured.
+    pub async fn flush_stats(&self) -> Result<(), String> {
+        let Some(path) = &self.stats_path else {
+            return Ok(());
+        };
+
+        let contents = {
+            let upload_stats = self.upload_stats.read().await;
+            serde_json::to_string_pretty(&*upload_stats)
+                .map_err(|e| format!("Failed to encode seeding stats: {}", e))?
+        };
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| format!("Failed to create {:?}: {}", parent, e))?;
+        }
+        // Write to a sibling file first so a crash never leaves a truncated stats file
+        let tmp_path = path.with_extension("json.tmp");
+        tokio::fs::write(&tmp_path, contents)
+            .await
+            .map_err(|e| format!("Failed to write {:?}: {}", tmp_path, e))?;
+        tokio::fs::rename(&tmp_path, path)
+            .await
+            .map_err(|e| format!("Failed to replace {:?}: {}", path, e))?;
+
+        *self.last_persisted.lock().unwrap() = Some(Instant::now());
+        Ok(())
+    }
+
+    async fn persist_if_due(&self) {
+        if self.stats_path.is_none() {
+            return;
+        }
+        let due = self
+            .last_persisted
+            .lock()
+            .unwrap()
+            .map_or(true, |at| at.elapsed() >= STATS_PERSIST_INTERVAL);
+        if due {
+            if let Err(e) = self.flush_stats().await {
+                warn!("Failed to save seeding stats: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn seeding_info(bytes_uploaded: u64, active_peers: usize) -> SeedingInfo {
+        SeedingInfo {
+            identifier: "magnet:?xt=urn:btih:abc".to_string(),
+            file_path: PathBuf::from("file.bin"),
+            protocol: "bittorrent".to_string(),
+            active_peers,
+            bytes_uploaded,
+        }
+    }
+
+    #[tokio::test]
+    async fn upload_stats_accumulate_and_survive_restart() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("file.bin");
+        std::fs::write(&file_path, b"seeded").unwrap();
+        let stats_path = dir.path().join("seeding_stats.json");
+
+        let registry = SeedingRegistry::with_stats_path(stats_path.clone());
+        registry
+            .add_seeding(
+                "hash".to_string(),
+                file_path.clone(),
+                "bittorrent".to_string(),
+                seeding_info(0, 0),
+            )
+            .await
+            .unwrap();
+
+        registry.record_upload("hash", Some("peer-a"), 100).await;
+        registry.record_upload("hash", Some("peer-a"), 50).await;
+        registry.record_upload("hash", Some("peer-b"), 25).await;
+        registry
+            .sync_protocol_stats("hash", "bittorrent", seeding_info(1000, 2))
+            .await;
+        registry
+            .sync_protocol_stats("hash", "bittorrent", seeding_info(1500, 1))
+            .await;
+
+        let entry = registry.list_all().await.pop().unwrap();
+        assert_eq!(entry.bytes_uploaded, 175 + 1500);
+        assert_eq!(entry.peer_count, 4);
+        assert!(entry.last_upload_at.is_some());
+
+        registry.flush_stats().await.unwrap();
+        let restarted = SeedingRegistry::with_stats_path(stats_path);
+        restarted
+            .add_seeding(
+                "hash".to_string(),
+                file_path,
+                "bittorrent".to_string(),
+                seeding_info(0, 0),
+            )
+            .await
+            .unwrap();
+        restarted.record_upload("hash", Some("peer-a"), 5).await;
+
+        let entry = restarted.list_all().await.pop().unwrap();
+        assert_eq!(entry.bytes_uploaded, 1680);
+        assert_eq!(entry.peer_count, 4);
+    }
+
+    #[test]
+    fn served_peers_are_counted_once_and_forgotten_when_stale_or_too_many() {
+        let mut record = UploadRecord::default();
+        record.serve_peer("peer-a", 1_000);
+        record.serve_peer("peer-a", 2_000);
+        assert_eq!(record.peer_count, 1);
+        assert_eq!(record.served_peers["peer-a"], 2_000);
+
+        // A peer not served for longer than the TTL is counted again
+        record.serve_peer("peer-b", 2_000 + SERVED_PEER_TTL_SECS);
+        record.serve_peer("peer-a", 2_000 + SERVED_PEER_TTL_SECS);
+        assert_eq!(record.peer_count, 3);
+
+        // Past the cap the least recently served make room
+        let now = 3_000 + SERVED_PEER_TTL_SECS;
+        for i in 0..MAX_SERVED_PEERS {
+            record.serve_peer(&format!("peer-{}", i), now + i as u64);
         }
+        assert_eq!(record.served_peers.len(), MAX_SERVED_PEERS);
+        assert!(!record.served_peers.contains_key("peer-b"));
+        assert_eq!(record.peer_count as usize, 3 + MAX_SERVED_PEERS);
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn labels_filter_entries_and_survive_restart() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("file.bin");
+        std::fs::write(&file_path, b"seeded").unwrap();
+        let stats_path = dir.path().join("seeding_stats.json");
+
+        let registry = SeedingRegistry::with_stats_path(stats_path.clone());
+        for file_hash in ["backup", "song"] {
+            registry
+                .add_seeding(
+                    file_hash.to_string(),
+                    file_path.clone(),
+                    "bittorrent".to_string(),
+                    seeding_info(0, 0),
+                )
+                .await
+                .unwrap();
+        }
+        registry
+            .set_labels("backup", vec!["backups".to_string(), " backups ".to_string()])
+            .await;
+        registry.set_labels("song", vec!["media".to_string()]).await;
+
+        let backups = registry.list_by_label("backups").await;
+        assert_eq!(backups.len(), 1);
+        assert_eq!(backups[0].file_hash, "backup");
+        assert_eq!(backups[0].labels, vec!["backups".to_string()]);
+
+        let restarted = SeedingRegistry::with_stats_path(stats_path);
+        restarted
+            .add_seeding(
+                "song".to_string(),
+                file_path,
+                "bittorrent".to_string(),
+                seeding_info(0, 0),
+            )
+            .await
+            .unwrap();
+        let media = restarted.list_by_label("media").await;
+        assert_eq!(media.len(), 1);
+        assert_eq!(media[0].file_hash, "song");
+    }
+
+    #[tokio::test]
+    async fn seeding_identifiers_survive_restart() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("file.bin");
+        std::fs::write(&file_path, b"seeded").unwrap();
+        let stats_path = dir.path().join("seeding_stats.json");
+
+        let registry = SeedingRegistry::with_stats_path(stats_path.clone());
+        registry
+            .add_seeding(
+                "hash".to_string(),
+                file_path,
+                "bittorrent".to_string(),
+                seeding_info(0, 0),
+            )
+            .await
+            .unwrap();
+        registry.remove_seeding("hash").await;
+
+        // Saved as soon as it was seeded, and kept after the file stopped seeding
+        let restarted = SeedingRegistry::with_stats_path(stats_path);
+        assert_eq!(
+            restarted.persisted_identifier("hash", "bittorrent").await.as_deref(),
+            Some("magnet:?xt=urn:btih:abc")
+        );
+        assert_eq!(restarted.persisted_identifier("hash", "ed2k").await, None);
+    }
+}
@@ -4,10 +4,17 @@
 //! Each protocol (BitTorrent, HTTP, FTP, ED2K) implements these traits to provide
 //! a consistent interface for file downloads and seeding operations.
 
+use crate::download_source::TorrentFileFilter;
+use crate::transfer_events::calculate_eta;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
+use tokio::io::AsyncRead;
+
+/// Content to seed that isn't already a file on disk
+pub type SeedReader = Box<dyn AsyncRead + Send + Unpin>;
 
 /// Options for initiating a download
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -22,6 +29,19 @@ pub struct DownloadOptions {
     pub encryption: bool,
     /// Bandwidth limit in bytes per second (0 = unlimited)
     pub bandwidth_limit: Option<u64>,
+    /// What to do if a file already exists at `output_path`
+    #[serde(default)]
+    pub on_conflict: OnConflict,
+    /// Extra request headers for HTTP-based protocols, e.g. `User-Agent` or `Authorization`
+    #[serde(default)]
+    pub http_headers: HashMap<String, String>,
+    /// Files to fetch from a multi-file torrent; the whole torrent when unset
+    #[serde(default)]
+    pub torrent_files: Option<TorrentFileFilter>,
+    /// Credentials for HTTP downloads. Never serialized, so they can't end up in
+    /// persisted queues or exported state.
+    #[serde(default, skip_serializing)]
+    pub http_auth: Option<HttpAuth>,
 }
 
 impl Default for DownloadOptions {
@@ -32,10 +52,85 @@ impl Default for DownloadOptions {
             chunk_size: None,
             encryption: false,
             bandwidth_limit: None,
+            on_conflict: OnConflict::default(),
+            http_headers: HashMap::new(),
+            torrent_files: None,
+            http_auth: None,
         }
     }
 }
 
+/// Credentials for servers that require HTTP authentication
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "camelCase")]
+pub enum HttpAuth {
+    /// Sent as `Authorization: Basic`; also used to answer a `Digest` challenge
+    Basic { user: String, pass: String },
+    /// Sent as `Authorization: Bearer`
+    Bearer(String),
+}
+
+impl std::fmt::Debug for HttpAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HttpAuth::Basic { user, .. } => f
+                .debug_struct("Basic")
+                .field("user", user)
+                .field("pass", &"<redacted>")
+                .finish(),
+            HttpAuth::Bearer(_) => f.debug_tuple("Bearer").field(&"<redacted>").finish(),
+        }
+    }
+}
+
+/// How to handle an output path that already holds a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnConflict {
+    /// Replace the existing file
+    #[default]
+    Overwrite,
+    /// Append " (1)", " (2)", ... to the file name until it is free
+    Rename,
+    /// Refuse to download
+    Fail,
+}
+
+/// Upper bound on " (n)" suffixes tried before giving up
+pub const MAX_RENAME_ATTEMPTS: u32 = 1000;
+
+impl OnConflict {
+    /// Pick the path to write to. Existing directories are not conflicts,
+    /// since handlers treat them as the folder to download into.
+    pub fn resolve(&self, path: &Path) -> Result<PathBuf, ProtocolError> {
+        if !path.is_file() || *self == OnConflict::Overwrite {
+            return Ok(path.to_path_buf());
+        }
+
+        if *self == OnConflict::Fail {
+            return Err(ProtocolError::AlreadyExists(path.display().to_string()));
+        }
+
+        (1..=MAX_RENAME_ATTEMPTS)
+            .map(|n| numbered_path(path, n))
+            .find(|candidate| !candidate.exists())
+            .ok_or_else(|| ProtocolError::AlreadyExists(path.display().to_string()))
+    }
+}
+
+/// Build "name (n).ext" next to `path`
+pub fn numbered_path(path: &Path, n: u32) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let file_name = match path.extension() {
+        Some(ext) => format!("{} ({}).{}", stem, n, ext.to_string_lossy()),
+        None => format!("{} ({})", stem, n),
+    };
+    path.with_file_name(file_name)
+}
+
 /// Options for seeding a file
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SeedOptions {
@@ -67,13 +162,30 @@ pub struct DownloadProgress {
     /// Current download speed in bytes per second
     pub download_speed: f64,
     /// Estimated time remaining in seconds
-    pub eta_seconds: Option<u64>,
+    pub eta_seconds: Option<u32>,
+    /// Bytes still to download
+    pub remaining_bytes: u64,
     /// Number of active peers/connections
     pub active_peers: usize,
     /// Download status
     pub status: DownloadStatus,
 }
 
+impl DownloadProgress {
+    /// Fill in `remaining_bytes` and `eta_seconds` from the total size and current speed
+    ///
+    /// There is no ETA while the total size is unknown or the download isn't running.
+    pub fn with_estimates(mut self) -> Self {
+        self.remaining_bytes = self.total_bytes.saturating_sub(self.downloaded_bytes);
+        self.eta_seconds = if self.total_bytes > 0 && self.status == DownloadStatus::Downloading {
+            calculate_eta(self.remaining_bytes, self.download_speed)
+        } else {
+            None
+        };
+        self
+    }
+}
+
 /// Status of a download
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum DownloadStatus {
@@ -132,6 +244,10 @@ pub struct ProtocolCapabilities {
     pub supports_encryption: bool,
     /// Uses DHT for peer discovery
     pub supports_dht: bool,
+    /// Seeding the same content again yields the same identifier, so links handed out
+    /// stay valid across re-seeding and restarts
+    #[serde(default)]
+    pub stable_seed_identifiers: bool,
 }
 
 impl Default for ProtocolCapabilities {
@@ -142,6 +258,7 @@ impl Default for ProtocolCapabilities {
             supports_multi_source: false,
             supports_encryption: false,
             supports_dht: false,
+            stable_seed_identifiers: false,
         }
     }
 }
@@ -292,6 +409,27 @@ pub trait ProtocolHandler: Send + Sync {
         options: SeedOptions,
     ) -> Result<SeedingInfo, ProtocolError>;
 
+    /// Starts seeding `size` bytes read from `reader`, named `file_name`
+    ///
+    /// Handlers that can only seed from a path (the default) get the content written
+    /// to a file under `default_seed_staging_dir` first.
+    async fn seed_reader(
+        &self,
+        mut reader: SeedReader,
+        size: u64,
+        file_name: &str,
+        options: SeedOptions,
+    ) -> Result<SeedingInfo, ProtocolError> {
+        let staging_dir = super::default_seed_staging_dir();
+        let staged =
+            super::stage_seed_content(&mut reader, size, file_name, &staging_dir).await?;
+        let result = self.seed(staged.file_path.clone(), options).await;
+        if result.is_err() {
+            staged.discard().await;
+        }
+        result
+    }
+
     /// Stops seeding a file
     async fn stop_seeding(&self, identifier: &str) -> Result<(), ProtocolError>;
 
@@ -323,6 +461,37 @@ pub trait ProtocolHandler: Send + Sync {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_download_progress_estimates() {
+        let progress = DownloadProgress {
+            downloaded_bytes: 400,
+            total_bytes: 1000,
+            download_speed: 100.0,
+            eta_seconds: None,
+            remaining_bytes: 0,
+            active_peers: 1,
+            status: DownloadStatus::Downloading,
+        }
+        .with_estimates();
+        assert_eq!(progress.remaining_bytes, 600);
+        assert_eq!(progress.eta_seconds, Some(6));
+
+        let unknown_size = DownloadProgress {
+            total_bytes: 0,
+            ..progress.clone()
+        }
+        .with_estimates();
+        assert_eq!(unknown_size.eta_seconds, None);
+
+        let paused = DownloadProgress {
+            status: DownloadStatus::Paused,
+            ..progress
+        }
+        .with_estimates();
+        assert_eq!(paused.remaining_bytes, 600);
+        assert_eq!(paused.eta_seconds, None);
+    }
+
     #[test]
     fn test_download_options_default() {
         let opts = DownloadOptions::default();
@@ -342,4 +511,32 @@ mod tests {
         let err = ProtocolError::NetworkError("connection refused".to_string());
         assert!(err.to_string().contains("connection refused"));
     }
+
+    #[test]
+    fn test_http_auth_stays_out_of_serialized_options() {
+        let opts: DownloadOptions = serde_json::from_value(serde_json::json!({
+            "output_path": "file.bin",
+            "max_peers": null,
+            "chunk_size": null,
+            "encryption": false,
+            "bandwidth_limit": null,
+            "http_auth": { "type": "basic", "value": { "user": "alice", "pass": "hunter2" } }
+        }))
+        .unwrap();
+        let auth = opts.http_auth.clone().unwrap();
+        assert_eq!(
+            auth,
+            HttpAuth::Basic {
+                user: "alice".to_string(),
+                pass: "hunter2".to_string()
+            }
+        );
+
+        let serialized = serde_json::to_string(&opts).unwrap();
+        assert!(!serialized.contains("hunter2"));
+        assert!(!format!("{:?}", auth).contains("hunter2"));
+        assert!(
+            !format!("{:?}", HttpAuth::Bearer("secret-token".to_string())).contains("secret-token")
+        );
+    }
 }
@@ -12,8 +12,10 @@
 // - Debuggable: All events carry contextual information for troubleshooting
 
 use crate::analytics::AnalyticsService;
+use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
 use tauri::{AppHandle, Emitter};
 use tracing::{debug, error};
@@ -28,6 +30,12 @@ pub enum TransferEvent {
     /// Transfer added to queue but not yet started
     Queued(TransferQueuedEvent),
     
+    /// Transfer left the queue, either to start or because it was canceled
+    Dequeued(TransferDequeuedEvent),
+    
+    /// Transfer is holding off until enough sources have been found
+    WaitingForSources(TransferWaitingForSourcesEvent),
+    
     /// Transfer is starting (discovering sources, initializing connections)
     Started(TransferStartedEvent),
     
@@ -43,6 +51,18 @@ pub enum TransferEvent {
     /// A chunk download failed (will be retried if possible)
     ChunkFailed(ChunkFailedEvent),
     
+    /// Failed chunks were moved from one source to another for retry
+    ChunkReassigned(ChunkReassignedEvent),
+    
+    /// A chunk arrived after another source had already delivered it and was dropped
+    ChunkDuplicateReceived(ChunkDuplicateReceivedEvent),
+    
+    /// A source failed integrity checks too often and was dropped for the rest of the transfer
+    SourceBlacklisted(SourceBlacklistedEvent),
+    
+    /// A source asked us to back off (HTTP 429/503) and is paused until `resume_at`
+    SourceThrottled(SourceThrottledEvent),
+    
     /// Progress update (periodic updates during transfer)
     Progress(TransferProgressEvent),
     
@@ -55,10 +75,16 @@ pub enum TransferEvent {
     /// Transfer completed successfully
     Completed(TransferCompletedEvent),
     
+    /// A completed download is now being seeded, as requested when it was started
+    SeedingStarted(SeedingStartedEvent),
+
+    /// A seeded file no longer matches its hash, so it stopped being seeded
+    SeedVerificationFailed(SeedVerificationFailedEvent),
+    
     /// Transfer failed permanently (no more retries)
     Failed(TransferFailedEvent),
     
-    /// Transfer was canceled by user
+    /// Transfer was canceled, by the user or by the app
     Canceled(TransferCanceledEvent),
     
     /// Speed/bandwidth update (more frequent than progress updates)
@@ -80,6 +106,28 @@ pub struct TransferQueuedEvent {
     pub estimated_sources: usize,
 }
 
+/// Event when a transfer leaves the download queue
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferDequeuedEvent {
+    pub transfer_id: String,
+    pub file_hash: String,
+    pub reason: DequeueReason,
+    pub waited_ms: u64,
+    pub dequeued_at: u64,
+}
+
+/// Event while a transfer waits for its minimum number of sources before starting
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferWaitingForSourcesEvent {
+    pub transfer_id: String,
+    pub file_hash: String,
+    pub found_sources: usize,
+    pub required_sources: usize,
+    pub deadline: u64, // Unix timestamp in milliseconds after which the transfer fails
+}
+
 /// Event when a transfer actually begins
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -120,6 +168,54 @@ pub struct SourceDisconnectedEvent {
     pub will_retry: bool,
 }
 
+/// Event when failed chunks are handed to a different source for retry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChunkReassignedEvent {
+    pub transfer_id: String,
+    pub chunk_ids: Vec<u32>,
+    pub from_source: Option<String>, // None if the original source is unknown (e.g. restored state)
+    pub to_source: String,
+    pub reassigned_at: u64,
+}
+
+/// Event when a second source delivers a chunk that is already complete
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChunkDuplicateReceivedEvent {
+    pub transfer_id: String,
+    pub chunk_id: u32,
+    pub chunk_size: usize,
+    pub source_id: String,
+    pub first_source_id: String, // Source that is credited with the chunk
+    pub received_at: u64,
+}
+
+/// Event when a source is blacklisted after repeated chunk integrity failures
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceBlacklistedEvent {
+    pub transfer_id: String,
+    pub source_id: String,
+    pub source_type: SourceType,
+    pub reason: String,
+    pub integrity_failures: u32,
+    pub blacklisted_at: u64,
+}
+
+/// Event when a source is paused because the server is rate limiting us
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceThrottledEvent {
+    pub transfer_id: String,
+    pub source_id: String,
+    pub source_type: SourceType,
+    pub status_code: u16,
+    pub retry_after_ms: u64,
+    pub resume_at: u64,
+    pub throttled_at: u64,
+}
+
 /// Event when a chunk is successfully downloaded and verified
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -131,6 +227,7 @@ pub struct ChunkCompletedEvent {
     pub source_type: SourceType,
     pub completed_at: u64,
     pub download_duration_ms: u64,
+    pub chunk_speed_bps: f64, // chunk_size over download_duration_ms, 0 if unknown
     pub verified: bool, // Whether hash verification passed
 }
 
@@ -162,6 +259,8 @@ pub struct TransferProgressEvent {
     pub download_speed_bps: f64, // Bytes per second
     pub upload_speed_bps: f64, // For seeding
     pub eta_seconds: Option<u32>,
+    #[serde(default)]
+    pub chunks_completed_delta: u32, // Chunk completions folded into this update since the last one
     pub active_sources: usize,
     pub timestamp: u64,
 }
@@ -205,6 +304,31 @@ pub struct TransferCompletedEvent {
     pub sources_used: Vec<SourceSummary>,
 }
 
+/// Event when a finished download starts seeding on the protocols requested for it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SeedingStartedEvent {
+    pub transfer_id: String,
+    pub file_hash: String,
+    pub file_path: String,
+    /// Shareable identifier (magnet link, ed2k link, ...) by protocol
+    pub identifiers: HashMap<String, String>,
+    pub started_at: u64,
+}
+
+/// Event when a seeded file failed re-verification and seeding was stopped
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SeedVerificationFailedEvent {
+    pub transfer_id: String,
+    pub file_hash: String,
+    pub file_path: String,
+    /// What the file hashes to now; unset when it couldn't be read
+    pub actual_hash: Option<String>,
+    pub error: String,
+    pub failed_at: u64,
+}
+
 /// Event when transfer fails permanently
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -219,7 +343,7 @@ pub struct TransferFailedEvent {
     pub retry_possible: bool,
 }
 
-/// Event when transfer is canceled by user
+/// Event when transfer is canceled
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TransferCanceledEvent {
@@ -228,6 +352,8 @@ pub struct TransferCanceledEvent {
     pub downloaded_bytes: u64,
     pub total_bytes: u64,
     pub keep_partial: bool, // Whether to keep partial download
+    #[serde(default)]
+    pub reason: CancelReason,
 }
 
 /// Speed update event (sent more frequently than progress)
@@ -259,6 +385,37 @@ impl Default for TransferPriority {
     }
 }
 
+/// Why a transfer left the download queue
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DequeueReason {
+    /// A download slot freed up and the transfer is starting
+    Started,
+    /// The transfer was canceled while waiting
+    Canceled,
+    /// Paused by `pause_all` while waiting; `resume_all` queues it again
+    Paused,
+}
+
+/// Why a transfer was canceled
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum CancelReason {
+    /// The user canceled it
+    #[default]
+    UserRequested,
+    /// The app stopped it while shutting down
+    Shutdown,
+    /// The disk ran out of room for the finished file
+    DiskFull,
+    /// A newer request for the same file or output path replaced it
+    Superseded,
+    /// Paused because the connection became metered; it starts again once it isn't
+    Metered,
+    /// Stopped by `pause_all`, keeping its chunks; `resume_all` starts it again
+    Paused,
+}
+
 /// Type of data source
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
@@ -337,6 +494,135 @@ pub enum ErrorCategory {
     Unknown,
 }
 
+// ============================================================================
+// Event Coalescing
+// ============================================================================
+
+/// How much per-transfer chatter reaches the frontend
+///
+/// By default chunk completions are folded into `chunks_completed_delta` of the next
+/// progress event, and progress is rate-limited per transfer. Turning `full_granularity`
+/// on sends every event as-is.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct EventCoalescingConfig {
+    /// Most progress events sent per transfer per second (0 = unlimited)
+    pub max_progress_per_second: f64,
+    /// Send every chunk completion and progress update as-is, for debugging
+    pub full_granularity: bool,
+}
+
+impl Default for EventCoalescingConfig {
+    fn default() -> Self {
+        Self {
+            max_progress_per_second: 4.0,
+            full_granularity: false,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct CoalescedTransfer {
+    last_progress_ms: Option<u64>,
+    pending_chunks: u32,
+}
+
+/// Folds chunk completions into the next progress update and rate-limits progress per transfer
+#[derive(Debug, Default)]
+pub struct EventCoalescer {
+    config: EventCoalescingConfig,
+    transfers: HashMap<String, CoalescedTransfer>,
+}
+
+impl EventCoalescer {
+    pub fn new(config: EventCoalescingConfig) -> Self {
+        Self {
+            config,
+            transfers: HashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &EventCoalescingConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: EventCoalescingConfig) {
+        self.config = config;
+        self.transfers.clear();
+    }
+
+    fn min_progress_interval_ms(&self) -> u64 {
+        if self.config.max_progress_per_second <= 0.0 {
+            return 0;
+        }
+        (1000.0 / self.config.max_progress_per_second) as u64
+    }
+
+    /// The event to send at `now_ms`, or None if it was folded into a later one.
+    ///
+    /// A progress update that reports the transfer as finished is never held back. A
+    /// transfer's state is dropped once it finishes, stops or is paused, so the map only
+    /// holds transfers that are running.
+    pub fn coalesce(&mut self, event: TransferEvent, now_ms: u64) -> Option<TransferEvent> {
+        match event {
+            TransferEvent::Completed(TransferCompletedEvent { ref transfer_id, .. })
+            | TransferEvent::Failed(TransferFailedEvent { ref transfer_id, .. })
+            | TransferEvent::Canceled(TransferCanceledEvent { ref transfer_id, .. })
+            | TransferEvent::Paused(TransferPausedEvent { ref transfer_id, .. }) => {
+                self.transfers.remove(transfer_id);
+                Some(event)
+            }
+            _ if self.config.full_granularity => Some(event),
+            TransferEvent::ChunkCompleted(chunk) => {
+                self.transfers.entry(chunk.transfer_id).or_default().pending_chunks += 1;
+                None
+            }
+            TransferEvent::Progress(mut progress) => {
+                let min_interval_ms = self.min_progress_interval_ms();
+                let finished = (progress.total_chunks > 0
+                    && progress.completed_chunks >= progress.total_chunks)
+                    || (progress.total_bytes > 0
+                        && progress.downloaded_bytes >= progress.total_bytes);
+                let state = self.transfers.entry(progress.transfer_id.clone()).or_default();
+                let too_soon = state
+                    .last_progress_ms
+                    .is_some_and(|last| now_ms.saturating_sub(last) < min_interval_ms);
+                if too_soon && !finished {
+                    return None;
+                }
+                state.last_progress_ms = Some(now_ms);
+                progress.chunks_completed_delta = std::mem::take(&mut state.pending_chunks);
+                if finished {
+                    self.transfers.remove(&progress.transfer_id);
+                }
+                Some(TransferEvent::Progress(progress))
+            }
+            other => Some(other),
+        }
+    }
+}
+
+// Shared by every bus, since each protocol handler builds its own
+static EVENT_COALESCER: Lazy<Mutex<EventCoalescer>> =
+    Lazy::new(|| Mutex::new(EventCoalescer::default()));
+
+/// Current limits on events sent to the frontend
+pub fn event_coalescing_config() -> EventCoalescingConfig {
+    EVENT_COALESCER
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .config()
+        .clone()
+}
+
+/// Change the limits on events sent to the frontend
+pub fn set_event_coalescing_config(config: EventCoalescingConfig) {
+    EVENT_COALESCER
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .set_config(config);
+}
+
 // ============================================================================
 // Event Bus Implementation
 // ============================================================================
@@ -344,44 +630,75 @@ pub enum ErrorCategory {
 /// The main event bus for emitting transfer events
 #[derive(Clone)]
 pub struct TransferEventBus {
-    app_handle: AppHandle,
+    /// Unset for a detached bus, which records history but reaches no window
+    app_handle: Option<AppHandle>,
 }
 
 impl TransferEventBus {
     /// Create a new event bus with the given app handle
     pub fn new(app_handle: AppHandle) -> Self {
         debug!("Initializing TransferEventBus");
-        Self { app_handle }
+        Self {
+            app_handle: Some(app_handle),
+        }
     }
 
-    /// Emit a transfer event to all listeners
+    /// Create an event bus with no window to emit to, e.g. for a headless service in tests
+    pub fn detached() -> Self {
+        Self { app_handle: None }
+    }
+
+    /// Emit a transfer event to all listeners, subject to event coalescing
     pub fn emit(&self, event: TransferEvent) {
         let event_type = match &event {
             TransferEvent::Queued(_) => "queued",
+            TransferEvent::Dequeued(_) => "dequeued",
+            TransferEvent::WaitingForSources(_) => "waiting_for_sources",
             TransferEvent::Started(_) => "started",
             TransferEvent::SourceConnected(_) => "source_connected",
             TransferEvent::SourceDisconnected(_) => "source_disconnected",
             TransferEvent::ChunkCompleted(_) => "chunk_completed",
             TransferEvent::ChunkFailed(_) => "chunk_failed",
+            TransferEvent::ChunkReassigned(_) => "chunk_reassigned",
+            TransferEvent::ChunkDuplicateReceived(_) => "chunk_duplicate_received",
+            TransferEvent::SourceBlacklisted(_) => "source_blacklisted",
+            TransferEvent::SourceThrottled(_) => "source_throttled",
             TransferEvent::Progress(_) => "progress",
             TransferEvent::Paused(_) => "paused",
             TransferEvent::Resumed(_) => "resumed",
             TransferEvent::Completed(_) => "completed",
+            TransferEvent::SeedingStarted(_) => "seeding_started",
+            TransferEvent::SeedVerificationFailed(_) => "seed_verification_failed",
             TransferEvent::Failed(_) => "failed",
             TransferEvent::Canceled(_) => "canceled",
             TransferEvent::SpeedUpdate(_) => "speed_update",
         };
 
+        // History sees finished transfers even if the frontend never does
+        crate::transfer_history::observe(&event);
+
+        let event = EVENT_COALESCER
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .coalesce(event, current_timestamp_ms());
+        let Some(event) = event else {
+            debug!("Coalesced transfer event: {}", event_type);
+            return;
+        };
+
+        let Some(app_handle) = &self.app_handle else {
+            return;
+        };
         debug!("Emitting transfer event: {}", event_type);
 
         // Emit to specific typed channel
         let typed_channel = format!("transfer:{}", event_type);
-        if let Err(e) = self.app_handle.emit(&typed_channel, &event) {
+        if let Err(e) = app_handle.emit(&typed_channel, &event) {
             error!("Failed to emit event to {}: {}", typed_channel, e);
         }
 
         // Also emit to generic channel for listeners who want all events
-        if let Err(e) = self.app_handle.emit("transfer:event", &event) {
+        if let Err(e) = app_handle.emit("transfer:event", &event) {
             error!("Failed to emit event to transfer:event: {}", e);
         }
     }
@@ -391,6 +708,16 @@ impl TransferEventBus {
         self.emit(TransferEvent::Queued(event));
     }
 
+    /// Helper to emit dequeued event
+    pub fn emit_dequeued(&self, event: TransferDequeuedEvent) {
+        self.emit(TransferEvent::Dequeued(event));
+    }
+
+    /// Helper to emit waiting for sources event
+    pub fn emit_waiting_for_sources(&self, event: TransferWaitingForSourcesEvent) {
+        self.emit(TransferEvent::WaitingForSources(event));
+    }
+
     /// Helper to emit started event
     pub fn emit_started(&self, event: TransferStartedEvent) {
         self.emit(TransferEvent::Started(event));
@@ -416,6 +743,26 @@ impl TransferEventBus {
         self.emit(TransferEvent::ChunkFailed(event));
     }
 
+    /// Helper to emit chunk reassigned event
+    pub fn emit_chunk_reassigned(&self, event: ChunkReassignedEvent) {
+        self.emit(TransferEvent::ChunkReassigned(event));
+    }
+
+    /// Helper to emit chunk duplicate received event
+    pub fn emit_chunk_duplicate_received(&self, event: ChunkDuplicateReceivedEvent) {
+        self.emit(TransferEvent::ChunkDuplicateReceived(event));
+    }
+
+    /// Helper to emit source blacklisted event
+    pub fn emit_source_blacklisted(&self, event: SourceBlacklistedEvent) {
+        self.emit(TransferEvent::SourceBlacklisted(event));
+    }
+
+    /// Helper to emit source throttled event
+    pub fn emit_source_throttled(&self, event: SourceThrottledEvent) {
+        self.emit(TransferEvent::SourceThrottled(event));
+    }
+
     /// Helper to emit progress event
     pub fn emit_progress(&self, event: TransferProgressEvent) {
         self.emit(TransferEvent::Progress(event));
@@ -436,6 +783,16 @@ impl TransferEventBus {
         self.emit(TransferEvent::Completed(event));
     }
 
+    /// Helper to emit seeding started event
+    pub fn emit_seeding_started(&self, event: SeedingStartedEvent) {
+        self.emit(TransferEvent::SeedingStarted(event));
+    }
+
+    /// Helper to emit seed verification failed event
+    pub fn emit_seed_verification_failed(&self, event: SeedVerificationFailedEvent) {
+        self.emit(TransferEvent::SeedVerificationFailed(event));
+    }
+
     /// Helper to emit failed event
     pub fn emit_failed(&self, event: TransferFailedEvent) {
         self.emit(TransferEvent::Failed(event));
@@ -458,7 +815,8 @@ impl TransferEventBus {
     /// Emit a transfer event to all listeners AND update analytics
     ///
     /// This method should be used when you want to emit an event and also
-    /// update the backend analytics service in a single call.
+    /// update the backend analytics service in a single call. Analytics sees
+    /// every event, even those coalesced away before reaching the frontend.
     pub async fn emit_with_analytics(&self, event: TransferEvent, analytics: &Arc<AnalyticsService>) {
         // Emit to frontend
         self.emit(event.clone());
@@ -474,6 +832,11 @@ impl TransferEventBus {
         self.emit_with_analytics(TransferEvent::Queued(event), analytics).await;
     }
 
+    /// Helper to emit dequeued event with analytics
+    pub async fn emit_dequeued_with_analytics(&self, event: TransferDequeuedEvent, analytics: &Arc<AnalyticsService>) {
+        self.emit_with_analytics(TransferEvent::Dequeued(event), analytics).await;
+    }
+
     /// Helper to emit started event with analytics
     pub async fn emit_started_with_analytics(&self, event: TransferStartedEvent, analytics: &Arc<AnalyticsService>) {
         self.emit_with_analytics(TransferEvent::Started(event), analytics).await;
@@ -551,6 +914,22 @@ pub fn calculate_eta(remaining_bytes: u64, speed_bps: f64) -> Option<u32> {
     Some((remaining_bytes as f64 / speed_bps) as u32)
 }
 
+/// Calculate the throughput of a single chunk in bytes per second
+pub fn calculate_chunk_speed(chunk_size: usize, duration_ms: u64) -> f64 {
+    if duration_ms == 0 {
+        return 0.0;
+    }
+    chunk_size as f64 * 1000.0 / duration_ms as f64
+}
+
+/// Share the duration of one fetch across a part of the bytes it returned
+pub fn apportion_duration_ms(total_duration_ms: u64, part_bytes: usize, total_bytes: usize) -> u64 {
+    if total_bytes == 0 {
+        return 0;
+    }
+    (total_duration_ms as u128 * part_bytes as u128 / total_bytes as u128) as u64
+}
+
 // ============================================================================
 // Tests
 // ============================================================================
@@ -574,6 +953,103 @@ mod tests {
         assert_eq!(calculate_eta(1000, 0.0), None);
     }
 
+    #[test]
+    fn test_calculate_chunk_speed() {
+        assert_eq!(calculate_chunk_speed(1000, 500), 2000.0);
+        assert_eq!(calculate_chunk_speed(1000, 0), 0.0);
+    }
+
+    #[test]
+    fn test_apportion_duration_ms() {
+        assert_eq!(apportion_duration_ms(900, 256, 1024), 225);
+        assert_eq!(apportion_duration_ms(900, 1024, 1024), 900);
+        assert_eq!(apportion_duration_ms(900, 10, 0), 0);
+    }
+
+    fn progress_event(completed_chunks: u32) -> TransferEvent {
+        TransferEvent::Progress(TransferProgressEvent {
+            transfer_id: "test-123".to_string(),
+            downloaded_bytes: completed_chunks as u64 * 1024,
+            total_bytes: 10 * 1024,
+            completed_chunks,
+            total_chunks: 10,
+            progress_percentage: completed_chunks as f64 * 10.0,
+            download_speed_bps: 0.0,
+            upload_speed_bps: 0.0,
+            eta_seconds: None,
+            chunks_completed_delta: 0,
+            active_sources: 1,
+            timestamp: 0,
+        })
+    }
+
+    fn chunk_event(chunk_id: u32) -> TransferEvent {
+        TransferEvent::ChunkCompleted(ChunkCompletedEvent {
+            transfer_id: "test-123".to_string(),
+            chunk_id,
+            chunk_size: 1024,
+            source_id: "peer-a".to_string(),
+            source_type: SourceType::P2p,
+            completed_at: 0,
+            download_duration_ms: 10,
+            chunk_speed_bps: 0.0,
+            verified: true,
+        })
+    }
+
+    fn delta(event: Option<TransferEvent>) -> u32 {
+        match event {
+            Some(TransferEvent::Progress(progress)) => progress.chunks_completed_delta,
+            other => panic!("Expected a progress event, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_coalescer_batches_chunks_and_throttles_progress() {
+        let mut coalescer = EventCoalescer::new(EventCoalescingConfig {
+            max_progress_per_second: 2.0,
+            full_granularity: false,
+        });
+
+        assert!(coalescer.coalesce(chunk_event(0), 0).is_none());
+        assert!(coalescer.coalesce(chunk_event(1), 10).is_none());
+        assert_eq!(delta(coalescer.coalesce(progress_event(2), 100)), 2);
+
+        // Within 500ms of the last update: held back, chunk keeps accumulating
+        assert!(coalescer.coalesce(chunk_event(2), 200).is_none());
+        assert!(coalescer.coalesce(progress_event(3), 300).is_none());
+        assert!(coalescer.coalesce(chunk_event(3), 400).is_none());
+        assert_eq!(delta(coalescer.coalesce(progress_event(4), 600)), 2);
+
+        // The final update always goes out
+        assert_eq!(delta(coalescer.coalesce(progress_event(10), 650)), 0);
+        assert!(coalescer.transfers.is_empty());
+    }
+
+    #[test]
+    fn test_coalescer_folds_chunks_by_default() {
+        let mut coalescer = EventCoalescer::default();
+
+        assert!(coalescer.coalesce(chunk_event(0), 0).is_none());
+        assert_eq!(delta(coalescer.coalesce(progress_event(1), 0)), 1);
+        assert!(coalescer.coalesce(progress_event(2), 1).is_none());
+    }
+
+    #[test]
+    fn test_coalescer_full_granularity_passes_everything() {
+        let mut coalescer = EventCoalescer::new(EventCoalescingConfig {
+            max_progress_per_second: 2.0,
+            full_granularity: true,
+        });
+
+        assert!(matches!(
+            coalescer.coalesce(chunk_event(0), 0),
+            Some(TransferEvent::ChunkCompleted(_))
+        ));
+        assert_eq!(delta(coalescer.coalesce(progress_event(1), 0)), 0);
+        assert_eq!(delta(coalescer.coalesce(progress_event(2), 1)), 0);
+    }
+
     #[test]
     fn test_event_serialization() {
         let event = TransferEvent::Queued(TransferQueuedEvent {
@@ -599,4 +1075,167 @@ mod tests {
             _ => panic!("Wrong event type"),
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_dequeued_serialization() {
+        let event = TransferEvent::Dequeued(TransferDequeuedEvent {
+            transfer_id: "test-123".to_string(),
+            file_hash: "abc123".to_string(),
+            reason: DequeueReason::Canceled,
+            waited_ms: 1500,
+            dequeued_at: 1234567890,
+        });
+
+        let value = serde_json::to_value(&event).unwrap();
+        assert_eq!(value["type"], "dequeued");
+        assert_eq!(value["reason"], "canceled");
+        assert_eq!(value["waitedMs"], 1500);
+    }
+
+    #[test]
+    fn test_canceled_serialization() {
+        let event = TransferEvent::Canceled(TransferCanceledEvent {
+            transfer_id: "test-123".to_string(),
+            canceled_at: 1234567890,
+            downloaded_bytes: 512,
+            total_bytes: 1024,
+            keep_partial: true,
+            reason: CancelReason::Shutdown,
+        });
+
+        let value = serde_json::to_value(&event).unwrap();
+        assert_eq!(value["type"], "canceled");
+        assert_eq!(value["reason"], "shutdown");
+
+        // Events recorded before cancellations had a reason count as user cancels
+        let legacy: TransferCanceledEvent = serde_json::from_value(serde_json::json!({
+            "transferId": "test-123",
+            "canceledAt": 1,
+            "downloadedBytes": 0,
+            "totalBytes": 0,
+            "keepPartial": false,
+        }))
+        .unwrap();
+        assert_eq!(legacy.reason, CancelReason::UserRequested);
+    }
+
+    #[test]
+    fn test_chunk_reassigned_serialization() {
+        let event = TransferEvent::ChunkReassigned(ChunkReassignedEvent {
+            transfer_id: "test-123".to_string(),
+            chunk_ids: vec![3, 7],
+            from_source: Some("peer-a".to_string()),
+            to_source: "ftp://mirror/file".to_string(),
+            reassigned_at: 1234567890,
+        });
+
+        let value = serde_json::to_value(&event).unwrap();
+        assert_eq!(value["type"], "chunk_reassigned");
+        assert_eq!(value["chunkIds"], serde_json::json!([3, 7]));
+        assert_eq!(value["fromSource"], "peer-a");
+        assert_eq!(value["toSource"], "ftp://mirror/file");
+    }
+
+    #[test]
+    fn test_chunk_duplicate_received_serialization() {
+        let event = TransferEvent::ChunkDuplicateReceived(ChunkDuplicateReceivedEvent {
+            transfer_id: "test-123".to_string(),
+            chunk_id: 4,
+            chunk_size: 1024,
+            source_id: "http://slow/file".to_string(),
+            first_source_id: "peer-a".to_string(),
+            received_at: 1234567890,
+        });
+
+        let value = serde_json::to_value(&event).unwrap();
+        assert_eq!(value["type"], "chunk_duplicate_received");
+        assert_eq!(value["chunkId"], 4);
+        assert_eq!(value["firstSourceId"], "peer-a");
+    }
+
+    #[test]
+    fn test_source_blacklisted_serialization() {
+        let event = TransferEvent::SourceBlacklisted(SourceBlacklistedEvent {
+            transfer_id: "test-123".to_string(),
+            source_id: "ftp://mirror/file".to_string(),
+            source_type: SourceType::Ftp,
+            reason: "3 chunks failed integrity verification".to_string(),
+            integrity_failures: 3,
+            blacklisted_at: 1234567890,
+        });
+
+        let value = serde_json::to_value(&event).unwrap();
+        assert_eq!(value["type"], "source_blacklisted");
+        assert_eq!(value["sourceId"], "ftp://mirror/file");
+        assert_eq!(value["integrityFailures"], 3);
+    }
+
+    #[test]
+    fn test_source_throttled_serialization() {
+        let event = TransferEvent::SourceThrottled(SourceThrottledEvent {
+            transfer_id: "test-123".to_string(),
+            source_id: "https://cdn/file".to_string(),
+            source_type: SourceType::Http,
+            status_code: 429,
+            retry_after_ms: 30_000,
+            resume_at: 1234597890,
+            throttled_at: 1234567890,
+        });
+
+        let value = serde_json::to_value(&event).unwrap();
+        assert_eq!(value["type"], "source_throttled");
+        assert_eq!(value["statusCode"], 429);
+        assert_eq!(value["retryAfterMs"], 30_000);
+    }
+
+    #[test]
+    fn test_waiting_for_sources_serialization() {
+        let event = TransferEvent::WaitingForSources(TransferWaitingForSourcesEvent {
+            transfer_id: "test-123".to_string(),
+            file_hash: "abc".to_string(),
+            found_sources: 2,
+            required_sources: 3,
+            deadline: 1234627890,
+        });
+
+        let value = serde_json::to_value(&event).unwrap();
+        assert_eq!(value["type"], "waiting_for_sources");
+        assert_eq!(value["foundSources"], 2);
+        assert_eq!(value["requiredSources"], 3);
+    }
+
+    #[test]
+    fn test_seeding_started_serialization() {
+        let event = TransferEvent::SeedingStarted(SeedingStartedEvent {
+            transfer_id: "test-123".to_string(),
+            file_hash: "abc".to_string(),
+            file_path: "/downloads/file.bin".to_string(),
+            identifiers: HashMap::from([(
+                "bittorrent".to_string(),
+                "magnet:?xt=urn:btih:abc".to_string(),
+            )]),
+            started_at: 1234567890,
+        });
+
+        let value = serde_json::to_value(&event).unwrap();
+        assert_eq!(value["type"], "seeding_started");
+        assert_eq!(value["filePath"], "/downloads/file.bin");
+        assert_eq!(value["identifiers"]["bittorrent"], "magnet:?xt=urn:btih:abc");
+    }
+
+    #[test]
+    fn test_seed_verification_failed_serialization() {
+        let event = TransferEvent::SeedVerificationFailed(SeedVerificationFailedEvent {
+            transfer_id: "abc".to_string(),
+            file_hash: "abc".to_string(),
+            file_path: "/downloads/file.bin".to_string(),
+            actual_hash: Some("def".to_string()),
+            error: "File no longer matches its hash".to_string(),
+            failed_at: 1234567890,
+        });
+
+        let value = serde_json::to_value(&event).unwrap();
+        assert_eq!(value["type"], "seed_verification_failed");
+        assert_eq!(value["actualHash"], "def");
+    }
+}
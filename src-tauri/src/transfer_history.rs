@@ -0,0 +1,747 @@
+// transfer_history.rs
+// Persistent history of finished transfers
+//
+// Completed, failed and canceled transfers are appended to a JSON Lines file as
+// their events pass through the TransferEventBus, so the history survives restarts
+// and can be paged through without holding every record in memory. Once the file
+// reaches MAX_HISTORY_FILE_BYTES it is rotated out, keeping one older generation.
+
+use crate::transfer_events::{CancelReason, ErrorCategory, SourceSummary, SourceType, TransferEvent};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Mutex};
+use tracing::{debug, warn};
+
+/// File name of the history inside the app data directory
+pub const HISTORY_FILE_NAME: &str = "transfer_history.jsonl";
+
+/// Page size used when a query doesn't ask for one
+pub const DEFAULT_PAGE_SIZE: usize = 50;
+
+/// Transfers that started but haven't finished yet are dropped beyond this many
+const MAX_PENDING_TRANSFERS: usize = 1024;
+
+/// Size at which the history file is rotated out; at most two generations are kept
+const MAX_HISTORY_FILE_BYTES: u64 = 8 * 1024 * 1024;
+
+/// How a transfer ended
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferOutcome {
+    Completed,
+    Failed,
+    Canceled,
+}
+
+/// Bytes one protocol contributed to a transfer
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ProtocolShare {
+    pub source_type: SourceType,
+    pub bytes: u64,
+    pub chunks: u32,
+}
+
+/// One finished transfer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferHistoryRecord {
+    pub transfer_id: String,
+    pub file_hash: String,
+    pub file_name: String,
+    pub file_size: u64,
+    pub started_at: Option<u64>,
+    pub finished_at: u64,
+    pub duration_seconds: u64,
+    pub average_speed_bps: f64,
+    pub bytes_transferred: u64,
+    pub protocols: Vec<ProtocolShare>,
+    pub outcome: TransferOutcome,
+    pub error: Option<String>,
+    pub error_category: Option<ErrorCategory>,
+    /// Why a canceled transfer was canceled
+    #[serde(default)]
+    pub cancel_reason: Option<CancelReason>,
+}
+
+/// Which records `query_history` returns; every field is optional
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HistoryFilter {
+    pub outcome: Option<TransferOutcome>,
+    pub file_hash: Option<String>,
+    /// Case-insensitive substring of the file name
+    pub search: Option<String>,
+    /// Only transfers that used this protocol
+    pub protocol: Option<SourceType>,
+    /// Finished at or after this Unix timestamp (ms)
+    pub since: Option<u64>,
+    /// Finished before this Unix timestamp (ms)
+    pub until: Option<u64>,
+    /// Records to skip, newest first
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+impl HistoryFilter {
+    fn matches(&self, record: &TransferHistoryRecord) -> bool {
+        if self.outcome.is_some_and(|outcome| outcome != record.outcome) {
+            return false;
+        }
+        if self.file_hash.as_ref().is_some_and(|hash| *hash != record.file_hash) {
+            return false;
+        }
+        if let Some(search) = &self.search {
+            if !record.file_name.to_lowercase().contains(&search.to_lowercase()) {
+                return false;
+            }
+        }
+        if let Some(protocol) = &self.protocol {
+            if !record.protocols.iter().any(|share| share.source_type == *protocol) {
+                return false;
+            }
+        }
+        if self.since.is_some_and(|since| record.finished_at < since) {
+            return false;
+        }
+        if self.until.is_some_and(|until| record.finished_at >= until) {
+            return false;
+        }
+        true
+    }
+}
+
+/// One page of history, newest first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryPage {
+    pub records: Vec<TransferHistoryRecord>,
+    /// Records matching the filter across all pages
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+/// What we know about a transfer before it finishes
+#[derive(Debug, Clone, Default)]
+struct PendingTransfer {
+    file_name: String,
+    file_size: u64,
+    started_at: Option<u64>,
+    protocols: Vec<ProtocolShare>,
+}
+
+impl PendingTransfer {
+    /// Record of a transfer that stopped at `finished_at` after `bytes_transferred` bytes
+    fn stopped(
+        self,
+        transfer_id: &str,
+        finished_at: u64,
+        bytes_transferred: u64,
+        total_bytes: u64,
+        outcome: TransferOutcome,
+    ) -> TransferHistoryRecord {
+        let duration_ms = self
+            .started_at
+            .map(|started_at| finished_at.saturating_sub(started_at))
+            .unwrap_or(0);
+        let average_speed_bps = if duration_ms > 0 {
+            bytes_transferred as f64 * 1000.0 / duration_ms as f64
+        } else {
+            0.0
+        };
+        let file_size = if self.file_size > 0 {
+            self.file_size
+        } else {
+            total_bytes
+        };
+        TransferHistoryRecord {
+            transfer_id: transfer_id.to_string(),
+            file_hash: transfer_id.to_string(),
+            file_name: self.file_name,
+            file_size,
+            started_at: self.started_at,
+            finished_at,
+            duration_seconds: duration_ms / 1000,
+            average_speed_bps,
+            bytes_transferred,
+            protocols: self.protocols,
+            outcome,
+            error: None,
+            error_category: None,
+            cancel_reason: None,
+        }
+    }
+}
+
+/// Append-only store of finished transfers
+pub struct TransferHistoryStore {
+    path: PathBuf,
+    pending: HashMap<String, PendingTransfer>,
+    max_file_bytes: u64,
+}
+
+impl TransferHistoryStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            pending: HashMap::new(),
+            max_file_bytes: MAX_HISTORY_FILE_BYTES,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The previous generation of the history, `{path}.1`
+    fn rotated_path(&self) -> PathBuf {
+        let mut path = self.path.as_os_str().to_owned();
+        path.push(".1");
+        PathBuf::from(path)
+    }
+
+    /// Track a transfer event, appending a record when the transfer finishes
+    pub fn observe(&mut self, event: &TransferEvent) -> Result<(), String> {
+        match self.track(event) {
+            Some(record) => self.append(&record),
+            None => Ok(()),
+        }
+    }
+
+    /// Track a transfer event, returning the record of the transfer it finishes
+    fn track(&mut self, event: &TransferEvent) -> Option<TransferHistoryRecord> {
+        match event {
+            TransferEvent::Started(started) => {
+                if self.pending.len() >= MAX_PENDING_TRANSFERS {
+                    self.drop_oldest_pending();
+                }
+                self.pending.insert(
+                    started.transfer_id.clone(),
+                    PendingTransfer {
+                        file_name: started.file_name.clone(),
+                        file_size: started.file_size,
+                        started_at: Some(started.started_at),
+                        protocols: Vec::new(),
+                    },
+                );
+                None
+            }
+            TransferEvent::ChunkCompleted(chunk) => {
+                if let Some(pending) = self.pending.get_mut(&chunk.transfer_id) {
+                    let bytes = chunk.chunk_size as u64;
+                    add_share(&mut pending.protocols, &chunk.source_type, bytes, 1);
+                }
+                None
+            }
+            TransferEvent::Canceled(canceled) => {
+                let pending = self.pending.remove(&canceled.transfer_id).unwrap_or_default();
+                Some(TransferHistoryRecord {
+                    cancel_reason: Some(canceled.reason),
+                    ..pending.stopped(
+                        &canceled.transfer_id,
+                        canceled.canceled_at,
+                        canceled.downloaded_bytes,
+                        canceled.total_bytes,
+                        TransferOutcome::Canceled,
+                    )
+                })
+            }
+            TransferEvent::Completed(completed) => {
+                let pending = self.pending.remove(&completed.transfer_id).unwrap_or_default();
+                let protocols = if completed.sources_used.is_empty() {
+                    pending.protocols
+                } else {
+                    protocol_breakdown(&completed.sources_used)
+                };
+                Some(TransferHistoryRecord {
+                    transfer_id: completed.transfer_id.clone(),
+                    file_hash: completed.file_hash.clone(),
+                    file_name: completed.file_name.clone(),
+                    file_size: completed.file_size,
+                    started_at: pending.started_at,
+                    finished_at: completed.completed_at,
+                    duration_seconds: completed.duration_seconds,
+                    average_speed_bps: completed.average_speed_bps,
+                    bytes_transferred: completed.file_size,
+                    protocols,
+                    outcome: TransferOutcome::Completed,
+                    error: None,
+                    error_category: None,
+                    cancel_reason: None,
+                })
+            }
+            TransferEvent::Failed(failed) => {
+                let pending = self.pending.remove(&failed.transfer_id).unwrap_or_default();
+                Some(TransferHistoryRecord {
+                    file_hash: failed.file_hash.clone(),
+                    error: Some(failed.error.clone()),
+                    error_category: Some(failed.error_category.clone()),
+                    ..pending.stopped(
+                        &failed.transfer_id,
+                        failed.failed_at,
+                        failed.downloaded_bytes,
+                        failed.total_bytes,
+                        TransferOutcome::Failed,
+                    )
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn drop_oldest_pending(&mut self) {
+        let oldest = self
+            .pending
+            .iter()
+            .min_by_key(|(_, pending)| pending.started_at.unwrap_or(0))
+            .map(|(transfer_id, _)| transfer_id.clone());
+        if let Some(transfer_id) = oldest {
+            self.pending.remove(&transfer_id);
+        }
+    }
+
+    /// Append one record to the history file
+    pub fn append(&self, record: &TransferHistoryRecord) -> Result<(), String> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create history directory: {}", e))?;
+        }
+        let line = serde_json::to_string(record)
+            .map_err(|e| format!("Failed to serialize history record: {}", e))?;
+        let full = fs::metadata(&self.path)
+            .map(|metadata| metadata.len() >= self.max_file_bytes)
+            .unwrap_or(false);
+        if full {
+            fs::rename(&self.path, self.rotated_path())
+                .map_err(|e| format!("Failed to rotate transfer history: {}", e))?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| format!("Failed to open transfer history: {}", e))?;
+        writeln!(file, "{}", line)
+            .map_err(|e| format!("Failed to write transfer history: {}", e))
+    }
+
+    /// One page of matching records, newest first
+    ///
+    /// The history is streamed twice, once to count matches and once to collect the
+    /// page, so only the requested records are kept in memory. Both passes read the
+    /// same snapshot, so records appended or rotated out meanwhile don't shift the page.
+    pub fn query_history(&self, filter: &HistoryFilter) -> Result<HistoryPage, String> {
+        let limit = filter.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+        let mut snapshot = self.snapshot()?;
+        let mut total = 0;
+        for_each_record(&mut snapshot, |record| {
+            if filter.matches(record) {
+                total += 1;
+            }
+        })?;
+
+        // Records are stored oldest first, so the newest-first page is a window from the end
+        let end = total.saturating_sub(filter.offset);
+        let start = end.saturating_sub(limit);
+        let mut records = Vec::with_capacity(end - start);
+        let mut index = 0;
+        for_each_record(&mut snapshot, |record| {
+            if filter.matches(record) {
+                if (start..end).contains(&index) {
+                    records.push(record.clone());
+                }
+                index += 1;
+            }
+        })?;
+        records.reverse();
+
+        Ok(HistoryPage {
+            records,
+            total,
+            offset: filter.offset,
+            limit,
+        })
+    }
+
+    /// Open both generations of the history, oldest first, along with their current
+    /// lengths. Open handles keep reading the same data after a rotation renames the file.
+    fn snapshot(&self) -> Result<Vec<(File, u64)>, String> {
+        let mut snapshot = Vec::new();
+        for path in [self.rotated_path(), self.path.clone()] {
+            let file = match File::open(&path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(format!("Failed to open transfer history: {}", e)),
+            };
+            let len = file
+                .metadata()
+                .map_err(|e| format!("Failed to read transfer history: {}", e))?
+                .len();
+            snapshot.push((file, len));
+        }
+        Ok(snapshot)
+    }
+
+    /// Remove every record
+    pub fn clear(&self) -> Result<(), String> {
+        for path in [self.rotated_path(), self.path.clone()] {
+            match fs::remove_file(&path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(format!("Failed to clear transfer history: {}", e)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Stream the records of a snapshot, oldest first, up to the length each file had
+fn for_each_record(
+    snapshot: &mut [(File, u64)],
+    mut f: impl FnMut(&TransferHistoryRecord),
+) -> Result<(), String> {
+    for (file, len) in snapshot.iter_mut() {
+        file.seek(SeekFrom::Start(0))
+            .map_err(|e| format!("Failed to read transfer history: {}", e))?;
+        for line in BufReader::new(Read::by_ref(file).take(*len)).lines() {
+            let line = line.map_err(|e| format!("Failed to read transfer history: {}", e))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<TransferHistoryRecord>(&line) {
+                Ok(record) => f(&record),
+                // A torn write from a crash shouldn't hide the rest of the history
+                Err(e) => debug!("Skipping unreadable transfer history line: {}", e),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn add_share(
+    protocols: &mut Vec<ProtocolShare>,
+    source_type: &SourceType,
+    bytes: u64,
+    chunks: u32,
+) {
+    match protocols.iter_mut().find(|share| share.source_type == *source_type) {
+        Some(share) => {
+            share.bytes += bytes;
+            share.chunks += chunks;
+        }
+        None => protocols.push(ProtocolShare {
+            source_type: source_type.clone(),
+            bytes,
+            chunks,
+        }),
+    }
+}
+
+/// Bytes and chunks per protocol across the sources of a transfer
+fn protocol_breakdown(sources: &[SourceSummary]) -> Vec<ProtocolShare> {
+    let mut protocols = Vec::new();
+    for source in sources {
+        add_share(
+            &mut protocols,
+            &source.source_type,
+            source.bytes_provided,
+            source.chunks_provided,
+        );
+    }
+    protocols
+}
+
+/// Work for the thread that owns the history file
+enum HistoryWrite {
+    Append(TransferHistoryRecord),
+    Clear(mpsc::Sender<Result<(), String>>),
+}
+
+/// The global history: transfers in progress, and the writer finished ones are sent to
+struct GlobalHistory {
+    store: TransferHistoryStore,
+    writer: mpsc::Sender<HistoryWrite>,
+}
+
+// Shared by every bus; stays empty until the app data directory is known
+static TRANSFER_HISTORY: Lazy<Mutex<Option<GlobalHistory>>> = Lazy::new(|| Mutex::new(None));
+
+/// Start recording finished transfers to `path`
+///
+/// Records are written on a thread of their own, so emitting an event never waits on the disk.
+pub fn init(path: PathBuf) {
+    let (writer, writes) = mpsc::channel();
+    let file = TransferHistoryStore::new(path.clone());
+    let spawned = std::thread::Builder::new()
+        .name("transfer-history".to_string())
+        .spawn(move || {
+            for write in writes {
+                match write {
+                    HistoryWrite::Append(record) => {
+                        if let Err(e) = file.append(&record) {
+                            warn!("Failed to record transfer history: {}", e);
+                        }
+                    }
+                    HistoryWrite::Clear(done) => {
+                        let _ = done.send(file.clear());
+                    }
+                }
+            }
+        });
+    if let Err(e) = spawned {
+        warn!("Failed to start the transfer history writer: {}", e);
+        return;
+    }
+    *TRANSFER_HISTORY
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(GlobalHistory {
+        store: TransferHistoryStore::new(path),
+        writer,
+    });
+}
+
+/// Feed a transfer event to the global history, if it's been initialized
+pub fn observe(event: &TransferEvent) {
+    let mut guard = TRANSFER_HISTORY
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(history) = guard.as_mut() {
+        if let Some(record) = history.store.track(event) {
+            let _ = history.writer.send(HistoryWrite::Append(record));
+        }
+    }
+}
+
+fn history_path() -> Result<PathBuf, String> {
+    TRANSFER_HISTORY
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .as_ref()
+        .map(|history| history.store.path().to_path_buf())
+        .ok_or_else(|| "Transfer history not initialized".to_string())
+}
+
+/// Query the global history, reading it off the async runtime
+pub async fn query_history(filter: HistoryFilter) -> Result<HistoryPage, String> {
+    let path = history_path()?;
+    tokio::task::spawn_blocking(move || TransferHistoryStore::new(path).query_history(&filter))
+        .await
+        .map_err(|e| format!("Transfer history query failed: {}", e))?
+}
+
+/// Clear the global history, after any records still waiting to be written
+pub async fn clear_history() -> Result<(), String> {
+    let writer = TRANSFER_HISTORY
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .as_ref()
+        .map(|history| history.writer.clone())
+        .ok_or_else(|| "Transfer history not initialized".to_string())?;
+    let (done, cleared) = mpsc::channel();
+    writer
+        .send(HistoryWrite::Clear(done))
+        .map_err(|_| "Transfer history writer stopped".to_string())?;
+    tokio::task::spawn_blocking(move || cleared.recv())
+        .await
+        .map_err(|e| format!("Transfer history clear failed: {}", e))?
+        .map_err(|_| "Transfer history writer stopped".to_string())?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfer_events::{
+        ChunkCompletedEvent, TransferCanceledEvent, TransferCompletedEvent, TransferFailedEvent,
+        TransferStartedEvent,
+    };
+    use tempfile::tempdir;
+
+    fn started(transfer_id: &str, file_name: &str, started_at: u64) -> TransferEvent {
+        TransferEvent::Started(TransferStartedEvent {
+            transfer_id: transfer_id.to_string(),
+            file_hash: format!("hash-{}", transfer_id),
+            file_name: file_name.to_string(),
+            file_size: 2048,
+            total_chunks: 2,
+            chunk_size: 1024,
+            started_at,
+            available_sources: Vec::new(),
+            selected_sources: Vec::new(),
+        })
+    }
+
+    fn chunk(transfer_id: &str, source_type: SourceType) -> TransferEvent {
+        TransferEvent::ChunkCompleted(ChunkCompletedEvent {
+            transfer_id: transfer_id.to_string(),
+            chunk_id: 0,
+            chunk_size: 1024,
+            source_id: "source".to_string(),
+            source_type,
+            completed_at: 0,
+            download_duration_ms: 0,
+            chunk_speed_bps: 0.0,
+            verified: true,
+        })
+    }
+
+    fn completed(transfer_id: &str, file_name: &str, completed_at: u64) -> TransferEvent {
+        TransferEvent::Completed(TransferCompletedEvent {
+            transfer_id: transfer_id.to_string(),
+            file_hash: format!("hash-{}", transfer_id),
+            file_name: file_name.to_string(),
+            file_size: 2048,
+            output_path: String::new(),
+            completed_at,
+            duration_seconds: 2,
+            average_speed_bps: 1024.0,
+            total_chunks: 2,
+            sources_used: Vec::new(),
+        })
+    }
+
+    fn failed(transfer_id: &str, failed_at: u64) -> TransferEvent {
+        TransferEvent::Failed(TransferFailedEvent {
+            transfer_id: transfer_id.to_string(),
+            file_hash: format!("hash-{}", transfer_id),
+            failed_at,
+            error: "no sources".to_string(),
+            error_category: ErrorCategory::NoSources,
+            downloaded_bytes: 1024,
+            total_bytes: 2048,
+            retry_possible: true,
+        })
+    }
+
+    #[test]
+    fn finished_transfers_are_recorded_with_their_protocols() {
+        let dir = tempdir().unwrap();
+        let mut store = TransferHistoryStore::new(dir.path().join(HISTORY_FILE_NAME));
+
+        store.observe(&started("a", "movie.mkv", 1_000)).unwrap();
+        store.observe(&chunk("a", SourceType::Http)).unwrap();
+        store.observe(&chunk("a", SourceType::Http)).unwrap();
+        store.observe(&completed("a", "movie.mkv", 3_000)).unwrap();
+
+        store.observe(&started("b", "song.mp3", 4_000)).unwrap();
+        store.observe(&chunk("b", SourceType::Ftp)).unwrap();
+        store.observe(&failed("b", 6_000)).unwrap();
+
+        let page = store.query_history(&HistoryFilter::default()).unwrap();
+        assert_eq!(page.total, 2);
+        assert_eq!(page.records[0].transfer_id, "b");
+        assert_eq!(page.records[0].outcome, TransferOutcome::Failed);
+        assert_eq!(page.records[0].file_name, "song.mp3");
+        assert_eq!(page.records[0].duration_seconds, 2);
+        assert_eq!(page.records[0].average_speed_bps, 512.0);
+        assert_eq!(page.records[1].outcome, TransferOutcome::Completed);
+        assert_eq!(
+            page.records[1].protocols,
+            vec![ProtocolShare { source_type: SourceType::Http, bytes: 2048, chunks: 2 }]
+        );
+        assert!(store.pending.is_empty());
+    }
+
+    #[test]
+    fn canceled_transfers_are_recorded_with_their_reason() {
+        let dir = tempdir().unwrap();
+        let mut store = TransferHistoryStore::new(dir.path().join(HISTORY_FILE_NAME));
+
+        store.observe(&started("c", "archive.zip", 1_000)).unwrap();
+        store.observe(&chunk("c", SourceType::P2p)).unwrap();
+        store
+            .observe(&TransferEvent::Canceled(TransferCanceledEvent {
+                transfer_id: "c".to_string(),
+                canceled_at: 3_000,
+                downloaded_bytes: 1024,
+                total_bytes: 2048,
+                keep_partial: true,
+                reason: CancelReason::DiskFull,
+            }))
+            .unwrap();
+
+        let filter = HistoryFilter {
+            outcome: Some(TransferOutcome::Canceled),
+            ..Default::default()
+        };
+        let page = store.query_history(&filter).unwrap();
+        assert_eq!(page.total, 1);
+        let record = &page.records[0];
+        assert_eq!(record.file_name, "archive.zip");
+        assert_eq!(record.bytes_transferred, 1024);
+        assert_eq!(record.average_speed_bps, 512.0);
+        assert_eq!(record.cancel_reason, Some(CancelReason::DiskFull));
+        assert!(record.error.is_none());
+        assert!(store.pending.is_empty());
+    }
+
+    #[test]
+    fn query_history_filters_and_pages_newest_first() {
+        let dir = tempdir().unwrap();
+        let mut store = TransferHistoryStore::new(dir.path().join(HISTORY_FILE_NAME));
+        for i in 0..5u64 {
+            let file_name = format!("file-{}.bin", i);
+            store.observe(&completed(&i.to_string(), &file_name, i * 1_000)).unwrap();
+        }
+        store.observe(&failed("x", 10_000)).unwrap();
+
+        let filter = HistoryFilter {
+            outcome: Some(TransferOutcome::Completed),
+            offset: 1,
+            limit: Some(2),
+            ..Default::default()
+        };
+        let page = store.query_history(&filter).unwrap();
+        assert_eq!(page.total, 5);
+        let ids: Vec<_> = page.records.iter().map(|r| r.transfer_id.as_str()).collect();
+        assert_eq!(ids, vec!["3", "2"]);
+
+        let filter = HistoryFilter {
+            search: Some("FILE-1".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(store.query_history(&filter).unwrap().records[0].transfer_id, "1");
+
+        let filter = HistoryFilter {
+            offset: 10,
+            ..Default::default()
+        };
+        assert!(store.query_history(&filter).unwrap().records.is_empty());
+
+        store.clear().unwrap();
+        assert_eq!(store.query_history(&HistoryFilter::default()).unwrap().total, 0);
+    }
+
+    #[test]
+    fn full_history_files_rotate_and_stay_queryable() {
+        let dir = tempdir().unwrap();
+        let mut store = TransferHistoryStore {
+            max_file_bytes: 1,
+            ..TransferHistoryStore::new(dir.path().join(HISTORY_FILE_NAME))
+        };
+        for i in 0..3u64 {
+            store.observe(&completed(&i.to_string(), "file.bin", i * 1_000)).unwrap();
+        }
+
+        // Each append finds the file full, so only the latest two records are left
+        assert!(store.rotated_path().exists());
+        let page = store.query_history(&HistoryFilter::default()).unwrap();
+        assert_eq!(page.total, 2);
+        let ids: Vec<_> = page.records.iter().map(|r| r.transfer_id.as_str()).collect();
+        assert_eq!(ids, vec!["2", "1"]);
+
+        // A snapshot ignores records appended after it was taken
+        let mut snapshot = store.snapshot().unwrap();
+        store.observe(&completed("3", "file.bin", 3_000)).unwrap();
+        let mut seen = 0;
+        for_each_record(&mut snapshot, |_| seen += 1).unwrap();
+        assert_eq!(seen, 2);
+
+        store.clear().unwrap();
+        assert!(!store.rotated_path().exists());
+        assert_eq!(store.query_history(&HistoryFilter::default()).unwrap().total, 0);
+    }
+}
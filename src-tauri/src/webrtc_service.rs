@@ -1,11 +1,14 @@
 use crate::connection_retry::{ConnectionManager, ConnectionState, RetryConfig, WebRtcRetryContext, };
 use crate::encryption::{decrypt_aes_key, encrypt_aes_key, EncryptedAesKeyBundle, FileEncryption};
-use crate::file_transfer::FileTransferService;
+use crate::file_transfer::{FileTransferService, StoredFileReader};
+use crate::ice_health;
 use crate::keystore::Keystore;
 use crate::bandwidth::BandwidthController;
-use crate::manager::{ChunkInfo, FileManifest};
+use crate::manager::{verify_chunk_merkle, ChunkInfo, ChunkMerkleProof, ChunkMerkleTree, FileManifest};
 use crate::multi_source_download::MultiSourceDownloadService;
 use crate::payment_checkpoint::PaymentCheckpointService;
+use crate::protocols::seeding::SeedingRegistry;
+use crate::transfer_events::SourceType;
 use aes_gcm::aead::Aead;
 use aes_gcm::{AeadCore, KeyInit};
 use serde::{Deserialize, Serialize};
@@ -23,9 +26,8 @@ use webrtc::data_channel::data_channel_message::DataChannelMessage;
 use webrtc::data_channel::data_channel_init::RTCDataChannelInit;
 use webrtc::data_channel::RTCDataChannel;
 use webrtc::ice_transport::ice_candidate::{RTCIceCandidate, RTCIceCandidateInit};
+use webrtc::ice_transport::ice_candidate_type::RTCIceCandidateType;
 use webrtc::ice_transport::ice_connection_state::RTCIceConnectionState;
-use webrtc::ice_transport::ice_credential_type::RTCIceCredentialType;
-use webrtc::ice_transport::ice_server::RTCIceServer;
 use webrtc::peer_connection::configuration::RTCConfiguration;
 use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
 use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
@@ -43,6 +45,137 @@ lazy_static::lazy_static! {
     /// This lets the download initiator (GUI/E2E API) control the final save location,
     /// while the assembler lives in this library crate (no access to binary AppState).
     static ref REQUESTED_WEBRTC_DOWNLOAD_OUTPUT_PATHS: Mutex<HashMap<String, String>> = Mutex::new(HashMap::new());
+
+    /// Registry that WebRTC uploads are counted against, once the protocol manager exists
+    static ref SEEDING_REGISTRY: Mutex<Option<SeedingRegistry>> = Mutex::new(None);
+
+    /// Connection caps applied to peers that connect to us
+    static ref PEER_LIMITS: Mutex<PeerLimits> = Mutex::new(PeerLimits::default());
+
+    /// Chunk-store contents served to partial-seeding requests, keyed by file hash
+    static ref STORED_CHUNK_CACHE: Mutex<HashMap<String, Arc<StoredChunks>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// How long a scan of the chunk store answers chunk and availability requests for a file.
+/// Chunks stored in the meantime are advertised and served once the scan is refreshed.
+const STORED_CHUNK_CACHE_TTL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Chunks of one file found in the chunk store
+#[derive(Debug)]
+struct StoredChunks {
+    /// Sorted chunk IDs
+    chunk_ids: Vec<u32>,
+    total_chunks: u32,
+    scanned_at: Instant,
+}
+
+impl StoredChunks {
+    fn contains(&self, chunk_index: u32) -> bool {
+        self.chunk_ids.binary_search(&chunk_index).is_ok()
+    }
+}
+
+/// Caps that keep a popular seeder from running out of memory and tasks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerLimits {
+    /// Peer connections open at once (0 = unlimited)
+    pub max_peer_connections: usize,
+    /// Active peers fetching the same file at once (0 = unlimited)
+    pub max_peers_per_file: usize,
+    /// A connection with no transfer and no message for this long counts as idle: it may be
+    /// closed to make room and no longer counts towards `max_peers_per_file`
+    pub idle_timeout_secs: u64,
+    /// Idle connections are closed after this long even without pressure, so abandoned
+    /// transfers don't leak peer connections (0 = never)
+    #[serde(default = "default_idle_reap_secs")]
+    pub idle_reap_secs: u64,
+}
+
+fn default_idle_reap_secs() -> u64 {
+    300
+}
+
+impl Default for PeerLimits {
+    fn default() -> Self {
+        Self {
+            max_peer_connections: 64,
+            max_peers_per_file: 16,
+            idle_timeout_secs: 120,
+            idle_reap_secs: default_idle_reap_secs(),
+        }
+    }
+}
+
+/// Change the caps for connections accepted from now on.
+pub async fn set_peer_limits(limits: PeerLimits) {
+    *PEER_LIMITS.lock().await = limits;
+}
+
+pub async fn peer_limits() -> PeerLimits {
+    *PEER_LIMITS.lock().await
+}
+
+impl PeerConnection {
+    fn is_idle(&self, idle_timeout: Duration, now: Instant) -> bool {
+        self.active_transfers.is_empty()
+            && now.saturating_duration_since(self.last_activity) >= idle_timeout
+    }
+}
+
+/// The peer idle the longest, if any has been idle for `idle_timeout`
+fn idle_connection_to_evict(
+    connections: &HashMap<String, PeerConnection>,
+    idle_timeout: Duration,
+    now: Instant,
+) -> Option<String> {
+    connections
+        .values()
+        .filter(|connection| connection.is_idle(idle_timeout, now))
+        .min_by_key(|connection| connection.last_activity)
+        .map(|connection| connection.peer_id.clone())
+}
+
+/// Every peer that has been idle for `idle_timeout`
+fn idle_connections(
+    connections: &HashMap<String, PeerConnection>,
+    idle_timeout: Duration,
+    now: Instant,
+) -> Vec<String> {
+    connections
+        .values()
+        .filter(|connection| connection.is_idle(idle_timeout, now))
+        .map(|connection| connection.peer_id.clone())
+        .collect()
+}
+
+/// Peers other than `peer_id` that asked for `file_hash` and are not idle
+fn active_peers_for_file(
+    connections: &HashMap<String, PeerConnection>,
+    file_hash: &str,
+    peer_id: &str,
+    idle_timeout: Duration,
+    now: Instant,
+) -> usize {
+    connections
+        .values()
+        .filter(|connection| {
+            connection.peer_id != peer_id
+                && connection.requested_files.contains(file_hash)
+                && !connection.is_idle(idle_timeout, now)
+        })
+        .count()
+}
+
+/// Record WebRTC uploads in the given seeding registry's upload statistics.
+pub async fn set_seeding_registry(registry: SeedingRegistry) {
+    *SEEDING_REGISTRY.lock().await = Some(registry);
+}
+
+/// The registry set with `set_seeding_registry`, if any
+pub(crate) async fn seeding_registry() -> Option<SeedingRegistry> {
+    SEEDING_REGISTRY.lock().await.clone()
 }
 
 /// Record the desired output path for a given WebRTC file hash.
@@ -62,63 +195,129 @@ async fn take_requested_download_output_path(file_hash: &str) -> Option<String>
 }
 
 const CHUNK_SIZE: usize = 32768; // 32KB chunks - configured data channel for larger messages (8x improvement over original 4KB)
+/// Chunk size published merkle roots are built over (the `ChunkManager` chunk size). A leaf
+/// goes out as `FRAMES_PER_MERKLE_LEAF` frames of `CHUNK_SIZE`, the last of which carries the
+/// leaf's proof, and the receiver checks the proof once it has every frame of the leaf.
+const MERKLE_LEAF_SIZE: usize = 256 * 1024;
+const FRAMES_PER_MERKLE_LEAF: u32 = (MERKLE_LEAF_SIZE / CHUNK_SIZE) as u32;
+
+/// The merkle leaf frame `chunk_index` belongs to, and the frames that make up that leaf
+fn merkle_leaf_frames(chunk_index: u32, total_chunks: u32) -> (u32, std::ops::Range<u32>) {
+    let leaf = chunk_index / FRAMES_PER_MERKLE_LEAF;
+    let start = leaf * FRAMES_PER_MERKLE_LEAF;
+    (leaf, start..(start + FRAMES_PER_MERKLE_LEAF).min(total_chunks))
+}
+
+/// Check the merkle leaf of frame `chunk_index` against `merkle_root` once `frames` holds
+/// all of the leaf's frames and `proofs` its proof
+///
+/// Returns `None` while something is still missing. A leaf that fails has its frames
+/// dropped, so they are requested again like any other missing frame.
+fn check_merkle_leaf(
+    frames: &mut HashMap<u32, FileChunk>,
+    proofs: &mut HashMap<u32, ChunkMerkleProof>,
+    chunk_index: u32,
+    total_chunks: u32,
+    merkle_root: &str,
+) -> Option<bool> {
+    let (leaf, leaf_frames) = merkle_leaf_frames(chunk_index, total_chunks);
+    if !leaf_frames.clone().all(|index| frames.contains_key(&index)) {
+        return None;
+    }
+    let proof = proofs.remove(&leaf)?;
+    let data: Vec<u8> = leaf_frames
+        .clone()
+        .flat_map(|index| frames[&index].data.iter().copied())
+        .collect();
+    let verified = matches!(
+        verify_chunk_merkle(leaf as usize, &data, &proof, merkle_root),
+        Ok(true)
+    );
+    if !verified {
+        for index in leaf_frames {
+            frames.remove(&index);
+        }
+    }
+    Some(verified)
+}
 
 // --- WebRTC binary framing for file chunks ---
-// We send file chunks as *binary* messages instead of JSON text to avoid massive JSON overhead
-// (Vec<u8> becomes a large numeric array in JSON, easily exceeding DataChannel max message size).
+// Chunk bodies are sent as *binary* messages; JSON text is reserved for control messages
+// (FileRequest, ManifestRequest, ChunkAck). Serializing Vec<u8> as JSON turns every byte into
+// a numeric array element, inflating payloads several times over and easily exceeding the
+// DataChannel max message size.
 //
-// Frame format (big-endian):
-//   0..4   : magic "CHNK"
-//   4      : version (1)
-//   5      : flags (bit0: encrypted_key_bundle present)
-//   6..10  : chunk_index (u32)
-//   10..14 : total_chunks (u32)
-//   14..16 : file_name_len (u16)
-//   16..48 : file_hash (32 bytes, raw)
-//   48..80 : checksum (32 bytes, raw sha256)
+// The first byte of a binary message is its frame type. Chunk frame (big-endian):
+//   0      : frame type (FRAME_TYPE_CHUNK)
+//   1      : flags (bit0: encrypted_key_bundle present, bit1: merkle proof present)
+//   2..6   : chunk_index (u32)
+//   6..10  : total_chunks (u32)
+//   10..12 : file_hash_len (u16)
+//   12..14 : file_name_len (u16)
+//   14..46 : checksum (32 bytes, raw sha256)
 //   [opt]  : key_bundle_len (u32) + key_bundle_json bytes (if flags bit0 set)
+//   [opt]  : total_leaves (u32) + proof_len (u16) + proof_len raw 32-byte hashes (if flags bit1 set)
+//   ...    : file_hash bytes (utf8, file_hash_len)
 //   ...    : file_name bytes (utf8, file_name_len)
 //   ...    : data bytes (rest)
+//
+// Older peers send and expect a "CHNK" frame instead (magic, version 1, raw 32-byte file hash,
+// no merkle proof), so peers whose file request doesn't set `binaryChunkFrames` get those.
+// Both are accepted on receive.
+const FRAME_TYPE_CHUNK: u8 = 0x01;
+const CHUNK_FRAME_HEADER_LEN: usize = 1 + 1 + 4 + 4 + 2 + 2 + 32;
 const CHUNK_FRAME_MAGIC: &[u8; 4] = b"CHNK";
 const CHUNK_FRAME_VERSION: u8 = 1;
 const CHUNK_FRAME_FLAG_KEY_BUNDLE: u8 = 1 << 0;
+const CHUNK_FRAME_FLAG_MERKLE_PROOF: u8 = 1 << 1;
 
-fn encode_chunk_frame(chunk: &FileChunk) -> Result<Vec<u8>, String> {
-    let file_hash_bytes_vec = hex::decode(&chunk.file_hash)
-        .map_err(|e| format!("Invalid file_hash hex for chunk framing: {}", e))?;
-    if file_hash_bytes_vec.len() != 32 {
-        return Err(format!(
-            "Invalid file_hash length for chunk framing (expected 32 bytes, got {})",
-            file_hash_bytes_vec.len()
-        ));
-    }
-    let mut file_hash_bytes = [0u8; 32];
-    file_hash_bytes.copy_from_slice(&file_hash_bytes_vec);
-
+/// Raw SHA-256 checksum for a chunk, recomputed from the data if the stored value
+/// isn't a 32-byte hex digest
+fn chunk_checksum_bytes(chunk: &FileChunk) -> Result<[u8; 32], String> {
     let checksum_hex = if chunk.checksum.len() == 64 {
         chunk.checksum.clone()
     } else {
-        // Fallback: recompute checksum from data if the stored value isn't a 32-byte hex digest.
         let mut hasher = Sha256::default();
         hasher.update(&chunk.data);
         format!("{:x}", hasher.finalize())
     };
     let checksum_vec =
         hex::decode(&checksum_hex).map_err(|e| format!("Invalid checksum hex for framing: {}", e))?;
-    if checksum_vec.len() != 32 {
-        return Err(format!(
+    checksum_vec.try_into().map_err(|v: Vec<u8>| {
+        format!(
             "Invalid checksum length for chunk framing (expected 32 bytes, got {})",
-            checksum_vec.len()
-        ));
+            v.len()
+        )
+    })
+}
+
+/// Encode `chunk` as a typed frame for peers that advertised `binary_chunk_frames`,
+/// and as a legacy "CHNK" frame for older peers
+fn encode_chunk_payload(chunk: &FileChunk, binary_frames: bool) -> Result<Vec<u8>, String> {
+    if binary_frames {
+        encode_chunk_frame(chunk)
+    } else {
+        encode_legacy_chunk_frame(chunk)
     }
-    let mut checksum_bytes = [0u8; 32];
-    checksum_bytes.copy_from_slice(&checksum_vec);
+}
 
+/// Encode `chunk` as a legacy "CHNK" frame, the only chunk format older peers decode
+///
+/// The format has no room for a merkle proof, which older peers never ask for anyway.
+fn encode_legacy_chunk_frame(chunk: &FileChunk) -> Result<Vec<u8>, String> {
+    let file_hash_bytes: [u8; 32] = hex::decode(&chunk.file_hash)
+        .map_err(|e| format!("Invalid file_hash hex for chunk framing: {}", e))?
+        .try_into()
+        .map_err(|v: Vec<u8>| {
+            format!(
+                "Invalid file_hash length for chunk framing (expected 32 bytes, got {})",
+                v.len()
+            )
+        })?;
+    let checksum_bytes = chunk_checksum_bytes(chunk)?;
     let file_name_bytes = chunk.file_name.as_bytes();
-    if file_name_bytes.len() > u16::MAX as usize {
-        return Err("file_name too long for chunk framing".to_string());
-    }
-    let file_name_len = file_name_bytes.len() as u16;
+    let file_name_len = u16::try_from(file_name_bytes.len())
+        .map_err(|_| "file_name too long for chunk framing".to_string())?;
 
     let mut flags: u8 = 0;
     let mut key_bundle_json: Vec<u8> = Vec::new();
@@ -162,7 +361,196 @@ fn encode_chunk_frame(chunk: &FileChunk) -> Result<Vec<u8>, String> {
     Ok(out)
 }
 
+fn encode_chunk_frame(chunk: &FileChunk) -> Result<Vec<u8>, String> {
+    let checksum_bytes = chunk_checksum_bytes(chunk)?;
+
+    let file_hash_bytes = chunk.file_hash.as_bytes();
+    let file_hash_len = u16::try_from(file_hash_bytes.len())
+        .map_err(|_| "file_hash too long for chunk framing".to_string())?;
+    let file_name_bytes = chunk.file_name.as_bytes();
+    let file_name_len = u16::try_from(file_name_bytes.len())
+        .map_err(|_| "file_name too long for chunk framing".to_string())?;
+
+    let mut flags: u8 = 0;
+    let mut key_bundle_json: Vec<u8> = Vec::new();
+    if let Some(bundle) = &chunk.encrypted_key_bundle {
+        flags |= CHUNK_FRAME_FLAG_KEY_BUNDLE;
+        key_bundle_json = serde_json::to_vec(bundle)
+            .map_err(|e| format!("Failed to serialize encrypted_key_bundle: {}", e))?;
+    }
+
+    let mut proof_bytes: Vec<u8> = Vec::new();
+    if let Some(proof) = &chunk.merkle_proof {
+        flags |= CHUNK_FRAME_FLAG_MERKLE_PROOF;
+        let total_leaves = u32::try_from(proof.total_leaves)
+            .map_err(|_| "Merkle proof total_leaves too large for chunk framing".to_string())?;
+        let proof_len = u16::try_from(proof.proof_hashes.len())
+            .map_err(|_| "Merkle proof too long for chunk framing".to_string())?;
+        proof_bytes.extend_from_slice(&total_leaves.to_be_bytes());
+        proof_bytes.extend_from_slice(&proof_len.to_be_bytes());
+        for hash_hex in &proof.proof_hashes {
+            let hash = hex::decode(hash_hex)
+                .map_err(|e| format!("Invalid merkle proof hash hex for framing: {}", e))?;
+            if hash.len() != 32 {
+                return Err("Invalid merkle proof hash length for chunk framing".to_string());
+            }
+            proof_bytes.extend_from_slice(&hash);
+        }
+    }
+
+    let mut out = Vec::with_capacity(
+        CHUNK_FRAME_HEADER_LEN
+            + if flags & CHUNK_FRAME_FLAG_KEY_BUNDLE != 0 {
+                4 + key_bundle_json.len()
+            } else {
+                0
+            }
+            + proof_bytes.len()
+            + file_hash_bytes.len()
+            + file_name_bytes.len()
+            + chunk.data.len(),
+    );
+
+    out.push(FRAME_TYPE_CHUNK);
+    out.push(flags);
+    out.extend_from_slice(&chunk.chunk_index.to_be_bytes());
+    out.extend_from_slice(&chunk.total_chunks.to_be_bytes());
+    out.extend_from_slice(&file_hash_len.to_be_bytes());
+    out.extend_from_slice(&file_name_len.to_be_bytes());
+    out.extend_from_slice(&checksum_bytes);
+
+    if flags & CHUNK_FRAME_FLAG_KEY_BUNDLE != 0 {
+        let len_u32: u32 = key_bundle_json
+            .len()
+            .try_into()
+            .map_err(|_| "encrypted_key_bundle too large".to_string())?;
+        out.extend_from_slice(&len_u32.to_be_bytes());
+        out.extend_from_slice(&key_bundle_json);
+    }
+    out.extend_from_slice(&proof_bytes);
+
+    out.extend_from_slice(file_hash_bytes);
+    out.extend_from_slice(file_name_bytes);
+    out.extend_from_slice(&chunk.data);
+    Ok(out)
+}
+
+/// Take the next `len` bytes of a frame, advancing `pos`
+fn take_frame_bytes<'a>(
+    data: &'a [u8],
+    pos: &mut usize,
+    len: usize,
+    field: &str,
+) -> Result<&'a [u8], String> {
+    let end = pos
+        .checked_add(len)
+        .filter(|end| *end <= data.len())
+        .ok_or_else(|| format!("Chunk frame truncated in {}", field))?;
+    let bytes = &data[*pos..end];
+    *pos = end;
+    Ok(bytes)
+}
+
+/// Decode a binary data-channel message. `Ok(None)` means the message is not a chunk
+/// frame (e.g. JSON control text) and should be parsed as text.
 fn decode_chunk_frame(data: &[u8]) -> Result<Option<FileChunk>, String> {
+    match data.first() {
+        Some(&FRAME_TYPE_CHUNK) => decode_typed_chunk_frame(data).map(Some),
+        _ if data.starts_with(CHUNK_FRAME_MAGIC) => decode_legacy_chunk_frame(data),
+        _ => Ok(None),
+    }
+}
+
+fn decode_typed_chunk_frame(data: &[u8]) -> Result<FileChunk, String> {
+    if data.len() < CHUNK_FRAME_HEADER_LEN {
+        return Err("Chunk frame shorter than its header".to_string());
+    }
+
+    let mut pos = 1;
+    let flags = take_frame_bytes(data, &mut pos, 1, "flags")?[0];
+    let chunk_index = u32::from_be_bytes(
+        take_frame_bytes(data, &mut pos, 4, "chunk_index")?
+            .try_into()
+            .map_err(|_| "Invalid chunk_index bytes".to_string())?,
+    );
+    let total_chunks = u32::from_be_bytes(
+        take_frame_bytes(data, &mut pos, 4, "total_chunks")?
+            .try_into()
+            .map_err(|_| "Invalid total_chunks bytes".to_string())?,
+    );
+    let file_hash_len = u16::from_be_bytes(
+        take_frame_bytes(data, &mut pos, 2, "file_hash_len")?
+            .try_into()
+            .map_err(|_| "Invalid file_hash_len bytes".to_string())?,
+    ) as usize;
+    let file_name_len = u16::from_be_bytes(
+        take_frame_bytes(data, &mut pos, 2, "file_name_len")?
+            .try_into()
+            .map_err(|_| "Invalid file_name_len bytes".to_string())?,
+    ) as usize;
+    let checksum = hex::encode(take_frame_bytes(data, &mut pos, 32, "checksum")?);
+
+    let encrypted_key_bundle = if flags & CHUNK_FRAME_FLAG_KEY_BUNDLE != 0 {
+        let key_len = u32::from_be_bytes(
+            take_frame_bytes(data, &mut pos, 4, "key_bundle_len")?
+                .try_into()
+                .map_err(|_| "Invalid key_bundle_len bytes".to_string())?,
+        ) as usize;
+        let bundle_bytes = take_frame_bytes(data, &mut pos, key_len, "key_bundle")?;
+        Some(
+            serde_json::from_slice::<EncryptedAesKeyBundle>(bundle_bytes)
+                .map_err(|e| format!("Failed to parse encrypted_key_bundle: {}", e))?,
+        )
+    } else {
+        None
+    };
+
+    let merkle_proof = if flags & CHUNK_FRAME_FLAG_MERKLE_PROOF != 0 {
+        let total_leaves = u32::from_be_bytes(
+            take_frame_bytes(data, &mut pos, 4, "merkle_total_leaves")?
+                .try_into()
+                .map_err(|_| "Invalid merkle_total_leaves bytes".to_string())?,
+        ) as usize;
+        let proof_len = u16::from_be_bytes(
+            take_frame_bytes(data, &mut pos, 2, "merkle_proof_len")?
+                .try_into()
+                .map_err(|_| "Invalid merkle_proof_len bytes".to_string())?,
+        ) as usize;
+        let proof_hashes = take_frame_bytes(data, &mut pos, proof_len * 32, "merkle_proof")?
+            .chunks(32)
+            .map(hex::encode)
+            .collect();
+        Some(ChunkMerkleProof {
+            proof_hashes,
+            total_leaves,
+        })
+    } else {
+        None
+    };
+
+    let file_hash = String::from_utf8(
+        take_frame_bytes(data, &mut pos, file_hash_len, "file_hash")?.to_vec(),
+    )
+    .map_err(|_| "Invalid utf8 in file_hash".to_string())?;
+    let file_name = String::from_utf8(
+        take_frame_bytes(data, &mut pos, file_name_len, "file_name")?.to_vec(),
+    )
+    .map_err(|_| "Invalid utf8 in file_name".to_string())?;
+
+    Ok(FileChunk {
+        file_hash,
+        file_name,
+        chunk_index,
+        total_chunks,
+        data: data[pos..].to_vec(),
+        checksum,
+        encrypted_key_bundle,
+        merkle_proof,
+    })
+}
+
+/// Decode a legacy "CHNK" frame as sent by older peers
+fn decode_legacy_chunk_frame(data: &[u8]) -> Result<Option<FileChunk>, String> {
     // Fast reject: must start with magic and have the minimal fixed header.
     const MIN_HEADER: usize = 4 + 1 + 1 + 4 + 4 + 2 + 32 + 32;
     if data.len() < MIN_HEADER {
@@ -247,6 +635,7 @@ fn decode_chunk_frame(data: &[u8]) -> Result<Option<FileChunk>, String> {
         data: chunk_data,
         checksum: hex::encode(checksum_bytes),
         encrypted_key_bundle,
+        merkle_proof: None,
     }))
 }
 
@@ -263,35 +652,194 @@ const MAX_RETRY_DELAY_MS: u64 = 15000;
 /// Without ICE servers, WebRTC connections will fail for users behind NAT (majority of users).
 /// 
 /// TURN servers are required for symmetric NAT (common in universities/corporate networks).
+/// Servers that failed their last health check are left out (see [`ice_health`]).
 fn create_rtc_configuration() -> RTCConfiguration {
     RTCConfiguration {
-        ice_servers: vec![
-            // Google STUN servers (reliable, no auth needed)
-            RTCIceServer {
-                urls: vec![
-                    "stun:stun.l.google.com:19302".to_string(),
-                    "stun:stun1.l.google.com:19302".to_string(),
-                    "stun:stun2.l.google.com:19302".to_string(),
-                    "stun:stun3.l.google.com:19302".to_string(),
-                ],
-                ..Default::default()
-            },
-            // Evan Brass experimental TURN server (free, public)
-            RTCIceServer {
-                urls: vec![
-                    "turn:stun.evan-brass.net".to_string(),
-                    "turn:stun.evan-brass.net?transport=tcp".to_string(),
-                    "stun:stun.evan-brass.net".to_string(),
-                ],
-                username: "guest".to_string(),
-                credential: "password".to_string(),
-                credential_type: RTCIceCredentialType::Password,
-            },
-        ],
+        ice_servers: ice_health::healthy_ice_servers(),
         ..Default::default()
     }
 }
 
+/// How often idle peer connections are looked for
+const IDLE_REAP_INTERVAL_SECS: u64 = 30;
+
+/// How often receives are checked for stalls
+const TRANSFER_WATCHDOG_INTERVAL_SECS: u64 = 5;
+
+/// How long a receive may go without a new chunk before its missing chunks are re-requested
+const TRANSFER_STALL_TIMEOUT_SECS: u64 = 30;
+
+/// Rounds of re-requesting missing chunks before a stalled receive fails
+const MAX_MISSING_CHUNK_ROUNDS: u32 = 3;
+
+/// Chunks re-requested per round; the rest follow in later rounds
+const MAX_MISSING_CHUNK_REQUESTS: usize = 256;
+
+/// Time a receive gets on top of what its size allows at `TRANSFER_MIN_RATE_BPS`
+const TRANSFER_DEADLINE_BASE_SECS: u64 = 10 * 60;
+
+/// Slowest average rate a receive may run at before it fails, however it's progressing
+const TRANSFER_MIN_RATE_BPS: u64 = 16 * 1024;
+
+/// How long the connectivity self-test waits for ICE gathering to finish
+const CONNECTIVITY_TEST_TIMEOUT_SECS: u64 = 10;
+
+/// NAT behaviour inferred from the ICE candidates gathered in a connectivity test
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum NatType {
+    /// A local address is publicly reachable, so there is no NAT in the way
+    Open,
+    /// The NAT reuses one public mapping for every destination; STUN is enough
+    EndpointIndependent,
+    /// The NAT maps each destination to a different public port; direct
+    /// connections usually fail and transfers need a TURN relay
+    Symmetric,
+    /// Fewer than two STUN servers answered, so the mapping couldn't be compared
+    Unknown,
+}
+
+/// Result of [`WebRTCService::run_connectivity_test`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NatTestResult {
+    pub nat_type: NatType,
+    /// A host or server-reflexive candidate usable for direct connections was gathered
+    pub direct_candidate: bool,
+    /// A TURN server handed out a relay candidate
+    pub relay_candidate: bool,
+    /// Whether any TURN server is configured at all
+    pub turn_configured: bool,
+    pub host_candidates: usize,
+    pub srflx_candidates: usize,
+    pub relay_candidates: usize,
+    /// Public `ip:port` mappings observed by the STUN servers
+    pub public_addresses: Vec<String>,
+    /// Whether gathering finished before the test timed out
+    pub gathering_complete: bool,
+    pub duration_ms: u64,
+    /// What the result means for transfers, and what to do about it
+    pub diagnosis: String,
+}
+
+/// Whether an address is reachable from the internet (not private, loopback,
+/// link-local or carrier-grade NAT space)
+fn is_public_ip(address: &str) -> bool {
+    match address.parse::<std::net::IpAddr>() {
+        Ok(std::net::IpAddr::V4(ip)) => {
+            let shared = ip.octets()[0] == 100 && (ip.octets()[1] & 0xc0) == 64;
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || shared)
+        }
+        Ok(std::net::IpAddr::V6(ip)) => {
+            let segment = ip.segments()[0];
+            let unique_local = (segment & 0xfe00) == 0xfc00;
+            let link_local = (segment & 0xffc0) == 0xfe80;
+            !(ip.is_loopback() || ip.is_unspecified() || unique_local || link_local)
+        }
+        Err(_) => false,
+    }
+}
+
+/// Classify gathered ICE candidates into a [`NatTestResult`]
+///
+/// Server-reflexive candidates that share a local base but differ in public
+/// address or port mean the NAT allocates a new mapping per STUN server,
+/// i.e. symmetric NAT. A single answer can't show either behaviour, so the
+/// NAT type stays unknown until two STUN servers have answered and agree.
+fn analyze_ice_candidates(candidates: &[RTCIceCandidate], turn_configured: bool) -> NatTestResult {
+    let of_type = |typ: RTCIceCandidateType| {
+        candidates
+            .iter()
+            .filter(move |candidate| candidate.typ == typ)
+    };
+    let host_addresses: std::collections::HashSet<&str> = of_type(RTCIceCandidateType::Host)
+        .map(|candidate| candidate.address.as_str())
+        .collect();
+
+    let mut mappings: HashMap<(&str, u16), Vec<(&str, u16)>> = HashMap::new();
+    let mut public_addresses = Vec::new();
+    for candidate in of_type(RTCIceCandidateType::Srflx) {
+        mappings
+            .entry((candidate.related_address.as_str(), candidate.related_port))
+            .or_default()
+            .push((candidate.address.as_str(), candidate.port));
+        let public_address = format!("{}:{}", candidate.address, candidate.port);
+        if !public_addresses.contains(&public_address) {
+            public_addresses.push(public_address);
+        }
+    }
+
+    let host_candidates = of_type(RTCIceCandidateType::Host).count();
+    let srflx_candidates = of_type(RTCIceCandidateType::Srflx).count();
+    let relay_candidates = of_type(RTCIceCandidateType::Relay).count();
+
+    let nat_type = if host_addresses.iter().any(|address| is_public_ip(address)) {
+        NatType::Open
+    } else if srflx_candidates == 0 {
+        NatType::Unknown
+    } else if of_type(RTCIceCandidateType::Srflx)
+        .any(|candidate| host_addresses.contains(candidate.address.as_str()))
+    {
+        NatType::Open
+    } else if mappings
+        .values()
+        .any(|mapped| mapped.iter().any(|mapping| *mapping != mapped[0]))
+    {
+        NatType::Symmetric
+    } else if mappings.values().any(|mapped| mapped.len() > 1) {
+        NatType::EndpointIndependent
+    } else {
+        NatType::Unknown
+    };
+    let direct_candidate = nat_type == NatType::Open || srflx_candidates > 0;
+    let relay_candidate = relay_candidates > 0;
+
+    let diagnosis = match (nat_type, relay_candidate) {
+        (NatType::Open, _) => {
+            "Your address is publicly reachable; peers can connect to you directly.".to_string()
+        }
+        (NatType::EndpointIndependent, _) => {
+            "Your NAT allows direct connections through STUN.".to_string()
+        }
+        (NatType::Symmetric, true) => "Your network uses symmetric NAT; transfers will go \
+            through the TURN relay and may be slower."
+            .to_string(),
+        (NatType::Symmetric, false) if turn_configured => "Your network requires TURN, but \
+            the configured TURN server gave no relay address. Check its address and credentials."
+            .to_string(),
+        (NatType::Symmetric, false) => {
+            "Your network requires TURN; none is configured.".to_string()
+        }
+        (NatType::Unknown, _) if srflx_candidates > 0 => "Only one STUN server answered, so \
+            it's unclear whether direct connections will work. Configure at least two."
+            .to_string(),
+        (NatType::Unknown, true) => "No STUN server answered, so UDP may be blocked; \
+            transfers will go through the TURN relay."
+            .to_string(),
+        (NatType::Unknown, false) => "No STUN or TURN server answered. A firewall is probably \
+            blocking UDP, so only peers on your local network can connect."
+            .to_string(),
+    };
+
+    NatTestResult {
+        nat_type,
+        direct_candidate,
+        relay_candidate,
+        turn_configured,
+        host_candidates,
+        srflx_candidates,
+        relay_candidates,
+        public_addresses,
+        gathering_complete: false,
+        duration_ms: 0,
+        diagnosis,
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct WebRTCFileRequest {
@@ -300,6 +848,14 @@ pub struct WebRTCFileRequest {
     pub file_size: u64,
     pub requester_peer_id: String,
     pub recipient_public_key: Option<String>, // For encrypted transfers
+    /// Merkle root the requester trusts; seeders that can prove their chunks against it
+    /// attach a `merkle_proof` to every chunk
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub merkle_root: Option<String>,
+    /// Set by requesters that decode `FRAME_TYPE_CHUNK` binary frames; peers that leave
+    /// it out get their chunks as legacy "CHNK" frames
+    #[serde(default)]
+    pub binary_chunk_frames: bool,
 }
 
 /// Sent by a downloader to request the full file manifest.
@@ -317,6 +873,79 @@ pub struct WebRTCManifestResponse {
     pub manifest_json: String, // The full FileManifest, serialized to JSON
 }
 
+/// Sent by a downloader to ask a peer for one chunk-store chunk of a file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebRTCChunkRequest {
+    pub file_hash: String,
+    pub chunk_index: u32,
+}
+
+/// Sent by a downloader to learn which chunks of a file a peer can serve.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebRTCAvailabilityRequest {
+    pub file_hash: String,
+}
+
+/// Sent by a peer in response to an availability request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebRTCChunkAvailability {
+    pub file_hash: String,
+    /// The peer has the whole file and serves every chunk
+    pub complete: bool,
+    pub total_chunks: u32,
+    /// Chunk `i` is available when bit `i` is set, most significant bit first
+    pub bitfield: Vec<u8>,
+}
+
+impl WebRTCChunkAvailability {
+    pub fn has_chunk(&self, chunk_index: u32) -> bool {
+        self.complete || bitfield_has_chunk(&self.bitfield, chunk_index)
+    }
+}
+
+/// Why a peer couldn't serve a requested chunk
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, thiserror::Error)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum ChunkServeError {
+    #[error("Peer is not seeding this file")]
+    NotSeeding,
+    #[error("Chunk is not available from this peer")]
+    ChunkUnavailable,
+    #[error("Peer failed to read the chunk: {message}")]
+    ReadFailed { message: String },
+    #[error("Peer is already serving this file to {limit} peers")]
+    TooManyPeers { limit: usize },
+}
+
+/// Sent by a peer that can't serve a requested chunk.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebRTCChunkRejected {
+    pub file_hash: String,
+    pub chunk_index: u32,
+    pub error: ChunkServeError,
+}
+
+/// Pack `chunk_ids` into a bitfield covering `total_chunks` chunks, most significant bit first.
+/// IDs at or past `total_chunks` are ignored.
+pub fn encode_chunk_bitfield(chunk_ids: &[u32], total_chunks: u32) -> Vec<u8> {
+    let mut bitfield = vec![0u8; (total_chunks as usize).div_ceil(8)];
+    for &chunk_id in chunk_ids.iter().filter(|&&chunk_id| chunk_id < total_chunks) {
+        bitfield[chunk_id as usize / 8] |= 0x80 >> (chunk_id % 8);
+    }
+    bitfield
+}
+
+/// Whether bit `chunk_index` is set in a bitfield built by `encode_chunk_bitfield`
+pub fn bitfield_has_chunk(bitfield: &[u8], chunk_index: u32) -> bool {
+    bitfield
+        .get(chunk_index as usize / 8)
+        .is_some_and(|byte| byte & (0x80 >> (chunk_index % 8)) != 0)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct FileChunk {
@@ -327,6 +956,9 @@ pub struct FileChunk {
     pub data: Vec<u8>,
     pub checksum: String,
     pub encrypted_key_bundle: Option<EncryptedAesKeyBundle>, // For encrypted transfers
+    /// Inclusion proof of the unencrypted chunk data under the requested merkle root
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub merkle_proof: Option<ChunkMerkleProof>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -347,10 +979,19 @@ pub struct PeerConnection {
     pub last_activity: Instant,
     pub peer_connection: Option<Arc<RTCPeerConnection>>,
     pub data_channel: Option<Arc<RTCDataChannel>>,
-    pub pending_chunks: HashMap<String, Vec<FileChunk>>, // file_hash -> chunks
     pub received_chunks: HashMap<String, HashMap<u32, FileChunk>>, // file_hash -> chunk_index -> chunk
     pub acked_chunks: HashMap<String, std::collections::HashSet<u32>>, // file_hash -> acked chunk indices
     pub pending_acks: HashMap<String, u32>, // file_hash -> number of unacked chunks
+    pub expected_merkle_roots: HashMap<String, String>, // file_hash -> merkle root chunk proofs must match
+    pub merkle_leaf_proofs: HashMap<String, HashMap<u32, ChunkMerkleProof>>, // file_hash -> leaf -> proof
+    pub incoming_transfers: HashMap<String, IncomingTransfer>, // file_hash -> receive deadline tracking
+    /// Files the peer has asked us for, counted against `PeerLimits::max_peers_per_file`
+    pub requested_files: std::collections::HashSet<String>,
+    /// Whether the peer's last file request advertised `binary_chunk_frames`
+    pub binary_chunk_frames: bool,
+    /// Whether the peer has sent a chunk-request or availability message, so it can be
+    /// asked for single chunks with `ChunkRequest`
+    pub chunk_requests: bool,
     /// Retry context for connection resilience
     pub retry_context: Option<WebRtcRetryContext>,
 }
@@ -366,6 +1007,105 @@ pub struct ActiveTransfer {
     pub start_time: Instant,
 }
 
+/// Receive-side tracking for a file requested from a peer, so a stalled transfer fails
+/// instead of waiting forever for its last chunks
+#[derive(Debug, Clone)]
+pub struct IncomingTransfer {
+    /// When the request went out; the deadline counts from here
+    pub started: Instant,
+    /// When the last new chunk arrived, or the request went out
+    pub last_progress: Instant,
+    /// Rounds of re-requesting missing chunks so far
+    pub retry_rounds: u32,
+}
+
+impl IncomingTransfer {
+    fn new(now: Instant) -> Self {
+        Self {
+            started: now,
+            last_progress: now,
+            retry_rounds: 0,
+        }
+    }
+
+    /// Time the transfer has to complete, scaled to the file's size once its first chunk
+    /// has told us how many chunks there are
+    fn time_allowed(total_chunks: Option<u32>) -> Duration {
+        let size = total_chunks.unwrap_or(0) as u64 * CHUNK_SIZE as u64;
+        Duration::from_secs(TRANSFER_DEADLINE_BASE_SECS + size / TRANSFER_MIN_RATE_BPS)
+    }
+}
+
+/// What the transfer watchdog does about a receive
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum IncomingTransferAction {
+    /// Still making progress
+    Wait,
+    /// Ask the peer again for these chunks
+    RequestMissing(Vec<u32>),
+    /// Give up on the transfer
+    Fail(String),
+}
+
+/// Decide what to do about a receive, given the chunks that arrived so far
+///
+/// Until the first chunk arrives the file's chunk count is unknown, so chunk 0 is re-requested;
+/// its answer tells us the rest.
+fn check_incoming_transfer(
+    transfer: &IncomingTransfer,
+    received: Option<&HashMap<u32, FileChunk>>,
+    now: Instant,
+) -> IncomingTransferAction {
+    let total_chunks = received
+        .and_then(|chunks| chunks.values().next())
+        .map(|chunk| chunk.total_chunks);
+    let missing: Vec<u32> = match total_chunks {
+        Some(total_chunks) => (0..total_chunks)
+            .filter(|index| !received.is_some_and(|chunks| chunks.contains_key(index)))
+            .collect(),
+        None => vec![0],
+    };
+
+    let time_allowed = IncomingTransfer::time_allowed(total_chunks);
+    if now.saturating_duration_since(transfer.started) >= time_allowed {
+        return IncomingTransferAction::Fail(format!(
+            "Transfer did not complete within {}s; {}",
+            time_allowed.as_secs(),
+            describe_missing_chunks(&missing, received.is_some_and(|c| !c.is_empty()))
+        ));
+    }
+    if now.saturating_duration_since(transfer.last_progress)
+        < Duration::from_secs(TRANSFER_STALL_TIMEOUT_SECS)
+    {
+        return IncomingTransferAction::Wait;
+    }
+    if transfer.retry_rounds >= MAX_MISSING_CHUNK_ROUNDS {
+        return IncomingTransferAction::Fail(format!(
+            "Transfer stalled after {} rounds of re-requesting chunks; {}",
+            transfer.retry_rounds,
+            describe_missing_chunks(&missing, received.is_some_and(|c| !c.is_empty()))
+        ));
+    }
+    IncomingTransferAction::RequestMissing(
+        missing.into_iter().take(MAX_MISSING_CHUNK_REQUESTS).collect(),
+    )
+}
+
+/// "missing chunks [3, 7]", shortened for long lists
+fn describe_missing_chunks(missing: &[u32], any_received: bool) -> String {
+    if !any_received {
+        return "no chunks were received".to_string();
+    }
+    const LISTED: usize = 32;
+    let listed: Vec<String> = missing.iter().take(LISTED).map(u32::to_string).collect();
+    let more = missing.len().saturating_sub(LISTED);
+    if more > 0 {
+        format!("missing chunks [{}, ...] ({} more)", listed.join(", "), more)
+    } else {
+        format!("missing chunks [{}]", listed.join(", "))
+    }
+}
+
 #[derive(Debug)]
 pub enum WebRTCCommand {
     EstablishConnection {
@@ -393,6 +1133,10 @@ pub enum WebRTCCommand {
         file_hash: String,
         chunk_index: u32,
     },
+    RequestChunkAvailability {
+        peer_id: String,
+        file_hash: String,
+    },
     CloseConnection {
         peer_id: String,
     },
@@ -450,6 +1194,16 @@ pub enum WebRTCEvent {
         file_hash: String,
         chunk_index: u32,
     },
+    ChunkAvailabilityReceived {
+        peer_id: String,
+        availability: WebRTCChunkAvailability,
+    },
+    ChunkRequestRejected {
+        peer_id: String,
+        file_hash: String,
+        chunk_index: u32,
+        error: ChunkServeError,
+    },
     TransferProgress {
         peer_id: String,
         progress: TransferProgress,
@@ -463,6 +1217,13 @@ pub enum WebRTCEvent {
         file_hash: String,
         error: String,
     },
+    /// A peer was turned away because a `PeerLimits` cap was reached
+    PeerRejected {
+        peer_id: String,
+        /// Set when the per-file cap was hit rather than the connection cap
+        file_hash: Option<String>,
+        reason: String,
+    },
 }
 
 /// ACK message sent by downloader to confirm chunk receipt
@@ -489,6 +1250,10 @@ pub enum WebRTCMessage {
     FileChunk(FileChunk),
     #[serde(alias = "ChunkAck")]
     ChunkAck(ChunkAck),
+    ChunkRequest(WebRTCChunkRequest),
+    AvailabilityRequest(WebRTCAvailabilityRequest),
+    Availability(WebRTCChunkAvailability),
+    ChunkRejected(WebRTCChunkRejected),
 }
 
 pub struct WebRTCService {
@@ -583,6 +1348,14 @@ impl WebRTCService {
         // Initialize connection manager with WebRTC-optimized retry config
         let connection_manager = Arc::new(ConnectionManager::new(RetryConfig::for_webrtc()));
 
+        tokio::spawn(Self::run_idle_reaper(connections.clone(), connection_manager.clone()));
+        tokio::spawn(Self::run_transfer_watchdog(connections.clone(), event_tx.clone()));
+
+        // Keep dead STUN/TURN servers out of new peer connections
+        ice_health::spawn_health_checks(Duration::from_secs(
+            ice_health::DEFAULT_HEALTH_CHECK_INTERVAL_SECS,
+        ));
+
         // Spawn the WebRTC service task
         let connection_manager_clone = connection_manager.clone();
         let multi_source_service_clone = multi_source_service.clone();
@@ -634,6 +1407,89 @@ impl WebRTCService {
     pub async fn get_connection_stats(&self) -> crate::connection_retry::ConnectionManagerStats {
         self.connection_manager.get_stats().await
     }
+
+    /// Gather ICE candidates against the configured STUN/TURN servers and report which
+    /// kinds of connection this network supports. Needs no remote peer.
+    pub async fn run_connectivity_test() -> Result<NatTestResult, String> {
+        let config = create_rtc_configuration();
+        let turn_configured = config
+            .ice_servers
+            .iter()
+            .flat_map(|server| server.urls.iter())
+            .any(|url| url.starts_with("turn:") || url.starts_with("turns:"));
+
+        let api = APIBuilder::new().build();
+        let peer_connection = api
+            .new_peer_connection(config)
+            .await
+            .map_err(|e| format!("Failed to create peer connection: {}", e))?;
+
+        let candidates = Arc::new(Mutex::new(Vec::new()));
+        let (done_tx, done_rx) = tokio::sync::oneshot::channel();
+        let done_tx = Arc::new(std::sync::Mutex::new(Some(done_tx)));
+        let candidates_for_ice = candidates.clone();
+        peer_connection.on_ice_candidate(Box::new(move |candidate: Option<RTCIceCandidate>| {
+            let candidates = candidates_for_ice.clone();
+            let done_tx = done_tx.clone();
+            Box::pin(async move {
+                match candidate {
+                    Some(candidate) => candidates.lock().await.push(candidate),
+                    // A null candidate marks the end of gathering
+                    None => {
+                        if let Some(done_tx) = done_tx.lock().unwrap().take() {
+                            let _ = done_tx.send(());
+                        }
+                    }
+                }
+            })
+        }));
+
+        let started = Instant::now();
+        // The data channel gives the offer something to negotiate, which starts gathering
+        let offered = async {
+            peer_connection
+                .create_data_channel("connectivity-test", None)
+                .await
+                .map_err(|e| format!("Failed to create data channel: {}", e))?;
+            let offer = peer_connection
+                .create_offer(None)
+                .await
+                .map_err(|e| format!("Failed to create offer: {}", e))?;
+            peer_connection
+                .set_local_description(offer)
+                .await
+                .map_err(|e| format!("Failed to set local description: {}", e))
+        }
+        .await;
+        let gathering_complete = match offered {
+            Ok(()) => matches!(
+                tokio::time::timeout(Duration::from_secs(CONNECTIVITY_TEST_TIMEOUT_SECS), done_rx)
+                    .await,
+                Ok(Ok(()))
+            ),
+            Err(e) => {
+                let _ = peer_connection.close().await;
+                return Err(e);
+            }
+        };
+        if let Err(e) = peer_connection.close().await {
+            warn!("Failed to close connectivity test peer connection: {}", e);
+        }
+
+        let candidates = candidates.lock().await;
+        let mut result = analyze_ice_candidates(&candidates, turn_configured);
+        result.gathering_complete = gathering_complete;
+        result.duration_ms = started.elapsed().as_millis() as u64;
+        info!(
+            "Connectivity test: nat={:?} host={} srflx={} relay={} in {}ms",
+            result.nat_type,
+            result.host_candidates,
+            result.srflx_candidates,
+            result.relay_candidates,
+            result.duration_ms
+        );
+        Ok(result)
+    }
     
     /// Manually trigger retry for a failed connection
     pub async fn retry_connection(&self, peer_id: &str) -> Result<(), String> {
@@ -666,6 +1522,16 @@ impl WebRTCService {
                         warn!("WebRTC establish_connection requested in headless mode (no AppHandle). Skipping.");
                         continue;
                     };
+                    if !Self::admit_connection(
+                        &peer_id,
+                        &event_tx,
+                        &connections,
+                        &connection_manager,
+                    )
+                    .await
+                    {
+                        continue;
+                    }
                     Self::handle_establish_connection_with_retry(
                         app_handle,
                         &peer_id,
@@ -712,6 +1578,13 @@ impl WebRTCService {
                     )
                     .await;
                 }
+                WebRTCCommand::RequestChunkAvailability { peer_id, file_hash } => {
+                    let message =
+                        WebRTCMessage::AvailabilityRequest(WebRTCAvailabilityRequest { file_hash });
+                    if let Err(e) = Self::send_message(&peer_id, &message, &connections).await {
+                        error!("Failed to request chunk availability from {}: {}", peer_id, e);
+                    }
+                }
                 WebRTCCommand::CloseConnection { peer_id } => {
                     Self::handle_close_connection(&peer_id, &connections, &connection_manager).await;
                 }
@@ -786,6 +1659,8 @@ impl WebRTCService {
                 let state = tracker.state;
                 let attempts = tracker.consecutive_failures;
                 let config = tracker.config.clone();
+                // The jittered delay the tracker scheduled, not a fresh draw
+                let delay = tracker.time_until_retry().unwrap_or_default();
                 connection_manager.update(tracker).await;
                 
                 if state == ConnectionState::Failed {
@@ -799,7 +1674,6 @@ impl WebRTCService {
                         .await;
                 } else {
                     // Will retry - notify with backoff info
-                    let delay = config.calculate_delay(attempts - 1);
                     let _ = event_tx
                         .send(WebRTCEvent::ConnectionRetrying {
                             peer_id: peer_id.to_string(),
@@ -1197,10 +2071,15 @@ impl WebRTCService {
             last_activity: Instant::now(),
             peer_connection: Some(peer_connection),
             data_channel: Some(data_channel),
-            pending_chunks: HashMap::new(),
             received_chunks: HashMap::new(),
             acked_chunks: HashMap::new(),
             pending_acks: HashMap::new(),
+            expected_merkle_roots: HashMap::new(),
+            merkle_leaf_proofs: HashMap::new(),
+            incoming_transfers: HashMap::new(),
+            requested_files: std::collections::HashSet::new(),
+            binary_chunk_frames: false,
+            chunk_requests: false,
             retry_context: Some(retry_ctx),
         };
         conns.insert(peer_id.to_string(), connection);
@@ -1291,12 +2170,22 @@ impl WebRTCService {
         let timeout = Duration::from_secs(10);
 
         let dc = loop {
-            let conns = connections.lock().await;
-            if let Some(connection) = conns.get(peer_id) {
+            let mut conns = connections.lock().await;
+            if let Some(connection) = conns.get_mut(peer_id) {
                 if let Some(dc) = &connection.data_channel {
                     let state = dc.ready_state();
                     if state == RTCDataChannelState::Open {
-                        break dc.clone();
+                        let dc = dc.clone();
+                        if let Some(merkle_root) = &request.merkle_root {
+                            connection
+                                .expected_merkle_roots
+                                .insert(request.file_hash.clone(), merkle_root.clone());
+                        }
+                        let transfer = IncomingTransfer::new(Instant::now());
+                        connection
+                            .incoming_transfers
+                            .insert(request.file_hash.clone(), transfer);
+                        break dc;
                     }
                     if state == RTCDataChannelState::Closed || state == RTCDataChannelState::Closing {
                         error!("Data channel is closed or closing for peer {}", peer_id);
@@ -1351,6 +2240,13 @@ impl WebRTCService {
             peer_id, request.file_hash, request.file_name
         );
 
+        if Self::admit_file_request(peer_id, &request.file_hash, event_tx, connections)
+            .await
+            .is_err()
+        {
+            return;
+        }
+
         // Check if we have the file locally
         let stored_files = file_transfer_service
             .get_stored_files()
@@ -1437,7 +2333,7 @@ impl WebRTCService {
         let start = Instant::now();
         let timeout = Duration::from_secs(10);
 
-        let dc = loop {
+        let (dc, binary_frames) = loop {
             let conns = connections.lock().await;
             if let Some(connection) = conns.get(peer_id) {
                 if let Some(dc) = &connection.data_channel {
@@ -1446,7 +2342,7 @@ impl WebRTCService {
                         if start.elapsed().as_millis() > 100 {
                             info!("📡 Data channel ready after {}ms for peer {}", start.elapsed().as_millis(), peer_id);
                         }
-                        break dc.clone();
+                        break (dc.clone(), connection.binary_chunk_frames);
                     }
                     if state == RTCDataChannelState::Closed || state == RTCDataChannelState::Closing {
                         error!("Data channel is closed or closing for peer {}", peer_id);
@@ -1476,79 +2372,468 @@ impl WebRTCService {
             sleep(Duration::from_millis(50)).await;
         };
 
-        // Encode chunk as a binary frame and send over data channel.
-        // This avoids huge JSON overhead and prevents "outbound packet larger than maximum message size".
-        let payload = match encode_chunk_frame(chunk) {
-            Ok(frame) => frame,
-            Err(e) => {
-                // Fallback to JSON only if framing fails for unexpected inputs.
-                warn!("Chunk framing failed; falling back to JSON send_text: {}", e);
-                let chunk_json =
-                    serde_json::to_string(chunk).map_err(|e| format!("Failed to serialize chunk: {}", e))?;
-                // Check buffer before sending - wait if buffer is too full
-                let max_buffered: usize = 2 * 1024 * 1024; // 2MB max buffer
-                let start_wait = Instant::now();
-                loop {
-                    let buffered = dc.buffered_amount().await;
-                    if buffered < max_buffered {
-                        break;
-                    }
-                    if start_wait.elapsed() > Duration::from_secs(10) {
-                        error!(
-                            "❌ Timeout waiting for data channel buffer to drain (buffered: {} bytes)",
-                            buffered
-                        );
-                        return Err("Data channel buffer timeout".to_string());
-                    }
-                    sleep(Duration::from_millis(1)).await;
-                }
-                dc.send_text(chunk_json)
-                    .await
-                    .map_err(|e| format!("Failed to send chunk (json): {}", e))?;
-                return Ok(());
+        // Typed frames only go to peers that asked for them; older peers expect "CHNK" frames
+        let payload = encode_chunk_payload(chunk, binary_frames)
+            .map_err(|e| format!("Failed to encode chunk {}: {}", chunk.chunk_index, e))?;
+
+        // Check buffer before sending - wait if buffer is too full
+        let max_buffered: usize = 2 * 1024 * 1024; // 2MB max buffer
+        let start_wait = Instant::now();
+        loop {
+            let buffered = dc.buffered_amount().await;
+            if buffered < max_buffered {
+                break;
+            }
+            if start_wait.elapsed() > Duration::from_secs(10) {
+                error!(
+                    "❌ Timeout waiting for data channel buffer to drain (buffered: {} bytes)",
+                    buffered
+                );
+                return Err("Data channel buffer timeout".to_string());
+            }
+            sleep(Duration::from_millis(1)).await;
+        }
+
+        dc.send(&Bytes::from(payload))
+            .await
+            .map_err(|e| format!("Failed to send chunk (binary): {}", e))?;
+
+        Ok(())
+    }
+
+    /// Ask a peer for one chunk. Peers that never sent a chunk-request or availability
+    /// message predate `ChunkRequest` and take the `FileChunkRequested` path instead.
+    async fn handle_request_chunk(
+        peer_id: &str,
+        file_hash: &str,
+        chunk_index: u32,
+        event_tx: &mpsc::Sender<WebRTCEvent>,
+        connections: &Arc<Mutex<HashMap<String, PeerConnection>>>,
+    ) {
+        let chunk_requests = connections
+            .lock()
+            .await
+            .get(peer_id)
+            .is_some_and(|connection| connection.chunk_requests);
+        if !chunk_requests {
+            let _ = event_tx
+                .send(WebRTCEvent::FileChunkRequested {
+                    peer_id: peer_id.to_string(),
+                    file_hash: file_hash.to_string(),
+                    chunk_index,
+                })
+                .await;
+            return;
+        }
+
+        let message = WebRTCMessage::ChunkRequest(WebRTCChunkRequest {
+            file_hash: file_hash.to_string(),
+            chunk_index,
+        });
+        if let Err(e) = Self::send_message(peer_id, &message, connections).await {
+            error!(
+                "Failed to request chunk {} of {} from {}: {}",
+                chunk_index, file_hash, peer_id, e
+            );
+        }
+    }
+
+    /// Send a JSON control message over the peer's data channel
+    async fn send_message(
+        peer_id: &str,
+        message: &WebRTCMessage,
+        connections: &Arc<Mutex<HashMap<String, PeerConnection>>>,
+    ) -> Result<(), String> {
+        let dc = {
+            let conns = connections.lock().await;
+            conns
+                .get(peer_id)
+                .and_then(|connection| connection.data_channel.clone())
+                .ok_or_else(|| format!("No data channel for peer {}", peer_id))?
+        };
+        let json = serde_json::to_string(message)
+            .map_err(|e| format!("Failed to serialize message: {}", e))?;
+        dc.send_text(json)
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("Failed to send message: {}", e))
+    }
+
+    /// Answer a peer's request for a single chunk
+    ///
+    /// Complete files are served by the file-transfer side through `FileChunkRequested`.
+    /// Otherwise the chunk comes from the chunk store, so unfinished downloads are seeded too;
+    /// chunks we don't have are rejected with a `ChunkServeError`.
+    async fn handle_chunk_request(
+        peer_id: &str,
+        request: &WebRTCChunkRequest,
+        event_tx: &mpsc::Sender<WebRTCEvent>,
+        file_transfer_service: &Arc<FileTransferService>,
+        connections: &Arc<Mutex<HashMap<String, PeerConnection>>>,
+        bandwidth: &Arc<BandwidthController>,
+        multi_source_service: Option<&Arc<MultiSourceDownloadService>>,
+    ) {
+        if let Err(limit) =
+            Self::admit_file_request(peer_id, &request.file_hash, event_tx, connections).await
+        {
+            let message = WebRTCMessage::ChunkRejected(WebRTCChunkRejected {
+                file_hash: request.file_hash.clone(),
+                chunk_index: request.chunk_index,
+                error: ChunkServeError::TooManyPeers { limit },
+            });
+            if let Err(e) = Self::send_message(peer_id, &message, connections).await {
+                warn!("Failed to reject chunk request from {}: {}", peer_id, e);
+            }
+            return;
+        }
+
+        if Self::has_complete_file(file_transfer_service, &request.file_hash).await {
+            let _ = event_tx
+                .send(WebRTCEvent::FileChunkRequested {
+                    peer_id: peer_id.to_string(),
+                    file_hash: request.file_hash.clone(),
+                    chunk_index: request.chunk_index,
+                })
+                .await;
+            return;
+        }
+
+        match Self::load_stored_chunk(multi_source_service, request).await {
+            Ok(chunk) => {
+                if let Err(e) = Self::handle_send_chunk(peer_id, &chunk, connections, bandwidth).await
+                {
+                    warn!("Failed to send stored chunk {} to {}: {}", chunk.chunk_index, peer_id, e);
+                }
+            }
+            Err(error) => {
+                debug!(
+                    "Rejecting chunk {} of {} for peer {}: {}",
+                    request.chunk_index, request.file_hash, peer_id, error
+                );
+                let message = WebRTCMessage::ChunkRejected(WebRTCChunkRejected {
+                    file_hash: request.file_hash.clone(),
+                    chunk_index: request.chunk_index,
+                    error,
+                });
+                if let Err(e) = Self::send_message(peer_id, &message, connections).await {
+                    warn!("Failed to reject chunk request from {}: {}", peer_id, e);
+                }
+            }
+        }
+    }
+
+    async fn has_complete_file(
+        file_transfer_service: &Arc<FileTransferService>,
+        file_hash: &str,
+    ) -> bool {
+        file_transfer_service
+            .get_stored_files()
+            .await
+            .unwrap_or_default()
+            .iter()
+            .any(|(hash, _)| hash == file_hash)
+    }
+
+    /// Chunks of `file_hash` in the chunk store, with the file's chunk count. Scans are
+    /// cached for `STORED_CHUNK_CACHE_TTL`, so a peer walking through a file doesn't list
+    /// the chunk store once per chunk.
+    async fn stored_chunks(
+        multi_source_service: Option<&Arc<MultiSourceDownloadService>>,
+        file_hash: &str,
+    ) -> Result<Arc<StoredChunks>, ChunkServeError> {
+        let service = multi_source_service.ok_or(ChunkServeError::NotSeeding)?;
+        if !service.config().await.partial_seeding {
+            return Err(ChunkServeError::NotSeeding);
+        }
+
+        let now = Instant::now();
+        if let Some(stored) = STORED_CHUNK_CACHE.lock().await.get(file_hash) {
+            if now.duration_since(stored.scanned_at) < STORED_CHUNK_CACHE_TTL {
+                return Ok(stored.clone());
+            }
+        }
+
+        let mut chunk_ids = service
+            .scan_existing_chunks(file_hash)
+            .await
+            .map_err(|message| ChunkServeError::ReadFailed { message })?;
+        chunk_ids.sort_unstable();
+        let Some(&last) = chunk_ids.last() else {
+            return Err(ChunkServeError::NotSeeding);
+        };
+
+        let total_chunks = match service.get_download_progress(file_hash).await {
+            Some(progress) => progress.total_chunks as u32,
+            None => last + 1,
+        };
+        let stored = Arc::new(StoredChunks {
+            chunk_ids,
+            total_chunks,
+            scanned_at: now,
+        });
+        let mut cache = STORED_CHUNK_CACHE.lock().await;
+        cache.retain(|_, entry| now.duration_since(entry.scanned_at) < STORED_CHUNK_CACHE_TTL);
+        cache.insert(file_hash.to_string(), stored.clone());
+        Ok(stored)
+    }
+
+    async fn load_stored_chunk(
+        multi_source_service: Option<&Arc<MultiSourceDownloadService>>,
+        request: &WebRTCChunkRequest,
+    ) -> Result<FileChunk, ChunkServeError> {
+        let stored = Self::stored_chunks(multi_source_service, &request.file_hash).await?;
+        if !stored.contains(request.chunk_index) {
+            return Err(ChunkServeError::ChunkUnavailable);
+        }
+
+        // stored_chunks only succeeds with a service present
+        let service = multi_source_service.ok_or(ChunkServeError::NotSeeding)?;
+        let data = service
+            .load_chunk_from_disk(&request.file_hash, request.chunk_index)
+            .await
+            .map_err(|message| ChunkServeError::ReadFailed { message })?;
+        let file_name = service
+            .get_download_progress(&request.file_hash)
+            .await
+            .map(|progress| progress.file_name)
+            .unwrap_or_default();
+
+        Ok(FileChunk {
+            file_hash: request.file_hash.clone(),
+            file_name,
+            chunk_index: request.chunk_index,
+            total_chunks: stored.total_chunks,
+            checksum: Self::calculate_chunk_checksum(&data),
+            data,
+            encrypted_key_bundle: None,
+            merkle_proof: None,
+        })
+    }
+
+    /// Describe which chunks of `file_hash` we can serve
+    async fn chunk_availability(
+        file_transfer_service: &Arc<FileTransferService>,
+        multi_source_service: Option<&Arc<MultiSourceDownloadService>>,
+        file_hash: &str,
+    ) -> WebRTCChunkAvailability {
+        if Self::has_complete_file(file_transfer_service, file_hash).await {
+            return WebRTCChunkAvailability {
+                file_hash: file_hash.to_string(),
+                complete: true,
+                total_chunks: 0,
+                bitfield: Vec::new(),
+            };
+        }
+
+        match Self::stored_chunks(multi_source_service, file_hash).await {
+            Ok(stored) => WebRTCChunkAvailability {
+                file_hash: file_hash.to_string(),
+                complete: false,
+                total_chunks: stored.total_chunks,
+                bitfield: encode_chunk_bitfield(&stored.chunk_ids, stored.total_chunks),
+            },
+            Err(_) => WebRTCChunkAvailability {
+                file_hash: file_hash.to_string(),
+                complete: false,
+                total_chunks: 0,
+                bitfield: Vec::new(),
+            },
+        }
+    }
+
+    /// Periodically close connections that stayed idle past `PeerLimits::idle_reap_secs`
+    async fn run_idle_reaper(
+        connections: Arc<Mutex<HashMap<String, PeerConnection>>>,
+        connection_manager: Arc<ConnectionManager>,
+    ) {
+        let mut interval = tokio::time::interval(Duration::from_secs(IDLE_REAP_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            let idle_reap_secs = peer_limits().await.idle_reap_secs;
+            if idle_reap_secs == 0 {
+                continue;
+            }
+
+            let idle_peers = {
+                let conns = connections.lock().await;
+                idle_connections(&conns, Duration::from_secs(idle_reap_secs), Instant::now())
+            };
+            for peer_id in idle_peers {
+                info!("Closing connection to {} after {}s idle", peer_id, idle_reap_secs);
+                Self::handle_close_connection(&peer_id, &connections, &connection_manager).await;
+            }
+        }
+    }
+
+    /// Periodically check receives for stalls: re-request their missing chunks, and fail the
+    /// ones that stay stuck or pass their deadline with `TransferFailed`
+    async fn run_transfer_watchdog(
+        connections: Arc<Mutex<HashMap<String, PeerConnection>>>,
+        event_tx: mpsc::Sender<WebRTCEvent>,
+    ) {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(TRANSFER_WATCHDOG_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            let now = Instant::now();
+
+            let mut requests = Vec::new();
+            let mut failures = Vec::new();
+            {
+                let mut conns = connections.lock().await;
+                for connection in conns.values_mut() {
+                    let mut failed = Vec::new();
+                    for (file_hash, transfer) in connection.incoming_transfers.iter_mut() {
+                        match check_incoming_transfer(
+                            transfer,
+                            connection.received_chunks.get(file_hash),
+                            now,
+                        ) {
+                            IncomingTransferAction::Wait => {}
+                            IncomingTransferAction::RequestMissing(missing) => {
+                                transfer.retry_rounds += 1;
+                                transfer.last_progress = now;
+                                requests.push((
+                                    connection.peer_id.clone(),
+                                    file_hash.clone(),
+                                    missing,
+                                ));
+                            }
+                            IncomingTransferAction::Fail(error) => {
+                                failed.push((file_hash.clone(), error));
+                            }
+                        }
+                    }
+                    for (file_hash, error) in failed {
+                        connection.incoming_transfers.remove(&file_hash);
+                        connection.received_chunks.remove(&file_hash);
+                        connection.expected_merkle_roots.remove(&file_hash);
+                        connection.merkle_leaf_proofs.remove(&file_hash);
+                        failures.push((connection.peer_id.clone(), file_hash, error));
+                    }
+                }
+            }
+
+            for (peer_id, file_hash, missing) in requests {
+                warn!(
+                    "Transfer of {} from {} stalled, re-requesting {} chunk(s)",
+                    file_hash,
+                    peer_id,
+                    missing.len()
+                );
+                for chunk_index in missing {
+                    Self::handle_request_chunk(
+                        &peer_id,
+                        &file_hash,
+                        chunk_index,
+                        &event_tx,
+                        &connections,
+                    )
+                    .await;
+                }
+            }
+            for (peer_id, file_hash, error) in failures {
+                error!("❌ Transfer of {} from {} failed: {}", file_hash, peer_id, error);
+                let _ = event_tx
+                    .send(WebRTCEvent::TransferFailed {
+                        peer_id,
+                        file_hash,
+                        error,
+                    })
+                    .await;
+            }
+        }
+    }
+
+    /// Make sure a new connection, incoming or outgoing, fits under `max_peer_connections`,
+    /// closing the longest-idle connection if needed. Returns false when the peer was rejected.
+    async fn admit_connection(
+        peer_id: &str,
+        event_tx: &mpsc::Sender<WebRTCEvent>,
+        connections: &Arc<Mutex<HashMap<String, PeerConnection>>>,
+        connection_manager: &Arc<ConnectionManager>,
+    ) -> bool {
+        let limits = peer_limits().await;
+        let evicted = {
+            let mut conns = connections.lock().await;
+            if limits.max_peer_connections == 0
+                || conns.contains_key(peer_id)
+                || conns.len() < limits.max_peer_connections
+            {
+                return true;
             }
+            let idle_timeout = Duration::from_secs(limits.idle_timeout_secs);
+            idle_connection_to_evict(&conns, idle_timeout, Instant::now())
+                .and_then(|idle_peer| conns.remove(&idle_peer))
         };
 
-        // Check buffer before sending - wait if buffer is too full
-        let max_buffered: usize = 2 * 1024 * 1024; // 2MB max buffer
-        let start_wait = Instant::now();
-        loop {
-            let buffered = dc.buffered_amount().await;
-            if buffered < max_buffered {
-                break;
-            }
-            if start_wait.elapsed() > Duration::from_secs(10) {
-                error!(
-                    "❌ Timeout waiting for data channel buffer to drain (buffered: {} bytes)",
-                    buffered
-                );
-                return Err("Data channel buffer timeout".to_string());
+        if let Some(mut connection) = evicted {
+            info!(
+                "Closing idle connection to {} to make room for {}",
+                connection.peer_id, peer_id
+            );
+            if let Some(pc) = connection.peer_connection.take() {
+                let _ = pc.close().await;
             }
-            sleep(Duration::from_millis(1)).await;
+            connection_manager.remove(&connection.peer_id).await;
+            return true;
         }
 
-        let bytes_data = Bytes::from(payload);
-        dc.send(&bytes_data)
-            .await
-            .map_err(|e| format!("Failed to send chunk (binary): {}", e))?;
-
-        Ok(())
+        let reason = format!(
+            "Connection limit of {} peers reached",
+            limits.max_peer_connections
+        );
+        warn!("Rejecting connection from {}: {}", peer_id, reason);
+        let _ = event_tx
+            .send(WebRTCEvent::PeerRejected {
+                peer_id: peer_id.to_string(),
+                file_hash: None,
+                reason,
+            })
+            .await;
+        false
     }
 
-    async fn handle_request_chunk(
+    /// Count the peer towards `max_peers_per_file` for `file_hash`, unless the file already
+    /// has that many active peers. Returns the limit that was hit when rejecting.
+    async fn admit_file_request(
         peer_id: &str,
         file_hash: &str,
-        chunk_index: u32,
         event_tx: &mpsc::Sender<WebRTCEvent>,
-        _connections: &Arc<Mutex<HashMap<String, PeerConnection>>>,
-    ) {
+        connections: &Arc<Mutex<HashMap<String, PeerConnection>>>,
+    ) -> Result<(), usize> {
+        let limits = peer_limits().await;
+        {
+            let mut conns = connections.lock().await;
+            let known = conns
+                .get(peer_id)
+                .is_some_and(|connection| connection.requested_files.contains(file_hash));
+            let idle_timeout = Duration::from_secs(limits.idle_timeout_secs);
+            if limits.max_peers_per_file == 0
+                || known
+                || active_peers_for_file(&conns, file_hash, peer_id, idle_timeout, Instant::now())
+                    < limits.max_peers_per_file
+            {
+                if let Some(connection) = conns.get_mut(peer_id) {
+                    connection.requested_files.insert(file_hash.to_string());
+                }
+                return Ok(());
+            }
+        }
+
+        let reason = format!(
+            "{} already has {} active peers",
+            file_hash, limits.max_peers_per_file
+        );
+        warn!("Rejecting request from {}: {}", peer_id, reason);
         let _ = event_tx
-            .send(WebRTCEvent::FileChunkRequested {
+            .send(WebRTCEvent::PeerRejected {
                 peer_id: peer_id.to_string(),
-                file_hash: file_hash.to_string(),
-                chunk_index,
+                file_hash: Some(file_hash.to_string()),
+                reason,
             })
             .await;
+        Err(limits.max_peers_per_file)
     }
 
     async fn handle_close_connection(
@@ -1581,6 +2866,9 @@ impl WebRTCService {
         payment_checkpoint: &Option<Arc<PaymentCheckpointService>>,
     ) {
         debug!("📩 Data channel message received from peer {}: {} bytes", peer_id, msg.data.len());
+        if let Some(connection) = connections.lock().await.get_mut(peer_id) {
+            connection.last_activity = Instant::now();
+        }
 
         // First, try to decode as a binary-framed FileChunk (preferred, avoids JSON overhead).
         match decode_chunk_frame(&msg.data) {
@@ -1699,8 +2987,23 @@ impl WebRTCService {
             }
             // Try to parse as a generic WebRTCMessage
             else if let Ok(message) = serde_json::from_str::<WebRTCMessage>(text) {
+                let chunk_requests = matches!(
+                    message,
+                    WebRTCMessage::ChunkRequest(_)
+                        | WebRTCMessage::AvailabilityRequest(_)
+                        | WebRTCMessage::Availability(_)
+                        | WebRTCMessage::ChunkRejected(_)
+                );
+                if chunk_requests {
+                    if let Some(connection) = connections.lock().await.get_mut(peer_id) {
+                        connection.chunk_requests = true;
+                    }
+                }
                 match message {
                     WebRTCMessage::FileRequest(request) => {
+                        if let Some(connection) = connections.lock().await.get_mut(peer_id) {
+                            connection.binary_chunk_frames = request.binary_chunk_frames;
+                        }
                         let _ = event_tx
                             .send(WebRTCEvent::FileRequestReceived {
                                 peer_id: peer_id.to_string(),
@@ -1732,9 +3035,9 @@ impl WebRTCService {
                             .any(|(hash, _)| hash == &request.file_hash);
 
                         if has_file {
-                            // Get file data
-                            if let Some(file_data) = file_transfer_service
-                                .get_file_data(&request.file_hash)
+                            // Read the file chunk by chunk; it may not fit in memory
+                            if let Some(mut reader) = file_transfer_service
+                                .open_file_reader(&request.file_hash, CHUNK_SIZE)
                                 .await
                             {
                                 // Get metadata
@@ -1781,19 +3084,21 @@ impl WebRTCService {
 
                                 // Calculate chunks
                                 let mut chunks = Vec::new();
-                                let total_chunks =
-                                    ((file_data.len() as f64) / CHUNK_SIZE as f64).ceil() as u32;
-                                for chunk_index in 0..total_chunks {
-                                    let start = (chunk_index as usize) * CHUNK_SIZE;
-                                    let end = (start + CHUNK_SIZE).min(file_data.len());
-                                    let chunk_data = &file_data[start..end];
-                                    let chunk_hash = Self::calculate_chunk_checksum(chunk_data);
+                                for chunk_index in 0..reader.total_chunks() {
+                                    let chunk_data = match reader.read_chunk(chunk_index).await {
+                                        Ok(data) => data,
+                                        Err(e) => {
+                                            error!("Failed to build manifest: {}", e);
+                                            return;
+                                        }
+                                    };
+                                    let chunk_hash = Self::calculate_chunk_checksum(&chunk_data);
                                     chunks.push(ChunkInfo {
                                         index: chunk_index,
                                         hash: chunk_hash.clone(),
-                                        size: (end - start),
+                                        size: chunk_data.len(),
                                         encrypted_hash: chunk_hash,
-                                        encrypted_size: (end - start),
+                                        encrypted_size: chunk_data.len(),
                                     });
                                 }
                                 let manifest = FileManifest {
@@ -1866,6 +3171,53 @@ impl WebRTCService {
                                   ack.chunk_index, ack.file_hash, peer_id);
                         }
                     }
+                    WebRTCMessage::ChunkRequest(request) => {
+                        Self::handle_chunk_request(
+                            peer_id,
+                            &request,
+                            event_tx,
+                            file_transfer_service,
+                            connections,
+                            &bandwidth,
+                            multi_source_service,
+                        )
+                        .await;
+                    }
+                    WebRTCMessage::AvailabilityRequest(request) => {
+                        let availability = Self::chunk_availability(
+                            file_transfer_service,
+                            multi_source_service,
+                            &request.file_hash,
+                        )
+                        .await;
+                        let message = WebRTCMessage::Availability(availability);
+                        if let Err(e) = Self::send_message(peer_id, &message, connections).await {
+                            warn!("Failed to send chunk availability to {}: {}", peer_id, e);
+                        }
+                    }
+                    WebRTCMessage::Availability(availability) => {
+                        if let Some(service) = multi_source_service {
+                            service
+                                .record_chunk_availability(peer_id, availability.clone())
+                                .await;
+                        }
+                        let _ = event_tx
+                            .send(WebRTCEvent::ChunkAvailabilityReceived {
+                                peer_id: peer_id.to_string(),
+                                availability,
+                            })
+                            .await;
+                    }
+                    WebRTCMessage::ChunkRejected(rejected) => {
+                        let _ = event_tx
+                            .send(WebRTCEvent::ChunkRequestRejected {
+                                peer_id: peer_id.to_string(),
+                                file_hash: rejected.file_hash,
+                                chunk_index: rejected.chunk_index,
+                                error: rejected.error,
+                            })
+                            .await;
+                    }
                 }
             } else {
                 // None of the parsing attempts succeeded - log for debugging
@@ -1933,12 +3285,14 @@ impl WebRTCService {
             sleep(Duration::from_millis(50)).await;
         }
         
-        // Get file data from local storage
-        let file_data = match file_transfer_service
-            .get_file_data(&request.file_hash)
+        // Open the file in local storage. Chunks are read from disk as the loop below gets to
+        // them, so the seeder holds one chunk at a time no matter how large the file is; the
+        // ACK window and the data channel's buffer limit bound what is in flight.
+        let mut reader = match file_transfer_service
+            .open_file_reader(&request.file_hash, CHUNK_SIZE)
             .await
         {
-            Some(data) => data,
+            Some(reader) => reader,
             None => {
                 let _ = event_tx
                     .send(WebRTCEvent::TransferFailed {
@@ -1952,12 +3306,13 @@ impl WebRTCService {
         };
 
         // Calculate total chunks
-        let total_chunks = ((file_data.len() as f64) / CHUNK_SIZE as f64).ceil() as u32;
+        let file_size = reader.file_size();
+        let total_chunks = reader.total_chunks();
 
         info!(
             "Starting real file transfer of {} ({} bytes, {} chunks) to peer {}",
             request.file_name,
-            file_data.len(),
+            file_size,
             total_chunks,
             peer_id
         );
@@ -1965,7 +3320,6 @@ impl WebRTCService {
         // Initialize payment checkpoint session if service available
         if let Some(checkpoint_service) = payment_checkpoint {
             let session_id = format!("{}_{}", request.file_hash, peer_id);
-            let file_size = file_data.len() as u64;
 
             checkpoint_service.init_session(
                 session_id.clone(),
@@ -1995,7 +3349,7 @@ impl WebRTCService {
                 let transfer = ActiveTransfer {
                     file_hash: request.file_hash.clone(),
                     file_name: request.file_name.clone(),
-                    file_size: file_data.len() as u64,
+                    file_size,
                     total_chunks,
                     chunks_sent: 0,
                     bytes_sent: 0,
@@ -2007,6 +3361,18 @@ impl WebRTCService {
             }
         }
 
+        // The tree is built over whole leaves, which are then sent a frame at a time
+        let merkle_tree = match file_transfer_service
+            .open_file_reader(&request.file_hash, MERKLE_LEAF_SIZE)
+            .await
+        {
+            Some(mut leaf_reader) => {
+                let merkle_root = request.merkle_root.as_deref();
+                Self::proving_tree(&mut leaf_reader, &request.file_hash, merkle_root).await?
+            }
+            None => None,
+        };
+
         // Flow control constants
         const BATCH_SIZE: u32 = 100; // Send 100 chunks before checking ACKs (increased from 10)
         const MAX_PENDING_ACKS: u32 = 200; // Maximum unacked chunks before pausing (increased from 20)
@@ -2042,6 +3408,8 @@ impl WebRTCService {
             }
         }
 
+        let seeding_registry = seeding_registry().await;
+
         // Send file chunks over WebRTC data channel with flow control
         for chunk_index in 0..total_chunks {
             // Log EVERY chunk for first 100 to debug stall
@@ -2154,9 +3522,19 @@ impl WebRTCService {
                 info!("🔓 FLOW_CONTROL_PASSED: chunk {} for peer {}", chunk_index, peer_id);
             }
 
-            let start = (chunk_index as usize) * CHUNK_SIZE;
-            let end = (start + CHUNK_SIZE).min(file_data.len());
-            let chunk_data: Vec<u8> = file_data[start..end].to_vec();
+            let chunk_data = match reader.read_chunk(chunk_index).await {
+                Ok(data) => data,
+                Err(e) => {
+                    let _ = event_tx
+                        .send(WebRTCEvent::TransferFailed {
+                            peer_id: peer_id.to_string(),
+                            file_hash: request.file_hash.clone(),
+                            error: e.clone(),
+                        })
+                        .await;
+                    return Err(e);
+                }
+            };
 
             let (final_chunk_data, encrypted_key_bundle) =
                 if let Some(ref recipient_key) = request.recipient_public_key {
@@ -2199,6 +3577,13 @@ impl WebRTCService {
                 data: final_chunk_data,
                 checksum,
                 encrypted_key_bundle,
+                // The last frame of each leaf carries the leaf's proof
+                merkle_proof: merkle_tree.as_ref().and_then(|tree| {
+                    let (leaf, leaf_frames) = merkle_leaf_frames(chunk_index, total_chunks);
+                    (chunk_index + 1 == leaf_frames.end)
+                        .then(|| tree.proof(leaf as usize))
+                        .flatten()
+                }),
             };
 
             // Create or update upload progress bar
@@ -2233,7 +3618,7 @@ impl WebRTCService {
             // Update payment checkpoint progress after sending chunk
             if let Some(checkpoint_service) = payment_checkpoint {
                 let session_id = format!("{}_{}", request.file_hash, peer_id);
-                let bytes_transferred = ((chunk_index as u64 + 1) * CHUNK_SIZE as u64).min(file_data.len() as u64);
+                let bytes_transferred = ((chunk_index as u64 + 1) * CHUNK_SIZE as u64).min(file_size);
 
                 checkpoint_service
                     .update_progress(&session_id, bytes_transferred)
@@ -2274,6 +3659,12 @@ impl WebRTCService {
                 }
             };
             
+            if let Some(registry) = &seeding_registry {
+                registry
+                    .record_upload(&request.file_hash, Some(peer_id), chunk.data.len() as u64)
+                    .await;
+            }
+
             // Send progress event OUTSIDE the lock to avoid deadlock
             // Use try_send to avoid blocking if channel is full - progress events are not critical
             if let Some(progress) = progress_to_send {
@@ -2310,13 +3701,19 @@ impl WebRTCService {
             }
         }
 
+        if let Some(registry) = &seeding_registry {
+            if let Err(e) = registry.flush_stats().await {
+                warn!("Failed to save seeding stats: {}", e);
+            }
+        }
+
         // Mark transfer as completed
         {
             let mut conns = connections.lock().await;
             if let Some(connection) = conns.get_mut(peer_id) {
                 if let Some(transfer) = connection.active_transfers.get_mut(&request.file_hash) {
                     transfer.chunks_sent = total_chunks;
-                    transfer.bytes_sent = file_data.len() as u64;
+                    transfer.bytes_sent = file_size;
                 }
             }
         }
@@ -2392,8 +3789,19 @@ impl WebRTCService {
             return;
         }
 
-        // 3. Verify SHA-256 hash if multi-source service is available
-        if let Some(service) = multi_source_service {
+        // 3. If we requested merkle proofs, the frame carrying its leaf's proof is checked with
+        // the whole leaf once every frame of it is in (see `check_merkle_leaf`); a valid proof
+        // stands in for the per-chunk hash list
+        let expected_merkle_root = {
+            let conns = connections.lock().await;
+            conns
+                .get(peer_id)
+                .and_then(|c| c.expected_merkle_roots.get(&chunk.file_hash).cloned())
+        };
+        let proven_by_leaf = expected_merkle_root.is_some() && chunk.merkle_proof.is_some();
+
+        // 4. Otherwise verify SHA-256 hash if multi-source service is available
+        if let Some(service) = multi_source_service.filter(|_| !proven_by_leaf) {
             if service.verify_chunk_for_download(
                 &chunk.file_hash,
                 chunk.chunk_index,
@@ -2416,8 +3824,6 @@ impl WebRTCService {
             }
         }
 
-        bandwidth.acquire_download(chunk_len).await;
-
         // Get data channel reference before locking connections
         let dc_for_ack = {
             let conns = connections.lock().await;
@@ -2425,6 +3831,7 @@ impl WebRTCService {
                 .and_then(|c| c.data_channel.clone())
         };
 
+        let mut leaf_failed = false;
         let mut conns = connections.lock().await;
         if let Some(connection) = conns.get_mut(peer_id) {
             // Store chunk
@@ -2432,7 +3839,31 @@ impl WebRTCService {
                 .received_chunks
                 .entry(chunk.file_hash.clone())
                 .or_insert_with(HashMap::new);
-            chunks.insert(chunk.chunk_index, chunk.clone());
+            let is_new = chunks.insert(chunk.chunk_index, chunk.clone()).is_none();
+            if let Some(transfer) = connection.incoming_transfers.get_mut(&chunk.file_hash) {
+                if is_new {
+                    transfer.last_progress = Instant::now();
+                    transfer.retry_rounds = 0;
+                }
+            }
+
+            if let Some(merkle_root) = &expected_merkle_root {
+                let proofs = connection
+                    .merkle_leaf_proofs
+                    .entry(chunk.file_hash.clone())
+                    .or_default();
+                if let Some(proof) = &chunk.merkle_proof {
+                    let (leaf, _) = merkle_leaf_frames(chunk.chunk_index, chunk.total_chunks);
+                    proofs.insert(leaf, proof.clone());
+                }
+                leaf_failed = check_merkle_leaf(
+                    chunks,
+                    proofs,
+                    chunk.chunk_index,
+                    chunk.total_chunks,
+                    merkle_root,
+                ) == Some(false);
+            }
 
             // Emit progress to frontend
             if let Some(total_chunks) = chunks.values().next().map(|c| c.total_chunks) {
@@ -2454,6 +3885,8 @@ impl WebRTCService {
                 }
 
                 if chunks.len() == total_chunks as usize {
+                    connection.incoming_transfers.remove(&chunk.file_hash);
+
                     // Finish and remove progress bar
                     if let Some(pb) = DOWNLOAD_PROGRESS_BARS.lock().await.remove(&chunk.file_hash) {
                         pb.finish_with_message(format!("✓ Downloaded {}", &chunk.file_hash[..8]));
@@ -2472,23 +3905,52 @@ impl WebRTCService {
                 }
             }
         }
+        drop(conns);
+
+        if leaf_failed {
+            warn!(
+                "WebRTC merkle leaf of chunk {} failed proof verification for file {}",
+                chunk.chunk_index, chunk.file_hash
+            );
+            if let Some(service) = multi_source_service {
+                service
+                    .record_integrity_failure(&chunk.file_hash, peer_id, SourceType::P2p)
+                    .await;
+            }
+            return; // Its frames were dropped, so don't acknowledge them
+        }
 
-        // Send ACK after releasing the lock to avoid blocking
-        if let Some(dc) = dc_for_ack {
-            let ack = ChunkAck {
-                file_hash: chunk.file_hash.clone(),
-                chunk_index: chunk.chunk_index,
-                ready_for_more: true,
-            };
-            let ack_message = WebRTCMessage::ChunkAck(ack);
-            if let Ok(ack_json) = serde_json::to_string(&ack_message) {
+        // The seeder stops sending a file once too many of its chunks are unacknowledged, so
+        // holding the ACK until the chunk's bandwidth is granted throttles this download alone.
+        // Waiting here instead would stall every message from the peer behind a throttled or
+        // preempted file.
+        let Some(dc) = dc_for_ack else {
+            warn!(
+                "⚠️ No data channel available to send ACK for chunk {} to peer",
+                chunk.chunk_index
+            );
+            return;
+        };
+        let bandwidth = bandwidth.clone();
+        let multi_source_service = multi_source_service.cloned();
+        let ack = ChunkAck {
+            file_hash: chunk.file_hash.clone(),
+            chunk_index: chunk.chunk_index,
+            ready_for_more: true,
+        };
+        tokio::spawn(async move {
+            bandwidth.acquire_download(chunk_len).await;
+            if let Some(service) = multi_source_service {
+                service.acquire_bandwidth(&ack.file_hash, chunk_len).await;
+            }
+
+            let chunk_index = ack.chunk_index;
+            if let Ok(ack_json) = serde_json::to_string(&WebRTCMessage::ChunkAck(ack)) {
                 if let Err(e) = dc.send_text(ack_json).await {
-                    error!("❌ Failed to send ACK for chunk {}: {}", chunk.chunk_index, e);
+                    error!("❌ Failed to send ACK for chunk {}: {}", chunk_index, e);
                 }
             }
-        } else {
-            warn!("⚠️ No data channel available to send ACK for chunk {} to peer", chunk.chunk_index);
-        }
+        });
     }
 
     async fn assemble_file_from_chunks(
@@ -2617,6 +4079,38 @@ impl WebRTCService {
         .await;
 }
 
+    /// Build the chunk Merkle tree of a stored file, reading one chunk at a time
+    async fn stream_chunk_tree(reader: &mut StoredFileReader) -> Result<ChunkMerkleTree, String> {
+        let mut leaves = Vec::with_capacity(reader.total_chunks() as usize);
+        for chunk_index in 0..reader.total_chunks() {
+            let chunk_data = reader.read_chunk(chunk_index).await?;
+            leaves.push(Sha256::digest(&chunk_data).into());
+        }
+        Ok(ChunkMerkleTree::from_leaf_hashes(leaves))
+    }
+
+    /// The tree to prove served chunks with, if the requester asked for proofs and the
+    /// file's chunks (as `reader` splits them) hash to the root it trusts
+    async fn proving_tree(
+        reader: &mut StoredFileReader,
+        file_hash: &str,
+        merkle_root: Option<&str>,
+    ) -> Result<Option<ChunkMerkleTree>, String> {
+        let Some(merkle_root) = merkle_root else {
+            return Ok(None);
+        };
+        let tree = Self::stream_chunk_tree(reader).await?;
+        if tree.root_hex().as_deref() == Some(merkle_root) {
+            Ok(Some(tree))
+        } else {
+            warn!(
+                "Chunk tree of {} does not match requested merkle root {}, sending chunks without proofs",
+                file_hash, merkle_root
+            );
+            Ok(None)
+        }
+    }
+
     fn calculate_chunk_checksum(data: &[u8]) -> String {
         let mut hasher = Sha256::default();
         hasher.update(data);
@@ -2626,6 +4120,17 @@ impl WebRTCService {
     pub async fn create_offer(&self, peer_id: String) -> Result<String, String> {
         info!("Creating WebRTC offer for peer: {}", peer_id);
 
+        if !Self::admit_connection(
+            &peer_id,
+            &self.event_tx,
+            &self.connections,
+            &self.connection_manager,
+        )
+        .await
+        {
+            return Err("Connection limit reached".to_string());
+        }
+
         // Close any existing connection to this peer first
         {
             let mut conns = self.connections.lock().await;
@@ -2850,10 +4355,15 @@ impl WebRTCService {
             last_activity: Instant::now(),
             peer_connection: Some(peer_connection.clone()),
             data_channel: Some(data_channel),
-            pending_chunks: HashMap::new(),
             received_chunks: HashMap::new(),
             acked_chunks: HashMap::new(),
             pending_acks: HashMap::new(),
+            expected_merkle_roots: HashMap::new(),
+            merkle_leaf_proofs: HashMap::new(),
+            incoming_transfers: HashMap::new(),
+            requested_files: std::collections::HashSet::new(),
+            binary_chunk_frames: false,
+            chunk_requests: false,
             retry_context: Some(retry_ctx),
         };
         conns.insert(peer_id, connection);
@@ -2893,6 +4403,18 @@ impl WebRTCService {
         peer_id: String,
         offer: String,
     ) -> Result<String, String> {
+        // Checked before the old connection goes, so replacing one doesn't count as a new peer
+        if !Self::admit_connection(
+            &peer_id,
+            &self.event_tx,
+            &self.connections,
+            &self.connection_manager,
+        )
+        .await
+        {
+            return Err("Connection limit reached".to_string());
+        }
+
         // Close any existing connection to this peer first
         {
             let mut conns = self.connections.lock().await;
@@ -3112,10 +4634,15 @@ impl WebRTCService {
             last_activity: Instant::now(),
             peer_connection: Some(peer_connection.clone()),
             data_channel: None, // Will be set when received via on_data_channel
-            pending_chunks: HashMap::new(),
             received_chunks: HashMap::new(),
             acked_chunks: HashMap::new(),
             pending_acks: HashMap::new(),
+            expected_merkle_roots: HashMap::new(),
+            merkle_leaf_proofs: HashMap::new(),
+            incoming_transfers: HashMap::new(),
+            requested_files: std::collections::HashSet::new(),
+            binary_chunk_frames: false,
+            chunk_requests: false,
             retry_context: Some(retry_ctx),
         };
         conns.insert(peer_id.clone(), connection);
@@ -3241,6 +4768,19 @@ impl WebRTCService {
             .map_err(|e| e.to_string())
     }
 
+    /// Ask `peer_id` which chunks of `file_hash` it can serve; the answer arrives as
+    /// `WebRTCEvent::ChunkAvailabilityReceived`
+    pub async fn request_chunk_availability(
+        &self,
+        peer_id: String,
+        file_hash: String,
+    ) -> Result<(), String> {
+        self.cmd_tx
+            .send(WebRTCCommand::RequestChunkAvailability { peer_id, file_hash })
+            .await
+            .map_err(|e| e.to_string())
+    }
+
     pub async fn close_connection(&self, peer_id: String) -> Result<(), String> {
         self.cmd_tx
             .send(WebRTCCommand::CloseConnection { peer_id })
@@ -3412,6 +4952,8 @@ impl FileTransferService {
                 file_size: 0,                             // Will be updated
                 requester_peer_id: "local_peer".to_string(), // Should be actual local peer ID
                 recipient_public_key: None,               // No encryption for basic downloads
+                merkle_root: None,
+                binary_chunk_frames: true,
             };
 
             webrtc_service.send_file_request(peer_id, request).await?;
@@ -3421,4 +4963,486 @@ impl FileTransferService {
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_chunk(file_hash: &str) -> FileChunk {
+        let data: Vec<u8> = (0..4096u32).map(|i| (i % 251) as u8).collect();
+        FileChunk {
+            file_hash: file_hash.to_string(),
+            file_name: "video.mp4".to_string(),
+            chunk_index: 7,
+            total_chunks: 12,
+            checksum: format!("{:x}", Sha256::digest(&data)),
+            data,
+            encrypted_key_bundle: None,
+            merkle_proof: None,
+        }
+    }
+
+    #[test]
+    fn chunk_frame_round_trips_any_file_hash() {
+        for file_hash in [
+            "ab".repeat(32),
+            "QmYwAPJzv5CZsnAzt8auVZRn1pfejJfLgdvdkhNnd8ZRX".to_string(),
+        ] {
+            let chunk = sample_chunk(&file_hash);
+            let frame = encode_chunk_frame(&chunk).unwrap();
+            assert_eq!(frame[0], FRAME_TYPE_CHUNK);
+
+            let decoded = decode_chunk_frame(&frame).unwrap().unwrap();
+            assert_eq!(decoded.file_hash, chunk.file_hash);
+            assert_eq!(decoded.file_name, chunk.file_name);
+            assert_eq!(decoded.chunk_index, 7);
+            assert_eq!(decoded.total_chunks, 12);
+            assert_eq!(decoded.checksum, chunk.checksum);
+            assert_eq!(decoded.data, chunk.data);
+        }
+    }
+
+    #[test]
+    fn chunk_frame_is_much_smaller_than_json() {
+        let chunk = sample_chunk(&"ab".repeat(32));
+        let frame = encode_chunk_frame(&chunk).unwrap();
+        let json = serde_json::to_vec(&chunk).unwrap();
+        assert!(frame.len() < chunk.data.len() + 256);
+        assert!(json.len() > frame.len() * 2);
+    }
+
+    #[test]
+    fn chunk_payload_is_typed_only_for_peers_that_ask() {
+        let chunk = sample_chunk(&"ab".repeat(32));
+
+        let frame = encode_chunk_payload(&chunk, true).unwrap();
+        assert_eq!(frame[0], FRAME_TYPE_CHUNK);
+        assert_eq!(decode_chunk_frame(&frame).unwrap().unwrap().data, chunk.data);
+
+        // Older peers get the "CHNK" frames they already decode
+        let frame = encode_chunk_payload(&chunk, false).unwrap();
+        assert!(frame.starts_with(CHUNK_FRAME_MAGIC));
+        let decoded = decode_chunk_frame(&frame).unwrap().unwrap();
+        assert_eq!(decoded.file_hash, chunk.file_hash);
+        assert_eq!(decoded.chunk_index, chunk.chunk_index);
+        assert_eq!(decoded.data, chunk.data);
+    }
+
+    #[test]
+    fn file_requests_without_the_capability_get_legacy_frames() {
+        let request = WebRTCFileRequest {
+            file_hash: "abc".to_string(),
+            file_name: "video.mp4".to_string(),
+            file_size: 10,
+            requester_peer_id: "peer".to_string(),
+            recipient_public_key: None,
+            merkle_root: None,
+            binary_chunk_frames: true,
+        };
+        let mut json = serde_json::to_value(&request).unwrap();
+        let parsed: WebRTCFileRequest = serde_json::from_value(json.clone()).unwrap();
+        assert!(parsed.binary_chunk_frames);
+
+        // A request from a peer that predates the flag
+        json.as_object_mut().unwrap().retain(|key, _| !key.starts_with("binary"));
+        let parsed: WebRTCFileRequest = serde_json::from_value(json).unwrap();
+        assert!(!parsed.binary_chunk_frames);
+    }
+
+    #[tokio::test]
+    async fn served_chunks_carry_proofs_against_the_published_root() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let data: Vec<u8> = (0..MERKLE_LEAF_SIZE * 2 + 1000).map(|i| (i % 251) as u8).collect();
+        let file_path = temp_dir.path().join("video.mp4");
+        std::fs::write(&file_path, &data).unwrap();
+
+        // The root a publisher announces for this file
+        let merkle_root = crate::manager::ChunkManager::new(temp_dir.path().join("chunks"))
+            .chunk_and_encrypt_file_canonical(&file_path)
+            .unwrap()
+            .manifest
+            .merkle_root;
+
+        let service = FileTransferService::new_with_storage_dir(
+            temp_dir.path().join("store"),
+            false,
+            Arc::new(Mutex::new(Keystore::new())),
+            None,
+        )
+        .await
+        .unwrap();
+        service
+            .store_file_data("hash".to_string(), "video.mp4".to_string(), data)
+            .await;
+
+        let mut leaf_reader = service.open_file_reader("hash", MERKLE_LEAF_SIZE).await.unwrap();
+        let tree = WebRTCService::proving_tree(&mut leaf_reader, "hash", Some(&merkle_root))
+            .await
+            .unwrap()
+            .expect("leaves match the published root");
+        assert_eq!(leaf_reader.total_chunks(), 3);
+
+        // Served a frame at a time, each leaf is checked once its last frame is in
+        let mut reader = service.open_file_reader("hash", CHUNK_SIZE).await.unwrap();
+        let total_chunks = reader.total_chunks();
+        assert_eq!(total_chunks, 2 * FRAMES_PER_MERKLE_LEAF + 1);
+        let mut frames = HashMap::new();
+        let mut proofs = HashMap::new();
+        for chunk_index in 0..total_chunks {
+            let (leaf, leaf_frames) = merkle_leaf_frames(chunk_index, total_chunks);
+            let last_of_leaf = chunk_index + 1 == leaf_frames.end;
+            let mut chunk = sample_chunk("hash");
+            chunk.chunk_index = chunk_index;
+            chunk.total_chunks = total_chunks;
+            chunk.data = reader.read_chunk(chunk_index).await.unwrap();
+            chunk.checksum = format!("{:x}", Sha256::digest(&chunk.data));
+            chunk.merkle_proof = tree.proof(leaf as usize).filter(|_| last_of_leaf);
+
+            let frame = encode_chunk_frame(&chunk).unwrap();
+            // Proofs add a few hashes, not a leaf's worth of data, to the frames
+            assert!(frame.len() < CHUNK_SIZE + 1024);
+            let served = decode_chunk_frame(&frame).unwrap().unwrap();
+            if let Some(proof) = served.merkle_proof.clone() {
+                proofs.insert(leaf, proof);
+            }
+            frames.insert(chunk_index, served);
+
+            let checked = check_merkle_leaf(
+                &mut frames,
+                &mut proofs,
+                chunk_index,
+                total_chunks,
+                &merkle_root,
+            );
+            assert_eq!(checked, last_of_leaf.then_some(true));
+        }
+
+        // A tampered leaf fails, and its frames are dropped to be fetched again
+        frames.get_mut(&3).unwrap().data[0] ^= 1;
+        proofs.insert(0, tree.proof(0).unwrap());
+        assert_eq!(
+            check_merkle_leaf(&mut frames, &mut proofs, 0, total_chunks, &merkle_root),
+            Some(false)
+        );
+        assert!((0..FRAMES_PER_MERKLE_LEAF).all(|index| !frames.contains_key(&index)));
+        assert!(frames.contains_key(&FRAMES_PER_MERKLE_LEAF));
+
+        // Split at the data channel's frame size, the leaves can't prove anything
+        let mut reader = service.open_file_reader("hash", CHUNK_SIZE).await.unwrap();
+        assert!(WebRTCService::proving_tree(&mut reader, "hash", Some(&merkle_root))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn decode_leaves_json_control_messages_alone() {
+        let request = WebRTCMessage::ManifestRequest(WebRTCManifestRequest {
+            file_hash: "abc".to_string(),
+        });
+        let text = serde_json::to_vec(&request).unwrap();
+        assert!(decode_chunk_frame(&text).unwrap().is_none());
+    }
+
+    #[test]
+    fn decode_rejects_truncated_frames() {
+        let frame = encode_chunk_frame(&sample_chunk("abc")).unwrap();
+        assert!(decode_chunk_frame(&frame[..CHUNK_FRAME_HEADER_LEN + 1]).is_err());
+    }
+
+    #[test]
+    fn decode_accepts_legacy_frames() {
+        let chunk = sample_chunk(&"cd".repeat(32));
+        let mut frame = Vec::new();
+        frame.extend_from_slice(CHUNK_FRAME_MAGIC);
+        frame.push(CHUNK_FRAME_VERSION);
+        frame.push(0);
+        frame.extend_from_slice(&chunk.chunk_index.to_be_bytes());
+        frame.extend_from_slice(&chunk.total_chunks.to_be_bytes());
+        frame.extend_from_slice(&(chunk.file_name.len() as u16).to_be_bytes());
+        frame.extend_from_slice(&hex::decode(&chunk.file_hash).unwrap());
+        frame.extend_from_slice(&hex::decode(&chunk.checksum).unwrap());
+        frame.extend_from_slice(chunk.file_name.as_bytes());
+        frame.extend_from_slice(&chunk.data);
+
+        let decoded = decode_chunk_frame(&frame).unwrap().unwrap();
+        assert_eq!(decoded.file_hash, chunk.file_hash);
+        assert_eq!(decoded.data, chunk.data);
+    }
+
+    #[test]
+    fn chunk_frame_carries_merkle_proof() {
+        let chunks: Vec<Vec<u8>> = (0..12u8).map(|i| vec![i; 64]).collect();
+        let tree = ChunkMerkleTree::from_chunks(chunks.iter().map(|c| c.as_slice()));
+        let mut chunk = sample_chunk(&"ab".repeat(32));
+        chunk.data = chunks[7].clone();
+        chunk.checksum = format!("{:x}", Sha256::digest(&chunk.data));
+        chunk.merkle_proof = tree.proof(7);
+
+        let decoded = decode_chunk_frame(&encode_chunk_frame(&chunk).unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(decoded.merkle_proof, chunk.merkle_proof);
+        assert!(verify_chunk_merkle(
+            7,
+            &decoded.data,
+            decoded.merkle_proof.as_ref().unwrap(),
+            &tree.root_hex().unwrap()
+        )
+        .unwrap());
+    }
+
+    #[test]
+    fn chunk_bitfield_marks_available_chunks() {
+        let bitfield = encode_chunk_bitfield(&[0, 3, 9, 12], 10);
+        assert_eq!(bitfield, vec![0b1001_0000, 0b0100_0000]);
+        assert!(bitfield_has_chunk(&bitfield, 0));
+        assert!(bitfield_has_chunk(&bitfield, 9));
+        assert!(!bitfield_has_chunk(&bitfield, 1));
+        assert!(!bitfield_has_chunk(&bitfield, 12));
+        assert!(!bitfield_has_chunk(&bitfield, 64));
+    }
+
+    #[test]
+    fn chunk_rejection_round_trips_as_a_typed_error() {
+        let message = WebRTCMessage::ChunkRejected(WebRTCChunkRejected {
+            file_hash: "abc".to_string(),
+            chunk_index: 4,
+            error: ChunkServeError::ChunkUnavailable,
+        });
+        let json = serde_json::to_string(&message).unwrap();
+
+        match serde_json::from_str::<WebRTCMessage>(&json).unwrap() {
+            WebRTCMessage::ChunkRejected(rejected) => {
+                assert_eq!(rejected.chunk_index, 4);
+                assert_eq!(rejected.error, ChunkServeError::ChunkUnavailable);
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+        // Control messages must not be mistaken for a plain file request
+        assert!(serde_json::from_str::<WebRTCFileRequest>(&json).is_err());
+    }
+
+    #[tokio::test]
+    async fn peers_without_chunk_requests_keep_the_old_request_path() {
+        let connections = Arc::new(Mutex::new(HashMap::from([(
+            "old".to_string(),
+            peer("old", Duration::ZERO, Instant::now()),
+        )])));
+        let (event_tx, mut event_rx) = mpsc::channel(4);
+
+        WebRTCService::handle_request_chunk("old", "abc", 3, &event_tx, &connections).await;
+        match event_rx.try_recv().unwrap() {
+            WebRTCEvent::FileChunkRequested { peer_id, chunk_index, .. } => {
+                assert_eq!(peer_id, "old");
+                assert_eq!(chunk_index, 3);
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+
+        // A peer that spoke the chunk-request protocol is asked over its data channel instead
+        connections.lock().await.get_mut("old").unwrap().chunk_requests = true;
+        WebRTCService::handle_request_chunk("old", "abc", 3, &event_tx, &connections).await;
+        assert!(event_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn stored_chunk_lookups_use_the_sorted_scan() {
+        let stored = StoredChunks {
+            chunk_ids: vec![0, 2, 5],
+            total_chunks: 6,
+            scanned_at: Instant::now(),
+        };
+        assert!(stored.contains(5));
+        assert!(!stored.contains(3));
+    }
+
+    fn ice_candidate(
+        typ: RTCIceCandidateType,
+        address: &str,
+        port: u16,
+        related: (&str, u16),
+    ) -> RTCIceCandidate {
+        RTCIceCandidate {
+            typ,
+            address: address.to_string(),
+            port,
+            related_address: related.0.to_string(),
+            related_port: related.1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn connectivity_analysis_classifies_nat_behaviour() {
+        let host = ice_candidate(RTCIceCandidateType::Host, "192.168.1.20", 50000, ("", 0));
+        let base = ("192.168.1.20", 50000);
+
+        let cone = analyze_ice_candidates(
+            &[
+                host.clone(),
+                ice_candidate(RTCIceCandidateType::Srflx, "203.0.113.5", 61000, base),
+                ice_candidate(RTCIceCandidateType::Srflx, "203.0.113.5", 61000, base),
+            ],
+            false,
+        );
+        assert_eq!(cone.nat_type, NatType::EndpointIndependent);
+        assert!(cone.direct_candidate);
+        assert_eq!(cone.public_addresses, vec!["203.0.113.5:61000".to_string()]);
+
+        let symmetric = analyze_ice_candidates(
+            &[
+                host.clone(),
+                ice_candidate(RTCIceCandidateType::Srflx, "203.0.113.5", 61000, base),
+                ice_candidate(RTCIceCandidateType::Srflx, "203.0.113.5", 61007, base),
+            ],
+            false,
+        );
+        assert_eq!(symmetric.nat_type, NatType::Symmetric);
+        assert!(!symmetric.relay_candidate);
+        assert!(symmetric.diagnosis.contains("none is configured"));
+
+        let blocked = analyze_ice_candidates(
+            &[
+                host.clone(),
+                ice_candidate(RTCIceCandidateType::Relay, "198.51.100.9", 3478, base),
+            ],
+            true,
+        );
+        assert_eq!(blocked.nat_type, NatType::Unknown);
+        assert!(!blocked.direct_candidate);
+        assert!(blocked.relay_candidate);
+
+        let single_answer = analyze_ice_candidates(
+            &[
+                host,
+                ice_candidate(RTCIceCandidateType::Srflx, "203.0.113.5", 61000, base),
+            ],
+            false,
+        );
+        assert_eq!(single_answer.nat_type, NatType::Unknown);
+        assert!(single_answer.direct_candidate);
+        assert!(single_answer.diagnosis.contains("Only one STUN server"));
+
+        let open = analyze_ice_candidates(
+            &[ice_candidate(RTCIceCandidateType::Host, "198.51.100.20", 50000, ("", 0))],
+            false,
+        );
+        assert_eq!(open.nat_type, NatType::Open);
+        assert!(is_public_ip("2001:db8::1"));
+        assert!(!is_public_ip("100.72.1.1"));
+        assert!(!is_public_ip("fe80::1"));
+    }
+
+    fn peer(peer_id: &str, idle_for: Duration, now: Instant) -> PeerConnection {
+        PeerConnection {
+            peer_id: peer_id.to_string(),
+            is_connected: true,
+            active_transfers: HashMap::new(),
+            last_activity: now - idle_for,
+            peer_connection: None,
+            data_channel: None,
+            received_chunks: HashMap::new(),
+            acked_chunks: HashMap::new(),
+            pending_acks: HashMap::new(),
+            expected_merkle_roots: HashMap::new(),
+            merkle_leaf_proofs: HashMap::new(),
+            incoming_transfers: HashMap::new(),
+            requested_files: std::collections::HashSet::new(),
+            binary_chunk_frames: false,
+            chunk_requests: false,
+            retry_context: None,
+        }
+    }
+
+    #[test]
+    fn longest_idle_peer_is_evicted_and_idle_peers_free_their_file_slot() {
+        let now = Instant::now();
+        let idle_timeout = Duration::from_millis(60);
+        let mut busy = peer("busy", Duration::from_millis(600), now);
+        busy.active_transfers.insert(
+            "file".to_string(),
+            ActiveTransfer {
+                file_hash: "file".to_string(),
+                file_name: "file.bin".to_string(),
+                file_size: 1,
+                total_chunks: 1,
+                chunks_sent: 0,
+                bytes_sent: 0,
+                start_time: now,
+            },
+        );
+        busy.requested_files.insert("file".to_string());
+        let mut idle = peer("idle", Duration::from_millis(120), now);
+        idle.requested_files.insert("file".to_string());
+        let mut idlest = peer("idlest", Duration::from_millis(300), now);
+        idlest.requested_files.insert("other".to_string());
+        let mut fresh = peer("fresh", Duration::from_millis(5), now);
+        fresh.requested_files.insert("file".to_string());
+        let connections: HashMap<String, PeerConnection> = [busy, idle, idlest, fresh]
+            .into_iter()
+            .map(|connection| (connection.peer_id.clone(), connection))
+            .collect();
+
+        // The busy peer is older but still transferring, so it stays
+        assert_eq!(
+            idle_connection_to_evict(&connections, idle_timeout, now).as_deref(),
+            Some("idlest")
+        );
+        assert_eq!(idle_connection_to_evict(&connections, Duration::from_millis(900), now), None);
+
+        let mut reaped = idle_connections(&connections, idle_timeout, now);
+        reaped.sort();
+        assert_eq!(reaped, vec!["idle".to_string(), "idlest".to_string()]);
+
+        assert_eq!(active_peers_for_file(&connections, "file", "new", idle_timeout, now), 2);
+        assert_eq!(active_peers_for_file(&connections, "file", "fresh", idle_timeout, now), 1);
+    }
+
+    #[test]
+    fn stalled_transfer_rerequests_missing_chunks_then_fails() {
+        let start = Instant::now();
+        let stall = Duration::from_secs(TRANSFER_STALL_TIMEOUT_SECS);
+        let mut transfer = IncomingTransfer::new(start);
+
+        // Nothing arrived yet: chunk 0 is asked for again to learn the chunk count
+        assert_eq!(check_incoming_transfer(&transfer, None, start), IncomingTransferAction::Wait);
+        assert_eq!(
+            check_incoming_transfer(&transfer, None, start + stall),
+            IncomingTransferAction::RequestMissing(vec![0])
+        );
+
+        // 12 chunks, all but 7 and 11 received
+        let received: HashMap<u32, FileChunk> = (0..12)
+            .filter(|index| *index != 7 && *index != 11)
+            .map(|index| {
+                let mut chunk = sample_chunk("file");
+                chunk.chunk_index = index;
+                (index, chunk)
+            })
+            .collect();
+        assert_eq!(
+            check_incoming_transfer(&transfer, Some(&received), start + stall),
+            IncomingTransferAction::RequestMissing(vec![7, 11])
+        );
+
+        transfer.retry_rounds = MAX_MISSING_CHUNK_ROUNDS;
+        match check_incoming_transfer(&transfer, Some(&received), start + stall) {
+            IncomingTransferAction::Fail(error) => {
+                assert!(error.contains("missing chunks [7, 11]"), "{}", error)
+            }
+            other => panic!("expected failure, got {:?}", other),
+        }
+
+        // Past the deadline it fails even while chunks keep arriving
+        let allowed = |chunks| IncomingTransfer::time_allowed(Some(chunks));
+        assert!(allowed(1_000_000) > allowed(12));
+        let late = start + allowed(12);
+        transfer.retry_rounds = 0;
+        transfer.last_progress = late;
+        assert!(matches!(
+            check_incoming_transfer(&transfer, Some(&received), late),
+            IncomingTransferAction::Fail(_)
+        ));
+    }
+}
@@ -6,6 +6,7 @@
 /// 3. Network activity is tracked
 /// 4. Historical data is recorded
 /// 5. Upload/download operations update analytics
+/// 6. Cancellations are counted by reason
 
 #[cfg(test)]
 mod analytics_integration_tests {
@@ -456,4 +457,33 @@ mod analytics_integration_tests {
             metrics.avg_download_speed_kbps
         );
     }
+
+    #[tokio::test]
+    async fn test_cancellations_counted_by_reason() {
+        use chiral_network::transfer_events::{CancelReason, TransferCanceledEvent, TransferEvent};
+
+        let analytics = AnalyticsService::new();
+        for reason in [
+            CancelReason::UserRequested,
+            CancelReason::UserRequested,
+            CancelReason::DiskFull,
+        ] {
+            analytics
+                .handle_transfer_event(&TransferEvent::Canceled(TransferCanceledEvent {
+                    transfer_id: "file".to_string(),
+                    canceled_at: 0,
+                    downloaded_bytes: 0,
+                    total_bytes: 0,
+                    keep_partial: true,
+                    reason,
+                }))
+                .await;
+        }
+
+        let canceled = analytics.get_performance_metrics().await.canceled_transfers;
+        assert_eq!(canceled.user_requested, 2);
+        assert_eq!(canceled.disk_full, 1);
+        assert_eq!(canceled.shutdown, 0);
+        assert_eq!(canceled.total(), 3);
+    }
 }
@@ -105,7 +105,9 @@ fn test_create_client_with_config() {
     let config = Ed2kConfig {
         server_url: "ed2k://|server|127.0.0.1|4661|/".to_string(),
         timeout: Duration::from_secs(60),
+        read_timeout: Duration::from_secs(300),
         client_id: Some("test_client".to_string()),
+        proxy: None,
     };
 
     let client = Ed2kClient::with_config(config);
@@ -153,7 +155,9 @@ async fn test_connect_timeout() {
     let config = Ed2kConfig {
         server_url: "ed2k://|server|192.0.2.1|4661|/".to_string(), // TEST-NET-1, unreachable
         timeout: Duration::from_millis(100),
+        read_timeout: Duration::from_secs(300),
         client_id: None,
+        proxy: None,
     };
 
     let mut client = Ed2kClient::with_config(config);
@@ -162,6 +166,60 @@ async fn test_connect_timeout() {
     assert!(result.is_err(), "Should timeout on unreachable server");
 }
 
+#[tokio::test]
+async fn test_connect_never_bypasses_unreachable_proxy() {
+    let server = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server_port = server.local_addr().unwrap().port();
+    // Bound and dropped, so nothing listens there
+    let dead_proxy = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let config = Ed2kConfig {
+        server_url: format!("ed2k://|server|127.0.0.1|{}|/", server_port),
+        timeout: Duration::from_secs(2),
+        read_timeout: Duration::from_secs(300),
+        client_id: None,
+        proxy: Some(dead_proxy.to_string()),
+    };
+
+    let mut client = Ed2kClient::with_config(config);
+    let result = client.connect().await;
+
+    assert!(
+        matches!(result, Err(Ed2kError::ConnectionError(ref e)) if e.contains("SOCKS5")),
+        "Should fail on the proxy, got {:?}",
+        result
+    );
+    let direct = tokio::time::timeout(Duration::from_millis(200), server.accept()).await;
+    assert!(direct.is_err(), "Should not connect to the server directly");
+}
+
+#[tokio::test]
+async fn test_proxy_set_after_creation_is_used() {
+    let server = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server_port = server.local_addr().unwrap().port();
+    let dead_proxy = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let mut client = Ed2kClient::new(format!("ed2k://|server|127.0.0.1|{}|/", server_port));
+    client.set_proxy(Some(dead_proxy.to_string()));
+    assert_eq!(client.proxy(), Some(dead_proxy.to_string().as_str()));
+
+    let result = client.connect().await;
+
+    assert!(
+        matches!(result, Err(Ed2kError::ConnectionError(ref e)) if e.contains("SOCKS5")),
+        "Should fail on the proxy, got {:?}",
+        result
+    );
+    let direct = tokio::time::timeout(Duration::from_millis(200), server.accept()).await;
+    assert!(direct.is_err(), "Should not connect to the server directly");
+}
+
 // ============================================================================
 // Download Chunk Tests (Tests 14-17)
 // ============================================================================
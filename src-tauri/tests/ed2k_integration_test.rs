@@ -29,6 +29,7 @@ fn test_ed2k_source_creation() {
         file_name: Some("test_file.txt".to_string()),
         sources: Some(vec!["192.168.1.100:4662".to_string()]),
         timeout_secs: Some(30),
+        chunk_timeout_secs: None,
         chunk_hashes: Some(Vec::new()),
     };
     
@@ -49,6 +50,7 @@ fn test_download_source_ed2k() {
         file_name: Some("test_file.txt".to_string()),
         sources: None,
         timeout_secs: Some(30),
+        chunk_timeout_secs: None,
         chunk_hashes: Some(Vec::new()),
     };
     
@@ -143,6 +145,7 @@ fn test_ed2k_url_validation() {
         file_name: Some("test.txt".to_string()),
         sources: None,
         timeout_secs: Some(60),
+        chunk_timeout_secs: None,
         chunk_hashes: Some(Vec::new()),
     };
     
@@ -174,6 +177,7 @@ async fn test_ed2k_integration_readiness() {
         file_name: Some("large_file.bin".to_string()),
         sources: Some(vec!["peer1:4662".to_string(), "peer2:4662".to_string()]),
         timeout_secs: Some(45),
+        chunk_timeout_secs: None,
         chunk_hashes: Some(Vec::new()),
     };
     
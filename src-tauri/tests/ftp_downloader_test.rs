@@ -15,6 +15,7 @@ async fn test_create_default_downloader() {
 async fn test_create_custom_downloader() {
     let config = FtpDownloadConfig {
         timeout_secs: 60,
+        read_timeout_secs: 60,
         max_retries: 5,
         passive_mode: false,
         connection_pool_size: 10,
@@ -381,6 +382,7 @@ async fn test_concurrent_connections() {
 async fn test_retry_on_timeout() {
     let config = FtpDownloadConfig {
         timeout_secs: 2,  // Very short timeout
+        read_timeout_secs: 2,
         max_retries: 3,
         passive_mode: true,
         connection_pool_size: 5,
@@ -29,6 +29,7 @@ fn create_test_ed2k_source() -> Ed2kSourceInfo {
         file_name: Some("test_file.txt".to_string()),
         sources: Some(vec!["192.168.1.100:4662".to_string()]),
         timeout_secs: Some(30),
+        chunk_timeout_secs: None,
     }
 }
 
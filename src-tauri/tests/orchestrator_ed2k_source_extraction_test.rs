@@ -256,6 +256,7 @@ fn test_ed2k_chunk_assignment() {
             file_name: None,
             sources: None,
             timeout_secs: Some(30),
+            chunk_timeout_secs: None,
             chunk_hashes: Some(Vec::new()),
         }),
         DownloadSource::Ed2k(DownloadEd2kSourceInfo {
@@ -265,6 +266,7 @@ fn test_ed2k_chunk_assignment() {
             file_name: None,
             sources: None,
             timeout_secs: Some(30),
+            chunk_timeout_secs: None,
             chunk_hashes: Some(Vec::new()),
         }),
     ];
@@ -310,6 +312,7 @@ fn test_mixed_source_download() {
             file_name: None,
             sources: None,
             timeout_secs: Some(30),
+            chunk_timeout_secs: None,
             chunk_hashes: Some(Vec::new()),
         }),
         DownloadSource::Ftp(DownloadFtpInfo {
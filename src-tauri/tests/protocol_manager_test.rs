@@ -1,14 +1,16 @@
 use chiral_network::protocols::{
     ProtocolManager,
     traits::{
-        DownloadHandle, DownloadOptions, DownloadProgress, ProtocolCapabilities, ProtocolError,
-        SeedOptions, SeedingInfo, ProtocolHandler
+        DownloadHandle, DownloadOptions, DownloadProgress, OnConflict, ProtocolCapabilities,
+        ProtocolError, SeedOptions, SeedingInfo, ProtocolHandler
     },
     seeding::SeedingRegistry,
     HttpProtocolHandler,
     FtpProtocolHandler,
 };
 
+use chiral_network::multi_source_download::HashAlgorithm;
+
 use async_trait::async_trait;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
@@ -147,6 +149,8 @@ impl ProtocolHandler for MockProtocolHandler {
     fn capabilities(&self) -> ProtocolCapabilities {
         ProtocolCapabilities {
             supports_seeding: self.supports_seeding,
+            // Like the real handlers, whose identifiers come from the content
+            stable_seed_identifiers: matches!(self.name, "bittorrent" | "ed2k"),
             ..Default::default()
         }
     }
@@ -167,6 +171,47 @@ async fn test_calculate_file_hash() {
     );
 }
 
+#[tokio::test]
+async fn test_calculate_file_hash_with_algorithm() {
+    let manager = ProtocolManager::new();
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("test_hash.txt");
+    fs::write(&file_path, "hello world").await.unwrap();
+
+    let sha1 = manager
+        .calculate_file_hash_with(&file_path, HashAlgorithm::Sha1)
+        .await
+        .unwrap();
+    assert_eq!(sha1, "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed");
+
+    let blake3 = manager
+        .calculate_file_hash_with(&file_path, HashAlgorithm::Blake3)
+        .await
+        .unwrap();
+    assert_eq!(
+        blake3,
+        "d74981efa70a0c880b8d8c1985d075dbcbf679b99a5f9914e5aaf96b831a9e24"
+    );
+}
+
+#[tokio::test]
+async fn test_calculate_file_hash_streams_large_files() {
+    let manager = ProtocolManager::new();
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("large.bin");
+    // Spans several read buffers with a partial final read
+    let data: Vec<u8> = (0..9 * 1024 * 1024 + 123).map(|i| (i % 251) as u8).collect();
+    fs::write(&file_path, &data).await.unwrap();
+
+    for algorithm in [HashAlgorithm::Sha256, HashAlgorithm::Sha1, HashAlgorithm::Blake3] {
+        let hash = manager
+            .calculate_file_hash_with(&file_path, algorithm)
+            .await
+            .unwrap();
+        assert_eq!(hash, algorithm.digest_hex(&data));
+    }
+}
+
 #[tokio::test]
 async fn test_seeding_registry_add_list_remove() {
     let registry = SeedingRegistry::new();
@@ -255,4 +300,287 @@ async fn test_protocol_manager_seed_and_stop_all() {
 
     // Verify the mock handler's stop_seeding was called
     assert_eq!(*stop_called_flag.lock().unwrap(), true);
-}
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn test_protocol_manager_seeds_from_reader() {
+    let mut manager = ProtocolManager::new();
+    manager.register(Arc::new(MockProtocolHandler::new("bittorrent", true)));
+    let dir = tempdir().unwrap();
+    manager.set_seed_staging_dir(dir.path().to_path_buf());
+
+    let content = b"generated on the fly".to_vec();
+    let (file_hash, results) = manager
+        .seed_reader_multi_protocol(
+            Box::new(std::io::Cursor::new(content.clone())),
+            content.len() as u64,
+            "generated.txt",
+            None,
+            vec!["bittorrent".to_string()],
+            SeedOptions::default(),
+        )
+        .await
+        .unwrap();
+
+    let staged_path = &results["bittorrent"].file_path;
+    assert_eq!(staged_path, &dir.path().join(&file_hash).join("generated.txt"));
+    assert_eq!(fs::read(staged_path).await.unwrap(), content);
+    assert_eq!(manager.calculate_file_hash(staged_path).await.unwrap(), file_hash);
+    assert_eq!(manager.list_seeding_files().await[0].file_hash, file_hash);
+
+    // Content shorter than declared is rejected and leaves nothing behind
+    let short = manager
+        .seed_reader_multi_protocol(
+            Box::new(std::io::Cursor::new(content.clone())),
+            content.len() as u64 + 1,
+            "short.txt",
+            None,
+            vec!["bittorrent".to_string()],
+            SeedOptions::default(),
+        )
+        .await;
+    assert!(short.is_err());
+    let mut entries = fs::read_dir(dir.path()).await.unwrap();
+    let mut names = Vec::new();
+    while let Some(entry) = entries.next_entry().await.unwrap() {
+        names.push(entry.file_name());
+    }
+    assert_eq!(names, vec![std::ffi::OsString::from(&file_hash)]);
+
+    // Content no protocol could seed isn't left behind either
+    manager.register(Arc::new(MockProtocolHandler::new("ftp", false)));
+    let unseeded = b"nobody seeds this".to_vec();
+    let failed = manager
+        .seed_reader_multi_protocol(
+            Box::new(std::io::Cursor::new(unseeded.clone())),
+            unseeded.len() as u64,
+            "unseeded.txt",
+            None,
+            vec!["ftp".to_string()],
+            SeedOptions::default(),
+        )
+        .await;
+    assert!(failed.is_err());
+    let mut entries = fs::read_dir(dir.path()).await.unwrap();
+    let mut names = Vec::new();
+    while let Some(entry) = entries.next_entry().await.unwrap() {
+        names.push(entry.file_name());
+    }
+    assert_eq!(names, vec![std::ffi::OsString::from(&file_hash)]);
+
+    // Staged content goes away once it is no longer seeded
+    manager.stop_seeding_all(&file_hash).await.unwrap();
+    let mut entries = fs::read_dir(dir.path()).await.unwrap();
+    assert!(entries.next_entry().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_protocol_manager_seeds_reader_content_from_its_output_path() {
+    let mut manager = ProtocolManager::new();
+    manager.register(Arc::new(MockProtocolHandler::new("bittorrent", true)));
+    let staging = tempdir().unwrap();
+    manager.set_seed_staging_dir(staging.path().to_path_buf());
+    let output = tempdir().unwrap();
+    let output_path = output.path().join("report.csv");
+
+    let content = b"a,b\n1,2\n".to_vec();
+    let (file_hash, results) = manager
+        .seed_reader_multi_protocol(
+            Box::new(std::io::Cursor::new(content.clone())),
+            content.len() as u64,
+            "report.csv",
+            Some(output_path.clone()),
+            vec!["bittorrent".to_string()],
+            SeedOptions::default(),
+        )
+        .await
+        .unwrap();
+
+    // Seeded straight from the output path, with no staged copy
+    assert_eq!(results["bittorrent"].file_path, output_path);
+    assert_eq!(fs::read(&output_path).await.unwrap(), content);
+    assert_eq!(manager.calculate_file_hash(&output_path).await.unwrap(), file_hash);
+    let mut staged = fs::read_dir(staging.path()).await.unwrap();
+    assert!(staged.next_entry().await.unwrap().is_none());
+
+    // The caller's file outlives the seed
+    manager.stop_seeding_all(&file_hash).await.unwrap();
+    assert_eq!(fs::read(&output_path).await.unwrap(), content);
+}
+
+#[tokio::test]
+async fn test_reseeding_same_content_keeps_identifier() {
+    let mut manager = ProtocolManager::new();
+    manager.register(Arc::new(MockProtocolHandler::new("bittorrent", true)));
+
+    let dir = tempdir().unwrap();
+    let first_path = dir.path().join("first.txt");
+    let second_path = dir.path().join("second.txt");
+    fs::write(&first_path, "same content").await.unwrap();
+    fs::write(&second_path, "same content").await.unwrap();
+    let protocols = vec!["bittorrent".to_string()];
+
+    let first = manager
+        .seed_file_multi_protocol(first_path, protocols.clone(), SeedOptions::default())
+        .await
+        .unwrap();
+    // The copy is seeded too, but the mock derives identifiers from the path, so only
+    // reusing the persisted one keeps it
+    let second = manager
+        .seed_file_multi_protocol(second_path.clone(), protocols.clone(), SeedOptions::default())
+        .await
+        .unwrap();
+
+    assert_eq!(first["bittorrent"].identifier, second["bittorrent"].identifier);
+    assert_eq!(second["bittorrent"].file_path, second_path);
+    let seeding = manager.list_seeding_files().await;
+    assert_eq!(seeding.len(), 1);
+    assert_eq!(seeding[0].file_path, second_path);
+}
+
+#[tokio::test]
+async fn test_verify_seed_stops_seeding_changed_files() {
+    let mut manager = ProtocolManager::new();
+    let mock_bt = MockProtocolHandler::new("bittorrent", true);
+    let stop_called_flag = mock_bt.stop_called.clone();
+    manager.register(Arc::new(mock_bt));
+
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("seeded.txt");
+    fs::write(&file_path, "original content").await.unwrap();
+    manager
+        .seed_file_multi_protocol(
+            file_path.clone(),
+            vec!["bittorrent".to_string()],
+            SeedOptions::default(),
+        )
+        .await
+        .unwrap();
+    let file_hash = manager.calculate_file_hash(&file_path).await.unwrap();
+
+    let result = manager.verify_seed(&file_hash).await.unwrap();
+    assert!(result.intact);
+    assert_eq!(result.actual_hash.as_deref(), Some(file_hash.as_str()));
+    let seeds = manager.list_seeding_files().await;
+    assert_eq!(seeds[0].last_verified_at, Some(result.verified_at));
+    // Just verified, so nothing is due yet
+    let stale = manager.verify_stale_seeds(std::time::Duration::from_secs(3600)).await;
+    assert!(stale.is_empty());
+
+    fs::write(&file_path, "bit rot").await.unwrap();
+    let result = manager.verify_seed(&file_hash).await.unwrap();
+    assert!(!result.intact);
+    assert_ne!(result.actual_hash.as_deref(), Some(file_hash.as_str()));
+    assert!(manager.list_seeding_files().await.is_empty());
+    assert!(*stop_called_flag.lock().unwrap());
+
+    assert!(manager.verify_seed(&file_hash).await.is_err());
+}
+
+#[test]
+fn test_capability_queries() {
+    let mut manager = ProtocolManager::new();
+    manager.register(Arc::new(MockProtocolHandler::new("bittorrent", true)));
+    manager.register(Arc::new(MockProtocolHandler::new("http", false)));
+    manager.register(Arc::new(MockProtocolHandler::new("ed2k", true)));
+
+    assert_eq!(manager.seeding_capable_protocols(), vec!["bittorrent", "ed2k"]);
+    assert!(manager.encryption_capable_protocols().is_empty());
+    assert_eq!(
+        manager.handlers_matching(|caps| caps.supports_pause_resume),
+        vec!["bittorrent", "http", "ed2k"]
+    );
+    assert_eq!(
+        manager.handlers_matching(|caps| caps.supports_seeding && caps.supports_encryption),
+        Vec::<&str>::new()
+    );
+}
+
+#[test]
+fn test_protocols_can_be_disabled_and_persisted() {
+    let dir = tempdir().unwrap();
+    let states_path = dir.path().join("protocol_states.json");
+
+    let mut manager = ProtocolManager::new();
+    manager.set_protocol_states_path(states_path.clone());
+    manager.register(Arc::new(MockProtocolHandler::new("bittorrent", true)));
+    manager.register(Arc::new(MockProtocolHandler::new("http", false)));
+
+    assert_eq!(manager.find_handler("magnet:?xt=urn:btih:abc").unwrap().name(), "bittorrent");
+    manager.set_protocol_enabled("bittorrent", false).unwrap();
+    assert!(!manager.is_protocol_enabled("bittorrent"));
+    assert_eq!(manager.find_handler("magnet:?xt=urn:btih:abc").unwrap().name(), "http");
+    assert_eq!(manager.get_best_handler("anything").unwrap().name(), "http");
+    assert!(manager.set_protocol_enabled("gopher", false).is_err());
+
+    // A new manager over the same file starts with BitTorrent still disabled
+    let mut restarted = ProtocolManager::new();
+    restarted.set_protocol_states_path(states_path);
+    restarted.register(Arc::new(MockProtocolHandler::new("bittorrent", true)));
+    restarted.register(Arc::new(MockProtocolHandler::new("http", false)));
+    let states: Vec<(String, bool)> = restarted
+        .protocol_states()
+        .into_iter()
+        .map(|state| (state.name, state.enabled))
+        .collect();
+    assert_eq!(
+        states,
+        vec![("bittorrent".to_string(), false), ("http".to_string(), true)]
+    );
+
+    restarted.set_protocol_enabled("bittorrent", true).unwrap();
+    assert_eq!(restarted.get_best_handler("anything").unwrap().name(), "bittorrent");
+}
+
+#[tokio::test]
+async fn test_disabled_protocols_reach_switches_and_seeding() {
+    let mut manager = ProtocolManager::new();
+    manager.register(Arc::new(MockProtocolHandler::new("bittorrent", true)));
+    manager.register(Arc::new(MockProtocolHandler::new("ftp", true)));
+
+    // The switches handed to other services follow later changes
+    let switches = manager.protocol_switches();
+    assert!(switches.is_enabled("bittorrent"));
+    manager.set_protocol_enabled("bittorrent", false).unwrap();
+    assert!(!switches.is_enabled("bittorrent"));
+    assert!(switches.is_enabled("ftp"));
+
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("seed_disabled.txt");
+    fs::write(&file_path, "seed this file").await.unwrap();
+    let results = manager
+        .seed_file_multi_protocol(
+            file_path,
+            vec!["bittorrent".to_string(), "ftp".to_string()],
+            SeedOptions::default(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(results.keys().collect::<Vec<_>>(), vec!["ftp"]);
+}
+
+#[test]
+fn test_on_conflict_resolution() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("report.pdf");
+
+    // Nothing there yet: every policy keeps the requested path
+    assert_eq!(OnConflict::Rename.resolve(&path).unwrap(), path);
+    assert_eq!(OnConflict::Fail.resolve(&path).unwrap(), path);
+
+    std::fs::write(&path, b"existing").unwrap();
+    std::fs::write(dir.path().join("report (1).pdf"), b"existing").unwrap();
+
+    assert_eq!(OnConflict::Overwrite.resolve(&path).unwrap(), path);
+    assert_eq!(
+        OnConflict::Rename.resolve(&path).unwrap(),
+        dir.path().join("report (2).pdf")
+    );
+    assert!(matches!(
+        OnConflict::Fail.resolve(&path),
+        Err(ProtocolError::AlreadyExists(_))
+    ));
+
+    // Directories are download targets, not conflicts
+    assert_eq!(OnConflict::Fail.resolve(dir.path()).unwrap(), dir.path());
+}
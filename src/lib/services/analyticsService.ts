@@ -22,9 +22,20 @@ export interface PerformanceMetrics {
   totalConnections: number;
   successfulTransfers: number;
   failedTransfers: number;
+  canceledTransfers: CancellationCounts;
   avgLatencyMs: number;
 }
 
+/** Canceled transfers, counted by why they were canceled */
+export interface CancellationCounts {
+  userRequested: number;
+  shutdown: number;
+  diskFull: number;
+  superseded: number;
+  metered: number;
+  paused: number;
+}
+
 export interface NetworkActivity {
   activeUploads: number;
   activeDownloads: number;
@@ -117,6 +128,14 @@ export class AnalyticsService {
         totalConnections: 0,
         successfulTransfers: 0,
         failedTransfers: 0,
+        canceledTransfers: {
+          userRequested: 0,
+          shutdown: 0,
+          diskFull: 0,
+          superseded: 0,
+          metered: 0,
+          paused: 0,
+        },
         avgLatencyMs: 0,
       };
     }
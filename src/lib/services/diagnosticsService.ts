@@ -21,6 +21,31 @@ export interface DiagResult {
   timestamp: number;
 }
 
+/** Result of the backend WebRTC connectivity self-test */
+export interface NatTestResult {
+  natType: "open" | "endpointIndependent" | "symmetric" | "unknown";
+  directCandidate: boolean;
+  relayCandidate: boolean;
+  turnConfigured: boolean;
+  hostCandidates: number;
+  srflxCandidates: number;
+  relayCandidates: number;
+  publicAddresses: string[];
+  gatheringComplete: boolean;
+  durationMs: number;
+  diagnosis: string;
+}
+
+/** Latest backend health check of one STUN/TURN server URL */
+export interface IceServerStatus {
+  url: string;
+  healthy: boolean;
+  lastCheckedMs: number | null;
+  rttMs: number | null;
+  consecutiveFailures: number;
+  lastError: string | null;
+}
+
 export interface DiagReport {
   timestamp: number;
   results: DiagResult[];
@@ -84,6 +109,8 @@ class DiagnosticsService {
       this.checkNATTraversal(),
       this.checkRelayConnections(),
       this.checkWebRTCSupport(),
+      this.checkWebRTCConnectivity(),
+      this.checkIceServers(),
       this.checkProxyConfiguration(),
       this.checkEncryptionCapability(),
       this.checkBandwidthLimits(),
@@ -623,6 +650,108 @@ class DiagnosticsService {
     }
   }
 
+  /**
+   * WebRTC connectivity check: gathers ICE candidates against the configured
+   * STUN/TURN servers to find out whether peers can reach us directly or via relay
+   */
+  private async checkWebRTCConnectivity(): Promise<DiagResult> {
+    if (!this.isTauri) {
+      return {
+        id: "webrtc_connectivity_check",
+        category: "network",
+        label: "WebRTC Connectivity",
+        status: "info",
+        details: "Skipped in web build",
+        timestamp: Date.now(),
+      };
+    }
+
+    try {
+      const result = await invoke<NatTestResult>("run_webrtc_connectivity_test");
+      let status: DiagStatus = "pass";
+      if (!result.directCandidate && !result.relayCandidate) {
+        status = "fail";
+      } else if (result.natType !== "open" && result.natType !== "endpointIndependent") {
+        status = "warn";
+      }
+
+      return {
+        id: "webrtc_connectivity_check",
+        category: "network",
+        label: "WebRTC Connectivity",
+        status,
+        details: result.diagnosis,
+        timestamp: Date.now(),
+      };
+    } catch (error) {
+      return {
+        id: "webrtc_connectivity_check",
+        category: "network",
+        label: "WebRTC Connectivity",
+        status: "fail",
+        error: error instanceof Error ? error.message : String(error),
+        timestamp: Date.now(),
+      };
+    }
+  }
+
+  /**
+   * ICE server check: probes every configured STUN/TURN server. Unreachable
+   * ones are already left out of new connections by the backend.
+   */
+  private async checkIceServers(): Promise<DiagResult> {
+    if (!this.isTauri) {
+      return {
+        id: "ice_servers_check",
+        category: "network",
+        label: "STUN/TURN Servers",
+        status: "info",
+        details: "Skipped in web build",
+        timestamp: Date.now(),
+      };
+    }
+
+    try {
+      const statuses = await invoke<IceServerStatus[]>("get_ice_server_health", {
+        refresh: true,
+      });
+      const down = statuses.filter((status) => !status.healthy);
+      const turnUp = statuses.some(
+        (status) => status.healthy && status.url.startsWith("turn"),
+      );
+      let status: DiagStatus = "pass";
+      if (down.length === statuses.length) {
+        status = "fail";
+      } else if (down.length > 0 || !turnUp) {
+        status = "warn";
+      }
+
+      const details = [
+        `${statuses.length - down.length}/${statuses.length} servers reachable`,
+        ...(turnUp ? [] : ["no TURN relay available"]),
+        ...down.map((server) => `${server.url}: ${server.lastError ?? "unreachable"}`),
+      ].join("; ");
+
+      return {
+        id: "ice_servers_check",
+        category: "network",
+        label: "STUN/TURN Servers",
+        status,
+        details,
+        timestamp: Date.now(),
+      };
+    } catch (error) {
+      return {
+        id: "ice_servers_check",
+        category: "network",
+        label: "STUN/TURN Servers",
+        status: "fail",
+        error: error instanceof Error ? error.message : String(error),
+        timestamp: Date.now(),
+      };
+    }
+  }
+
   /**
    * Proxy configuration check
    */
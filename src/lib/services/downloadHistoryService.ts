@@ -5,7 +5,9 @@
  * Stores completed, failed, and canceled downloads for future reference.
  */
 
+import { invoke } from "@tauri-apps/api/core";
 import type { FileItem } from "$lib/stores";
+import type { CancelReason } from "$lib/stores/transferEventsStore";
 
 export interface DownloadHistoryEntry {
   id: string;
@@ -24,6 +26,52 @@ export interface DownloadHistoryEntry {
   cids?: string[];
 }
 
+export type TransferOutcome = "completed" | "failed" | "canceled";
+
+export type TransferSourceType = "http" | "ftp" | "p2p" | "bittorrent" | "webrtc" | "relay";
+
+export interface ProtocolShare {
+  sourceType: TransferSourceType;
+  bytes: number;
+  chunks: number;
+}
+
+/** A finished transfer as recorded by the backend transfer history */
+export interface TransferHistoryRecord {
+  transferId: string;
+  fileHash: string;
+  fileName: string;
+  fileSize: number;
+  startedAt: number | null;
+  finishedAt: number;
+  durationSeconds: number;
+  averageSpeedBps: number;
+  bytesTransferred: number;
+  protocols: ProtocolShare[];
+  outcome: TransferOutcome;
+  error: string | null;
+  errorCategory: string | null;
+  cancelReason: CancelReason | null;
+}
+
+export interface TransferHistoryFilter {
+  outcome?: TransferOutcome;
+  fileHash?: string;
+  search?: string;
+  protocol?: TransferSourceType;
+  since?: number;
+  until?: number;
+  offset?: number;
+  limit?: number;
+}
+
+export interface TransferHistoryPage {
+  records: TransferHistoryRecord[];
+  total: number;
+  offset: number;
+  limit: number;
+}
+
 const STORAGE_KEY = "chiral.downloadHistory";
 const MAX_HISTORY_ENTRIES = 1000; // Limit history to prevent storage bloat
 
@@ -199,6 +247,20 @@ class DownloadHistoryService {
     };
   }
 
+  /**
+   * Query the backend transfer history, newest first
+   */
+  async queryTransferHistory(filter: TransferHistoryFilter = {}): Promise<TransferHistoryPage> {
+    return await invoke<TransferHistoryPage>("query_transfer_history", { filter });
+  }
+
+  /**
+   * Clear the backend transfer history
+   */
+  async clearTransferHistory(): Promise<void> {
+    await invoke("clear_transfer_history");
+  }
+
   /**
    * Export history as JSON
    */
@@ -1,6 +1,22 @@
 import { invoke } from "@tauri-apps/api/core";
 import { join } from "@tauri-apps/api/path";
 
+/** A registered transfer protocol and whether it is currently used */
+export interface ProtocolState {
+  name: string;
+  enabled: boolean;
+}
+
+/** Outcome of re-checking a seeded file against its hash */
+export interface SeedVerifyResult {
+  fileHash: string;
+  filePath: string;
+  intact: boolean;  // False means seeding was stopped
+  actualHash?: string;
+  error?: string;
+  verifiedAt: number;
+}
+
 /**
  * A service class to interact with the file transfer and DHT commands
  * on the Rust backend. This is adapted from the implementation guide to match
@@ -134,6 +150,29 @@ export class FileService {
       return null;
     }
   }
+
+  /**
+   * Lists the registered protocols (bittorrent, ed2k, ftp, ...) and whether each is enabled.
+   */
+  async getProtocolStates(): Promise<ProtocolState[]> {
+    return invoke<ProtocolState[]>("get_protocol_states");
+  }
+
+  /**
+   * Turns a protocol off or back on without unregistering it; the choice persists.
+   * @param protocol The protocol name, e.g. "bittorrent".
+   */
+  async setProtocolEnabled(protocol: string, enabled: boolean): Promise<void> {
+    await invoke("set_protocol_enabled", { protocol, enabled });
+  }
+
+  /**
+   * Re-hashes a seeded file; if it no longer matches its hash, seeding is stopped.
+   * @param fileHash The SHA-256 hash the file is seeded under.
+   */
+  async verifySeed(fileHash: string): Promise<SeedVerifyResult> {
+    return invoke<SeedVerifyResult>("verify_seed", { fileHash });
+  }
 }
 
 // It's often useful to export a singleton instance of the service.
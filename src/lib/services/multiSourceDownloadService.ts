@@ -1,4 +1,6 @@
 import { invoke } from '@tauri-apps/api/core';
+import type { FileMetadata } from '$lib/dht';
+import type { CancelReason, SourceSummary, TransferPriority } from '$lib/stores/transferEventsStore';
 
 export interface ChunkInfo {
   chunkId: number;
@@ -61,6 +63,116 @@ export interface MultiSourceProgress {
   downloadSpeedBps: number;
   etaSeconds?: number;
   sourceAssignments: SourceAssignment[];
+  labels: string[];
+}
+
+/**
+ * How well a file is seeded; at risk while rarestChunkReplication is 1
+ */
+export interface SwarmHealth {
+  fileHash: string;
+  totalSources: number;
+  fullCopiesAvailable: number;
+  rarestChunkReplication: number;  // 0 when some chunk still needed has no source
+  estimatedThroughputBps: number;
+}
+
+export interface ImportResult {
+  fileHash: string;
+  totalChunks: number;
+  matchedChunks: number[];
+  mismatchedChunks: number[];
+  importedBytes: number;
+}
+
+export interface RepairResult {
+  fileHash: string;
+  totalChunks: number;
+  goodChunks: number;
+  badChunks: number[];
+  repairing: boolean;
+}
+
+/**
+ * Disk usage of one file's directory in the chunk cache
+ */
+export interface ChunkDirectoryInfo {
+  fileHash: string;
+  bytes: number;
+  chunkCount: number;
+  lastModified: number;  // Unix timestamp in seconds
+  active: boolean;  // Backs a running download and can't be pruned
+}
+
+/**
+ * A pinned certificate (`certificate`) or public key (`publicKey`) for an HTTPS/FTPS host
+ */
+export interface CertificatePin {
+  host: string;
+  kind?: "certificate" | "publicKey";
+  /** Hex-encoded SHA-256 */
+  sha256: string;
+}
+
+/**
+ * Why `start_multi_source_download` refused a download; `message` is ready to show
+ */
+export interface StartDownloadError {
+  kind: 'outputDir' | 'diskSpace' | 'other';
+  message: string;
+  /** Tagged with `type`, e.g. `permissionDenied` or `insufficientDiskSpace`; absent for `other` */
+  error?: { type: string; path?: string; reason?: string; needed?: number; available?: number };
+}
+
+/** Thrown by `startDownload`, carrying the backend's typed error */
+export class StartDownloadFailure extends Error {
+  constructor(readonly details: StartDownloadError) {
+    super(details.message);
+    this.name = 'StartDownloadFailure';
+  }
+}
+
+// Field names follow the backend's ConnectionManagerStats, which is not camelCased
+export interface WebRtcConnectionStats {
+  total_connections: number;
+  connected: number;
+  connecting: number;
+  disconnected: number;
+  backing_off: number;
+  failed: number;
+  total_attempts: number;
+  total_successes: number;
+}
+
+export interface SystemStatus {
+  activeDownloads: number;
+  queuedDownloads: number;
+  activeSeeds: number;
+  downloadSpeedBps: number;
+  uploadSpeedBps: number;
+  webrtcConnections: WebRtcConnectionStats;
+  ftpPoolSize: number;
+  chunkStore: { chunkCount: number; totalBytes: number };
+  chunkCache: { chunkCount: number; totalBytes: number };  // Chunks of unfinished downloads
+  downloadStates: { files: number; bytes: number };  // Saved states of resumable downloads
+}
+
+// Mirrors the backend's DisconnectReason, which serializes in snake_case
+export type DisconnectReason =
+  | 'network_error'
+  | 'timeout'
+  | 'source_unavailable'
+  | 'protocol_error'
+  | 'user_canceled'
+  | 'completed'
+  | 'rate_limited'
+  | { other: string };
+
+export interface SourceErrorEntry {
+  timestampMs: number;
+  reason: DisconnectReason;
+  chunkId: number | null;
+  error: string;
 }
 
 export interface MultiSourceDownloadOptions {
@@ -69,6 +181,36 @@ export interface MultiSourceDownloadOptions {
   preferMultiSource?: boolean;
   selectedPeers?: string[];  // Explicitly selected peers from peer selection modal
   peerAllocation?: Array<{peerId: string; percentage: number}>;  // Manual chunk allocation
+  metadata?: FileMetadata;  // Fallback when the DHT has no record (e.g. magnet or direct HTTP)
+  useHttpSources?: boolean;  // Download from the HTTP servers the metadata lists; off by default
+  labels?: string[];  // Groups the download, e.g. "backups" or "media"
+  seedAfterDownload?: string[];  // Protocols to seed the finished file on, e.g. ["bittorrent", "ed2k"]
+  encryptAtRest?: boolean;  // Encrypt chunks on disk with a key from the unlocked account
+  expectedSha256?: string;  // Checked at the end of a streamed download of unknown size
+  minSources?: number;  // Wait until this many sources are found before starting
+  minSourcesWaitSecs?: number;  // Give up waiting for minSources after this long (default 60)
+  priority?: TransferPriority;  // "high" preempts low-priority downloads when bandwidth or slots run out
+  outputPermissions?: number;  // Unix mode bits for the finished file, e.g. 0o600 for keys
+  preallocate?: boolean;  // Reserve the file's full size at start so a full disk fails early
+  sourceWeights?: SourceTypeWeights;  // Prefer or avoid source types when choosing sources
+  maxDownloadBps?: number;  // This download's own speed limit in bytes/s; 0 or unset for none
+}
+
+/**
+ * Multipliers on each source type's built-in priority when choosing among a file's sources,
+ * e.g. { http: 2 } to prefer servers. Unset types keep a weight of 1.
+ */
+export interface SourceTypeWeights {
+  p2p?: number;
+  http?: number;
+  ftp?: number;
+  ed2k?: number;
+  bittorrent?: number;
+}
+
+export interface MeteredMode {
+  enabled: boolean;
+  smallDownloadBytes: number;
 }
 
 export class MultiSourceDownloadService {
@@ -81,21 +223,75 @@ export class MultiSourceDownloadService {
     outputPath: string,
     options?: MultiSourceDownloadOptions
   ): Promise<string> {
-    return invoke('start_multi_source_download', {
+    return invoke<string>('start_multi_source_download', {
       fileHash,
       outputPath,
       maxPeers: options?.maxPeers,
       chunkSize: options?.chunkSize,
       selectedPeers: options?.selectedPeers,
-      peerAllocation: options?.peerAllocation
+      peerAllocation: options?.peerAllocation,
+      metadata: options?.metadata,
+      useHttpSources: options?.useHttpSources,
+      labels: options?.labels,
+      seedAfterDownload: options?.seedAfterDownload,
+      encryptAtRest: options?.encryptAtRest,
+      expectedSha256: options?.expectedSha256,
+      minSources: options?.minSources,
+      minSourcesWaitSecs: options?.minSourcesWaitSecs,
+      priority: options?.priority,
+      outputPermissions: options?.outputPermissions,
+      preallocate: options?.preallocate,
+      sourceWeights: options?.sourceWeights,
+      maxDownloadBps: options?.maxDownloadBps
+    }).catch((error: StartDownloadError) => {
+      throw new StartDownloadFailure(error);
     });
   }
 
+  /**
+   * Start a download from a magnet link; its name (dn) and size (xl) are used when the DHT
+   * has no record. Resolves with the file hash the download runs under.
+   */
+  static async startMagnetDownload(
+    magnet: string,
+    outputPath: string,
+    options?: Pick<MultiSourceDownloadOptions, 'maxPeers' | 'chunkSize'>
+  ): Promise<string> {
+    return invoke('start_multi_source_magnet_download', {
+      magnet,
+      outputPath,
+      maxPeers: options?.maxPeers,
+      chunkSize: options?.chunkSize
+    });
+  }
+
+  /**
+   * Describe a running, queued or planned download as a JSON manifest another node can
+   * import. It carries the file metadata, chunk plan and known sources, but no credentials.
+   */
+  static async exportManifest(fileHash: string): Promise<string> {
+    return invoke('export_download_manifest', { fileHash });
+  }
+
+  /**
+   * Start (or queue) the download described by an exported manifest.
+   * Returns the file hash; malformed or oversized manifests are rejected.
+   */
+  static async importManifest(manifest: string, outputPath: string): Promise<string> {
+    return invoke('import_download_manifest', { manifest, outputPath });
+  }
+
   /**
    * Cancel an active multi-source download
+   * Pass purge to also delete downloaded chunks and saved state (no later resume).
+   * The reason (user_requested by default) is kept in the transfer history and analytics.
    */
-  static async cancelDownload(fileHash: string): Promise<void> {
-    return invoke('cancel_multi_source_download', { fileHash });
+  static async cancelDownload(
+    fileHash: string,
+    purge = false,
+    reason?: CancelReason
+  ): Promise<void> {
+    return invoke('cancel_multi_source_download', { fileHash, purge, reason });
   }
 
   /**
@@ -105,6 +301,166 @@ export class MultiSourceDownloadService {
     return invoke('get_multi_source_progress', { fileHash });
   }
 
+  /**
+   * Get what each source has contributed so far, for a live per-source view
+   */
+  static async getSourceStats(fileHash: string): Promise<SourceSummary[]> {
+    return invoke('get_multi_source_source_stats', { fileHash });
+  }
+
+  /**
+   * Get how well-seeded a file is, before starting it or while it downloads
+   */
+  static async getSwarmHealth(fileHash: string): Promise<SwarmHealth> {
+    return invoke('get_swarm_health', { fileHash });
+  }
+
+  /**
+   * Get the recent failures of one source of an active download, oldest first
+   */
+  static async getSourceErrorLog(fileHash: string, sourceId: string): Promise<SourceErrorEntry[]> {
+    return invoke('get_source_error_log', { fileHash, sourceId });
+  }
+
+  /**
+   * Get one snapshot of the whole transfer subsystem, for status dashboards
+   */
+  static async getSystemStatus(): Promise<SystemStatus> {
+    return invoke('get_transfer_system_status');
+  }
+
+  /**
+   * Resume a persisted download, writing the finished file to a different path
+   */
+  static async resumeDownloadTo(fileHash: string, outputPath: string): Promise<void> {
+    return invoke('resume_multi_source_download_to', { fileHash, outputPath });
+  }
+
+  /**
+   * Stop every download but keep its progress for a later resume, e.g. when going offline.
+   * Queued downloads are kept too. Resolves with how many downloads were paused.
+   */
+  static async pauseAll(): Promise<number> {
+    return invoke('pause_all_multi_source_downloads');
+  }
+
+  /**
+   * Start every download pauseAll stopped again. Resolves with how many were resumed.
+   */
+  static async resumeAll(): Promise<number> {
+    return invoke('resume_all_multi_source_downloads');
+  }
+
+  /**
+   * Turn metered-connection mode on or off; the setting is saved for the next run. While on,
+   * downloads larger than smallDownloadBytes are paused or wait in the queue, and resume once
+   * it is turned off. Resolves with how many downloads were paused.
+   */
+  static async setMeteredConnection(
+    metered: boolean,
+    smallDownloadBytes?: number
+  ): Promise<number> {
+    return invoke('set_metered_connection', { metered, smallDownloadBytes });
+  }
+
+  static async getMeteredConnection(): Promise<MeteredMode> {
+    return invoke('get_metered_connection');
+  }
+
+  /**
+   * Cancel every download, queued ones included; purge also deletes chunks and saved state
+   */
+  static async cancelAll(purge = false): Promise<number> {
+    return invoke('cancel_all_multi_source_downloads', { purge });
+  }
+
+  /**
+   * Get progress for every active download tagged with a label
+   */
+  static async listDownloadsByLabel(label: string): Promise<MultiSourceProgress[]> {
+    return invoke('list_multi_source_downloads_by_label', { label });
+  }
+
+  /**
+   * Verify a local copy of a file chunk by chunk and store the chunks that match,
+   * so they don't have to be downloaded again
+   */
+  static async importLocalFile(filePath: string, fileHash: string): Promise<ImportResult> {
+    return invoke('import_local_file', { filePath, fileHash });
+  }
+
+  /**
+   * Get the certificate pins HTTPS and FTPS sources are checked against
+   */
+  static async getCertificatePins(): Promise<CertificatePin[]> {
+    return invoke('get_certificate_pins');
+  }
+
+  /**
+   * Replace the certificate pins; connections to a pinned host that match none of its
+   * pins are rejected. The pins are saved and survive a restart.
+   */
+  static async setCertificatePins(pins: CertificatePin[]): Promise<void> {
+    return invoke('set_certificate_pins', { pins });
+  }
+
+  /**
+   * Add a source to a running download, e.g. a seeder that came online after it started
+   */
+  static async addSource(fileHash: string, source: DownloadSource): Promise<void> {
+    return invoke('add_multi_source_download_source', { fileHash, source });
+  }
+
+  /**
+   * Ask a source whether it currently has the file, without downloading any of it
+   */
+  static async sourceHasFile(fileHash: string, source: DownloadSource): Promise<boolean> {
+    return invoke('check_download_source', { fileHash, source });
+  }
+
+  /**
+   * Disk usage of every file in the chunk cache, largest first
+   */
+  static async listChunkDirectories(): Promise<ChunkDirectoryInfo[]> {
+    return invoke('list_chunk_directories');
+  }
+
+  /**
+   * Delete the cached chunks of a file that isn't downloading; resolves with the bytes freed
+   */
+  static async pruneChunkDirectory(fileHash: string): Promise<number> {
+    return invoke('prune_chunk_directory', { fileHash });
+  }
+
+  /**
+   * Check a finished file against its chunk hashes and re-download only the bad chunks
+   */
+  static async repairFile(fileHash: string, existingFilePath: string): Promise<RepairResult> {
+    return invoke('repair_file', { fileHash, existingFilePath });
+  }
+
+  /**
+   * Set how much of the shared download bandwidth a download gets relative to others
+   */
+  static async setDownloadPriority(fileHash: string, priority: TransferPriority): Promise<void> {
+    return invoke('set_multi_source_download_priority', { fileHash, priority });
+  }
+
+  /**
+   * Limit a running download to maxDownloadBps bytes per second; 0 lifts the limit
+   */
+  static async setDownloadSpeedLimit(fileHash: string, maxDownloadBps: number): Promise<void> {
+    return invoke('set_multi_source_download_speed_limit', { fileHash, maxDownloadBps });
+  }
+
+  /**
+   * Change how many sources a running download uses. Raising it connects reserve sources;
+   * lowering it drops HTTP, FTP and ed2k sources once their current chunks are done.
+   */
+  static async setMaxPeers(fileHash: string, maxPeers: number): Promise<void> {
+    return invoke('set_multi_source_download_max_peers', { fileHash, maxPeers });
+  }
+
   /**
    * Download a file with automatic multi-source detection
    * Falls back to single-source if multi-source is not beneficial
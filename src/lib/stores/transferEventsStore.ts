@@ -21,6 +21,7 @@
 
 import { writable, derived, get, type Readable } from "svelte/store";
 import { listen, type UnlistenFn } from "@tauri-apps/api/event";
+import { invoke } from "@tauri-apps/api/core";
 
 // ============================================================================
 // Type Definitions (matching Rust types)
@@ -28,6 +29,14 @@ import { listen, type UnlistenFn } from "@tauri-apps/api/event";
 
 export type TransferPriority = "low" | "normal" | "high";
 
+export type CancelReason =
+  | "user_requested"
+  | "shutdown"
+  | "disk_full"
+  | "superseded"
+  | "metered"
+  | "paused";
+
 export type SourceType =
   | "http"
   | "ftp"
@@ -38,6 +47,7 @@ export type SourceType =
 
 export type TransferStatus =
   | "queued"
+  | "waiting_for_sources"
   | "starting"
   | "downloading"
   | "paused"
@@ -72,6 +82,7 @@ export interface ConnectedSource {
   assignedChunks: number[];
   completedChunks: number;
   isActive: boolean;
+  throttledUntil?: number;
 }
 
 export interface Transfer {
@@ -106,6 +117,7 @@ export interface Transfer {
   pausedAt?: number;
   failedAt?: number;
   canceledAt?: number;
+  cancelReason?: CancelReason;
   durationSeconds?: number;
   averageSpeedBps?: number;
 
@@ -117,6 +129,10 @@ export interface Transfer {
   // Queue management
   queuePosition?: number;
 
+  // Sources found so far while waiting for the requested minimum
+  foundSources?: number;
+  requiredSources?: number;
+
   // Pause/resume state
   canResume?: boolean;
   pauseReason?: string;
@@ -124,6 +140,21 @@ export interface Transfer {
   // Completion data
   sourcesUsed?: SourceSummary[];
   keepPartial?: boolean;
+
+  // Shareable identifiers by protocol, once a finished download is seeded
+  seedingIdentifiers?: Record<string, string>;
+  // Why seeding stopped, when the seeded file no longer matched its hash
+  seedingError?: string;
+}
+
+/**
+ * Limits on how often the backend sends per-transfer events.
+ * By default chunk completions arrive folded into progress events as
+ * `chunksCompletedDelta`; full granularity sends each one as its own event.
+ */
+export interface EventCoalescingConfig {
+  maxProgressPerSecond: number; // 0 = unlimited
+  fullGranularity: boolean;
 }
 
 export interface TransferEventPayload {
@@ -179,6 +210,12 @@ function createTransferStore() {
           case "queued":
             handleQueuedEvent(transfers, event);
             break;
+          case "dequeued":
+            handleDequeuedEvent(transfers, event);
+            break;
+          case "waiting_for_sources":
+            handleWaitingForSourcesEvent(transfers, event);
+            break;
           case "started":
             handleStartedEvent(transfers, event);
             break;
@@ -194,6 +231,18 @@ function createTransferStore() {
           case "chunk_failed":
             handleChunkFailedEvent(transfers, event);
             break;
+          case "chunk_reassigned":
+            handleChunkReassignedEvent(transfers, event);
+            break;
+          case "chunk_duplicate_received":
+            handleChunkDuplicateReceivedEvent(transfers, event);
+            break;
+          case "source_blacklisted":
+            handleSourceBlacklistedEvent(transfers, event);
+            break;
+          case "source_throttled":
+            handleSourceThrottledEvent(transfers, event);
+            break;
           case "progress":
             handleProgressEvent(transfers, event);
             break;
@@ -206,6 +255,12 @@ function createTransferStore() {
           case "completed":
             handleCompletedEvent(transfers, event);
             break;
+          case "seeding_started":
+            handleSeedingStartedEvent(transfers, event);
+            break;
+          case "seed_verification_failed":
+            handleSeedVerificationFailedEvent(transfers, event);
+            break;
           case "failed":
             handleFailedEvent(transfers, event);
             break;
@@ -307,6 +362,53 @@ function handleQueuedEvent(transfers: Map<string, Transfer>, event: any) {
   transfers.set(transfer.transferId, transfer);
 }
 
+function handleDequeuedEvent(transfers: Map<string, Transfer>, event: any) {
+  const transfer = transfers.get(event.transferId);
+  if (!transfer) return;
+
+  transfer.queuePosition = undefined;
+  if (event.reason === "canceled") {
+    transfer.status = "canceled";
+    transfer.canceledAt = event.dequeuedAt;
+  } else if (event.reason === "paused") {
+    transfer.status = "paused";
+  } else {
+    transfer.status = "starting";
+  }
+}
+
+function handleWaitingForSourcesEvent(
+  transfers: Map<string, Transfer>,
+  event: any
+) {
+  let transfer = transfers.get(event.transferId);
+  if (!transfer) {
+    transfer = {
+      transferId: event.transferId,
+      fileHash: event.fileHash,
+      fileName: "Unknown",
+      fileSize: 0,
+      outputPath: "",
+      status: "waiting_for_sources",
+      priority: "normal",
+      downloadedBytes: 0,
+      completedChunks: 0,
+      totalChunks: 0,
+      progressPercentage: 0,
+      downloadSpeedBps: 0,
+      uploadSpeedBps: 0,
+      availableSources: [],
+      connectedSources: new Map(),
+      activeSources: 0,
+    };
+    transfers.set(transfer.transferId, transfer);
+  }
+
+  transfer.status = "waiting_for_sources";
+  transfer.foundSources = event.foundSources;
+  transfer.requiredSources = event.requiredSources;
+}
+
 function handleStartedEvent(transfers: Map<string, Transfer>, event: any) {
   let transfer = transfers.get(event.transferId);
   
@@ -406,12 +508,75 @@ function handleChunkFailedEvent(_transfers: Map<string, Transfer>, event: any) {
   console.warn("Chunk failed:", event);
 }
 
+function handleChunkReassignedEvent(
+  _transfers: Map<string, Transfer>,
+  event: any
+) {
+  // Currently just logging, useful for spotting flaky sources
+  console.debug(
+    `Chunks ${event.chunkIds.join(", ")} reassigned from ${event.fromSource ?? "unknown"} to ${event.toSource}`
+  );
+}
+
+function handleChunkDuplicateReceivedEvent(
+  _transfers: Map<string, Transfer>,
+  event: any
+) {
+  // Debug only: the first source keeps the credit, so nothing to update
+  console.debug(
+    `Duplicate chunk ${event.chunkId} from ${event.sourceId} dropped (already received from ${event.firstSourceId})`
+  );
+}
+
+function handleSourceBlacklistedEvent(
+  transfers: Map<string, Transfer>,
+  event: any
+) {
+  console.warn(
+    `Source ${event.sourceId} blacklisted for ${event.transferId}: ${event.reason}`
+  );
+
+  const transfer = transfers.get(event.transferId);
+  if (!transfer) return;
+
+  const source = transfer.connectedSources.get(event.sourceId);
+  if (source) {
+    source.isActive = false;
+  }
+
+  transfer.activeSources = Array.from(
+    transfer.connectedSources.values()
+  ).filter((s) => s.isActive).length;
+}
+
+function handleSourceThrottledEvent(
+  transfers: Map<string, Transfer>,
+  event: any
+) {
+  console.info(
+    `Source ${event.sourceId} returned HTTP ${event.statusCode}, pausing for ${Math.round(event.retryAfterMs / 1000)}s`
+  );
+
+  const transfer = transfers.get(event.transferId);
+  if (!transfer) return;
+
+  const source = transfer.connectedSources.get(event.sourceId);
+  if (source) {
+    source.throttledUntil = event.resumeAt;
+  }
+}
+
 function handleProgressEvent(transfers: Map<string, Transfer>, event: any) {
   const transfer = transfers.get(event.transferId);
   if (!transfer) return;
 
   transfer.downloadedBytes = event.downloadedBytes;
-  transfer.completedChunks = event.completedChunks;
+  // Chunk completions folded into this update never arrive as chunk_completed
+  // events, so count them here when the emitter doesn't track a total
+  transfer.completedChunks =
+    event.completedChunks > 0
+      ? event.completedChunks
+      : transfer.completedChunks + (event.chunksCompletedDelta ?? 0);
   transfer.progressPercentage = event.progressPercentage;
   transfer.downloadSpeedBps = event.downloadSpeedBps;
   transfer.uploadSpeedBps = event.uploadSpeedBps;
@@ -459,6 +624,27 @@ function handleCompletedEvent(transfers: Map<string, Transfer>, event: any) {
   transfer.sourcesUsed = event.sourcesUsed;
 }
 
+function handleSeedingStartedEvent(
+  transfers: Map<string, Transfer>,
+  event: any
+) {
+  const transfer = transfers.get(event.transferId);
+  if (!transfer) return;
+
+  transfer.seedingIdentifiers = event.identifiers;
+}
+
+function handleSeedVerificationFailedEvent(
+  transfers: Map<string, Transfer>,
+  event: any
+) {
+  const transfer = transfers.get(event.transferId);
+  if (!transfer) return;
+
+  transfer.seedingIdentifiers = undefined;
+  transfer.seedingError = event.error;
+}
+
 function handleFailedEvent(transfers: Map<string, Transfer>, event: any) {
   const transfer = transfers.get(event.transferId);
   if (!transfer) return;
@@ -475,8 +661,10 @@ function handleCanceledEvent(transfers: Map<string, Transfer>, event: any) {
   const transfer = transfers.get(event.transferId);
   if (!transfer) return;
 
-  transfer.status = "canceled";
+  // pause_all keeps the download's chunks for resume_all
+  transfer.status = event.reason === "paused" ? "paused" : "canceled";
   transfer.canceledAt = event.canceledAt;
+  transfer.cancelReason = event.reason;
   transfer.downloadedBytes = event.downloadedBytes;
   transfer.keepPartial = event.keepPartial;
 }
@@ -506,6 +694,7 @@ function calculateStats(transfers: Map<string, Transfer>) {
       case "queued":
         queuedCount++;
         break;
+      case "waiting_for_sources":
       case "starting":
       case "downloading":
         activeCount++;
@@ -636,6 +825,22 @@ export async function unsubscribeFromTransferEvents(): Promise<void> {
   unlistenFunctions = [];
 }
 
+/**
+ * Get the current event coalescing limits
+ */
+export async function getEventCoalescing(): Promise<EventCoalescingConfig> {
+  return invoke<EventCoalescingConfig>("get_transfer_event_coalescing");
+}
+
+/**
+ * Change the event coalescing limits, e.g. full granularity while debugging
+ */
+export async function setEventCoalescing(
+  config: EventCoalescingConfig
+): Promise<void> {
+  await invoke("set_transfer_event_coalescing", { config });
+}
+
 // ============================================================================
 // Utility Functions
 // ============================================================================
@@ -677,6 +882,8 @@ export function getStatusColor(status: TransferStatus): string {
   switch (status) {
     case "queued":
       return "gray";
+    case "waiting_for_sources":
+      return "blue";
     case "starting":
       return "blue";
     case "downloading":